@@ -0,0 +1,30 @@
+#!/bin/sh
+# riscv-testsをビルドし、rv32の-p版のELFをtests/isa/elves以下のスイートごとのディレクトリに配置する
+# riscv64-unknown-elf-gccなどのツールチェインが必要(プレフィックスはRISCV_PREFIXで変更できる)
+# 既にチェックアウトしたriscv-testsを使う場合はRISCV_TESTS_DIRで指定する
+set -eu
+
+root=$(cd "$(dirname "$0")/.." && pwd)
+src=${RISCV_TESTS_DIR:-$root/target/riscv-tests}
+dst=$root/tests/isa/elves
+
+if [ ! -d "$src" ]; then
+    git clone --recursive https://github.com/riscv-software-src/riscv-tests.git "$src"
+fi
+
+make -C "$src/isa" XLEN=32 ${RISCV_PREFIX:+RISCV_PREFIX=$RISCV_PREFIX}
+
+for suite in ui um ua uf ud uzba uzbb uzbs mi si; do
+    case $suite in
+        si) dir=rv32si-p ;;
+        *) dir=rv32$suite ;;
+    esac
+
+    rm -rf "${dst:?}/$dir"
+    mkdir -p "$dst/$dir"
+
+    find "$src/isa" -maxdepth 1 -type f -name "rv32$suite-p-*" ! -name '*.dump' \
+        -exec cp {} "$dst/$dir" \;
+
+    echo "$dir: $(ls "$dst/$dir" | wc -l) tests"
+done
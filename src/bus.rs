@@ -1,18 +1,27 @@
-use std::{collections::VecDeque, ops::Range};
+#[cfg(target_arch = "wasm32")]
+use std::collections::VecDeque;
+use std::{fmt::Display, ops::Range};
 
 #[cfg(target_arch = "wasm32")]
 use crate::device::DeviceMessage;
 use crate::{
-    AccessType, IRQ, Priv, Result, Trap,
-    bus::{clint::Clint, plic::Plic},
+    AccessType, Result, Trap,
+    bus::{
+        clint::Clint,
+        plic::{PLIC_NUM, Plic},
+    },
     csr::Csr,
-    device::DeviceTrait,
-    memory::Memory,
+    device::{BusError, BusResult, DeviceTrait, Trigger},
+    fdt::FdtBuilder,
+    memory::{MEMORY_SIZE, Memory},
+    snapshot::{self, Snapshot, SnapshotError, SnapshotReader, SnapshotWriter},
 };
 
 mod clint;
 mod plic;
 
+pub use clint::ClintLayout;
+
 pub mod uart;
 pub mod virtio_gpu;
 pub mod virtio_mmio;
@@ -20,21 +29,39 @@ pub mod virtio_net;
 
 pub const MEMORY_BASE: u32 = 0x80000000;
 pub const MEMORY_END: u32 = 0x90000000;
+pub const MAX_MEMORY_SIZE: usize = (MEMORY_END - MEMORY_BASE) as usize;
+
+pub const MAX_HART_COUNT: usize = 32;
 
 const CLINT_BASE: u32 = 0x2000000;
 const CLINT_END: u32 = CLINT_BASE + 0x10000;
 
+// ClintLayout::Aclintの場合はCLINTをMSWIとMTIMERに分け、SSWIを追加する
+const ACLINT_MSWI_BASE: u32 = CLINT_BASE;
+const ACLINT_MSWI_SIZE: u32 = 0x4000;
+
+// MTIMERはmtimecmpの後にmtimeが置かれる
+const ACLINT_MTIMER_BASE: u32 = CLINT_BASE + 0x4000;
+const ACLINT_MTIME: u32 = ACLINT_MTIMER_BASE + 0x7ff8;
+
+const ACLINT_SSWI_BASE: u32 = 0x2f00000;
+const ACLINT_SSWI_END: u32 = ACLINT_SSWI_BASE + 0x4000;
+
 const PLIC_BASE: u32 = 0xc000000;
 const PLIC_END: u32 = PLIC_BASE + 0x4000000;
 
+// 標準で接続するデバイスの割り込み源の番号は、既存のdtbを使えるように固定する
 pub const UART_BASE: u32 = 0x10000000;
 pub const UART_END: u32 = UART_BASE + 0x100;
+pub const UART_IRQ: u32 = 0xa;
 
 pub const VIRTIO_NET_BASE: u32 = 0x10008000;
 pub const VIRTIO_NET_END: u32 = VIRTIO_NET_BASE + 0x1000;
+pub const VIRTIO_NET_IRQ: u32 = 1;
 
 pub const VIRTIO_GPU_BASE: u32 = 0x10009000;
 pub const VIRTIO_GPU_END: u32 = VIRTIO_GPU_BASE + 0x801000;
+pub const VIRTIO_GPU_IRQ: u32 = 2;
 
 pub struct CpuContext<'a> {
     pub csr: &'a mut Csr,
@@ -46,6 +73,7 @@ pub struct CpuContext<'a> {
 pub struct BusDevice {
     device: Box<dyn DeviceTrait>,
     range: Range<u32>,
+    irq: Option<u32>, // PLICの割り込み源の番号 Noneの場合はadd_deviceで割り当てる
 }
 
 pub struct Bus {
@@ -56,7 +84,8 @@ pub struct Bus {
 
     devices: Vec<BusDevice>,
 
-    irqs_to_raise: VecDeque<IRQ>,
+    // hartごとのLR.Wで予約した物理アドレス
+    reservations: Vec<Option<u32>>,
 
     #[cfg(target_arch = "wasm32")]
     incoming_messages: VecDeque<DeviceMessage>,
@@ -64,29 +93,100 @@ pub struct Bus {
 
 impl BusDevice {
     pub fn new(device: Box<dyn DeviceTrait>, range: Range<u32>) -> Self {
-        Self { device, range }
+        Self {
+            device,
+            range,
+            irq: None,
+        }
     }
+
+    // 割り込み源の番号を指定する関数
+    pub fn set_irq(mut self, irq: u32) -> Self {
+        self.irq = Some(irq);
+
+        self
+    }
+
+    #[inline]
+    fn irq(&self) -> u32 {
+        self.irq.unwrap()
+    }
+}
+
+// デバイスを接続できない理由
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddDeviceError {
+    // 割り込み源の番号が1からPLIC_NUM-1の範囲外
+    InvalidIrq(u32),
+    // 割り込み源の番号が他のデバイスで使われている
+    IrqInUse(u32),
+    // 空いている割り込み源が無い
+    NoIrqLeft,
+    // アドレスの範囲が空か、アドレス空間の末尾を超える
+    InvalidRange { base: u32, size: u32 },
+    // アドレスの範囲がメモリや他のデバイスと重なる
+    Overlap { start: u32, end: u32 },
 }
 
+impl Display for AddDeviceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidIrq(irq) => write!(f, "irq {} is out of range", irq),
+            Self::IrqInUse(irq) => write!(f, "irq {} is already used", irq),
+            Self::NoIrqLeft => write!(f, "no irq is left"),
+            Self::InvalidRange { base, size } => write!(
+                f,
+                "0x{:x} bytes at 0x{:08x} do not fit in the address space",
+                size, base
+            ),
+            Self::Overlap { start, end } => write!(
+                f,
+                "0x{:08x}-0x{:08x} overlaps memory or another device",
+                start, end
+            ),
+        }
+    }
+}
+
+impl std::error::Error for AddDeviceError {}
+
 impl<'a> CpuContext<'a> {
     #[inline]
     pub fn make_trap(&self) -> Trap {
         self.access_type.into_trap(self.is_walk)
     }
+
+    // バスでのアクセスの失敗をログに出力し、アクセスフォルトにする関数
+    // ゲストのドライバの不具合でシミュレータ全体が止まらないようにする
+    #[cold]
+    fn make_bus_fault(&self, addr: u32, e: BusError) -> Trap {
+        eprintln!(
+            "[WARNING]: hart {} {:?} access to 0x{:08x} failed: {}",
+            self.csr.mhartid, self.access_type, addr, e
+        );
+
+        self.make_trap()
+    }
 }
 
 impl Default for Bus {
     fn default() -> Self {
-        let memory = Memory::default();
-        let clint = Clint::default();
-        let plic = Plic::default();
+        Self::new(MEMORY_SIZE, 1)
+    }
+}
+
+impl Bus {
+    pub fn new(memory_size: usize, hart_count: usize) -> Self {
+        let memory = Memory::new(memory_size);
+        let clint = Clint::new(hart_count);
+        let plic = Plic::new(hart_count);
 
         Self {
             memory,
             clint,
             plic,
             devices: Vec::new(),
-            irqs_to_raise: VecDeque::new(),
+            reservations: vec![None; hart_count],
             #[cfg(target_arch = "wasm32")]
             incoming_messages: VecDeque::new(),
         }
@@ -96,81 +196,166 @@ impl Default for Bus {
 impl Bus {
     #[inline]
     pub fn read(&mut self, addr: u32, size: u32, ctx: CpuContext) -> Result<u32> {
-        match addr {
-            CLINT_BASE..CLINT_END => self.clint.read(addr - CLINT_BASE, size, ctx.csr),
-            PLIC_BASE..PLIC_END => self.plic.read(addr - PLIC_BASE, size, ctx.csr),
-            MEMORY_BASE..MEMORY_END => {
-                self.memory
-                    .read(addr - MEMORY_BASE, size, ctx.access_type, ctx.is_walk)
+        let res = match addr {
+            CLINT_BASE..CLINT_END => self.clint.read(addr - CLINT_BASE, size, ctx.csr.time),
+            ACLINT_SSWI_BASE..ACLINT_SSWI_END if self.clint.layout() == ClintLayout::Aclint => {
+                self.clint.read_sswi(addr - ACLINT_SSWI_BASE, size)
             }
-            _ => {
-                for i in 0..self.devices.len() {
-                    if self.devices[i].range.contains(&addr) {
-                        let offset = addr - self.devices[i].range.start;
-                        let res = self.devices[i]
-                            .device
-                            //[todo] read内でaccess_type事に例外を出すように変更する。
-                            .read(offset, size, &mut self.memory)?;
-
-                        if res.is_interrupting {
-                            let irq = self.devices[i].device.irq();
-                            self.irqs_to_raise.push_back(irq);
-                        }
-                        return Ok(res.value);
-                    }
+            PLIC_BASE..PLIC_END => {
+                let offset = addr - PLIC_BASE;
+                let res = self.plic.read(offset, size);
+
+                // claimした割り込み源のデバイスに割り込みが受け付けられたことを伝える
+                if let Ok(irq) = res
+                    && irq != 0
+                    && Plic::is_claim_register(offset)
+                {
+                    self.take_interrupt(irq);
                 }
 
-                Err(ctx.make_trap())
+                res
             }
-        }
+            MEMORY_BASE..MEMORY_END => {
+                return self
+                    .memory
+                    .read(addr - MEMORY_BASE, size, ctx.access_type, ctx.is_walk);
+            }
+            _ => self.read_device(addr, size),
+        };
+
+        res.map_err(|e| ctx.make_bus_fault(addr, e))
     }
 
     #[inline]
     pub fn write(&mut self, addr: u32, size: u32, value: u32, ctx: CpuContext) -> Result<()> {
-        match addr {
-            CLINT_BASE..CLINT_END => self.clint.write(addr - CLINT_BASE, size, value, ctx.csr),
-            PLIC_BASE..PLIC_END => self.plic.write(addr - PLIC_BASE, size, value, ctx.csr),
-            MEMORY_BASE..MEMORY_END => self.memory.write(
-                addr - MEMORY_BASE,
-                size,
-                value,
-                ctx.access_type,
-                ctx.is_walk,
-            ),
-            _ => {
-                for i in 0..self.devices.len() {
-                    if self.devices[i].range.contains(&addr) {
-                        let offset = addr - self.devices[i].range.start;
-                        let res =
-                            self.devices[i]
-                                .device
-                                .write(offset, size, value, &mut self.memory)?;
-
-                        if res.is_interrupting {
-                            let irq = self.devices[i].device.irq();
-                            self.irqs_to_raise.push_back(irq);
-                        }
-                        return Ok(res.value);
-                    }
-                }
-
-                Err(ctx.make_trap())
+        let res = match addr {
+            CLINT_BASE..CLINT_END => self
+                .clint
+                .write(addr - CLINT_BASE, size, value, ctx.csr.time),
+            ACLINT_SSWI_BASE..ACLINT_SSWI_END if self.clint.layout() == ClintLayout::Aclint => {
+                self.clint.write_sswi(addr - ACLINT_SSWI_BASE, size, value)
             }
-        }
+            PLIC_BASE..PLIC_END => self.plic.write(addr - PLIC_BASE, size, value),
+            MEMORY_BASE..MEMORY_END => {
+                self.invalidate_reservations(addr, size, ctx.csr.mhartid as usize);
+
+                return self.memory.write(
+                    addr - MEMORY_BASE,
+                    size,
+                    value,
+                    ctx.access_type,
+                    ctx.is_walk,
+                );
+            }
+            _ => self.write_device(addr, size, value),
+        };
+
+        res.map_err(|e| ctx.make_bus_fault(addr, e))
     }
 
-    pub fn add_device(&mut self, device: BusDevice) -> &mut Self {
-        self.devices.push(device);
+    #[inline]
+    fn read_device(&mut self, addr: u32, size: u32) -> BusResult<u32> {
+        let i = self.device_index(addr)?;
+        let offset = addr - self.devices[i].range.start;
+        let res = self.devices[i]
+            .device
+            .read(offset, size, &mut self.memory)?;
 
-        self
+        self.invalidate_device_writes();
+
+        signal_interrupt(&mut self.plic, &self.devices[i], res.is_interrupting);
+        Ok(res.value)
     }
 
     #[inline]
-    pub fn tick(&mut self, prv: Priv, csr: &mut Csr) {
-        if !csr.can_external_interrupt(prv) {
-            return;
+    fn write_device(&mut self, addr: u32, size: u32, value: u32) -> BusResult<()> {
+        let i = self.device_index(addr)?;
+        let offset = addr - self.devices[i].range.start;
+        let res = self.devices[i]
+            .device
+            .write(offset, size, value, &mut self.memory)?;
+
+        self.invalidate_device_writes();
+
+        signal_interrupt(&mut self.plic, &self.devices[i], res.is_interrupting);
+        Ok(())
+    }
+
+    // addrを含むデバイスの添字を返す関数
+    #[inline]
+    fn device_index(&self, addr: u32) -> BusResult<usize> {
+        self.devices
+            .iter()
+            .position(|d| d.range.contains(&addr))
+            .ok_or(BusError::Unmapped)
+    }
+
+    // デバイスを接続し、割り当てた割り込み源の番号を返す関数
+    // 番号が指定されていない場合は使われていない最小の番号を割り当てる
+    pub fn add_device(
+        &mut self,
+        mut device: BusDevice,
+    ) -> std::result::Result<u32, AddDeviceError> {
+        let Range { start, end } = device.range;
+
+        if start >= end {
+            return Err(AddDeviceError::InvalidRange {
+                base: start,
+                size: end.wrapping_sub(start),
+            });
+        }
+
+        if self.is_range_used(&device.range) {
+            return Err(AddDeviceError::Overlap { start, end });
+        }
+
+        let irq = match device.irq {
+            Some(irq) if !(1..PLIC_NUM).contains(&irq) => {
+                return Err(AddDeviceError::InvalidIrq(irq));
+            }
+            Some(irq) if self.is_irq_used(irq) => return Err(AddDeviceError::IrqInUse(irq)),
+            Some(irq) => irq,
+            None => (1..PLIC_NUM)
+                .find(|irq| !self.is_irq_used(*irq))
+                .ok_or(AddDeviceError::NoIrqLeft)?,
+        };
+
+        device.irq = Some(irq);
+        self.devices.push(device);
+
+        Ok(irq)
+    }
+
+    // rangeがRAMやCLINT、PLIC、接続済みのデバイスと重なるかを返す関数
+    // Bus::readとBus::writeで先に一致する範囲に重なるデバイスにはアクセスできない
+    fn is_range_used(&self, range: &Range<u32>) -> bool {
+        let overlaps = |other: &Range<u32>| range.start < other.end && other.start < range.end;
+
+        let mut fixed = vec![
+            CLINT_BASE..CLINT_END,
+            PLIC_BASE..PLIC_END,
+            MEMORY_BASE..MEMORY_END,
+        ];
+
+        if self.clint.layout() == ClintLayout::Aclint {
+            fixed.push(ACLINT_SSWI_BASE..ACLINT_SSWI_END);
         }
 
+        fixed.iter().any(overlaps) || self.devices.iter().any(|d| overlaps(&d.range))
+    }
+
+    #[inline]
+    fn is_irq_used(&self, irq: u32) -> bool {
+        self.devices.iter().any(|d| d.irq == Some(irq))
+    }
+
+    pub fn hart_count(&self) -> usize {
+        self.reservations.len()
+    }
+
+    // 1命令ごとに一度だけ呼び出す関数
+    #[inline]
+    pub fn tick(&mut self) {
         #[cfg(target_arch = "wasm32")]
         let message = self
             .incoming_messages
@@ -183,57 +368,321 @@ impl Bus {
 
             let is_interrupting = device.device.tick(&mut self.memory);
 
-            if is_interrupting {
-                self.irqs_to_raise.push_back(device.device.irq());
-            }
+            signal_interrupt(&mut self.plic, device, is_interrupting);
         }
 
-        if self.irqs_to_raise.len() != 0 {
-            let irq = self.irqs_to_raise.pop_front().unwrap();
-            self.raise_irq(irq);
-            self.raise_interrupt(csr);
-            return;
-        } else {
-            csr.set_mip_seip(0);
+        self.invalidate_device_writes();
+    }
+
+    // CLINTとPLICの状態を各hartのCSRに反映する関数
+    // hartが命令を実行するごとに呼び出し、mtimeが書き込まれた場合はその値を返す
+    #[inline]
+    pub fn sync_harts<'a>(&mut self, csrs: impl Iterator<Item = &'a mut Csr>) -> Option<u64> {
+        let is_clint_dirty = self.clint.take_dirty();
+        let is_plic_dirty = self.plic.take_dirty();
+
+        if !is_clint_dirty && !is_plic_dirty {
+            return None;
         }
+
+        let mtime = self.clint.take_mtime_write();
+
+        for (hart, csr) in csrs.enumerate() {
+            if is_clint_dirty {
+                self.clint.sync(hart, csr, mtime);
+            }
+
+            if is_plic_dirty {
+                self.plic.sync(hart, csr);
+            }
+        }
+
+        mtime
+    }
+
+    // デバイスを介さずにPLICの割り込み源に信号を入れる関数
+    pub fn set_irq_level(&mut self, irq: u32, level: bool) {
+        self.plic.set_level(irq, level);
+    }
+
+    pub fn trigger_irq(&mut self, irq: u32) {
+        self.plic.trigger_edge(irq);
     }
 
+    pub fn clint_layout(&self) -> ClintLayout {
+        self.clint.layout()
+    }
+
+    pub fn set_clint_layout(&mut self, layout: ClintLayout) {
+        self.clint.set_layout(layout);
+    }
+
+    // LR.Wでpaを予約する関数
+    #[inline]
+    pub fn reserve(&mut self, hart: usize, pa: u32) {
+        self.reservations[hart] = Some(pa);
+    }
+
+    // SC.Wで予約を取り消し、他のhartに書き込まれずに残っていたかを返す関数
     #[inline]
-    fn raise_irq(&mut self, irq: IRQ) {
-        self.plic.set_pending(irq);
+    pub fn take_reservation(&mut self, hart: usize) -> bool {
+        self.reservations[hart].take().is_some()
     }
 
+    // 他のhartが予約しているアドレスへの書き込みで予約を取り消す関数
+    // 予約は4バイト単位
     #[inline]
-    fn raise_interrupt(&mut self, csr: &mut Csr) {
-        if let Some(prv) = self.plic.raise_interrupt() {
-            match prv {
-                Priv::Machine => csr.set_mip_meip(1),
-                Priv::Supervisor => csr.set_mip_seip(1),
-                _ => unreachable!(),
+    fn invalidate_reservations(&mut self, addr: u32, size: u32, hart: usize) {
+        let first = addr & !0x3;
+        let last = addr.wrapping_add(size - 1) & !0x3;
+
+        for (i, reservation) in self.reservations.iter_mut().enumerate() {
+            if i != hart && matches!(*reservation, Some(pa) if pa == first || pa == last) {
+                *reservation = None;
             }
         }
     }
 
+    // デバイスがDMAで書き込んだ範囲にある予約を全てのhartで取り消す関数
     #[inline]
-    pub fn prepare_interrupt(&mut self) {
-        let irq = self.plic.interrupting_irq().unwrap();
+    fn invalidate_device_writes(&mut self) {
+        if !self.memory.has_device_writes() {
+            return;
+        }
 
-        for i in 0..self.devices.len() {
-            if self.devices[i].device.irq() == irq {
-                self.devices[i].device.take_interrupt();
-                return;
+        for range in self.memory.take_device_writes() {
+            self.invalidate_range(range);
+        }
+    }
+
+    // CPU以外から書き込まれた物理アドレスの範囲にある予約を全てのhartで取り消す関数
+    fn invalidate_range(&mut self, range: Range<usize>) {
+        for reservation in &mut self.reservations {
+            // 予約は4バイト単位
+            let is_written = reservation.is_some_and(|pa| {
+                let pa = pa as usize;
+
+                pa < range.end && range.start < pa + 4
+            });
+
+            if is_written {
+                *reservation = None;
             }
         }
+    }
 
-        unreachable!();
+    // claimされた割り込み源のデバイスに割り込みが受け付けられたことを伝える関数
+    #[inline]
+    fn take_interrupt(&mut self, irq: u32) {
+        if let Some(device) = self.devices.iter_mut().find(|d| d.irq() == irq) {
+            device.device.take_interrupt();
+        }
     }
 
     pub fn memory(&mut self) -> &mut Memory {
         &mut self.memory
     }
 
+    pub fn memory_size(&self) -> usize {
+        self.memory.size()
+    }
+
+    // デバッガ用にメモリを読み込む関数
+    // デバイスのレジスタは読み込みで状態が変わるのでRAMのみ対象とする
+    pub fn debug_read(&self, addr: u32, buf: &mut [u8]) -> bool {
+        match self.ram_offset(addr, buf.len()) {
+            Some(offset) => {
+                buf.copy_from_slice(&self.memory.array[offset..offset + buf.len()]);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn debug_write(&mut self, addr: u32, data: &[u8]) -> bool {
+        match self.ram_offset(addr, data.len()) {
+            Some(offset) => {
+                self.memory.raw_write(offset, data);
+
+                // 書き換えた値に対してSC.Wが成功しないようにする
+                let start = addr as usize;
+                self.invalidate_range(start..start + data.len());
+
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn ram_offset(&self, addr: u32, len: usize) -> Option<usize> {
+        let offset = addr.checked_sub(MEMORY_BASE)? as usize;
+
+        (offset + len <= self.memory.size()).then_some(offset)
+    }
+
+    // fdt_nameが一致する最初のデバイスのベースアドレスを返す関数
+    pub fn find_device(&self, fdt_name: &str) -> Option<u32> {
+        self.devices
+            .iter()
+            .find(|d| d.device.fdt_name() == fdt_name)
+            .map(|d| d.range.start)
+    }
+
+    // soc以下のノードを書き込む関数
+    // intc_phandlesは各hartの割り込みコントローラのphandle
+    pub fn write_fdt(&self, fdt: &mut FdtBuilder, intc_phandles: &[u32], plic_phandle: u32) {
+        self.write_clint_fdt(fdt, intc_phandles);
+
+        // コンテキストの順(hartごとにM、S)に並べる
+        let plic_interrupts: Vec<u32> = intc_phandles
+            .iter()
+            .flat_map(|phandle| [*phandle, 11, *phandle, 9])
+            .collect();
+
+        fdt.begin_node(&format!("plic@{:x}", PLIC_BASE))
+            .property_strings("compatible", &["sifive,plic-1.0.0", "riscv,plic0"])
+            .property_cells("reg", &[PLIC_BASE, PLIC_END - PLIC_BASE])
+            .property_cells("interrupts-extended", &plic_interrupts)
+            .property_u32("riscv,ndev", PLIC_NUM - 1)
+            .property_null("interrupt-controller")
+            .property_u32("#interrupt-cells", 1)
+            .property_u32("#address-cells", 0)
+            .property_u32("phandle", plic_phandle)
+            .end_node();
+
+        for device in &self.devices {
+            let base = device.range.start;
+            let size = device.range.end - device.range.start;
+
+            fdt.begin_node(&format!("{}@{:x}", device.device.fdt_name(), base))
+                .property_strings("compatible", device.device.fdt_compatible())
+                .property_cells("reg", &[base, size])
+                .property_u32("interrupts", device.irq())
+                .property_u32("interrupt-parent", plic_phandle);
+
+            device.device.fdt_properties(fdt);
+
+            fdt.end_node();
+        }
+    }
+
+    fn write_clint_fdt(&self, fdt: &mut FdtBuilder, intc_phandles: &[u32]) {
+        // 各hartの割り込みコントローラにcausesの割り込みを繋ぐ
+        let interrupts = |causes: &[u32]| -> Vec<u32> {
+            intc_phandles
+                .iter()
+                .flat_map(|phandle| causes.iter().flat_map(move |cause| [*phandle, *cause]))
+                .collect()
+        };
+
+        match self.clint.layout() {
+            ClintLayout::Clint => {
+                fdt.begin_node(&format!("clint@{:x}", CLINT_BASE))
+                    .property_strings("compatible", &["sifive,clint0", "riscv,clint0"])
+                    .property_cells("reg", &[CLINT_BASE, CLINT_END - CLINT_BASE])
+                    .property_cells("interrupts-extended", &interrupts(&[3, 7]))
+                    .end_node();
+            }
+            ClintLayout::Aclint => {
+                fdt.begin_node(&format!("mswi@{:x}", ACLINT_MSWI_BASE))
+                    .property_string("compatible", "riscv,aclint-mswi")
+                    .property_cells("reg", &[ACLINT_MSWI_BASE, ACLINT_MSWI_SIZE])
+                    .property_cells("interrupts-extended", &interrupts(&[3]))
+                    .end_node();
+
+                // regはmtime、mtimecmpの順
+                fdt.begin_node(&format!("mtimer@{:x}", ACLINT_MTIMER_BASE))
+                    .property_string("compatible", "riscv,aclint-mtimer")
+                    .property_cells(
+                        "reg",
+                        &[
+                            ACLINT_MTIME,
+                            8,
+                            ACLINT_MTIMER_BASE,
+                            ACLINT_MTIME - ACLINT_MTIMER_BASE,
+                        ],
+                    )
+                    .property_cells("interrupts-extended", &interrupts(&[7]))
+                    .end_node();
+
+                fdt.begin_node(&format!("sswi@{:x}", ACLINT_SSWI_BASE))
+                    .property_string("compatible", "riscv,aclint-sswi")
+                    .property_cells(
+                        "reg",
+                        &[ACLINT_SSWI_BASE, ACLINT_SSWI_END - ACLINT_SSWI_BASE],
+                    )
+                    .property_cells("interrupts-extended", &interrupts(&[1]))
+                    .end_node();
+            }
+        }
+    }
+
+    fn device_list(&self) -> String {
+        self.devices
+            .iter()
+            .map(|d| format!("{}@{:x}:{}", d.device.fdt_name(), d.range.start, d.irq()))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
     #[cfg(target_arch = "wasm32")]
     pub fn push_messaeg(&mut self, message: DeviceMessage) {
         self.incoming_messages.push_back(message);
     }
 }
+
+// デバイスの割り込みの信号をPLICのゲートウェイに伝える関数
+#[inline]
+fn signal_interrupt(plic: &mut Plic, device: &BusDevice, is_interrupting: bool) {
+    let irq = device.irq();
+
+    match device.device.trigger() {
+        Trigger::Edge => {
+            if is_interrupting {
+                plic.trigger_edge(irq);
+            }
+        }
+        Trigger::Level => plic.set_level(irq, is_interrupting),
+    }
+}
+
+impl Snapshot for Bus {
+    fn save(&self, w: &mut SnapshotWriter) {
+        self.memory.save(w);
+        self.clint.save(w);
+        self.plic.save(w);
+
+        for reservation in &self.reservations {
+            w.write_option_u32(*reservation);
+        }
+
+        // 復元時に同じ構成か確認するために名前とアドレスも保存する
+        w.write_string(&self.device_list());
+        for device in &self.devices {
+            device.device.save(w);
+        }
+    }
+
+    fn restore(&mut self, r: &mut SnapshotReader) -> snapshot::Result<()> {
+        self.memory.restore(r)?;
+        self.clint.restore(r)?;
+        self.plic.restore(r)?;
+
+        for reservation in &mut self.reservations {
+            *reservation = r.read_option_u32()?;
+        }
+
+        let expected = r.read_string()?;
+        let actual = self.device_list();
+
+        if expected != actual {
+            return Err(SnapshotError::DeviceMismatch { expected, actual });
+        }
+
+        for device in &mut self.devices {
+            device.device.restore(r)?;
+        }
+
+        Ok(())
+    }
+}
@@ -1,44 +1,223 @@
-use crate::{Result, csr::Csr};
+use crate::{
+    csr::Csr,
+    device::{BusError, BusResult},
+    snapshot::{self, Snapshot, SnapshotReader, SnapshotWriter},
+};
 
-#[derive(Default)]
-pub struct Clint {}
+// hartごとのレジスタの配置
+// ACLINTの場合も、MSWIは先頭、MTIMERは0x4000からの同じ配置になる
+const CLINT_MSIP_BASE: u32 = 0;
+const CLINT_MSIP_UNIT: u32 = 4;
+
+const CLINT_MTIMECMP_BASE: u32 = 0x4000;
+const CLINT_MTIMECMP_UNIT: u32 = 8;
+
+const CLINT_MTIME: u32 = 0xbff8;
+
+// SSWIのsetssipの配置
+const SSWI_SETSSIP_UNIT: u32 = 4;
+
+// CLINTの構成
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ClintLayout {
+    // SiFiveのCLINT
+    #[default]
+    Clint,
+    // ACLINTのMSWI、MTIMER、SSWIに分けたもの
+    Aclint,
+}
+
+// msip、mtimecmp、mtimeはCLINTが持ち、各hartのCSRにはsyncで反映する
+// 64bitのレジスタは下位と上位の4バイトずつアクセスする
+pub struct Clint {
+    layout: ClintLayout,
+
+    msip: Vec<u32>,
+    mtimecmp: Vec<u64>,
+
+    // CSRに反映していない書き込み
+    mtime_write: Option<u64>,
+    setssip: Vec<bool>,
+    is_dirty: bool,
+}
 
 impl Clint {
+    pub fn new(hart_count: usize) -> Self {
+        Self {
+            layout: ClintLayout::default(),
+            msip: vec![0; hart_count],
+            mtimecmp: vec![0; hart_count],
+            mtime_write: None,
+            setssip: vec![false; hart_count],
+            is_dirty: false,
+        }
+    }
+
+    pub fn layout(&self) -> ClintLayout {
+        self.layout
+    }
+
+    pub fn set_layout(&mut self, layout: ClintLayout) {
+        self.layout = layout;
+    }
+
     #[inline]
-    pub fn read(&mut self, offset: u32, size: u32, csr: &mut Csr) -> Result<u32> {
-        if size != 4 {
-            unimplemented!();
+    pub fn read(&mut self, offset: u32, size: u32, time: u64) -> BusResult<u32> {
+        check_access(offset, size)?;
+
+        if let Some(hart) = self.hart_of(offset, CLINT_MSIP_BASE, CLINT_MSIP_UNIT) {
+            Ok(self.msip[hart])
+        } else if let Some(hart) = self.hart_of(offset, CLINT_MTIMECMP_BASE, CLINT_MTIMECMP_UNIT) {
+            Ok(read_half(self.mtimecmp[hart], offset))
+        } else if offset == CLINT_MTIME || offset == CLINT_MTIME + 4 {
+            let mtime = self.mtime_write.unwrap_or(time);
+
+            Ok(read_half(mtime, offset))
+        } else {
+            Err(BusError::Register(offset))
         }
+    }
+
+    #[inline]
+    pub fn write(&mut self, offset: u32, size: u32, value: u32, time: u64) -> BusResult<()> {
+        check_access(offset, size)?;
 
-        match offset {
-            0 => Ok(csr.get_mip_msip()),
-            _ => unreachable!(),
+        if let Some(hart) = self.hart_of(offset, CLINT_MSIP_BASE, CLINT_MSIP_UNIT) {
+            self.msip[hart] = value & 0x1;
+        } else if let Some(hart) = self.hart_of(offset, CLINT_MTIMECMP_BASE, CLINT_MTIMECMP_UNIT) {
+            self.mtimecmp[hart] = write_half(self.mtimecmp[hart], offset, value);
+        } else if offset == CLINT_MTIME || offset == CLINT_MTIME + 4 {
+            let mtime = self.mtime_write.unwrap_or(time);
+
+            self.mtime_write = Some(write_half(mtime, offset, value));
+        } else {
+            return Err(BusError::Register(offset));
         }
+
+        self.is_dirty = true;
+
+        Ok(())
     }
 
+    // SSWIのsetssipは読み込むと常に0になる
     #[inline]
-    pub fn write(&mut self, offset: u32, size: u32, value: u32, csr: &mut Csr) -> Result<()> {
-        if size != 4 {
-            unimplemented!();
+    pub fn read_sswi(&mut self, offset: u32, size: u32) -> BusResult<u32> {
+        check_access(offset, size)?;
+
+        match self.hart_of(offset, 0, SSWI_SETSSIP_UNIT) {
+            Some(_) => Ok(0),
+            None => Err(BusError::Register(offset)),
         }
+    }
 
-        match offset {
-            0 => {
-                // msip
-                let msip = value & 0x1;
-                csr.set_mip_msip(msip);
-            }
-            0x4000 => {
-                // mtimecmp
-                csr.set_mtimecmp(value);
-            }
-            0x4004 => {
-                // mtimecmph
-                csr.set_mtimecmph(value);
-            }
-            _ => unreachable!(),
+    // setssipに1を書き込むとhartのmip.SSIPが立つ
+    #[inline]
+    pub fn write_sswi(&mut self, offset: u32, size: u32, value: u32) -> BusResult<()> {
+        check_access(offset, size)?;
+
+        let hart = self
+            .hart_of(offset, 0, SSWI_SETSSIP_UNIT)
+            .ok_or(BusError::Register(offset))?;
+
+        if value & 0x1 != 0 {
+            self.setssip[hart] = true;
+            self.is_dirty = true;
         }
 
         Ok(())
     }
+
+    // offsetがbaseからunitごとに並んだレジスタのどのhartのものかを返す関数
+    #[inline]
+    fn hart_of(&self, offset: u32, base: u32, unit: u32) -> Option<usize> {
+        let offset = offset.checked_sub(base)?;
+        let hart = (offset / unit) as usize;
+
+        (offset.is_multiple_of(4) && hart < self.msip.len()).then_some(hart)
+    }
+
+    #[inline]
+    pub fn take_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.is_dirty)
+    }
+
+    // 前回のsync以降のmtimeへの書き込みを返す関数
+    #[inline]
+    pub fn take_mtime_write(&mut self) -> Option<u64> {
+        self.mtime_write.take()
+    }
+
+    // 前回のsync以降の書き込みをhartのCSRに反映する関数
+    #[inline]
+    pub fn sync(&mut self, hart: usize, csr: &mut Csr, mtime: Option<u64>) {
+        if let Some(mtime) = mtime {
+            csr.write_time(mtime);
+        }
+
+        csr.set_mip_msip(self.msip[hart]);
+        csr.set_mtimecmp(self.mtimecmp[hart]);
+
+        if std::mem::take(&mut self.setssip[hart]) {
+            csr.set_mip_ssip();
+        }
+    }
+}
+
+// レジスタへのアクセスは4バイト単位で、アラインされていない場合は存在しないレジスタとして扱う
+#[inline]
+fn check_access(offset: u32, size: u32) -> BusResult<()> {
+    if size != 4 {
+        Err(BusError::Size(size))
+    } else if !offset.is_multiple_of(4) {
+        Err(BusError::Register(offset))
+    } else {
+        Ok(())
+    }
+}
+
+// 64bitのレジスタのoffsetの位置の4バイトを返す関数
+#[inline]
+fn read_half(value: u64, offset: u32) -> u32 {
+    match offset % 8 {
+        0 => value as u32,
+        _ => (value >> 32) as u32,
+    }
+}
+
+// 64bitのレジスタのoffsetの位置の4バイトを書き換えた値を返す関数
+#[inline]
+fn write_half(value: u64, offset: u32, half: u32) -> u64 {
+    match offset % 8 {
+        0 => (value & (0xffffffff << 32)) | half as u64,
+        _ => (value & 0xffffffff) | ((half as u64) << 32),
+    }
+}
+
+// 書き込みは毎命令CSRに反映されるので、msipとmtimecmpのみ保存する
+impl Snapshot for Clint {
+    fn save(&self, w: &mut SnapshotWriter) {
+        for msip in &self.msip {
+            w.write_u32(*msip);
+        }
+
+        for mtimecmp in &self.mtimecmp {
+            w.write_u64(*mtimecmp);
+        }
+    }
+
+    fn restore(&mut self, r: &mut SnapshotReader) -> snapshot::Result<()> {
+        for msip in &mut self.msip {
+            *msip = r.read_u32()? & 0x1;
+        }
+
+        for mtimecmp in &mut self.mtimecmp {
+            *mtimecmp = r.read_u64()?;
+        }
+
+        self.mtime_write = None;
+        self.setssip.fill(false);
+        self.is_dirty = false;
+
+        Ok(())
+    }
 }
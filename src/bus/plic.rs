@@ -1,234 +1,337 @@
-use crate::{IRQ, Priv, Result, csr::Csr};
+use crate::{
+    csr::Csr,
+    device::{BusError, BusResult},
+    snapshot::{self, Snapshot, SnapshotReader, SnapshotWriter},
+};
 
-const PLIC_MAX_NUM: u32 = 1024;
-const PLIC_CONTEXT_MAX_NUM: u32 = 15872;
+// 割り込み源の数
+// 0は割り込みがないことを表すので、使用できるのは1から1023まで
+pub const PLIC_NUM: u32 = 1024;
+const PLIC_WORD_NUM: usize = (PLIC_NUM / 32) as usize;
 
-const PLIC_NUM: u32 = 32;
-const PLIC_CONTEXT_NUM: u32 = 2;
+// 優先度と閾値は3bitで、優先度0の割り込み源は割り込みを起こさない
+const PLIC_PRIORITY_MASK: u32 = 0x7;
 
 const PLIC_PRIORITY_BASE: u32 = 0;
-const PLIC_PRIORITY_END: u32 = PLIC_PRIORITY_BASE + PLIC_MAX_NUM * 4;
+const PLIC_PRIORITY_END: u32 = PLIC_PRIORITY_BASE + PLIC_NUM * 4;
+
+const PLIC_PENDING_BASE: u32 = 0x1000;
+const PLIC_PENDING_END: u32 = PLIC_PENDING_BASE + PLIC_NUM / 8;
 
 const PLIC_ENABLE_BASE: u32 = 0x2000;
 const PLIC_ENABLE_UNIT: u32 = 0x80;
-const PLIC_ENABLE_END: u32 = PLIC_ENABLE_BASE + PLIC_CONTEXT_MAX_NUM * PLIC_ENABLE_UNIT;
 
+// コンテキストごとに閾値の後にclaim/completeが置かれる
 const PLIC_THREADSHOLD_BASE: u32 = 0x200000;
 const PLIC_THREADSHOLD_UNIT: u32 = 0x1000;
-
-const PLIC_CLAIM_END: u32 =
-    PLIC_THREADSHOLD_BASE + PLIC_CONTEXT_MAX_NUM * PLIC_THREADSHOLD_UNIT + 4;
-
-#[derive(Default, Debug)]
+const PLIC_CLAIM: u32 = 4;
+
+// hartごとにMモードとSモードの2つのコンテキストを持つ
+// hart Nのコンテキストは2NがMモード、2N+1がSモードになる
+//
+// 割り込み源ごとのゲートウェイは、claimされてからcompleteされるまで次の割り込みを保留にしない
+// レベルトリガは信号が立っている間、エッジトリガは信号が立つたびに割り込みを保留にする
+#[derive(Debug)]
 pub struct Plic {
     priories: [u32; PLIC_NUM as usize],
-    pending: [u32; (PLIC_NUM / 32) as usize],
-    enables: [[u32; (PLIC_NUM / 32) as usize]; 2],
-    threasholds: [u32; PLIC_CONTEXT_NUM as usize],
-
-    interrupting_irq: Option<IRQ>,
-    interrupting_ctx: Option<usize>,
+    pending: [u32; PLIC_WORD_NUM],
+    enables: Vec<[u32; PLIC_WORD_NUM]>,
+    threasholds: Vec<u32>,
+
+    // claimされてcompleteされていない割り込み源
+    claimed: [u32; PLIC_WORD_NUM],
+    // レベルトリガの信号の状態
+    levels: [u32; PLIC_WORD_NUM],
+    // claimされている間に来たエッジトリガの割り込み
+    edges: [u32; PLIC_WORD_NUM],
+
+    // 各hartのmip.MEIP、mip.SEIPに反映していない変更があるか
+    is_dirty: bool,
 }
 
 impl Plic {
+    pub fn new(hart_count: usize) -> Self {
+        let context_num = hart_count * 2;
+
+        Self {
+            priories: [0; PLIC_NUM as usize],
+            pending: [0; PLIC_WORD_NUM],
+            enables: vec![[0; PLIC_WORD_NUM]; context_num],
+            threasholds: vec![0; context_num],
+            claimed: [0; PLIC_WORD_NUM],
+            levels: [0; PLIC_WORD_NUM],
+            edges: [0; PLIC_WORD_NUM],
+            is_dirty: false,
+        }
+    }
+
     #[inline]
-    pub fn read(&mut self, offset: u32, size: u32, _: &mut Csr) -> Result<u32> {
+    pub fn read(&mut self, offset: u32, size: u32) -> BusResult<u32> {
         if size != 4 {
-            unimplemented!()
+            return Err(BusError::Size(size));
         }
 
-        match offset {
-            PLIC_ENABLE_BASE..PLIC_ENABLE_END => {
-                let offset = (offset - PLIC_ENABLE_BASE) as usize;
-
-                let ctx_idx = offset / PLIC_ENABLE_UNIT as usize;
-                let idx = (offset % PLIC_ENABLE_UNIT as usize) / 4;
-
-                if ctx_idx > 1 {
-                    unreachable!();
-                }
+        if !offset.is_multiple_of(4) {
+            return Err(BusError::Register(offset));
+        }
 
-                Ok(self.enables[ctx_idx][idx])
+        match offset {
+            PLIC_PRIORITY_BASE..PLIC_PRIORITY_END => {
+                Ok(self.priories[((offset - PLIC_PRIORITY_BASE) / 4) as usize])
             }
-            PLIC_THREADSHOLD_BASE..PLIC_CLAIM_END => {
-                let idx = ((offset - PLIC_THREADSHOLD_BASE) / PLIC_THREADSHOLD_UNIT) as usize;
-
-                if idx > 1 {
-                    unimplemented!();
-                }
-
-                let filter = offset & 0x4;
-
-                if filter == 4 {
-                    // Claim
-
-                    if self.interrupting_irq.is_none() {
-                        return Ok(0);
+            PLIC_PENDING_BASE..PLIC_PENDING_END => {
+                Ok(self.pending[((offset - PLIC_PENDING_BASE) / 4) as usize])
+            }
+            _ => {
+                if let Some((context, idx)) = self.enable_of(offset) {
+                    Ok(self.enables[context][idx])
+                } else if let Some((context, reg)) = self.context_of(offset) {
+                    match reg {
+                        PLIC_CLAIM => Ok(self.claim(context)),
+                        _ => Ok(self.threasholds[context]),
                     }
-
-                    let irq = self.lower_interrupt();
-
-                    Ok(irq as u32)
                 } else {
-                    // Threashold
-                    Ok(self.threasholds[idx])
+                    Err(BusError::Register(offset))
                 }
             }
-            _ => unreachable!(),
         }
     }
 
     #[inline]
-    pub fn write(&mut self, offset: u32, size: u32, value: u32, csr: &mut Csr) -> Result<()> {
+    pub fn write(&mut self, offset: u32, size: u32, value: u32) -> BusResult<()> {
         if size != 4 {
-            unimplemented!();
+            return Err(BusError::Size(size));
+        }
+
+        if !offset.is_multiple_of(4) {
+            return Err(BusError::Register(offset));
         }
 
         match offset {
             PLIC_PRIORITY_BASE..PLIC_PRIORITY_END => {
-                let idx = (offset - PLIC_PRIORITY_BASE) as usize / 4;
+                let irq = ((offset - PLIC_PRIORITY_BASE) / 4) as usize;
 
-                self.priories[idx] = value;
+                // 割り込み源0の優先度は0固定
+                if irq != 0 {
+                    self.priories[irq] = value & PLIC_PRIORITY_MASK;
+                }
             }
-            PLIC_ENABLE_BASE..PLIC_ENABLE_END => {
-                let offset = (offset - PLIC_ENABLE_BASE) as usize;
-
-                let ctx_idx = offset / PLIC_ENABLE_UNIT as usize;
-                let idx = (offset % PLIC_ENABLE_UNIT as usize) / 4;
-
-                if ctx_idx > 1 {
-                    unimplemented!();
+            // 保留中のビットは読み込み専用
+            PLIC_PENDING_BASE..PLIC_PENDING_END => return Ok(()),
+            _ => {
+                if let Some((context, idx)) = self.enable_of(offset) {
+                    // 割り込み源0のビットは0固定
+                    self.enables[context][idx] = match idx {
+                        0 => value & !0x1,
+                        _ => value,
+                    };
+                } else if let Some((context, reg)) = self.context_of(offset) {
+                    match reg {
+                        PLIC_CLAIM => self.complete(context, value),
+                        _ => self.threasholds[context] = value & PLIC_PRIORITY_MASK,
+                    }
+                } else {
+                    return Err(BusError::Register(offset));
                 }
-
-                self.enables[ctx_idx][idx] = value;
             }
-            PLIC_THREADSHOLD_BASE..PLIC_CLAIM_END => {
-                let idx = ((offset - PLIC_THREADSHOLD_BASE) / PLIC_THREADSHOLD_UNIT) as usize;
+        }
 
-                if idx > 1 {
-                    unimplemented!();
-                }
+        self.is_dirty = true;
 
-                let filter = offset & 0x4;
+        Ok(())
+    }
 
-                if filter == 4 {
-                    // Completion
-                    let irq = self.interrupting_irq.unwrap();
+    // offsetがどのコンテキストの何番目の有効ビットのレジスタかを返す関数
+    #[inline]
+    fn enable_of(&self, offset: u32) -> Option<(usize, usize)> {
+        let offset = offset.checked_sub(PLIC_ENABLE_BASE)?;
+        let context = (offset / PLIC_ENABLE_UNIT) as usize;
+        let idx = ((offset % PLIC_ENABLE_UNIT) / 4) as usize;
 
-                    if irq as u32 == value {
-                        let i_ctx = self.interrupting_ctx.unwrap();
+        (context < self.enables.len() && idx < PLIC_WORD_NUM).then_some((context, idx))
+    }
 
-                        self.interrupting_ctx = None;
-                        self.interrupting_irq = None;
+    // offsetがどのコンテキストの閾値かclaim/completeのレジスタかを返す関数
+    #[inline]
+    fn context_of(&self, offset: u32) -> Option<(usize, u32)> {
+        let offset = offset.checked_sub(PLIC_THREADSHOLD_BASE)?;
+        let context = (offset / PLIC_THREADSHOLD_UNIT) as usize;
+        let reg = offset % PLIC_THREADSHOLD_UNIT;
 
-                        if i_ctx == 0 {
-                            csr.set_mip_meip(0);
-                        } else if i_ctx == 1 {
-                            csr.set_mip_seip(0);
-                        } else {
-                            unreachable!();
-                        }
-                    }
-                } else {
-                    // Threashold
-                    self.threasholds[idx] = value;
-                }
-            }
-            _ => unreachable!(),
-        }
+        (context < self.threasholds.len() && reg <= PLIC_CLAIM).then_some((context, reg))
+    }
 
-        Ok(())
+    // claim/completeのレジスタのオフセットか
+    #[inline]
+    pub fn is_claim_register(offset: u32) -> bool {
+        offset >= PLIC_THREADSHOLD_BASE && offset % PLIC_THREADSHOLD_UNIT == PLIC_CLAIM
     }
 }
 
 impl Plic {
+    // レベルトリガの割り込み源の信号を変える関数
+    // claimされていない間は保留中のビットが信号に従う
     #[inline]
-    pub fn set_pending(&mut self, irq: IRQ) {
-        let irq = irq as usize;
-        let idx = irq / 32;
-        let bit = 1 << (irq % 32);
+    pub fn set_level(&mut self, irq: u32, level: bool) {
+        let Some((idx, bit)) = source_bit(irq) else {
+            return;
+        };
+
+        if level == (self.levels[idx] & bit != 0) {
+            return;
+        }
+
+        self.levels[idx] ^= bit;
 
-        self.pending[idx] |= bit;
+        if self.claimed[idx] & bit == 0 {
+            self.pending[idx] = (self.pending[idx] & !bit) | (self.levels[idx] & bit);
+            self.is_dirty = true;
+        }
     }
 
+    // エッジトリガの割り込み源に信号が立ったことを伝える関数
+    // claimされている場合はcompleteされるまで保留にしない
     #[inline]
-    fn unset_pending(&mut self, irq: IRQ) {
-        let irq = irq as usize;
-        let idx = irq / 32;
-        let bit = 1 << (irq % 32);
+    pub fn trigger_edge(&mut self, irq: u32) {
+        let Some((idx, bit)) = source_bit(irq) else {
+            return;
+        };
+
+        if self.claimed[idx] & bit != 0 {
+            self.edges[idx] |= bit;
+        } else {
+            self.pending[idx] |= bit;
+            self.is_dirty = true;
+        }
+    }
+
+    // 保留中の割り込みのうち優先度が最も高いものをclaimする関数
+    // 割り込みがない場合は0を返す
+    #[inline]
+    fn claim(&mut self, context: usize) -> u32 {
+        let Some((irq, _)) = self.find_interrupt_active(context) else {
+            return 0;
+        };
+
+        let (idx, bit) = source_bit(irq).unwrap();
 
         self.pending[idx] &= !bit;
+        self.claimed[idx] |= bit;
+        self.is_dirty = true;
+
+        irq
     }
 
-    // 割り込みが起こっているものを調べる関数
-    // 何回も呼ぶとめっちゃ重くなるので割り込みが起こっているとわかっている場面で呼ぶべき
+    // claimした割り込みの処理が終わったことを伝える関数
+    // コンテキストで有効になっていない割り込み源の場合は無視する
     #[inline]
-    pub fn find_interrupt_active(&self) -> (u32, IRQ, usize) {
-        let mut max_priority = 0;
-        let mut target_irq = IRQ::None;
-        let mut target_ctx = 0;
+    fn complete(&mut self, context: usize, irq: u32) {
+        let Some((idx, bit)) = source_bit(irq) else {
+            return;
+        };
 
-        for irq in 0..PLIC_NUM {
-            let irq = irq as usize;
+        if self.enables[context][idx] & bit == 0 || self.claimed[idx] & bit == 0 {
+            return;
+        }
 
-            let idx = irq / 32;
-            let bit = 1 << (irq % 32);
+        self.claimed[idx] &= !bit;
 
-            if self.pending[idx] & bit == 0 {
-                continue;
-            }
+        // 信号が立ったままのレベルトリガと、claim中に来たエッジトリガはまた保留にする
+        if (self.levels[idx] | self.edges[idx]) & bit != 0 {
+            self.edges[idx] &= !bit;
+            self.pending[idx] |= bit;
+        }
+    }
+
+    // コンテキストで有効な保留中の割り込みのうち、優先度が最も高いものとその優先度を返す関数
+    // 同じ優先度の場合は番号の小さいものを選ぶ
+    #[inline]
+    fn find_interrupt_active(&self, context: usize) -> Option<(u32, u32)> {
+        let mut target = None;
+        let mut max_priority = 0;
 
-            let priority = self.priories[irq];
+        for (idx, (pending, enable)) in self.pending.iter().zip(&self.enables[context]).enumerate()
+        {
+            let mut bits = pending & enable;
 
-            for ctx_idx in 0..PLIC_CONTEXT_NUM {
-                let ctx_idx = ctx_idx as usize;
+            while bits != 0 {
+                let irq = idx as u32 * 32 + bits.trailing_zeros();
+                let priority = self.priories[irq as usize];
 
-                if self.enables[ctx_idx][idx] & bit != 0 {
-                    if priority > self.threasholds[ctx_idx] {
-                        max_priority = priority;
-                        target_irq = irq.into();
-                        target_ctx = ctx_idx;
-                    }
+                if priority > max_priority {
+                    max_priority = priority;
+                    target = Some(irq);
                 }
+
+                bits &= bits - 1;
             }
         }
 
-        (max_priority, target_irq, target_ctx)
+        target.map(|irq| (irq, max_priority))
     }
 
+    // コンテキストに外部割り込みを起こすか
     #[inline]
-    pub fn raise_interrupt(&mut self) -> Option<Priv> {
-        let (_, irq, ctx) = self.find_interrupt_active();
+    fn is_interrupting(&self, context: usize) -> bool {
+        self.find_interrupt_active(context)
+            .is_some_and(|(_, priority)| priority > self.threasholds[context])
+    }
 
-        // デバイス的には割り込みが起こってもいい場面で
-        // 条件が揃わない場合はNoneを返し、条件が揃った瞬間に外部割り込みを起こす
-        if irq == IRQ::None {
-            return None;
-        }
+    #[inline]
+    pub fn take_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.is_dirty)
+    }
 
-        self.interrupting_irq = Some(irq);
-        self.interrupting_ctx = Some(ctx);
+    // hartの2つのコンテキストの状態をmip.MEIPとmip.SEIPに反映する関数
+    #[inline]
+    pub fn sync(&self, hart: usize, csr: &mut Csr) {
+        csr.set_mip_meip(self.is_interrupting(hart * 2) as u32);
+        csr.set_mip_seip(self.is_interrupting(hart * 2 + 1) as u32);
+    }
+}
 
-        if ctx == 0 {
-            Some(Priv::Machine)
-        } else if ctx == 1 {
-            Some(Priv::Supervisor)
-        } else {
-            unimplemented!();
+// 割り込み源の保留中などのビットの位置を返す関数
+#[inline]
+fn source_bit(irq: u32) -> Option<(usize, u32)> {
+    (irq != 0 && irq < PLIC_NUM).then_some(((irq / 32) as usize, 1 << (irq % 32)))
+}
+
+impl Snapshot for Plic {
+    fn save(&self, w: &mut SnapshotWriter) {
+        for value in self
+            .priories
+            .iter()
+            .chain(&self.pending)
+            .chain(self.enables.iter().flatten())
+            .chain(&self.threasholds)
+            .chain(&self.claimed)
+            .chain(&self.levels)
+            .chain(&self.edges)
+        {
+            w.write_u32(*value);
         }
     }
 
-    #[inline]
-    pub fn lower_interrupt(&mut self) -> IRQ {
-        let irq = self.interrupting_irq.unwrap();
+    fn restore(&mut self, r: &mut SnapshotReader) -> snapshot::Result<()> {
+        for value in self
+            .priories
+            .iter_mut()
+            .chain(&mut self.pending)
+            .chain(self.enables.iter_mut().flatten())
+            .chain(&mut self.threasholds)
+            .chain(&mut self.claimed)
+            .chain(&mut self.levels)
+            .chain(&mut self.edges)
+        {
+            *value = r.read_u32()?;
+        }
 
-        self.unset_pending(irq);
+        for value in self.priories.iter_mut().chain(&mut self.threasholds) {
+            *value &= PLIC_PRIORITY_MASK;
+        }
 
-        irq
-    }
+        self.is_dirty = true;
 
-    #[inline]
-    pub fn interrupting_irq(&self) -> Option<IRQ> {
-        self.interrupting_irq
+        Ok(())
     }
 }
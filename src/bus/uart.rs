@@ -2,12 +2,15 @@ use std::io::Write;
 
 use crate::device::DeviceMessage;
 use crate::{
-    Result,
     bus::DeviceTrait,
-    device::{DeviceRecieverTrait, DeviceResponse},
+    device::{BusError, DeviceRecieverTrait, DeviceResponse, DeviceResult, Trigger},
+    fdt::FdtBuilder,
     memory::Memory,
+    snapshot::{self, Snapshot, SnapshotError, SnapshotReader, SnapshotWriter},
 };
 
+const CLOCK_FREQ: u32 = 0x384000;
+
 #[allow(unused)]
 const IER_ERBFI: u8 = 1; // 受け取ったときの例外のIEのbit
 const IER_ETBEI: u8 = 0x2; // 出力したときの例外のIEのbit
@@ -44,9 +47,9 @@ where
 
 impl<R: DeviceRecieverTrait> DeviceTrait for Uart<R> {
     #[inline]
-    fn read(&mut self, offset: u32, size: u32, _: &mut Memory) -> Result<DeviceResponse<u32>> {
+    fn read(&mut self, offset: u32, size: u32, _: &mut Memory) -> DeviceResult<u32> {
         if size != 1 {
-            unimplemented!();
+            return Err(BusError::Size(size));
         }
 
         let offset = offset & 0xFF;
@@ -104,15 +107,9 @@ impl<R: DeviceRecieverTrait> DeviceTrait for Uart<R> {
     }
 
     #[inline]
-    fn write(
-        &mut self,
-        offset: u32,
-        size: u32,
-        value: u32,
-        _: &mut Memory,
-    ) -> Result<DeviceResponse<()>> {
+    fn write(&mut self, offset: u32, size: u32, value: u32, _: &mut Memory) -> DeviceResult<()> {
         if size != 1 {
-            unimplemented!()
+            return Err(BusError::Size(size));
         }
 
         let value = value as u8;
@@ -175,9 +172,21 @@ impl<R: DeviceRecieverTrait> DeviceTrait for Uart<R> {
         })
     }
 
-    #[inline]
-    fn irq(&self) -> crate::IRQ {
-        crate::IRQ::Uart
+    // IIRが割り込みを示している間は割り込みを起こし続ける
+    fn trigger(&self) -> Trigger {
+        Trigger::Level
+    }
+
+    fn fdt_name(&self) -> &'static str {
+        "serial"
+    }
+
+    fn fdt_compatible(&self) -> &'static [&'static str] {
+        &["ns16550a"]
+    }
+
+    fn fdt_properties(&self, fdt: &mut FdtBuilder) {
+        fdt.property_u32("clock-frequency", CLOCK_FREQ);
     }
 
     #[inline]
@@ -201,15 +210,54 @@ impl<R: DeviceRecieverTrait> DeviceTrait for Uart<R> {
         if self.input_buf != Vec::new() && self.is_ready_for_recieving() {
             if let Some(c) = self.input_buf.pop() {
                 self.push_char(c);
-                return true;
             }
+        }
 
-            if self.input_buf == Vec::new() {
-                return false;
-            }
+        self.is_interrupting
+    }
+}
+
+impl<R: DeviceRecieverTrait> Snapshot for Uart<R> {
+    fn save(&self, w: &mut SnapshotWriter) {
+        for reg in [
+            self.lcr, self.dlm, self.dll, self.lsr, self.ier, self.rbr, self.iir,
+        ] {
+            w.write_u8(reg);
+        }
+
+        w.write_bool(self.is_interrupting);
+        w.write_bool(self.is_taken_interrupt);
+
+        w.write_len(self.input_buf.len());
+        for c in &self.input_buf {
+            w.write_u32(*c as u32);
+        }
+    }
+
+    fn restore(&mut self, r: &mut SnapshotReader) -> snapshot::Result<()> {
+        for reg in [
+            &mut self.lcr,
+            &mut self.dlm,
+            &mut self.dll,
+            &mut self.lsr,
+            &mut self.ier,
+            &mut self.rbr,
+            &mut self.iir,
+        ] {
+            *reg = r.read_u8()?;
+        }
+
+        self.is_interrupting = r.read_bool()?;
+        self.is_taken_interrupt = r.read_bool()?;
+
+        let len = r.read_len()?;
+        self.input_buf.clear();
+        for _ in 0..len {
+            let c = char::from_u32(r.read_u32()?).ok_or(SnapshotError::InvalidValue("char"))?;
+            self.input_buf.push(c);
         }
 
-        false
+        Ok(())
     }
 }
 
@@ -6,12 +6,13 @@ use crate::{
         DeviceTrait,
         virtio_mmio::{
             VIRTIO_REG_CONFIG, VIRTIO_REG_NOTIFY, VIRTIO_REG_STATUS, VirtQueueDesc, VirtioMmio,
-            VirtioType, read_panic,
+            VirtioType,
         },
     },
-    device::{DeviceResponse, DeviceResult, DeviceSenderTrait},
+    device::{BusError, BusResult, DeviceResponse, DeviceResult, DeviceSenderTrait},
     host_device::{GpuMessage, GpuOperation, GpuRect},
     memory::Memory,
+    snapshot::{self, Snapshot, SnapshotError, SnapshotReader, SnapshotWriter},
 };
 
 #[cfg(target_arch = "wasm32")]
@@ -53,6 +54,9 @@ pub struct VirtioGpu<S: DeviceSenderTrait> {
     resources: HashMap<u32, GpuResouce>,
     scanouts: [GpuScanout; MAX_SCANOUTS as usize],
 
+    // スナップショットから復元した場合はホスト側の画面を描き直す必要がある
+    needs_redraw: bool,
+
     sender: S,
 }
 
@@ -65,11 +69,11 @@ pub struct GpuResouce {
     entries: Vec<VirtioGpuMemEntry>,
 }
 
-// まだ具体的な使い方はない
+// resource_idは復元時の再描画に使う
 #[derive(Debug, Default)]
 pub struct GpuScanout {
     _r: VirtioGpuRect,
-    _resource_id: u32,
+    resource_id: u32,
 }
 
 #[derive(Debug)]
@@ -84,7 +88,7 @@ enum VirtioGpuCtrlType {
     RespOkDisplayInfo,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 #[repr(C)]
 pub struct VirtioGpuCtrlHeader {
     ctrl_type: u32,
@@ -119,7 +123,7 @@ pub struct VirtioGpuRespDisplayInfo {
     pmodes: [VirtioGpuDisplayOne; 16],
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 #[repr(C)]
 pub struct VirtioGpuResourceCreate2D {
     header: VirtioGpuCtrlHeader,
@@ -129,7 +133,7 @@ pub struct VirtioGpuResourceCreate2D {
     height: u32,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 #[repr(C)]
 pub struct VirtioGpuResouceAttachBacking {
     header: VirtioGpuCtrlHeader,
@@ -145,7 +149,7 @@ pub struct VirtioGpuMemEntry {
     _padding: u32,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 #[repr(C)]
 pub struct VirtioGpuSetScanout {
     header: VirtioGpuCtrlHeader,
@@ -154,7 +158,7 @@ pub struct VirtioGpuSetScanout {
     resource_id: u32,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 #[repr(C)]
 pub struct VirtioGpuTransferToHost2d {
     header: VirtioGpuCtrlHeader,
@@ -164,7 +168,7 @@ pub struct VirtioGpuTransferToHost2d {
     _padding: u32,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 #[repr(C)]
 pub struct VitioGpuResourceFlush {
     header: VirtioGpuCtrlHeader,
@@ -176,24 +180,29 @@ pub struct VitioGpuResourceFlush {
 impl<S: DeviceSenderTrait> DeviceTrait for VirtioGpu<S> {
     fn read(&mut self, offset: u32, size: u32, _: &mut crate::memory::Memory) -> DeviceResult<u32> {
         if size != 4 {
-            unimplemented!();
+            return Err(BusError::Size(size));
         }
 
+        // 存在しない共有メモリの長さは-1になる
+        let shm_len = SHM_LENS
+            .get(self.virtio.shm_sel())
+            .copied()
+            .unwrap_or(u64::MAX);
+        let shm_base = SHM_BASES.get(self.virtio.shm_sel()).copied().unwrap_or(0);
+
         let value = match offset {
-            0xb0 => SHM_LENS[self.virtio.shm_sel()] as u32, // SHM Len Low
-            0xb4 => (SHM_LENS[self.virtio.shm_sel()] >> 32) as u32, // SHM Base High
-            0xb8 => SHM_BASES[self.virtio.shm_sel()] as u32, // SHM Base Low
-            0xbc => (SHM_BASES[self.virtio.shm_sel()] >> 32) as u32, // SHM Base High
+            0xb0 => shm_len as u32,          // SHM Len Low
+            0xb4 => (shm_len >> 32) as u32,  // SHM Base High
+            0xb8 => shm_base as u32,         // SHM Base Low
+            0xbc => (shm_base >> 32) as u32, // SHM Base High
             _ => {
                 if offset < VIRTIO_REG_CONFIG {
                     return self.virtio.read(offset, size);
                 } else {
-                    let offset = offset - VIRTIO_REG_CONFIG;
-
-                    match offset {
+                    match offset - VIRTIO_REG_CONFIG {
                         8 => MAX_SCANOUTS,
                         0xc => MAX_CAPSETS,
-                        _ => read_panic(offset),
+                        _ => return Err(BusError::Register(offset)),
                     }
                 }
             }
@@ -213,12 +222,12 @@ impl<S: DeviceSenderTrait> DeviceTrait for VirtioGpu<S> {
         memory: &mut crate::memory::Memory,
     ) -> DeviceResult<()> {
         if size != 4 {
-            unimplemented!();
+            return Err(BusError::Size(size));
         }
 
         match offset {
             VIRTIO_REG_NOTIFY => {
-                let is_interrupting = self.handle_notify(value, memory);
+                let is_interrupting = self.handle_notify(value, memory)?;
 
                 return Ok(DeviceResponse {
                     value: (),
@@ -243,25 +252,133 @@ impl<S: DeviceSenderTrait> DeviceTrait for VirtioGpu<S> {
         })
     }
 
-    fn irq(&self) -> crate::IRQ {
-        crate::IRQ::VirtioGpu
+    fn fdt_name(&self) -> &'static str {
+        "virtio_mmio"
+    }
+
+    fn fdt_compatible(&self) -> &'static [&'static str] {
+        &["virtio,mmio"]
+    }
+
+    fn tick(&mut self, memory: &mut Memory) -> bool {
+        if self.needs_redraw {
+            self.needs_redraw = false;
+
+            if let Err(e) = self.redraw(memory) {
+                eprintln!("[WARNING]: virtio-gpu failed to redraw: {}", e);
+            }
+        }
+
+        false
+    }
+}
+
+impl<S: DeviceSenderTrait> Snapshot for VirtioGpu<S> {
+    fn save(&self, w: &mut SnapshotWriter) {
+        self.virtio.save(w);
+
+        for idx in self.last_idxes {
+            w.write_u16(idx);
+        }
+
+        // HashMapの順番は毎回変わるのでidでソートしてから書き込む
+        let mut ids = self.resources.keys().copied().collect::<Vec<_>>();
+        ids.sort_unstable();
+
+        w.write_len(ids.len());
+        for id in ids {
+            let resource = &self.resources[&id];
+
+            w.write_u32(id);
+            w.write_u32(resource.format);
+            w.write_u32(resource._width);
+            w.write_u32(resource._height);
+
+            w.write_len(resource.entries.len());
+            for entry in &resource.entries {
+                w.write_u64(entry.addr);
+                w.write_u32(entry.length);
+            }
+        }
+
+        for scanout in &self.scanouts {
+            let r = scanout._r;
+
+            for value in [r.x, r.y, r.width, r.height, scanout.resource_id] {
+                w.write_u32(value);
+            }
+        }
+    }
+
+    fn restore(&mut self, r: &mut SnapshotReader) -> snapshot::Result<()> {
+        self.virtio.restore(r)?;
+
+        for idx in self.last_idxes.iter_mut() {
+            *idx = r.read_u16()?;
+        }
+
+        self.resources.clear();
+
+        let len = r.read_len()?;
+        for _ in 0..len {
+            let id = r.read_u32()?;
+            let format = r.read_u32()?;
+
+            if format != 2 {
+                return Err(SnapshotError::InvalidValue("gpu resource format"));
+            }
+
+            let mut resource = GpuResouce {
+                format,
+                _width: r.read_u32()?,
+                _height: r.read_u32()?,
+                entries: Vec::new(),
+            };
+
+            let entry_len = r.read_len()?;
+            for _ in 0..entry_len {
+                resource.entries.push(VirtioGpuMemEntry {
+                    addr: r.read_u64()?,
+                    length: r.read_u32()?,
+                    _padding: 0,
+                });
+            }
+
+            self.resources.insert(id, resource);
+        }
+
+        for scanout in self.scanouts.iter_mut() {
+            scanout._r = VirtioGpuRect {
+                x: r.read_u32()?,
+                y: r.read_u32()?,
+                width: r.read_u32()?,
+                height: r.read_u32()?,
+            };
+            scanout.resource_id = r.read_u32()?;
+        }
+
+        self.needs_redraw = true;
+
+        Ok(())
     }
 }
 
-fn write_ok_nodata_response(dst_desc: &VirtQueueDesc, memory: &mut Memory) -> u32 {
+fn write_ok_nodata_response(dst_desc: &VirtQueueDesc, memory: &mut Memory) -> BusResult<u32> {
     let response = VirtioGpuCtrlHeader::new(VirtioGpuCtrlType::RespOkNodata);
 
     let response_data: &[u8; VIRTIO_GPU_HEADER_SIZE] = unsafe { transmute(&response as *const _) };
 
     if VIRTIO_GPU_HEADER_SIZE > dst_desc.len as usize {
-        unimplemented!()
+        return Err(BusError::Unsupported(
+            "response buffer smaller than the response",
+        ));
     }
 
-    let ptr = memory.raw_mut_ptr(dst_desc.addr as usize, VIRTIO_GPU_HEADER_SIZE);
+    let ptr = memory.raw_mut_ptr(dst_desc.addr as usize, VIRTIO_GPU_HEADER_SIZE)?;
 
     ptr.copy_from_slice(response_data);
 
-    VIRTIO_GPU_HEADER_SIZE as u32
+    Ok(VIRTIO_GPU_HEADER_SIZE as u32)
 }
 
 // XRGBに変換する関数
@@ -316,6 +433,7 @@ impl<S: DeviceSenderTrait> VirtioGpu<S> {
             resources: HashMap::new(),
             sender,
             scanouts: [GpuScanout::default()],
+            needs_redraw: false,
         }
     }
 
@@ -325,20 +443,90 @@ impl<S: DeviceSenderTrait> VirtioGpu<S> {
         *self = Self::new(sender);
     }
 
-    fn handle_notify(&mut self, queue_idx: u32, memory: &mut Memory) -> bool {
+    // リソースの内容をホストに送るメッセージを作る関数
+    fn transfer_to_host(
+        &self,
+        resource_id: u32,
+        r: VirtioGpuRect,
+        memory: &Memory,
+    ) -> BusResult<GpuMessage> {
+        let buffer_size = SUPPORTED_RECT.size();
+        let mut buffer = vec![0; buffer_size];
+
+        let resource = self
+            .resources
+            .get(&resource_id)
+            .ok_or(BusError::Unsupported("unknown resource"))?;
+
+        let mut copied_size: usize = 0;
+
+        for entry in &resource.entries {
+            let entry_len = entry.length as usize;
+            let entry_ptr = memory.raw_ptr(entry.addr as usize, entry_len)?;
+
+            let actual_len = if copied_size + entry_len > buffer_size {
+                buffer_size - copied_size
+            } else {
+                entry_len
+            };
+
+            buffer[copied_size..copied_size + actual_len].copy_from_slice(&entry_ptr[..actual_len]);
+            copied_size += actual_len;
+        }
+
+        let buffer = format_array(resource.format, &buffer);
+
+        Ok(GpuMessage {
+            operation: GpuOperation::Copy,
+            resource_id,
+            rect: GpuRect::from(r),
+            buffer,
+        })
+    }
+
+    // スキャンアウトに設定されているリソースを転送してフラッシュする関数
+    fn redraw(&mut self, memory: &Memory) -> BusResult<()> {
+        for i in 0..self.scanouts.len() {
+            let resource_id = self.scanouts[i].resource_id;
+
+            if !self.resources.contains_key(&resource_id) {
+                continue;
+            }
+
+            let message = self.transfer_to_host(resource_id, SUPPORTED_RECT, memory)?;
+            self.sender
+                .send_to_host(DeviceMessage::Gpu(message))
+                .unwrap();
+
+            let message = GpuMessage {
+                operation: GpuOperation::Flush,
+                resource_id,
+                rect: GpuRect::from(SUPPORTED_RECT),
+                buffer: Vec::new(),
+            };
+            self.sender
+                .send_to_host(DeviceMessage::Gpu(message))
+                .unwrap();
+        }
+
+        Ok(())
+    }
+
+    // notifyを処理する関数
+    // 対応していない要求やゲストのメモリの範囲外への参照はエラーを返す
+    fn handle_notify(&mut self, queue_idx: u32, memory: &mut Memory) -> BusResult<bool> {
         if queue_idx != VIRTIO_GPU_CONTROL_IDX {
-            unimplemented!()
+            return Err(BusError::Unsupported("cursor queue"));
         }
 
         let last_idx = self.last_idxes[queue_idx as usize];
 
-        let driver = self.virtio.driver::<MAX_QUEUE_SIZE>(queue_idx, memory);
+        let driver = self.virtio.driver::<MAX_QUEUE_SIZE>(queue_idx, memory)?;
 
         if driver.idx == last_idx {
-            return false;
+            return Ok(false);
         }
 
-        let device = self.virtio.device::<MAX_QUEUE_SIZE>(queue_idx, memory);
         let now_driver_idx = driver.idx;
 
         let diff = driver.idx.wrapping_sub(last_idx);
@@ -349,26 +537,25 @@ impl<S: DeviceSenderTrait> VirtioGpu<S> {
             let ring_idx = last_idx.wrapping_add(i) as usize % MAX_QUEUE_SIZE;
             let command_idx = driver.ring[ring_idx];
 
-            let command_desc = self.virtio.desc(command_idx, desc_base, memory);
+            let command_desc = self.virtio.desc(command_idx, desc_base, memory)?;
 
             if !command_desc.is_next() {
-                unimplemented!();
+                return Err(BusError::Unsupported("command without a response buffer"));
             }
 
-            let second_desc = self.virtio.desc(command_desc.next, desc_base, memory);
-
-            let command_data_ptr =
-                memory.raw_ptr(command_desc.addr as usize, command_desc.len as usize);
+            let second_desc = self.virtio.desc(command_desc.next, desc_base, memory)?;
 
-            let command_data: &VirtioGpuCtrlHeader =
-                unsafe { transmute(command_data_ptr.as_ptr()) };
+            let command_data = memory.view_as::<VirtioGpuCtrlHeader>(
+                command_desc.addr as usize,
+                command_desc.len as usize,
+            )?;
 
-            let ctrl_type = VirtioGpuCtrlType::from(command_data.ctrl_type);
+            let ctrl_type = VirtioGpuCtrlType::try_from(command_data.ctrl_type)?;
 
             let len = match ctrl_type {
                 VirtioGpuCtrlType::CmdGetDisplayInfo => {
                     if second_desc.is_next() || !second_desc.is_write_only() {
-                        unimplemented!();
+                        return Err(BusError::Unsupported("descriptor chain layout"));
                     }
 
                     let response = VirtioGpuRespDisplayInfo::as_response();
@@ -376,11 +563,15 @@ impl<S: DeviceSenderTrait> VirtioGpu<S> {
                         unsafe { transmute(&response as *const _) };
 
                     if VIRTIO_GPU_RESP_DISPLAY_INFO_SIZE > second_desc.len as usize {
-                        unimplemented!()
+                        return Err(BusError::Unsupported(
+                            "response buffer smaller than the response",
+                        ));
                     }
 
-                    let second_ptr = memory
-                        .raw_mut_ptr(second_desc.addr as usize, VIRTIO_GPU_RESP_DISPLAY_INFO_SIZE);
+                    let second_ptr = memory.raw_mut_ptr(
+                        second_desc.addr as usize,
+                        VIRTIO_GPU_RESP_DISPLAY_INFO_SIZE,
+                    )?;
 
                     second_ptr.copy_from_slice(response_data);
 
@@ -388,68 +579,68 @@ impl<S: DeviceSenderTrait> VirtioGpu<S> {
                 }
                 VirtioGpuCtrlType::CmdResourceCreate2D => {
                     if second_desc.is_next() || !second_desc.is_write_only() {
-                        unimplemented!();
+                        return Err(BusError::Unsupported("descriptor chain layout"));
                     }
 
                     let resource_create_2d = memory.view_as::<VirtioGpuResourceCreate2D>(
                         command_desc.addr as usize,
                         command_desc.len as usize,
-                    );
+                    )?;
 
                     if resource_create_2d.format != 2 {
                         // BGRX以外とりあえずサポートしない
-                        unimplemented!()
+                        return Err(BusError::Unsupported("resource format"));
                     }
 
                     self.resources.insert(
                         resource_create_2d.resource_id,
-                        GpuResouce::from(resource_create_2d),
+                        GpuResouce::from(&resource_create_2d),
                     );
 
-                    write_ok_nodata_response(second_desc, memory)
+                    write_ok_nodata_response(&second_desc, memory)?
                 }
                 VirtioGpuCtrlType::CmdResourceAttachBacking => {
                     if !second_desc.is_next() {
-                        unimplemented!();
+                        return Err(BusError::Unsupported("backing without memory entries"));
                     }
 
-                    let third_desc = self.virtio.desc(second_desc.next, desc_base, memory);
+                    let third_desc = self.virtio.desc(second_desc.next, desc_base, memory)?;
 
                     if third_desc.is_next() || !third_desc.is_write_only() {
                         // 1以上の場合はサポートしない
-                        unimplemented!()
+                        return Err(BusError::Unsupported("descriptor chain layout"));
                     }
 
                     let resource_attach_backing = memory.view_as::<VirtioGpuResouceAttachBacking>(
                         command_desc.addr as usize,
                         command_desc.len as usize,
-                    );
+                    )?;
 
                     let resource = self
                         .resources
                         .get_mut(&resource_attach_backing.resouce_id)
-                        .unwrap();
+                        .ok_or(BusError::Unsupported("unknown resource"))?;
 
                     for i in 0..resource_attach_backing.nr_entries {
                         let entry = memory.view_as::<VirtioGpuMemEntry>(
                             second_desc.addr as usize + i as usize * size_of::<VirtioGpuMemEntry>(),
                             second_desc.len as usize,
-                        );
+                        )?;
 
                         resource.entries.push(entry.clone());
                     }
 
-                    write_ok_nodata_response(third_desc, memory)
+                    write_ok_nodata_response(&third_desc, memory)?
                 }
                 VirtioGpuCtrlType::CmdSetScanout => {
                     if second_desc.is_next() || !second_desc.is_write_only() {
-                        unimplemented!();
+                        return Err(BusError::Unsupported("descriptor chain layout"));
                     }
 
                     let set_scanout = memory.view_as::<VirtioGpuSetScanout>(
                         command_desc.addr as usize,
                         command_desc.len as usize,
-                    );
+                    )?;
 
                     let resource_id = set_scanout.resource_id;
 
@@ -461,82 +652,57 @@ impl<S: DeviceSenderTrait> VirtioGpu<S> {
                             .send_to_host(DeviceMessage::Gpu(message))
                             .unwrap();
                     } else {
-                        if set_scanout.scanout_id > MAX_SCANOUTS {
-                            unimplemented!();
+                        if set_scanout.scanout_id >= MAX_SCANOUTS {
+                            return Err(BusError::Unsupported("scanout"));
                         }
 
                         self.scanouts[set_scanout.scanout_id as usize] = GpuScanout {
                             _r: set_scanout.r,
-                            _resource_id: set_scanout.resource_id,
+                            resource_id: set_scanout.resource_id,
                         };
                     }
 
-                    write_ok_nodata_response(second_desc, memory)
+                    write_ok_nodata_response(&second_desc, memory)?
                 }
                 VirtioGpuCtrlType::CmdTransferToHost2D => {
                     // [todo] 現在のところ２~3回コピーしているので１回でできるようにする。
                     if second_desc.is_next() || !second_desc.is_write_only() {
-                        unimplemented!();
+                        return Err(BusError::Unsupported("descriptor chain layout"));
                     }
 
                     let transfer_to_host_2d = memory.view_as::<VirtioGpuTransferToHost2d>(
                         command_desc.addr as usize,
                         command_desc.len as usize,
-                    );
+                    )?;
 
                     if transfer_to_host_2d.r != SUPPORTED_RECT {
-                        unimplemented!();
-                    }
-
-                    let buffer_size = SUPPORTED_RECT.size();
-                    let mut buffer = vec![0; buffer_size];
-
-                    let resource_id = transfer_to_host_2d.resource_id;
-                    let resource = self.resources.get(&resource_id).unwrap();
-
-                    let mut copied_size: usize = 0;
-
-                    for entry in &resource.entries {
-                        let entry_len = entry.length as usize;
-                        let entry_ptr = memory.raw_ptr(entry.addr as usize, entry_len);
-
-                        let actual_len = if copied_size + entry_len > buffer_size {
-                            buffer_size - copied_size
-                        } else {
-                            entry_len
-                        };
-
-                        buffer[copied_size..copied_size + actual_len]
-                            .copy_from_slice(&entry_ptr[..actual_len]);
-                        copied_size += actual_len;
+                        return Err(BusError::Unsupported("partial transfer"));
                     }
 
-                    let buffer = format_array(resource.format, &buffer);
-                    let message = GpuMessage {
-                        operation: GpuOperation::Copy,
-                        resource_id,
-                        rect: GpuRect::from(transfer_to_host_2d.r),
-                        buffer,
-                    };
+                    let message = self.transfer_to_host(
+                        transfer_to_host_2d.resource_id,
+                        transfer_to_host_2d.r,
+                        memory,
+                    )?;
 
                     self.sender
                         .send_to_host(DeviceMessage::Gpu(message))
                         .unwrap();
 
-                    write_ok_nodata_response(second_desc, memory)
+                    write_ok_nodata_response(&second_desc, memory)?
                 }
                 VirtioGpuCtrlType::CmdResourceFlush => {
                     if second_desc.is_next() || !second_desc.is_write_only() {
-                        unimplemented!();
+                        return Err(BusError::Unsupported("descriptor chain layout"));
                     }
 
                     let resource_flush = memory.view_as::<VitioGpuResourceFlush>(
                         command_desc.addr as usize,
                         command_desc.len as usize,
-                    );
+                    )?;
 
                     if resource_flush.r != SUPPORTED_RECT {
-                        unimplemented!();
+                        return Err(BusError::Unsupported("partial flush"));
                     }
 
                     let message = GpuMessage {
@@ -550,11 +716,13 @@ impl<S: DeviceSenderTrait> VirtioGpu<S> {
                         .send_to_host(DeviceMessage::Gpu(message))
                         .unwrap();
 
-                    write_ok_nodata_response(second_desc, memory)
+                    write_ok_nodata_response(&second_desc, memory)?
                 }
-                _ => unimplemented!(),
+                _ => return Err(BusError::Unsupported("gpu command")),
             };
 
+            let device = self.virtio.device::<MAX_QUEUE_SIZE>(queue_idx, memory)?;
+
             device.elems[ring_idx].len = len;
             device.elems[ring_idx].id = command_idx as u32;
             device.idx = device.idx.wrapping_add(1);
@@ -562,7 +730,7 @@ impl<S: DeviceSenderTrait> VirtioGpu<S> {
 
         self.last_idxes[queue_idx as usize] = now_driver_idx;
 
-        true
+        Ok(true)
     }
 }
 
@@ -577,21 +745,20 @@ impl From<&VirtioGpuResourceCreate2D> for GpuResouce {
     }
 }
 
-impl From<u32> for VirtioGpuCtrlType {
-    fn from(value: u32) -> Self {
+impl TryFrom<u32> for VirtioGpuCtrlType {
+    type Error = BusError;
+
+    fn try_from(value: u32) -> BusResult<Self> {
         match value {
-            0x100 => Self::CmdGetDisplayInfo,
-            0x101 => Self::CmdResourceCreate2D,
-            0x103 => Self::CmdSetScanout,
-            0x104 => Self::CmdResourceFlush,
-            0x105 => Self::CmdTransferToHost2D,
-            0x106 => Self::CmdResourceAttachBacking,
-            0x1100 => Self::RespOkNodata,
-            0x1101 => Self::CmdGetDisplayInfo,
-            _ => panic!(
-                "[ERROR] VirtioGpuCtrlType 0x{:x} is not implemented. ",
-                value
-            ),
+            0x100 => Ok(Self::CmdGetDisplayInfo),
+            0x101 => Ok(Self::CmdResourceCreate2D),
+            0x103 => Ok(Self::CmdSetScanout),
+            0x104 => Ok(Self::CmdResourceFlush),
+            0x105 => Ok(Self::CmdTransferToHost2D),
+            0x106 => Ok(Self::CmdResourceAttachBacking),
+            0x1100 => Ok(Self::RespOkNodata),
+            0x1101 => Ok(Self::CmdGetDisplayInfo),
+            _ => Err(BusError::Unsupported("gpu command")),
         }
     }
 }
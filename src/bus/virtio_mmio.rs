@@ -1,8 +1,7 @@
-use std::mem::transmute;
-
 use crate::{
-    device::{DeviceResponse, DeviceResult},
+    device::{BusError, BusResult, DeviceResponse, DeviceResult},
     memory::Memory,
+    snapshot::{self, Snapshot, SnapshotError, SnapshotReader, SnapshotWriter},
 };
 
 pub const VIRTIO_REG_QUEUE_READY: u32 = 0x44;
@@ -43,7 +42,7 @@ pub struct VirtioMmio {
     shm_sel: u32,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 #[repr(C)]
 pub struct VirtQueueDesc {
     pub addr: u64,
@@ -52,7 +51,7 @@ pub struct VirtQueueDesc {
     pub next: u16,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 #[repr(C)]
 pub struct VirtQueueDriver<const L: usize> {
     pub flags: u16,
@@ -110,7 +109,7 @@ impl VirtioMmio {
     #[inline]
     pub fn read(&mut self, offset: u32, size: u32) -> DeviceResult<u32> {
         if size != 4 {
-            unimplemented!();
+            return Err(BusError::Size(size));
         }
 
         let value = match offset {
@@ -130,7 +129,7 @@ impl VirtioMmio {
             0x60 => 1, // Interrupt Status
             VIRTIO_REG_STATUS => self.status,
             0xfc => 0, // Config Generation 設定を変更する場合は要変更
-            _ => read_panic(offset),
+            _ => return Err(BusError::Register(offset)),
         };
 
         Ok(DeviceResponse {
@@ -142,11 +141,16 @@ impl VirtioMmio {
     #[inline]
     pub fn write(&mut self, offset: u32, size: u32, value: u32) -> DeviceResult<()> {
         if size != 4 {
-            unimplemented!()
+            return Err(BusError::Size(size));
         }
 
+        let invalid_value = Err(BusError::Value { offset, value });
+
         match offset {
-            0x14 => self.features_sel = value as usize, // Device Features Sel
+            // 選択できるのは実装しているものだけ
+            0x14 if (value as usize) < self.features_supported.len() => {
+                self.features_sel = value as usize
+            } // Device Features Sel
             0x20 => {
                 if value != self.features_supported[self.driver_features_sel] {
                     self.set_failed();
@@ -154,8 +158,10 @@ impl VirtioMmio {
                     self.driver_features[self.driver_features_sel] = value;
                 }
             } // Driver Features
-            0x24 => self.driver_features_sel = value as usize, // Driver Features Sel
-            0x30 => self.queue_sel = value as usize,
+            0x24 if (value as usize) < self.driver_features.len() => {
+                self.driver_features_sel = value as usize
+            } // Driver Features Sel
+            0x30 if (value as usize) < self.readies.len() => self.queue_sel = value as usize,
             0x38 => {
                 if value != self.queue_size_max {
                     eprintln!(
@@ -170,41 +176,30 @@ impl VirtioMmio {
                 self.readies[self.queue_sel] = match value {
                     0 => false,
                     1 => true,
-                    _ => write_panic(offset, value),
+                    _ => return invalid_value,
                 };
             }
             0x64 => {
                 if value != 1 {
-                    unimplemented!()
+                    return invalid_value;
                 }
             } // Interrupt ACK
             VIRTIO_REG_STATUS => match value {
                 1 | 3 | 0xb | 0xf => self.status = value, // ACK, DRIVER, Features OK
-                _ => write_panic(offset, value),
+                _ => return invalid_value,
             },
             0x80 => self.desc_addrs[self.queue_sel] = value as u64, // Queue Desc Low
-            0x84 => {
+            // 32bitのアドレスのみ対応する
+            0x84 | 0x94 | 0xa4 => {
                 if value != 0 {
-                    // 32bit only
-                    unimplemented!();
+                    return invalid_value;
                 }
-            } // Queue Desc High
+            } // Queue Desc High, Queue Driver High, Queue Device High
             0x90 => self.driver_addrs[self.queue_sel] = value as u64, // Queue Driver Low
-            0x94 => {
-                if value != 0 {
-                    // 32bit only
-                    unimplemented!();
-                }
-            } // Queue Driver High
             0xa0 => self.device_addrs[self.queue_sel] = value as u64, // Queue Device Low
-            0xa4 => {
-                if value != 0 {
-                    // 32bit only
-                    unimplemented!();
-                }
-            } // Queue Device High
             0xac => self.shm_sel = value,
-            _ => write_panic(offset, value),
+            0x14 | 0x24 | 0x30 => return invalid_value,
+            _ => return Err(BusError::Register(offset)),
         }
 
         Ok(DeviceResponse {
@@ -233,38 +228,40 @@ impl VirtioMmio {
         self.shm_sel as usize
     }
 
+    // キューの位置はゲストが書き込んだものなので、メモリの範囲外の場合はエラーを返す
+    // デバイスが書き込むのはdeviceのみで、driverとdescは読み出したコピーを返す
     pub fn driver<const L: usize>(
         &self,
         queue_idx: u32,
-        memory: &mut Memory,
-    ) -> &VirtQueueDriver<L> {
-        let driver_ptr = memory.raw_ptr(
+        memory: &Memory,
+    ) -> BusResult<VirtQueueDriver<L>> {
+        memory.view_as(
             self.driver_addr(queue_idx),
             VIRTIO_QUEUE_DRIVER_BASE_SIZE + VIRTIO_QUEUE_DRIVER_RING_SIZE * L,
-        );
-
-        unsafe { transmute(driver_ptr.as_ptr()) }
+        )
     }
 
-    pub fn device<const L: usize>(
+    pub fn device<'a, const L: usize>(
         &self,
         queue_idx: u32,
-        memory: &mut Memory,
-    ) -> &mut VirtQueueDevice<L> {
-        let device_ptr = memory.raw_mut_ptr(
+        memory: &'a mut Memory,
+    ) -> BusResult<&'a mut VirtQueueDevice<L>> {
+        memory.mut_view_as(
             self.device_addr(queue_idx),
             VIRTIO_QUEUE_DEVICE_BASE_SIZE + VIRTIO_QUEUE_DEVICE_ELEM_SIZE * L,
-        );
-
-        unsafe { transmute(device_ptr.as_ptr()) }
+        )
     }
 
-    pub fn desc(&self, desc_idx: u16, desc_base: usize, memory: &mut Memory) -> &VirtQueueDesc {
-        let desc_ptr = memory.raw_ptr(
+    pub fn desc(
+        &self,
+        desc_idx: u16,
+        desc_base: usize,
+        memory: &Memory,
+    ) -> BusResult<VirtQueueDesc> {
+        memory.view_as(
             desc_base + calc_desc_offset(desc_idx as usize),
             VIRTIO_QUEUE_DESC_SIZE,
-        );
-        unsafe { transmute(desc_ptr.as_ptr()) }
+        )
     }
 
     pub fn is_ready(&self, queue_idx: u32) -> bool {
@@ -272,6 +269,60 @@ impl VirtioMmio {
     }
 }
 
+// device_typeやfeatures_supportedなどデバイスの種類で決まるものは保存しない
+impl Snapshot for VirtioMmio {
+    fn save(&self, w: &mut SnapshotWriter) {
+        w.write_u32(self.status);
+        w.write_len(self.features_sel);
+        w.write_len(self.driver_features_sel);
+        for feature in self.driver_features {
+            w.write_u32(feature);
+        }
+        w.write_len(self.queue_sel);
+        w.write_u32(self.shm_sel);
+
+        w.write_len(self.readies.len());
+        for i in 0..self.readies.len() {
+            w.write_u32(self.queue_sizes[i]);
+            w.write_bool(self.readies[i]);
+            w.write_u64(self.desc_addrs[i]);
+            w.write_u64(self.driver_addrs[i]);
+            w.write_u64(self.device_addrs[i]);
+        }
+    }
+
+    fn restore(&mut self, r: &mut SnapshotReader) -> snapshot::Result<()> {
+        self.status = r.read_u32()?;
+        self.features_sel = r.read_u32()? as usize;
+        self.driver_features_sel = r.read_u32()? as usize;
+        for feature in self.driver_features.iter_mut() {
+            *feature = r.read_u32()?;
+        }
+        self.queue_sel = r.read_u32()? as usize;
+        self.shm_sel = r.read_u32()?;
+
+        let queue_num = self.readies.len();
+
+        if self.features_sel >= self.features_supported.len()
+            || self.driver_features_sel >= self.driver_features.len()
+            || self.queue_sel >= queue_num
+            || r.read_u32()? as usize != queue_num
+        {
+            return Err(SnapshotError::InvalidValue("virtio queue"));
+        }
+
+        for i in 0..queue_num {
+            self.queue_sizes[i] = r.read_u32()?;
+            self.readies[i] = r.read_bool()?;
+            self.desc_addrs[i] = r.read_u64()?;
+            self.driver_addrs[i] = r.read_u64()?;
+            self.device_addrs[i] = r.read_u64()?;
+        }
+
+        Ok(())
+    }
+}
+
 impl VirtQueueDesc {
     pub fn is_next(&self) -> bool {
         self.flags & 1 != 0
@@ -290,17 +341,3 @@ impl VirtQueueDesc {
 pub fn calc_desc_offset(desc_idx: usize) -> usize {
     VIRTIO_QUEUE_DESC_SIZE * desc_idx
 }
-
-pub fn read_panic(offset: u32) -> ! {
-    panic!(
-        "[VIRTIO]: Reading offset 0x{:x?} is not implemented.",
-        offset
-    )
-}
-
-pub fn write_panic(offset: u32, value: u32) -> ! {
-    panic!(
-        "[VIRTIO]: Writing offset 0x{:x?} with 0x{:x?} is not implemented.",
-        offset, value
-    )
-}
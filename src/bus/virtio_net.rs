@@ -5,11 +5,14 @@ use crate::{
         DeviceTrait,
         virtio_mmio::{
             VIRTIO_REG_CONFIG, VIRTIO_REG_NOTIFY, VIRTIO_REG_STATUS, VirtioMmio, VirtioType,
-            read_panic,
         },
     },
-    device::{DeviceMessage, DeviceRecieverTrait, DeviceResponse, DeviceResult, DeviceSenderTrait},
+    device::{
+        BusError, BusResult, DeviceMessage, DeviceRecieverTrait, DeviceResponse, DeviceResult,
+        DeviceSenderTrait,
+    },
     memory::Memory,
+    snapshot::{self, Snapshot, SnapshotReader, SnapshotWriter},
 };
 
 const VIRTIO_NET_HEADER_SIZE: usize = size_of::<VirtioNetHeader>();
@@ -36,7 +39,7 @@ where
     reciever: R,
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone, Copy)]
 #[repr(C)]
 pub struct VirtioNetHeader {
     flags: u8,
@@ -59,14 +62,12 @@ where
             0..VIRTIO_REG_CONFIG => self.virtio.read(offset, size),
             _ => {
                 if size != 1 {
-                    unimplemented!();
+                    return Err(BusError::Size(size));
                 }
 
-                let offset = (offset - 0x100) as usize;
-
-                let value = match offset {
-                    0..6 => MAC_ADDRESS[offset] as u32,
-                    _ => read_panic(offset as u32 + 0x100),
+                let value = match (offset - VIRTIO_REG_CONFIG) as usize {
+                    i @ 0..6 => MAC_ADDRESS[i] as u32,
+                    _ => return Err(BusError::Register(offset)),
                 };
 
                 Ok(DeviceResponse {
@@ -87,7 +88,7 @@ where
     ) -> DeviceResult<()> {
         match offset {
             VIRTIO_REG_NOTIFY => {
-                let is_interrupting = self.handle_notify(value, memory);
+                let is_interrupting = self.handle_notify(value, memory)?;
 
                 return Ok(DeviceResponse {
                     value: (),
@@ -112,8 +113,12 @@ where
         })
     }
 
-    fn irq(&self) -> crate::IRQ {
-        crate::IRQ::VirtioNet
+    fn fdt_name(&self) -> &'static str {
+        "virtio_mmio"
+    }
+
+    fn fdt_compatible(&self) -> &'static [&'static str] {
+        &["virtio,mmio"]
     }
 
     fn tick(&mut self, memory: &mut Memory) -> bool {
@@ -122,51 +127,38 @@ where
         }
 
         if let Ok(DeviceMessage::Net(v)) = self.reciever.try_recv_from_host() {
-            let mut header = VirtioNetHeader::default();
-            header.num_buffers = 1;
-
-            let driver = self
-                .virtio
-                .driver::<MAX_QUEUE_SIZE>(VIRTIO_NET_RECV_IDX, memory);
-
-            let device = self
-                .virtio
-                .device::<MAX_QUEUE_SIZE>(VIRTIO_NET_RECV_IDX, memory);
-
-            let last_idx = self.last_idxes[VIRTIO_NET_RECV_IDX as usize];
-
-            if driver.idx == last_idx {
-                // キューが足りない場合
-                return false;
-            }
-
-            let desc_base = self.virtio.desc_addr(VIRTIO_NET_RECV_IDX);
-            let desc_idx = driver.ring[last_idx as usize % MAX_QUEUE_SIZE];
-            let desc = self.virtio.desc(desc_idx, desc_base, memory);
-
-            let data_size = v.len() + VIRTIO_NET_HEADER_SIZE as usize;
-
-            if data_size > desc.len as usize {
-                panic!("[ERROR]: size of packet is more than desc.len.");
+            // CPUのアクセスではないのでフォルトにはできず、パケットを捨てる
+            match self.recieve_packet(&v, memory) {
+                Ok(is_interrupting) => return is_interrupting,
+                Err(e) => eprintln!("[WARNING]: virtio-net dropped a packet: {}", e),
             }
+        }
 
-            let data_ptr = memory.raw_mut_ptr(desc.addr as usize, desc.len as usize);
-            let header_data: &[u8; VIRTIO_NET_HEADER_SIZE] =
-                unsafe { transmute(&header as *const _) };
+        false
+    }
+}
 
-            data_ptr[..VIRTIO_NET_HEADER_SIZE].copy_from_slice(header_data);
-            data_ptr[VIRTIO_NET_HEADER_SIZE..data_size].copy_from_slice(&v);
+impl<S, R> Snapshot for VirtioNet<S, R>
+where
+    S: DeviceSenderTrait,
+    R: DeviceRecieverTrait,
+{
+    fn save(&self, w: &mut SnapshotWriter) {
+        self.virtio.save(w);
 
-            device.elems[last_idx as usize % MAX_QUEUE_SIZE].id = desc_idx as u32;
-            device.elems[last_idx as usize % MAX_QUEUE_SIZE].len = data_size as u32;
+        for idx in self.last_idxes {
+            w.write_u16(idx);
+        }
+    }
 
-            device.idx = device.idx.wrapping_add(1);
-            self.last_idxes[VIRTIO_NET_RECV_IDX as usize] = last_idx.wrapping_add(1);
+    fn restore(&mut self, r: &mut SnapshotReader) -> snapshot::Result<()> {
+        self.virtio.restore(r)?;
 
-            return true;
+        for idx in self.last_idxes.iter_mut() {
+            *idx = r.read_u16()?;
         }
 
-        false
+        Ok(())
     }
 }
 
@@ -195,17 +187,68 @@ where
         *self = Self::new(reciever, sender);
     }
 
+    // 受け取ったパケットを受信用のキューに書き込む関数
+    // interruptが発生する場合はtrueを返す
+    fn recieve_packet(&mut self, v: &[u8], memory: &mut Memory) -> BusResult<bool> {
+        let mut header = VirtioNetHeader::default();
+        header.num_buffers = 1;
+
+        let driver = self
+            .virtio
+            .driver::<MAX_QUEUE_SIZE>(VIRTIO_NET_RECV_IDX, memory)?;
+
+        let last_idx = self.last_idxes[VIRTIO_NET_RECV_IDX as usize];
+
+        if driver.idx == last_idx {
+            // キューが足りない場合
+            return Ok(false);
+        }
+
+        let desc_base = self.virtio.desc_addr(VIRTIO_NET_RECV_IDX);
+        let desc_idx = driver.ring[last_idx as usize % MAX_QUEUE_SIZE];
+        let desc = self.virtio.desc(desc_idx, desc_base, memory)?;
+
+        let data_size = v.len() + VIRTIO_NET_HEADER_SIZE as usize;
+
+        if data_size > desc.len as usize {
+            return Err(BusError::Unsupported("packet larger than the buffer"));
+        }
+
+        let data_ptr = memory.raw_mut_ptr(desc.addr as usize, desc.len as usize)?;
+        let header_data: &[u8; VIRTIO_NET_HEADER_SIZE] = unsafe { transmute(&header as *const _) };
+
+        data_ptr[..VIRTIO_NET_HEADER_SIZE].copy_from_slice(header_data);
+        data_ptr[VIRTIO_NET_HEADER_SIZE..data_size].copy_from_slice(v);
+
+        let device = self
+            .virtio
+            .device::<MAX_QUEUE_SIZE>(VIRTIO_NET_RECV_IDX, memory)?;
+
+        device.elems[last_idx as usize % MAX_QUEUE_SIZE].id = desc_idx as u32;
+        device.elems[last_idx as usize % MAX_QUEUE_SIZE].len = data_size as u32;
+
+        device.idx = device.idx.wrapping_add(1);
+        self.last_idxes[VIRTIO_NET_RECV_IDX as usize] = last_idx.wrapping_add(1);
+
+        Ok(true)
+    }
+
     // notifyを処理する関数
     // interruptが発生する場合はtrueを返す
-    fn handle_notify(&mut self, queue_idx: u32, memory: &mut Memory) -> bool {
-        let driver = self.virtio.driver::<MAX_QUEUE_SIZE>(queue_idx, memory);
-        let device = self.virtio.device::<MAX_QUEUE_SIZE>(queue_idx, memory);
+    fn handle_notify(&mut self, queue_idx: u32, memory: &mut Memory) -> BusResult<bool> {
+        if queue_idx as usize >= self.last_idxes.len() {
+            return Err(BusError::Value {
+                offset: VIRTIO_REG_NOTIFY,
+                value: queue_idx,
+            });
+        }
+
+        let driver = self.virtio.driver::<MAX_QUEUE_SIZE>(queue_idx, memory)?;
 
         let last_idx = self.last_idxes[queue_idx as usize];
 
-        eprintln!("[NOTIFY]");
         if driver.idx == last_idx {
-            return false;
+            return Ok(false);
         }
 
         match queue_idx {
@@ -224,15 +267,15 @@ where
                     let ring_idx = last_idx.wrapping_add(i) as usize % MAX_QUEUE_SIZE;
                     let desc_idx = driver.ring[ring_idx];
 
-                    let desc = self.virtio.desc(desc_idx, desc_base, memory);
+                    let desc = self.virtio.desc(desc_idx, desc_base, memory)?;
 
                     if desc.is_next() {
-                        unimplemented!();
+                        return Err(BusError::Unsupported("chained descriptor"));
                     }
 
-                    let data_ptr = memory.raw_ptr(desc.addr as usize, desc.len as usize);
-                    let virtio_net_header: &VirtioNetHeader =
-                        unsafe { transmute(data_ptr.as_ptr()) };
+                    let virtio_net_header =
+                        memory.view_as::<VirtioNetHeader>(desc.addr as usize, desc.len as usize)?;
+                    let data_ptr = memory.raw_ptr(desc.addr as usize, desc.len as usize)?;
 
                     if virtio_net_header.num_buffers != 0 {
                         eprintln!(
@@ -246,6 +289,8 @@ where
                         .send_to_host(DeviceMessage::Net(data.to_vec()))
                         .unwrap();
 
+                    let device = self.virtio.device::<MAX_QUEUE_SIZE>(queue_idx, memory)?;
+
                     device.elems[ring_idx].len = 0;
                     device.elems[ring_idx].id = desc_idx as u32;
                     device.idx = device.idx.wrapping_add(1);
@@ -253,11 +298,11 @@ where
 
                 self.last_idxes[queue_idx as usize] = now_driver_idx;
 
-                return true;
+                return Ok(true);
             }
             _ => unreachable!(),
         }
 
-        false
+        Ok(false)
     }
 }
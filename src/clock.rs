@@ -0,0 +1,57 @@
+use std::time::Instant;
+
+use crate::csr::TIMEBASE_FREQ;
+
+// ホストの時計を読む間隔(命令数)
+// 毎命令読むと遅いのでこの間隔でtimeを合わせる
+const SYNC_INTERVAL: u32 = 0x100;
+
+// timeの進め方
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TimerMode {
+    // 1命令ごとに1進める
+    // ホストの速度に関係なく同じ結果になる
+    #[default]
+    Instret,
+    // ホストの単調増加する時計をtimebase-frequencyで換算した値にする
+    WallClock,
+}
+
+// ホストの時計からtimeの値を求める
+pub struct WallClock {
+    start: Instant,
+    start_time: u64, // startの時点でのtimeの値
+    count: u32,
+}
+
+impl WallClock {
+    pub fn new(time: u64) -> Self {
+        Self {
+            start: Instant::now(),
+            start_time: time,
+            count: 0,
+        }
+    }
+
+    // 現在のtimeの値を返す関数
+    pub fn now(&self) -> u64 {
+        let ticks = self.start.elapsed().as_nanos() * TIMEBASE_FREQ as u128 / 1_000_000_000;
+
+        self.start_time.wrapping_add(ticks as u64)
+    }
+
+    // 1命令ごとに呼び出す関数
+    // SYNC_INTERVAL回に1回だけtimeの値を返す
+    #[inline]
+    pub fn tick(&mut self) -> Option<u64> {
+        self.count += 1;
+
+        if self.count < SYNC_INTERVAL {
+            return None;
+        }
+
+        self.count = 0;
+
+        Some(self.now())
+    }
+}
@@ -3,11 +3,18 @@ use std::fmt::Display;
 use crate::{
     AccessType, Priv, Result, Trap,
     bus::{Bus, CpuContext},
-    csr::Csr,
+    csr::{CACHE_BLOCK_SIZE, Csr, ENVCFG_CBCFE, ENVCFG_CBIE, ENVCFG_CBZE},
+    decode::{Decoded, Op, decode, inst_len},
+    disasm::{disassemble, reg_name},
+    icache::ICache,
     illegal,
+    snapshot::{self, Snapshot, SnapshotError, SnapshotReader, SnapshotWriter},
     tlb::{Tlb, TlbEntry},
+    trace::Tracer,
 };
 
+mod fpu;
+
 const PTE_V: u32 = 1;
 const PTE_R: u32 = 1 << 1;
 const PTE_W: u32 = 1 << 2;
@@ -47,7 +54,12 @@ impl Default for Registers {
 impl Display for Registers {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         for (idx, reg) in self.regs.iter().enumerate() {
-            f.write_str(&format!("[{:02}]: 0x{:08x}\n", idx, reg))?;
+            f.write_str(&format!(
+                "[{:02}] {:<4}: 0x{:08x}\n",
+                idx,
+                reg_name(idx as u32),
+                reg
+            ))?;
         }
 
         Ok(())
@@ -77,16 +89,55 @@ impl Registers {
 pub struct Cpu {
     prv: Priv, // privは予約済みらしい
     regs: Registers,
-    pc: u32, // 当面はVirtual Address想定
+    fregs: [u64; 32], // 単精度の値はNaN-boxingして格納する
+    pc: u32,          // 当面はVirtual Address想定
 
     // 現在実行中の命令列
     inst: u32,
 
     csr: Csr,
     tlb: Tlb,
+    icache: ICache,
 
     reserved_addr: Option<u32>, // For LR.W or SC.W
     fault_addr: Option<u32>,
+
+    // WFIを実行して割り込みを待っているか
+    is_waiting: bool,
+
+    // デバッガから設定されたウォッチポイント
+    watchpoints: Vec<Watchpoint>,
+    watch_hit: Option<(WatchKind, u32)>,
+
+    tracer: Option<Tracer>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    Write,
+    Read,
+    Access,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub addr: u32,
+    pub len: u32,
+    pub kind: WatchKind,
+}
+
+impl Watchpoint {
+    fn is_hit(&self, addr: u32, size: u32, access_type: AccessType) -> bool {
+        let kind_matches = match self.kind {
+            WatchKind::Write => access_type.is_write(),
+            WatchKind::Read => access_type.is_read(),
+            WatchKind::Access => true,
+        };
+
+        kind_matches
+            && (addr as u64) < self.addr as u64 + self.len as u64
+            && (self.addr as u64) < addr as u64 + size as u64
+    }
 }
 
 impl Display for Cpu {
@@ -97,20 +148,10 @@ impl Display for Cpu {
         f.write_str(&format!("Priv: {:?}\n", self.prv))?;
         f.write_str(&format!("{}\n", self.regs))?;
 
-        let opcode = self.inst & 0x7f;
-        let rd = (self.inst >> 7) & 0x1f;
-        let rs1 = (self.inst >> 15) & 0x1f;
-        let rs2 = (self.inst >> 20) & 0x1f;
-        let funct3 = (self.inst >> 12) & 0x7;
-        let funct7 = self.inst >> 25;
-        f.write_str(&format!("inst: 0x{:08x}\n", self.inst))?;
-        f.write_str(&format!(
-            "opcode: 0b{:07b} funct3: 0b{:03b} funct7: 0b{:07b}\n",
-            opcode, funct3, funct7
-        ))?;
         f.write_str(&format!(
-            "rd: 0b{:05b} rs1: 0b{:05b} rs2: 0b{:05b}\n",
-            rd, rs1, rs2
+            "inst: 0x{:08x} ({})\n",
+            self.inst,
+            disassemble(self.inst, self.pc)
         ))?;
 
         f.write_str(&format!("{:x?}\n", self.csr))?;
@@ -129,51 +170,79 @@ impl Default for Cpu {
         Self {
             prv,
             regs,
+            fregs: [0; 32],
             pc: 0,
             inst: 0,
             csr,
             tlb,
+            icache: ICache::default(),
             reserved_addr: None,
             fault_addr: None,
+            is_waiting: false,
+            watchpoints: Vec::new(),
+            watch_hit: None,
+            tracer: None,
         }
     }
 }
 
 impl Cpu {
+    // a0にmhartidを入れた状態で作る
+    pub fn new(hartid: u32) -> Self {
+        let mut cpu = Self::default();
+
+        cpu.csr.mhartid = hartid;
+        cpu.regs.write(10, hartid);
+
+        cpu
+    }
+
+    // PMPの設定方法は引き継ぐ
     pub fn init(&mut self) {
-        *self = Self::default();
+        let is_permissive = self.csr.pmp.is_permissive();
+
+        *self = Self::new(self.csr.mhartid);
+        self.csr.pmp.set_permissive(is_permissive);
     }
 
-    fn read_reg(&self, reg: u32) -> u32 {
-        self.regs.read(reg)
+    #[inline]
+    pub fn hartid(&self) -> usize {
+        self.csr.mhartid as usize
     }
 
-    fn write_reg(&mut self, reg: u32, value: u32) {
-        self.regs.write(reg, value)
+    pub fn read_reg(&self, reg: u32) -> u32 {
+        self.regs.read(reg)
     }
 
-    fn translate_va(&mut self, va: u32, access_type: AccessType, bus: &mut Bus) -> Result<u32> {
-        if !self.csr.is_paging_enabled() {
-            return Ok(va);
+    pub fn write_reg(&mut self, reg: u32, value: u32) {
+        if let Some(tracer) = &mut self.tracer {
+            tracer.write_reg(reg, value);
         }
 
-        //[todo] 権限元を使用し、チェック
+        self.regs.write(reg, value)
+    }
 
-        let mut local_prv = self.prv;
+    pub fn read_freg(&self, reg: u32) -> u64 {
+        self.fregs[reg as usize]
+    }
 
-        if self.csr.is_enabled_mstatus_mprv() && (access_type.is_read() || access_type.is_write()) {
-            let mpp_prv = self.csr.get_mstatus_mpp().into();
-            local_prv = mpp_prv;
+    pub fn write_freg(&mut self, reg: u32, value: u64) {
+        if let Some(tracer) = &mut self.tracer {
+            tracer.write_freg(reg, value);
         }
 
-        if local_prv == Priv::Machine {
+        self.fregs[reg as usize] = value;
+    }
+
+    fn translate_va(&mut self, va: u32, access_type: AccessType, bus: &mut Bus) -> Result<u32> {
+        if !self.csr.is_paging_enabled() {
             return Ok(va);
         }
 
-        let vpn = va >> 12;
+        let local_prv = self.effective_prv(access_type);
 
-        if let Some(entry) = self.tlb.lookup_ppn(va, local_prv) {
-            return Ok(entry.ppn() | (va & 0xfff));
+        if local_prv == Priv::Machine {
+            return Ok(va);
         }
 
         macro_rules! fault {
@@ -187,16 +256,38 @@ impl Cpu {
             };
         }
 
-        let mut addr = self.csr.get_satp_ppn() * PAGESIZE;
-        let mut pte = 0;
+        let asid = self.csr.get_satp_asid();
+
+        if let Some(entry) = self.tlb.lookup(va, asid) {
+            let pte = entry.pte();
 
-        let mut last = None;
+            if !self.is_permitted(pte, access_type, local_prv) {
+                fault!();
+            }
+
+            // Dビットが立っていないエントリへの書き込みはページテーブルをたどり直す
+            if !access_type.is_write() || pte & PTE_D != 0 {
+                return Ok(entry.translate(va));
+            }
+        }
+
+        let vpn = va >> 12;
+        let mut addr = self.csr.get_satp_ppn().wrapping_mul(PAGESIZE);
 
         for i in (0..2).rev() {
-            let vpn = (vpn >> (10 * i)) & 0x3ff;
-            let pte_addr = addr + vpn * PTESIZE;
+            let pte_addr = addr + ((vpn >> (10 * i)) & 0x3ff) * PTESIZE;
+
+            // ページテーブルへのアクセスはSモードとしてPMPで確認する
+            if !self
+                .csr
+                .pmp
+                .is_permitted(pte_addr, PTESIZE, AccessType::Read, Priv::Supervisor)
+            {
+                fault!(access_type.into_trap(false));
+            }
 
-            pte = bus.read(
+            // PTEを読み書きできない場合は元のアクセスのアクセスフォルトになる
+            let Ok(mut pte) = bus.read(
                 pte_addr,
                 4,
                 crate::bus::CpuContext {
@@ -204,98 +295,186 @@ impl Cpu {
                     is_walk: true,
                     access_type: AccessType::Read,
                 },
-            )?;
-
-            let v = pte & PTE_V;
-            let r = pte & PTE_R;
-            let w = pte & PTE_W;
-            let x = pte & PTE_X;
+            ) else {
+                fault!(access_type.into_trap(false));
+            };
 
-            if v == 0 || (r == 0 && w != 0) {
+            if pte & PTE_V == 0 || (pte & PTE_R == 0 && pte & PTE_W != 0) {
                 fault!();
             }
 
-            if r != 0 || x != 0 {
-                // PTEを発見
+            if pte & (PTE_R | PTE_X) == 0 {
+                // 次の段のページテーブルを指している
+                addr = (pte >> 10).wrapping_mul(PAGESIZE);
+                continue;
+            }
 
-                if (r == access_type as u32)
-                    || (w == access_type as u32)
-                    || (x == access_type as u32)
-                {
-                    // 権限の確認
+            // PTEを発見
 
-                    if i > 0 && pte & (0x3ff << 10) != 0 {
-                        // superpageのエラー
-                        fault!();
-                    }
+            if !self.is_permitted(pte, access_type, local_prv) {
+                fault!();
+            }
 
-                    let u = pte & PTE_U;
-
-                    if (u == 0 && local_prv == Priv::User)
-                        || (u != 0
-                            && local_prv == Priv::Supervisor
-                            && (r != 0 || w != 0)
-                            && !self.csr.is_enabled_mstatus_sum())
-                    {
-                        // U=0かつ権限がUモードの場合と
-                        // U=1かつ権限がSモードかつmstatus.SUM=1の場合は例外を出す
-                        fault!();
-                    }
+            if i > 0 && pte & (0x3ff << 10) != 0 {
+                // superpageのエラー
+                fault!();
+            }
 
-                    let a = pte & PTE_A;
-                    let d = pte & PTE_D;
+            let is_write = access_type.is_write();
 
-                    let is_write = access_type.is_write();
+            if pte & PTE_A == 0 || (is_write && pte & PTE_D == 0) {
+                // menvcfg.ADUE=0の場合はページフォルトを出してソフトウェアに更新させる
+                if !self.csr.is_adue_enabled() {
+                    fault!();
+                }
 
-                    if a == 0 || (is_write && d == 0) {
-                        //自動更新の方ではテストが通らなさそう。
+                let new_pte = pte | PTE_A | if is_write { PTE_D } else { 0 };
 
-                        if self.csr.is_svadu_enabled() {
-                            fault!();
-                        } else {
-                            todo!();
-                        }
-                    }
+                if !self.csr.pmp.is_permitted(
+                    pte_addr,
+                    PTESIZE,
+                    AccessType::Write,
+                    Priv::Supervisor,
+                ) {
+                    fault!(access_type.into_trap(false));
+                }
 
-                    last = Some(i);
-                    break;
+                // 1命令の実行中に他からPTEが書き換えられることはないので
+                // そのまま書き込んでもアトミックに更新したことになる
+                if bus
+                    .write(
+                        pte_addr,
+                        4,
+                        new_pte,
+                        crate::bus::CpuContext {
+                            csr: &mut self.csr,
+                            is_walk: true,
+                            access_type,
+                        },
+                    )
+                    .is_err()
+                {
+                    fault!(access_type.into_trap(false));
                 }
+
+                pte = new_pte;
             }
 
-            addr = (pte >> 10) * 4096;
+            // 34bitのはずだけど2bitは無視できるっぽい
+            let ppn = (pte << 2) & 0xfffff000;
+            let entry = TlbEntry::new(va, ppn, asid, pte, i == 1);
+
+            self.tlb.register_entry(entry);
+
+            return Ok(entry.translate(va));
         }
 
-        if let Some(i) = last {
-            // 34bitのはずだけど2bitは無視できるっぽい
-            let ppn = if i == 1 {
-                ((pte << 2) & 0xffc00000) | (va & 0x3ff000)
-            } else {
-                (pte << 2) & 0xfffff000
-            };
+        // 2段目もページテーブルを指している
+        fault!();
+    }
 
-            let pa = ppn | (va & 0xfff);
+    // cbo.clean, cbo.flush, cbo.invalでアクセスできるかを確認する関数
+    // 書き込めるページは必ず読み込めるので読み込みで確認し、フォルトはストアのものとして扱う
+    // PMPはvaを含むブロック全体で確認する
+    fn check_cbo_permission(&mut self, va: u32, bus: &mut Bus) -> Result<()> {
+        let access_type = AccessType::Read;
+        let result = self.translate_va(va, access_type, bus).and_then(|pa| {
+            let pa = pa & !(CACHE_BLOCK_SIZE - 1);
 
-            let entry = TlbEntry::new(va, ppn, local_prv);
+            self.check_pmp(va, pa, CACHE_BLOCK_SIZE, access_type)
+        });
 
-            self.tlb.register_entry(entry);
+        match result {
+            Ok(_) => Ok(()),
+            Err(Trap::LoadPageFault) => Err(Trap::StoreOrAMOPageFault),
+            Err(Trap::LoadAccessFault) => Err(Trap::StoreOrAMOAccessFault),
+            Err(e) => Err(e),
+        }
+    }
 
-            Ok(pa)
+    // ロードとストアではmstatus.MPRV=1の場合にMPPの権限でアクセスする
+    #[inline]
+    fn effective_prv(&self, access_type: AccessType) -> Priv {
+        if self.csr.is_enabled_mstatus_mprv() && !access_type.is_exec() {
+            self.csr.get_mstatus_mpp().into()
         } else {
-            fault!();
+            self.prv
+        }
+    }
+
+    // PMPでアクセスが許可されているかを確認する関数
+    // 許可されていない場合はvaをtvalとしてアクセスフォルトを起こす
+    #[inline]
+    fn check_pmp(&mut self, va: u32, pa: u32, size: u32, access_type: AccessType) -> Result<()> {
+        let prv = self.effective_prv(access_type);
+
+        if !self.csr.pmp.is_permitted(pa, size, access_type, prv) {
+            self.fault_addr = Some(va);
+
+            return Err(access_type.into_trap(false));
+        }
+
+        Ok(())
+    }
+
+    // PTEの権限でアクセスできるかを確認する関数
+    fn is_permitted(&self, pte: u32, access_type: AccessType, prv: Priv) -> bool {
+        let is_user_page = pte & PTE_U != 0;
+
+        match prv {
+            // U=0のページにはUモードからアクセスできない
+            Priv::User if !is_user_page => return false,
+            // U=1のページはSモードから実行できず、mstatus.SUM=1の場合のみ読み書きできる
+            Priv::Supervisor
+                if is_user_page
+                    && (access_type.is_exec() || !self.csr.is_enabled_mstatus_sum()) =>
+            {
+                return false;
+            }
+            _ => {}
+        }
+
+        match access_type {
+            // mstatus.MXR=1の場合は実行可能なページも読み込める
+            AccessType::Read => {
+                pte & PTE_R != 0 || (self.csr.is_enabled_mstatus_mxr() && pte & PTE_X != 0)
+            }
+            AccessType::Write => pte & PTE_W != 0,
+            AccessType::Fetch => pte & PTE_X != 0,
         }
     }
 
     #[inline]
     pub fn read_memory(&mut self, addr: u32, size: u32, bus: &mut Bus) -> Result<u32> {
+        let pa = self.translate_va(addr, AccessType::Read, bus)?;
+
+        self.read_translated(addr, pa, size, bus)
+    }
+
+    // translate_vaで変換済みの物理アドレスpaから読み込む関数
+    #[inline]
+    fn read_translated(&mut self, addr: u32, pa: u32, size: u32, bus: &mut Bus) -> Result<u32> {
         let access_type = AccessType::Read;
-        let pa = self.translate_va(addr, access_type, bus)?;
+
+        self.check_pmp(addr, pa, size, access_type)?;
+
         let ctx = CpuContext {
             csr: &mut self.csr,
             is_walk: false,
             access_type,
         };
 
-        bus.read(pa, size, ctx)
+        let value = bus
+            .read(pa, size, ctx)
+            .inspect_err(|_| self.fault_addr = Some(addr))?;
+
+        self.check_watchpoints(addr, size, access_type);
+
+        if let Some(tracer) = &mut self.tracer {
+            tracer.read_memory(addr);
+        }
+
+        Ok(value)
     }
 
     #[inline]
@@ -315,9 +494,25 @@ impl Cpu {
 
     #[inline]
     pub fn write_memory(&mut self, addr: u32, size: u32, value: u32, bus: &mut Bus) -> Result<()> {
+        let pa = self.translate_va(addr, AccessType::Write, bus)?;
+
+        self.write_translated(addr, pa, size, value, bus)
+    }
+
+    // translate_vaで変換済みの物理アドレスpaに書き込む関数
+    #[inline]
+    fn write_translated(
+        &mut self,
+        addr: u32,
+        pa: u32,
+        size: u32,
+        value: u32,
+        bus: &mut Bus,
+    ) -> Result<()> {
         let access_type = AccessType::Write;
 
-        let pa = self.translate_va(addr, access_type, bus)?;
+        self.check_pmp(addr, pa, size, access_type)?;
+
         let ctx = CpuContext {
             csr: &mut self.csr,
             is_walk: false,
@@ -325,6 +520,15 @@ impl Cpu {
         };
 
         bus.write(pa, size, value, ctx)
+            .inspect_err(|_| self.fault_addr = Some(addr))?;
+
+        self.check_watchpoints(addr, size, access_type);
+
+        if let Some(tracer) = &mut self.tracer {
+            tracer.write_memory(addr, value as u64, size);
+        }
+
+        Ok(())
     }
 
     #[inline]
@@ -353,11 +557,25 @@ impl Cpu {
     }
 
     // jump命令: Ok(true) 他の命令: Ok(false)
+    #[inline]
+    pub fn step(&mut self, bus: &mut Bus) -> Result<bool> {
+        let result = self.execute(bus);
+
+        if let Some(tracer) = &mut self.tracer {
+            match result {
+                Ok(_) => tracer.commit(),
+                Err(_) => tracer.abort(),
+            }
+        }
+
+        result
+    }
+
     // [todo] テストを通すためにテストで明示的に指定されるillegalな命令でillegal!を呼ぶが
     // テストが全て終わり、rv32imaの命令がすべて実装し終わったらunimplemented!をillegal!
     // に変更する。
     #[inline]
-    pub fn step(&mut self, bus: &mut Bus) -> Result<bool> {
+    fn execute(&mut self, bus: &mut Bus) -> Result<bool> {
         macro_rules! reg {
             ($reg:expr) => {
                 self.read_reg($reg)
@@ -367,259 +585,240 @@ impl Cpu {
             };
         }
 
-        self.inst = self.fetch(bus)?;
+        let decoded = self.fetch(bus)?;
+
+        self.inst = decoded.inst;
 
-        if self.inst == 0 {
-            illegal!();
+        if let Some(tracer) = &mut self.tracer {
+            tracer.begin(self.pc, self.inst, self.prv);
         }
 
-        let opcode = self.inst & 0x7f;
-        let rd = (self.inst >> 7) & 0x1f;
-        let rs1 = (self.inst >> 15) & 0x1f;
-        let rs2 = (self.inst >> 20) & 0x1f;
-        let funct3 = (self.inst >> 12) & 0x7;
+        let Decoded {
+            op,
+            rd,
+            rs1,
+            rs2,
+            imm,
+            ..
+        } = decoded;
 
         let mut is_jump = false;
 
-        match opcode {
-            0b0000011 => {
-                let imm = ((self.inst as i32) >> 20) as u32;
-                let addr = reg!(rs1).wrapping_add(imm);
-
-                let value = match funct3 {
-                    //[todo] refactor
-                    0b000 => {
-                        // LB
-                        let value = self.read_memory_u8(addr, bus)?;
-                        (((value << 24) as i32) >> 24) as u32
-                    }
-                    0b001 => {
-                        // LH
-                        let value = self.read_memory_u16(addr, bus)?;
-                        (((value << 16) as i32) >> 16) as u32
-                    }
-                    0b010 => {
-                        // LW
-                        self.read_memory_u32(addr, bus)?
-                    }
-                    0b100 => {
-                        // LBU
-                        self.read_memory_u8(addr, bus)?
-                    }
-                    0b101 => {
-                        // LHU
-                        self.read_memory_u16(addr, bus)?
-                    }
-                    _ => unimplemented!(),
-                };
-
+        match op {
+            Op::Lb => {
+                let value = self.read_memory_u8(reg!(rs1).wrapping_add(imm), bus)?;
+                reg!(rd, (((value << 24) as i32) >> 24) as u32);
+            }
+            Op::Lh => {
+                let value = self.read_memory_u16(reg!(rs1).wrapping_add(imm), bus)?;
+                reg!(rd, (((value << 16) as i32) >> 16) as u32);
+            }
+            Op::Lw => {
+                let value = self.read_memory_u32(reg!(rs1).wrapping_add(imm), bus)?;
                 reg!(rd, value);
             }
-            0b0001111 => {
-                match funct3 {
-                    0 => match self.inst {
-                        0x8330000f => illegal!(), // FENCE.TSO
-                        0x0100000f => {}          // PAUSE Zinhintpause拡張
-                        _ => {}                   // FENCE
-                    },
-                    1 => {} // FENCE.I
-                    _ => unimplemented!(),
-                }
+            Op::Lbu => {
+                let value = self.read_memory_u8(reg!(rs1).wrapping_add(imm), bus)?;
+                reg!(rd, value);
             }
-            0b0010011 => {
-                match funct3 {
-                    0b000 => {
-                        // ADDI
-                        let imm = ((self.inst as i32) >> 20) as u32;
-                        reg!(rd, reg!(rs1).wrapping_add(imm))
-                    }
-                    0b001 => {
-                        // SLLI
-                        let imm = self.inst >> 20;
-                        if imm >> 5 != 0 {
-                            illegal!();
-                        }
+            Op::Lhu => {
+                let value = self.read_memory_u16(reg!(rs1).wrapping_add(imm), bus)?;
+                reg!(rd, value);
+            }
+            Op::Fence => {}
+            Op::FenceI => self.icache.clear(),
+            Op::CboInval | Op::CboClean | Op::CboFlush => {
+                let field = if op == Op::CboInval {
+                    ENVCFG_CBIE
+                } else {
+                    ENVCFG_CBCFE
+                };
 
-                        reg!(rd, reg!(rs1) << ((imm as u32) & 0x1f))
-                    }
-                    0b010 => {
-                        // SLTI
-                        let imm = (self.inst as i32) >> 20;
-                        reg!(rd, if imm > reg!(rs1) as i32 { 1 } else { 0 })
-                    }
-                    0b011 => {
-                        // SLTIU
-                        let imm = ((self.inst as i32) >> 20) as u32;
-                        reg!(rd, if imm > reg!(rs1) { 1 } else { 0 })
-                    }
-                    0b100 => {
-                        // XORI
-                        let imm = ((self.inst as i32) >> 20) as u32;
-                        reg!(rd, reg!(rs1) ^ imm);
-                    }
-                    0b101 => {
-                        let imm = (self.inst >> 20) & 0x1f;
-                        let funct7 = self.inst >> 25;
-
-                        match funct7 {
-                            0b0000000 => reg!(rd, reg!(rs1) >> imm), // SRLI
-                            0b0100000 => reg!(rd, ((reg!(rs1) as i32) >> imm) as u32), // SRAI
-                            _ => unimplemented!(),
-                        }
-                    }
-                    0b110 => {
-                        // ORI
-                        let imm = ((self.inst as i32) >> 20) as u32;
-                        reg!(rd, reg!(rs1) | imm);
-                    }
-                    0b111 => {
-                        // ANDI
-                        let imm = ((self.inst as i32) >> 20) as u32;
-                        reg!(rd, reg!(rs1) & imm);
-                    }
-                    _ => unimplemented!(),
-                }
+                self.csr.check_cbo_access(field, self.prv)?;
+
+                // キャッシュを持たないのでメモリは常に一貫しており、アクセスできるかの確認のみ行う
+                self.check_cbo_permission(reg!(rs1), bus)?;
             }
-            0b0010111 => reg!(rd, self.pc.wrapping_add(self.inst & 0xfffff000)), // AUIPC
-            0b0100011 => {
-                let imm = ((self.inst >> (25 - 5)) & 0xfe0) | ((self.inst >> 7) & 0x1f);
-                let imm = (((imm << 20) as i32) >> 20) as u32;
-                let addr = reg!(rs1).wrapping_add(imm);
+            Op::CboZero => {
+                self.csr.check_cbo_access(ENVCFG_CBZE, self.prv)?;
 
-                match funct3 {
-                    //[todo] refactor
-                    0b000 => {
-                        //SB
-                        let value = reg!(rs2) as u8;
+                // フォルトした場合のtvalはrs1の値になる
+                let addr = reg!(rs1);
+                let offset_mask = CACHE_BLOCK_SIZE - 1;
 
-                        self.write_memory_u8(addr, value as u32, bus)?;
-                    }
-                    0b001 => {
-                        // SH
-                        let value = reg!(rs2) as u16;
+                // ブロックはページをまたがないが、PMPの境界はブロックの途中にあることがあるので
+                // 書き込む前にブロック全体を確認する
+                let pa = self.translate_va(addr, AccessType::Write, bus)? & !offset_mask;
+                self.check_pmp(addr, pa, CACHE_BLOCK_SIZE, AccessType::Write)?;
 
-                        self.write_memory_u16(addr, value as u32, bus)?;
-                    }
-                    0b010 => {
-                        // SW
-                        let value = reg!(rs2);
+                let base = addr & !offset_mask;
 
-                        self.write_memory_u32(addr, value, bus)?;
-                    }
-                    _ => unimplemented!(),
+                for offset in (0..CACHE_BLOCK_SIZE).step_by(4) {
+                    self.write_translated(base + offset, pa + offset, 4, 0, bus)
+                        .inspect_err(|_| self.fault_addr = Some(addr))?;
+                }
+            }
+            Op::Addi => reg!(rd, reg!(rs1).wrapping_add(imm)),
+            Op::Slli => reg!(rd, reg!(rs1) << imm),
+            Op::Slti => reg!(
+                rd,
+                if (imm as i32) > reg!(rs1) as i32 {
+                    1
+                } else {
+                    0
                 }
+            ),
+            Op::Sltiu => reg!(rd, if imm > reg!(rs1) { 1 } else { 0 }),
+            Op::Xori => reg!(rd, reg!(rs1) ^ imm),
+            Op::Srli => reg!(rd, reg!(rs1) >> imm),
+            Op::Srai => reg!(rd, ((reg!(rs1) as i32) >> imm) as u32),
+            Op::Ori => reg!(rd, reg!(rs1) | imm),
+            Op::Andi => reg!(rd, reg!(rs1) & imm),
+            Op::Auipc => reg!(rd, self.pc.wrapping_add(imm)),
+            Op::Sb => {
+                let addr = reg!(rs1).wrapping_add(imm);
+                self.write_memory_u8(addr, reg!(rs2) as u8 as u32, bus)?;
+            }
+            Op::Sh => {
+                let addr = reg!(rs1).wrapping_add(imm);
+                self.write_memory_u16(addr, reg!(rs2) as u16 as u32, bus)?;
+            }
+            Op::Sw => {
+                let addr = reg!(rs1).wrapping_add(imm);
+                self.write_memory_u32(addr, reg!(rs2), bus)?;
             }
-            0b0110011 => {
-                let funct7 = self.inst >> 25;
+            Op::Add => reg!(rd, reg!(rs1).wrapping_add(reg!(rs2))),
+            Op::Mul => reg!(rd, reg!(rs1).wrapping_mul(reg!(rs2))),
+            Op::Sub => reg!(rd, reg!(rs1).wrapping_sub(reg!(rs2))),
+            Op::Sll => reg!(rd, reg!(rs1) << (reg!(rs2) & 0x1f)),
+            Op::Mulh => {
+                let rs1_value = (((reg!(rs1) as u64) << 32) as i64) >> 32;
+                let rs2_value = (((reg!(rs2) as u64) << 32) as i64) >> 32;
+
+                reg!(rd, ((rs1_value * rs2_value) >> 32) as u32);
+            }
+            Op::Slt => reg!(
+                rd,
+                if (reg!(rs1) as i32) < (reg!(rs2) as i32) {
+                    1
+                } else {
+                    0
+                }
+            ),
+            Op::Mulhsu => {
+                let rs1_value = ((((reg!(rs1) as u64) << 32) as i64) >> 32) as u64;
+                let rs2_value = reg!(rs2) as u64;
 
-                match (funct3, funct7) {
-                    (0b000, 0b0000000) => reg!(rd, reg!(rs1).wrapping_add(reg!(rs2))), // ADD
-                    (0b000, 0b0000001) => reg!(rd, reg!(rs1).wrapping_mul(reg!(rs2))), // MUL
-                    (0b000, 0b0100000) => reg!(rd, reg!(rs1).wrapping_sub(reg!(rs2))), // SUB
-                    (0b001, 0b0000000) => reg!(rd, reg!(rs1) << (reg!(rs2) & 0x1f)),   // SLL
-                    (0b001, 0b0000001) => {
-                        // MULH
-                        let rs1_value = (((reg!(rs1) as u64) << 32) as i64) >> 32;
-                        let rs2_value = (((reg!(rs2) as u64) << 32) as i64) >> 32;
+                reg!(rd, (rs1_value.wrapping_mul(rs2_value) >> 32) as u32);
+            }
+            Op::Sltu => reg!(rd, if reg!(rs1) < reg!(rs2) { 1 } else { 0 }),
+            Op::Mulhu => {
+                let rs1_value = reg!(rs1) as u64;
+                let rs2_value = reg!(rs2) as u64;
 
-                        reg!(rd, ((rs1_value * rs2_value) >> 32) as u32);
-                    }
-                    (0b010, 0b0000000) => {
-                        reg!(
-                            rd,
-                            if (reg!(rs1) as i32) < (reg!(rs2) as i32) {
-                                1
-                            } else {
-                                0
-                            }
-                        )
-                    } // SLT
-                    (0b010, 0b0000001) => {
-                        // MULHSU
-                        let rs1_value = ((((reg!(rs1) as u64) << 32) as i64) >> 32) as u64;
-                        let rs2_value = reg!(rs2) as u64;
-
-                        reg!(rd, (rs1_value.wrapping_mul(rs2_value) >> 32) as u32);
-                    }
-                    (0b011, 0b0000000) => reg!(rd, if reg!(rs1) < reg!(rs2) { 1 } else { 0 }), // SLTU
-                    (0b011, 0b0000001) => {
-                        // MULHU
-                        let rs1_value = reg!(rs1) as u64;
-                        let rs2_value = reg!(rs2) as u64;
+                reg!(rd, (rs1_value.wrapping_mul(rs2_value) >> 32) as u32);
+            }
+            Op::Xor => reg!(rd, reg!(rs1) ^ reg!(rs2)),
+            Op::Div => {
+                let rs1_value = reg!(rs1);
+                let rs2_value = reg!(rs2);
+
+                let value = if rs1_value == 1 << 31 && rs2_value == !0 {
+                    rs1_value
+                } else if rs2_value == 0 {
+                    u32::MAX
+                } else {
+                    (rs1_value as i32 / rs2_value as i32) as u32
+                };
 
-                        reg!(rd, (rs1_value.wrapping_mul(rs2_value) >> 32) as u32);
-                    }
-                    (0b100, 0b0000000) => reg!(rd, reg!(rs1) ^ reg!(rs2)), // XOR
-                    (0b100, 0b0000001) => {
-                        // DIV
-                        let rs1_value = reg!(rs1);
-                        let rs2_value = reg!(rs2);
-
-                        let value = if rs1_value == 1 << 31 && rs2_value == !0 {
-                            rs1_value
-                        } else if rs2_value == 0 {
-                            u32::MAX
-                        } else {
-                            (rs1_value as i32 / rs2_value as i32) as u32
-                        };
+                reg!(rd, value);
+            }
+            Op::Srl => reg!(rd, reg!(rs1) >> (reg!(rs2) & 0x1f)),
+            Op::Divu => {
+                let rs1_value = reg!(rs1);
+                let rs2_value = reg!(rs2);
 
-                        reg!(rd, value);
-                    }
-                    (0b101, 0b0000000) => reg!(rd, reg!(rs1) >> (reg!(rs2) & 0x1f)), // SRL
-                    (0b101, 0b0000001) => {
-                        // DIVU
-                        let rs1_value = reg!(rs1);
-                        let rs2_value = reg!(rs2);
-
-                        reg!(
-                            rd,
-                            if rs2_value == 0 {
-                                u32::MAX
-                            } else {
-                                rs1_value / rs2_value
-                            }
-                        );
-                    }
-                    (0b101, 0b0100000) => {
-                        reg!(rd, ((reg!(rs1) as i32) >> (reg!(rs2) & 0x1f)) as u32)
-                    } // SRA
-                    (0b110, 0b0000000) => reg!(rd, reg!(rs1) | reg!(rs2)), // OR
-                    (0b110, 0b0000001) => {
-                        // REM
-                        let rs1_value = reg!(rs1);
-                        let rs2_value = reg!(rs2);
-
-                        let value = if rs1_value == 1 << 31 && rs2_value == !0 {
-                            0
-                        } else if rs2_value == 0 {
-                            rs1_value
-                        } else {
-                            (rs1_value as i32 % rs2_value as i32) as u32
-                        };
+                reg!(rd, rs1_value.checked_div(rs2_value).unwrap_or(u32::MAX));
+            }
+            Op::Sra => reg!(rd, ((reg!(rs1) as i32) >> (reg!(rs2) & 0x1f)) as u32),
+            Op::Or => reg!(rd, reg!(rs1) | reg!(rs2)),
+            Op::Rem => {
+                let rs1_value = reg!(rs1);
+                let rs2_value = reg!(rs2);
+
+                let value = if rs1_value == 1 << 31 && rs2_value == !0 {
+                    0
+                } else if rs2_value == 0 {
+                    rs1_value
+                } else {
+                    (rs1_value as i32 % rs2_value as i32) as u32
+                };
 
-                        reg!(rd, value);
-                    }
-                    (0b111, 0b0000000) => reg!(rd, reg!(rs1) & reg!(rs2)), // AND
-                    (0b111, 0b0000001) => {
-                        // REMU
-                        let rs1_value = reg!(rs1);
-                        let rs2_value = reg!(rs2);
-
-                        reg!(
-                            rd,
-                            if rs2_value == 0 {
-                                rs1_value
-                            } else {
-                                rs1_value % rs2_value
-                            }
-                        );
+                reg!(rd, value);
+            }
+            Op::And => reg!(rd, reg!(rs1) & reg!(rs2)),
+            Op::Remu => {
+                let rs1_value = reg!(rs1);
+                let rs2_value = reg!(rs2);
+
+                reg!(
+                    rd,
+                    if rs2_value == 0 {
+                        rs1_value
+                    } else {
+                        rs1_value % rs2_value
                     }
-                    _ => unimplemented!(),
-                }
+                );
+            }
+            Op::Sh1add => reg!(rd, (reg!(rs1) << 1).wrapping_add(reg!(rs2))),
+            Op::Sh2add => reg!(rd, (reg!(rs1) << 2).wrapping_add(reg!(rs2))),
+            Op::Sh3add => reg!(rd, (reg!(rs1) << 3).wrapping_add(reg!(rs2))),
+            Op::Andn => reg!(rd, reg!(rs1) & !reg!(rs2)),
+            Op::Orn => reg!(rd, reg!(rs1) | !reg!(rs2)),
+            Op::Xnor => reg!(rd, !(reg!(rs1) ^ reg!(rs2))),
+            Op::Clz => reg!(rd, reg!(rs1).leading_zeros()),
+            Op::Ctz => reg!(rd, reg!(rs1).trailing_zeros()),
+            Op::Cpop => reg!(rd, reg!(rs1).count_ones()),
+            Op::Max => reg!(rd, (reg!(rs1) as i32).max(reg!(rs2) as i32) as u32),
+            Op::Maxu => reg!(rd, reg!(rs1).max(reg!(rs2))),
+            Op::Min => reg!(rd, (reg!(rs1) as i32).min(reg!(rs2) as i32) as u32),
+            Op::Minu => reg!(rd, reg!(rs1).min(reg!(rs2))),
+            Op::SextB => reg!(rd, reg!(rs1) as i8 as u32),
+            Op::SextH => reg!(rd, reg!(rs1) as i16 as u32),
+            Op::ZextH => reg!(rd, reg!(rs1) & 0xffff),
+            Op::Rol => reg!(rd, reg!(rs1).rotate_left(reg!(rs2) & 0x1f)),
+            Op::Ror => reg!(rd, reg!(rs1).rotate_right(reg!(rs2) & 0x1f)),
+            Op::Rori => reg!(rd, reg!(rs1).rotate_right(imm)),
+            Op::OrcB => {
+                // 0以外のバイトを0xffに、0のバイトを0にする
+                let value = reg!(rs1)
+                    .to_le_bytes()
+                    .map(|b| if b == 0 { 0 } else { 0xff });
+
+                reg!(rd, u32::from_le_bytes(value));
             }
-            0b0101111 => {
+            Op::Rev8 => reg!(rd, reg!(rs1).swap_bytes()),
+            Op::Bclr => reg!(rd, reg!(rs1) & !(1 << (reg!(rs2) & 0x1f))),
+            Op::Bclri => reg!(rd, reg!(rs1) & !(1 << imm)),
+            Op::Bext => reg!(rd, (reg!(rs1) >> (reg!(rs2) & 0x1f)) & 1),
+            Op::Bexti => reg!(rd, (reg!(rs1) >> imm) & 1),
+            Op::Binv => reg!(rd, reg!(rs1) ^ (1 << (reg!(rs2) & 0x1f))),
+            Op::Binvi => reg!(rd, reg!(rs1) ^ (1 << imm)),
+            Op::Bset => reg!(rd, reg!(rs1) | (1 << (reg!(rs2) & 0x1f))),
+            Op::Bseti => reg!(rd, reg!(rs1) | (1 << imm)),
+            Op::LrW
+            | Op::ScW
+            | Op::AmoswapW
+            | Op::AmoaddW
+            | Op::AmoxorW
+            | Op::AmoandW
+            | Op::AmoorW
+            | Op::AmominW
+            | Op::AmomaxW
+            | Op::AmominuW
+            | Op::AmomaxuW => {
                 // AMO系命令
-                // hartは１つの想定なのでaq, rlは無視する。
+                // hartは1命令ずつ順番に実行されメモリアクセスは常に逐次一貫になるので、aq, rlは無視する。
                 let addr = reg!(rs1);
 
                 if addr % 4 != 0 {
@@ -627,20 +826,21 @@ impl Cpu {
                     return Err(Trap::StoreOrAMOAddressMisaligned);
                 }
 
-                let upper_funct7 = self.inst >> 27;
-
-                match (funct3, upper_funct7) {
-                    (0b010, 0b00010) => {
-                        let value = self.read_memory_u32(addr, bus)?;
+                match op {
+                    Op::LrW => {
+                        // 他のhartの書き込みで予約を取り消せるように物理アドレスでも予約する
+                        // 読み込んだアドレスと予約するアドレスが同じになるように変換は1回だけ行う
+                        let pa = self.translate_va(addr, AccessType::Read, bus)?;
+                        let value = self.read_translated(addr, pa, 4, bus)?;
 
                         reg!(rd, value);
                         self.reserved_addr = Some(addr);
-                    } // LR.W
-                    (0b010, 0b00011) => {
-                        // SC.W
-                        if let Some(reserved_addr) = self.reserved_addr
-                            && reserved_addr == addr
-                        {
+                        bus.reserve(self.hartid(), pa);
+                    }
+                    Op::ScW => {
+                        let is_reserved = self.reserved_addr == Some(addr);
+
+                        if bus.take_reservation(self.hartid()) && is_reserved {
                             self.write_memory_u32(addr, reg!(rs2), bus)?;
                             reg!(rd, 0);
                         } else {
@@ -652,17 +852,17 @@ impl Cpu {
                     _ => {
                         let original = self.read_memory_u32(addr, bus)?;
 
-                        let value = match (funct3, upper_funct7) {
-                            (0b010, 0b00000) => original.wrapping_add(reg!(rs2)), // AMOADD.W
-                            (0b010, 0b00001) => reg!(rs2),                        // AMOSWAP.W
-                            (0b010, 0b00100) => original ^ reg!(rs2),             // AMOOXOR.W
-                            (0b010, 0b01000) => original | reg!(rs2),             // AMOOOR.W
-                            (0b010, 0b01100) => original & reg!(rs2),             // AMOAND.W
-                            (0b010, 0b10000) => (original as i32).min(reg!(rs2) as i32) as u32, // AMOMIN.W
-                            (0b010, 0b10100) => (original as i32).max(reg!(rs2) as i32) as u32, // AMOMAX.W
-                            (0b010, 0b11000) => original.min(reg!(rs2)), // AMOMINU.W
-                            (0b010, 0b11100) => original.max(reg!(rs2)), // AMOMAXU.W
-                            _ => unimplemented!(),
+                        let value = match op {
+                            Op::AmoaddW => original.wrapping_add(reg!(rs2)),
+                            Op::AmoswapW => reg!(rs2),
+                            Op::AmoxorW => original ^ reg!(rs2),
+                            Op::AmoorW => original | reg!(rs2),
+                            Op::AmoandW => original & reg!(rs2),
+                            Op::AmominW => (original as i32).min(reg!(rs2) as i32) as u32,
+                            Op::AmomaxW => (original as i32).max(reg!(rs2) as i32) as u32,
+                            Op::AmominuW => original.min(reg!(rs2)),
+                            Op::AmomaxuW => original.max(reg!(rs2)),
+                            _ => unreachable!(),
                         };
 
                         reg!(rd, original);
@@ -670,21 +870,16 @@ impl Cpu {
                     }
                 }
             }
-            0b0110111 => reg!(rd, self.inst & 0xfffff000), // LUIll
-            0b1100011 => {
-                let imm = ((self.inst >> 19) & 0x1000)
-                    | ((self.inst << 4) & 0x800)
-                    | ((self.inst >> 20) & 0x7e0)
-                    | ((self.inst >> 7) & 0x1e);
-                let imm = (((imm << 19) as i32) >> 19) as u32;
-                let flag = match funct3 {
-                    0b000 => reg!(rs1) == reg!(rs2),               // BEQ
-                    0b001 => reg!(rs1) != reg!(rs2),               // BNE
-                    0b100 => reg!(rs2) as i32 > reg!(rs1) as i32,  //BLT
-                    0b101 => reg!(rs1) as i32 >= reg!(rs2) as i32, // BGE
-                    0b110 => reg!(rs2) > reg!(rs1),                // BLTU
-                    0b111 => reg!(rs1) >= reg!(rs2),               // BGEU
-                    _ => unimplemented!(),
+            Op::Lui => reg!(rd, imm),
+            Op::Beq | Op::Bne | Op::Blt | Op::Bge | Op::Bltu | Op::Bgeu => {
+                let flag = match op {
+                    Op::Beq => reg!(rs1) == reg!(rs2),
+                    Op::Bne => reg!(rs1) != reg!(rs2),
+                    Op::Blt => reg!(rs2) as i32 > reg!(rs1) as i32,
+                    Op::Bge => reg!(rs1) as i32 >= reg!(rs2) as i32,
+                    Op::Bltu => reg!(rs2) > reg!(rs1),
+                    Op::Bgeu => reg!(rs1) >= reg!(rs2),
+                    _ => unreachable!(),
                 };
 
                 if flag {
@@ -696,226 +891,250 @@ impl Cpu {
                     is_jump = true;
                 }
             }
-            0b1100111 => {
-                //JALR
-                if funct3 != 0 {
-                    // funct3の検証
-                    // これは検証すべきかはわからない。
-                    // tinyemuでは無視してた。
-                    unimplemented!();
-                }
-
-                let imm = (self.inst as i32) >> 20;
+            Op::Jalr => {
                 let pc = self.pc;
-                let next_pc = (imm as u32).wrapping_add(reg!(rs1)) & !1;
+                let next_pc = imm.wrapping_add(reg!(rs1)) & !1;
 
                 self.check_misaligned_addr(next_pc)?;
 
                 self.pc = next_pc;
 
-                reg!(rd, pc + 4);
+                reg!(rd, pc.wrapping_add(inst_len(self.inst)));
 
                 is_jump = true;
             }
-            0b1101111 => {
-                // JAL
-                let imm = ((self.inst >> (31 - 20)) & (1 << 20))
-                    | ((self.inst >> (21 - 1)) & 0x7fe)
-                    | ((self.inst >> (20 - 11)) & (1 << 11))
-                    | (self.inst & 0xff000);
-
-                let imm = ((imm << 11) as i32) >> 11;
+            Op::Jal => {
                 let pc = self.pc;
-                let next_pc = pc.wrapping_add(imm as u32);
+                let next_pc = pc.wrapping_add(imm);
 
                 self.check_misaligned_addr(next_pc)?;
 
                 self.pc = next_pc;
-                reg!(rd, pc + 4);
+                reg!(rd, pc.wrapping_add(inst_len(self.inst)));
 
                 is_jump = true;
             }
-            0b1110011 => {
-                //[todo] valueについてはもっと綺麗に描けるかも。
-                //リファクタ時にはアクセスの順序に注意する。
-                let csr = self.inst >> 20;
+            //[todo] valueについてはもっと綺麗に描けるかも。
+            //リファクタ時にはアクセスの順序に注意する。
+            Op::Csrrw => {
+                let value = if rd != 0 { self.read_csr(imm)? } else { 0 };
 
-                match funct3 {
-                    0b001 => {
-                        // CSRRW
-                        let value = if rd != 0 { self.read_csr(csr)? } else { 0 };
+                self.write_csr(imm, reg!(rs1))?;
 
-                        self.write_csr(csr, reg!(rs1))?;
+                reg!(rd, value);
+            }
+            Op::Csrrs => {
+                let value = self.read_csr(imm)?;
+                let rs1_value = reg!(rs1);
 
-                        reg!(rd, value);
-                    }
-                    0b010 => {
-                        // CSRRS
-                        let value = self.read_csr(csr)?;
-                        let rs1_value = reg!(rs1);
+                if rs1_value != 0 {
+                    self.write_csr(imm, value | rs1_value)?;
+                }
 
-                        if rs1_value != 0 {
-                            self.write_csr(csr, value | rs1_value)?;
-                        }
+                reg!(rd, value);
+            }
+            Op::Csrrc => {
+                let value = self.read_csr(imm)?;
+                let rs1_value = reg!(rs1);
 
-                        reg!(rd, value);
-                    }
-                    0b011 => {
-                        // CSRRC
-                        let value = self.read_csr(csr)?;
-                        let rs1_value = reg!(rs1);
+                if rs1_value != 0 {
+                    self.write_csr(imm, value & !rs1_value)?;
+                }
 
-                        if rs1_value != 0 {
-                            self.write_csr(csr, value & !rs1_value)?;
-                        }
+                reg!(rd, value);
+            }
+            Op::Csrrwi => {
+                // rs1の部分が即値になる
+                let value = if rd != 0 { self.read_csr(imm)? } else { 0 };
 
-                        reg!(rd, value);
-                    }
-                    0b101 => {
-                        // CSRRWI
-                        let value = if rd != 0 { self.read_csr(csr)? } else { 0 };
-                        let imm = rs1;
+                self.write_csr(imm, rs1)?;
 
-                        self.write_csr(csr, imm)?;
+                reg!(rd, value);
+            }
+            Op::Csrrsi => {
+                let value = self.read_csr(imm)?;
 
-                        reg!(rd, value);
-                    }
-                    0b110 => {
-                        // CSRRSI
-                        let value = self.read_csr(csr)?;
-                        let imm = rs1;
+                if rs1 != 0 {
+                    self.write_csr(imm, value | rs1)?;
+                }
 
-                        if imm != 0 {
-                            self.write_csr(csr, value | imm)?;
-                        }
+                reg!(rd, value);
+            }
+            Op::Csrrci => {
+                let value = self.read_csr(imm)?;
 
-                        reg!(rd, value);
-                    }
-                    0b111 => {
-                        // CSRRCI
-                        let value = self.read_csr(csr)?;
-                        let imm = rs1;
+                if rs1 != 0 {
+                    self.write_csr(imm, value & !rs1)?;
+                }
 
-                        if imm != 0 {
-                            self.write_csr(csr, value & !imm)?;
-                        }
+                reg!(rd, value);
+            }
+            Op::SfenceVma => {
+                if self.csr.is_enabled_mstatus_tvm() && self.prv == Priv::Supervisor {
+                    illegal!()
+                }
 
-                        reg!(rd, value);
-                    }
-                    _ => {
-                        let funct7 = self.inst >> 25;
-
-                        // [todo] リファクタリングをいつかする。
-                        match funct7 {
-                            0b0001001 => {
-                                // SFENCE.VMA
-                                if self.csr.is_enabled_mstatus_tvm() && self.prv == Priv::Supervisor
-                                {
-                                    illegal!()
-                                }
-
-                                self.tlb.clear();
-                            }
-                            _ => match self.inst {
-                                0x00000073 => {
-                                    // ECALL
-                                    match self.prv {
-                                        Priv::Supervisor => {
-                                            return Err(Trap::EnvCallFromSupervisor);
-                                        }
-                                        Priv::User => {
-                                            return Err(Trap::EnvCallFromUser);
-                                        }
-                                        Priv::Machine => return Err(Trap::EnvCallFromMachine),
-                                    }
-                                }
-                                0x00100073 => {
-                                    //EBREAK
-                                    return Err(Trap::BreakPoint);
-                                }
-                                0x10500073 => {
-                                    // WFI
-                                    if self.csr.is_enabled_mstatus_tw()
-                                        || self.csr.is_enabled_mstatus_tvm()
-                                    {
-                                        illegal!()
-                                    } else {
-                                        //loop {
-                                        //    if self.csr.is_interrupt_active() {
-                                        //        break;
-                                        //    }
-
-                                        //    panic!("{}", self);
-                                        //}
-                                    }
-                                }
-                                0x10200073 => {
-                                    // SRET
-                                    if self.prv == Priv::User || self.csr.is_enabled_mstatus_tsr() {
-                                        illegal!()
-                                    }
-
-                                    let spp = self.csr.handle_sret()?;
-
-                                    self.change_prv(spp.into());
-                                    self.pc = self.csr.sepc;
-                                    is_jump = true;
-                                }
-                                0x30200073 => {
-                                    // MRET
-
-                                    if self.prv != Priv::Machine {
-                                        illegal!();
-                                    }
-
-                                    let mpp = self.csr.handle_mret()?;
-
-                                    self.change_prv(mpp.into());
-                                    self.pc = self.csr.mepc;
-                                    is_jump = true;
-                                }
-                                _ => unimplemented!(),
-                            },
-                        }
-                    }
+                // rs1=x0の場合は全てのアドレス、rs2=x0の場合は全てのASIDが対象になる
+                let va = (rs1 != 0).then(|| reg!(rs1));
+                let asid = (rs2 != 0).then(|| reg!(rs2) & 0x1ff);
+
+                self.tlb.flush(va, asid);
+                self.icache.clear();
+            }
+            Op::Ecall => match self.prv {
+                Priv::Supervisor => return Err(Trap::EnvCallFromSupervisor),
+                Priv::User => return Err(Trap::EnvCallFromUser),
+                Priv::Machine => return Err(Trap::EnvCallFromMachine),
+            },
+            Op::Ebreak => return Err(Trap::BreakPoint),
+            Op::Wfi => {
+                // Uモードと、mstatus.TW=1の場合のSモードでは不正な命令になる
+                if self.prv == Priv::User
+                    || (self.prv == Priv::Supervisor && self.csr.is_enabled_mstatus_tw())
+                {
+                    illegal!()
+                }
+
+                self.is_waiting = true;
+            }
+            Op::Sret => {
+                if self.prv == Priv::User || self.csr.is_enabled_mstatus_tsr() {
+                    illegal!()
+                }
+
+                let spp = self.csr.handle_sret()?;
+
+                self.change_prv(spp.into());
+                self.pc = self.csr.sepc;
+                is_jump = true;
+            }
+            Op::Mret => {
+                if self.prv != Priv::Machine {
+                    illegal!();
                 }
+
+                let mpp = self.csr.handle_mret()?;
+
+                self.change_prv(mpp.into());
+                self.pc = self.csr.mepc;
+                is_jump = true;
             }
-            _ => unimplemented!(),
+            Op::Flw
+            | Op::Fsw
+            | Op::Fld
+            | Op::Fsd
+            | Op::Fmadd(_)
+            | Op::Fmsub(_)
+            | Op::Fnmsub(_)
+            | Op::Fnmadd(_)
+            | Op::Fadd(_)
+            | Op::Fsub(_)
+            | Op::Fmul(_)
+            | Op::Fdiv(_)
+            | Op::Fsqrt(_)
+            | Op::Fsgnj(_)
+            | Op::Fsgnjn(_)
+            | Op::Fsgnjx(_)
+            | Op::Fmin(_)
+            | Op::Fmax(_)
+            | Op::FcvtW(_)
+            | Op::FcvtWu(_)
+            | Op::FcvtFromW(_)
+            | Op::FcvtFromWu(_)
+            | Op::FcvtSD
+            | Op::FcvtDS
+            | Op::FmvXW
+            | Op::FmvWX
+            | Op::Feq(_)
+            | Op::Flt(_)
+            | Op::Fle(_)
+            | Op::Fclass(_) => self.execute_fp(decoded, bus)?,
+            Op::Illegal => illegal!(),
+            Op::Unimplemented => unimplemented!(),
         }
 
         Ok(is_jump)
     }
 
+    // RAM上の命令は命令キャッシュからデコード済みのものを取り出す
+    // pcは常に2バイト境界にあるのでフェッチ時にアライメントの例外は起こらない
     #[inline]
-    fn fetch(&mut self, bus: &mut Bus) -> Result<u32> {
-        let next_pc = self.translate_va(self.pc, AccessType::Fetch, bus)?;
+    fn fetch(&mut self, bus: &mut Bus) -> Result<Decoded> {
+        let pa = self.translate_va(self.pc, AccessType::Fetch, bus)?;
+
+        self.check_pmp(self.pc, pa, 2, AccessType::Fetch)?;
+
+        if let Some(decoded) = self.icache.fetch(pa, bus.memory()) {
+            // キャッシュされた命令はページをまたがないので後半は連続したアドレスにある
+            if inst_len(decoded.inst) == 4 {
+                self.check_pmp(
+                    self.pc.wrapping_add(2),
+                    pa.wrapping_add(2),
+                    2,
+                    AccessType::Fetch,
+                )?;
+            }
 
-        if next_pc % 4 == 0 {
-            let inst = bus.read(
-                next_pc,
-                4,
-                crate::bus::CpuContext {
-                    csr: &mut self.csr,
-                    is_walk: false,
-                    access_type: AccessType::Fetch,
-                },
-            )?;
+            return Ok(decoded);
+        }
 
-            Ok(inst)
-        } else {
-            Err(Trap::InstructionAddressMisaligned)
+        let low = self.fetch_u16(self.pc, pa, bus)?;
+
+        if inst_len(low) == 2 {
+            return Ok(decode(low));
         }
+
+        // 32bit命令がページをまたぐ場合は後半を別に変換する
+        // 後半で例外が起こった場合のtvalは後半のアドレスになる
+        let high_va = self.pc.wrapping_add(2);
+        let high_pa = if high_va.is_multiple_of(PAGESIZE) {
+            self.translate_va(high_va, AccessType::Fetch, bus)?
+        } else {
+            pa.wrapping_add(2)
+        };
+
+        self.check_pmp(high_va, high_pa, 2, AccessType::Fetch)?;
+
+        let high = self.fetch_u16(high_va, high_pa, bus)?;
+
+        Ok(decode(low | high << 16))
+    }
+
+    // アクセスフォルトの場合はvaをtvalにする
+    #[inline]
+    fn fetch_u16(&mut self, va: u32, pa: u32, bus: &mut Bus) -> Result<u32> {
+        bus.read(
+            pa,
+            2,
+            crate::bus::CpuContext {
+                csr: &mut self.csr,
+                is_walk: false,
+                access_type: AccessType::Fetch,
+            },
+        )
+        .inspect_err(|_| self.fault_addr = Some(va))
     }
 
     // [todo]: handle_{exception,intrrupt}をまとめてhandle_trapにする。
     //[todo] MMU実装時にself.csr.handle_trapに渡すvaを仮想アドレスを表すものに変更する。
     #[inline]
-    pub fn handle_trap(&mut self, e: Trap, bus: &mut Bus) {
-        let (next_pc, next_prv) = match e {
+    pub fn handle_trap(&mut self, e: Trap) {
+        let (epc, from_prv) = (self.pc, self.prv);
+
+        // 実装していない命令やCSRはゲストの不具合でも到達するので、ログを出力して不正命令例外にする
+        let e = match e {
             Trap::UnimplementedCSR | Trap::UnimplementedInstruction => {
-                eprintln!("{:?}", e);
-                panic!("{}", self);
+                eprintln!(
+                    "[WARNING]: hart {} {:?} at 0x{:08x} (inst 0x{:08x})",
+                    self.csr.mhartid, e, self.pc, self.inst
+                );
+
+                Trap::IlligalInstruction
             }
+            _ => e,
+        };
+
+        let (next_pc, next_prv) = match e {
             Trap::InstructionAddressMisaligned
             | Trap::LoadPageFault
             | Trap::StoreOrAMOPageFault
@@ -924,28 +1143,38 @@ impl Cpu {
                 self.fault_addr = None;
                 self.csr.handle_trap(self.prv, e, self.pc, fault_addr)
             }
-            Trap::IlligalInstruction => self.csr.handle_trap(self.prv, e, self.pc, self.inst),
-            Trap::SupervisorExternalInterrupt => {
-                self.prepare_external_interrupt(bus);
-                self.csr.handle_trap(self.prv, e, self.pc, 0)
+            Trap::InstructionAccessFault | Trap::LoadAccessFault | Trap::StoreOrAMOAccessFault => {
+                // バスやPMPでのアクセスフォルトではアクセスした仮想アドレスが記録されている
+                let fault_addr = self.fault_addr.take().unwrap_or(0);
+                self.csr.handle_trap(self.prv, e, self.pc, fault_addr)
             }
+            Trap::IlligalInstruction => self.csr.handle_trap(self.prv, e, self.pc, self.inst),
             _ => self.csr.handle_trap(self.prv, e, self.pc, 0),
         };
 
         self.pc = next_pc;
         self.change_prv(next_prv);
 
+        if let Some(tracer) = &mut self.tracer {
+            let is_ecall = matches!(
+                e,
+                Trap::EnvCallFromUser | Trap::EnvCallFromSupervisor | Trap::EnvCallFromMachine
+            );
+
+            let tval = (!e.is_interrupt() && !is_ecall).then_some(match next_prv {
+                Priv::Machine => self.csr.mtval,
+                _ => self.csr.stval,
+            });
+
+            tracer.trap(e, epc, from_prv, tval);
+        }
+
         //    if self.csr.time == 0x0d5e517f {
         //        panic!("break");
         //    }
         //}
     }
 
-    #[inline]
-    pub fn prepare_external_interrupt(&mut self, bus: &mut Bus) {
-        bus.prepare_interrupt();
-    }
-
     // 割り込みが起こっているか確認する関数
     // 起こっている場合は割り込みに対応するExceptionを返す。
     #[inline]
@@ -954,8 +1183,9 @@ impl Cpu {
     }
 
     #[inline]
+    // C拡張があるので命令は2バイト境界にあればよい
     pub fn check_misaligned_addr(&mut self, addr: u32) -> Result<()> {
-        if addr % 4 != 0 {
+        if !addr.is_multiple_of(2) {
             self.fault_addr = Some(addr);
             Err(Trap::InstructionAddressMisaligned)
         } else {
@@ -968,6 +1198,23 @@ impl Cpu {
         self.prv
     }
 
+    #[inline]
+    pub fn is_waiting(&self) -> bool {
+        self.is_waiting
+    }
+
+    // WFIから起きるべきかを判定する関数
+    // mstatusのMIE/SIEやmidelegに関わらずmieで有効な割り込みが保留されていれば起きる
+    #[inline]
+    pub fn has_pending_interrupt(&self) -> bool {
+        self.csr.mip & self.csr.mie != 0
+    }
+
+    #[inline]
+    pub fn wake(&mut self) {
+        self.is_waiting = false;
+    }
+
     #[inline]
     pub fn mut_csr(&mut self) -> &mut Csr {
         &mut self.csr
@@ -980,10 +1227,193 @@ impl Cpu {
 
     #[inline]
     pub fn progress_pc(&mut self) {
-        self.pc += 4;
+        self.pc = self.pc.wrapping_add(inst_len(self.inst));
+    }
+
+    pub fn pc(&self) -> u32 {
+        self.pc
     }
 
     pub fn set_pc(&mut self, pc: u32) {
         self.pc = pc;
     }
+
+    // a1にdtbのアドレスを設定する関数
+    pub fn set_dtb_addr(&mut self, addr: u32) {
+        self.write_reg(11, addr);
+    }
+
+    pub fn csr(&self) -> &Csr {
+        &self.csr
+    }
+
+    pub fn set_pmp_permissive(&mut self, is_permissive: bool) {
+        self.csr.pmp.set_permissive(is_permissive);
+    }
+
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) {
+        self.tracer = tracer;
+    }
+
+    pub fn flush_trace(&mut self) {
+        if let Some(tracer) = &mut self.tracer
+            && let Err(e) = tracer.flush()
+        {
+            eprintln!("[ERROR]: failed to write trace: {}", e);
+        }
+    }
+}
+
+// デバッガ向けの関数
+impl Cpu {
+    #[inline]
+    fn check_watchpoints(&mut self, addr: u32, size: u32, access_type: AccessType) {
+        if self.watchpoints.is_empty() {
+            return;
+        }
+
+        if let Some(w) = self
+            .watchpoints
+            .iter()
+            .find(|w| w.is_hit(addr, size, access_type))
+        {
+            self.watch_hit = Some((w.kind, addr));
+        }
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        if !self.watchpoints.contains(&watchpoint) {
+            self.watchpoints.push(watchpoint);
+        }
+    }
+
+    pub fn remove_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.retain(|w| *w != watchpoint);
+    }
+
+    // 直前の命令で当たったウォッチポイントの種類とアドレスを返す
+    pub fn take_watch_hit(&mut self) -> Option<(WatchKind, u32)> {
+        self.watch_hit.take()
+    }
+
+    // 仮想アドレスを変換する関数
+    // 通常の変換とは異なりTLBやA/Dビット、例外の状態を変更せず権限も確認しない
+    pub fn debug_translate(&self, va: u32, bus: &Bus) -> Option<u32> {
+        if !self.csr.is_paging_enabled() || self.prv == Priv::Machine {
+            return Some(va);
+        }
+
+        let vpn = va >> 12;
+        let mut addr = self.csr.get_satp_ppn().wrapping_mul(PAGESIZE);
+
+        for i in (0..2).rev() {
+            let pte_addr = addr + ((vpn >> (10 * i)) & 0x3ff) * PTESIZE;
+
+            let mut buf = [0; 4];
+
+            if !bus.debug_read(pte_addr, &mut buf) {
+                return None;
+            }
+
+            let pte = u32::from_le_bytes(buf);
+
+            if pte & PTE_V == 0 || (pte & PTE_R == 0 && pte & PTE_W != 0) {
+                return None;
+            }
+
+            if pte & (PTE_R | PTE_X) != 0 {
+                let ppn = if i == 1 {
+                    ((pte << 2) & 0xffc00000) | (va & 0x3ff000)
+                } else {
+                    (pte << 2) & 0xfffff000
+                };
+
+                return Some(ppn | (va & 0xfff));
+            }
+
+            addr = (pte >> 10).wrapping_mul(PAGESIZE);
+        }
+
+        None
+    }
+
+    // 仮想アドレスからbuf.len()バイト読み込む関数
+    pub fn debug_read_memory(&self, va: u32, buf: &mut [u8], bus: &Bus) -> bool {
+        for (i, byte) in buf.iter_mut().enumerate() {
+            let Some(pa) = self.debug_translate(va.wrapping_add(i as u32), bus) else {
+                return false;
+            };
+
+            if !bus.debug_read(pa, std::slice::from_mut(byte)) {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    // 仮想アドレスにdataを書き込む関数
+    pub fn debug_write_memory(&self, va: u32, data: &[u8], bus: &mut Bus) -> bool {
+        for (i, byte) in data.iter().enumerate() {
+            let Some(pa) = self.debug_translate(va.wrapping_add(i as u32), bus) else {
+                return false;
+            };
+
+            if !bus.debug_write(pa, std::slice::from_ref(byte)) {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+impl Snapshot for Cpu {
+    fn save(&self, w: &mut SnapshotWriter) {
+        for reg in self.regs.regs {
+            w.write_u32(reg);
+        }
+
+        for freg in self.fregs {
+            w.write_u64(freg);
+        }
+
+        w.write_u32(self.pc);
+        w.write_u32(self.prv as u32);
+        w.write_u32(self.inst);
+        w.write_option_u32(self.reserved_addr);
+        w.write_bool(self.is_waiting);
+
+        self.csr.save(w);
+    }
+
+    fn restore(&mut self, r: &mut SnapshotReader) -> snapshot::Result<()> {
+        for reg in self.regs.regs.iter_mut() {
+            *reg = r.read_u32()?;
+        }
+
+        if self.regs.regs[0] != 0 {
+            return Err(SnapshotError::InvalidValue("x0"));
+        }
+
+        for freg in self.fregs.iter_mut() {
+            *freg = r.read_u64()?;
+        }
+
+        self.pc = r.read_u32()?;
+        self.prv = r.read_priv()?;
+        self.inst = r.read_u32()?;
+        self.reserved_addr = r.read_option_u32()?;
+        self.is_waiting = r.read_bool()?;
+
+        self.csr.restore(r)?;
+
+        // 変換結果やデコード結果はsatpやメモリの内容に依存するので作り直す
+        self.tlb.clear();
+        self.icache.clear();
+        self.fault_addr = None;
+        self.watch_hit = None;
+
+        Ok(())
+    }
 }
@@ -0,0 +1,220 @@
+use crate::{
+    Result, Trap,
+    bus::Bus,
+    cpu::Cpu,
+    decode::{Decoded, Op},
+    illegal,
+    softfloat::{Format, RoundingMode, Softfloat},
+};
+
+// 丸めモードがdynの場合はfrmを使用する
+const RM_DYN: u32 = 0b111;
+
+// 単精度の値は上位32bitを全て1にしてレジスタに格納する(NaN-boxing)
+const NAN_BOX: u64 = 0xffffffff_00000000;
+
+// F/D拡張の命令を実行する
+impl Cpu {
+    pub(super) fn execute_fp(&mut self, decoded: Decoded, bus: &mut Bus) -> Result<()> {
+        // mstatus.FSがOffの場合は全ての浮動小数点命令が不正な命令になる
+        if !self.csr.is_fs_enabled() {
+            illegal!();
+        }
+
+        let Decoded {
+            op,
+            rd,
+            rs1,
+            rs2,
+            rs3,
+            imm,
+            ..
+        } = decoded;
+
+        match op {
+            Op::Flw => {
+                let value = self.read_memory_u32(self.read_reg(rs1).wrapping_add(imm), bus)?;
+                self.write_fp(rd, Format::Single, value as u64);
+            }
+            Op::Fld => {
+                let addr = self.read_reg(rs1).wrapping_add(imm);
+                let low = self.read_memory_u32(addr, bus)?;
+                let high = self.read_memory_u32(addr.wrapping_add(4), bus)?;
+
+                self.write_fp(rd, Format::Double, (high as u64) << 32 | low as u64);
+            }
+            Op::Fsw => {
+                // NaN-boxingされているかに関わらず下位32bitをそのまま書き込む
+                let addr = self.read_reg(rs1).wrapping_add(imm);
+                self.write_memory_u32(addr, self.fregs[rs2 as usize] as u32, bus)?;
+            }
+            Op::Fsd => {
+                let addr = self.read_reg(rs1).wrapping_add(imm);
+                let value = self.fregs[rs2 as usize];
+
+                self.write_memory_u32(addr, value as u32, bus)?;
+                self.write_memory_u32(addr.wrapping_add(4), (value >> 32) as u32, bus)?;
+
+                if let Some(tracer) = &mut self.tracer {
+                    tracer.write_memory(addr, value, 8);
+                }
+            }
+            Op::Fmadd(fmt) | Op::Fmsub(fmt) | Op::Fnmsub(fmt) | Op::Fnmadd(fmt) => {
+                let (negate_product, negate_c) = match op {
+                    Op::Fmadd(_) => (false, false),
+                    Op::Fmsub(_) => (false, true),
+                    Op::Fnmsub(_) => (true, false),
+                    _ => (true, true),
+                };
+
+                let (a, b, c) = (
+                    self.read_fp(rs1, fmt),
+                    self.read_fp(rs2, fmt),
+                    self.read_fp(rs3, fmt),
+                );
+
+                let mut sf = self.softfloat(imm)?;
+                let value = sf.fma(fmt, a, b, c, negate_product, negate_c);
+
+                self.write_fp_result(rd, fmt, value, &sf);
+            }
+            Op::Fadd(fmt) | Op::Fsub(fmt) | Op::Fmul(fmt) | Op::Fdiv(fmt) => {
+                let (a, b) = (self.read_fp(rs1, fmt), self.read_fp(rs2, fmt));
+
+                let mut sf = self.softfloat(imm)?;
+                let value = match op {
+                    Op::Fadd(_) => sf.add(fmt, a, b),
+                    Op::Fsub(_) => sf.sub(fmt, a, b),
+                    Op::Fmul(_) => sf.mul(fmt, a, b),
+                    _ => sf.div(fmt, a, b),
+                };
+
+                self.write_fp_result(rd, fmt, value, &sf);
+            }
+            Op::Fsqrt(fmt) => {
+                let a = self.read_fp(rs1, fmt);
+
+                let mut sf = self.softfloat(imm)?;
+                let value = sf.sqrt(fmt, a);
+
+                self.write_fp_result(rd, fmt, value, &sf);
+            }
+            Op::Fsgnj(fmt) | Op::Fsgnjn(fmt) | Op::Fsgnjx(fmt) => {
+                let (a, b) = (self.read_fp(rs1, fmt), self.read_fp(rs2, fmt));
+                let sign_bit = fmt.sign_bit();
+
+                let sign = match op {
+                    Op::Fsgnj(_) => b & sign_bit,
+                    Op::Fsgnjn(_) => !b & sign_bit,
+                    _ => (a ^ b) & sign_bit,
+                };
+
+                self.write_fp(rd, fmt, (a & !sign_bit) | sign);
+            }
+            Op::Fmin(fmt) | Op::Fmax(fmt) => {
+                let (a, b) = (self.read_fp(rs1, fmt), self.read_fp(rs2, fmt));
+
+                // 丸めモードを使用しない
+                let mut sf = Softfloat::new(RoundingMode::NearestEven);
+                let value = match op {
+                    Op::Fmin(_) => sf.min(fmt, a, b),
+                    _ => sf.max(fmt, a, b),
+                };
+
+                self.write_fp_result(rd, fmt, value, &sf);
+            }
+            Op::FcvtW(fmt) | Op::FcvtWu(fmt) => {
+                let a = self.read_fp(rs1, fmt);
+
+                let mut sf = self.softfloat(imm)?;
+                let value = sf.float_to_int(fmt, a, matches!(op, Op::FcvtW(_)));
+
+                self.csr.accrue_fflags(sf.flags());
+                self.write_reg(rd, value);
+            }
+            Op::FcvtFromW(fmt) | Op::FcvtFromWu(fmt) => {
+                let a = self.read_reg(rs1);
+
+                let mut sf = self.softfloat(imm)?;
+                let value = sf.int_to_float(fmt, a, matches!(op, Op::FcvtFromW(_)));
+
+                self.write_fp_result(rd, fmt, value, &sf);
+            }
+            Op::FcvtSD | Op::FcvtDS => {
+                let (from, to) = match op {
+                    Op::FcvtSD => (Format::Double, Format::Single),
+                    _ => (Format::Single, Format::Double),
+                };
+
+                let a = self.read_fp(rs1, from);
+
+                let mut sf = self.softfloat(imm)?;
+                let value = sf.convert(from, to, a);
+
+                self.write_fp_result(rd, to, value, &sf);
+            }
+            Op::FmvXW => self.write_reg(rd, self.fregs[rs1 as usize] as u32),
+            Op::FmvWX => self.write_fp(rd, Format::Single, self.read_reg(rs1) as u64),
+            Op::Feq(fmt) | Op::Flt(fmt) | Op::Fle(fmt) => {
+                let (a, b) = (self.read_fp(rs1, fmt), self.read_fp(rs2, fmt));
+
+                let mut sf = Softfloat::new(RoundingMode::NearestEven);
+                let value = match op {
+                    Op::Feq(_) => sf.eq(fmt, a, b),
+                    Op::Flt(_) => sf.lt(fmt, a, b),
+                    _ => sf.le(fmt, a, b),
+                };
+
+                self.csr.accrue_fflags(sf.flags());
+                self.write_reg(rd, value as u32);
+            }
+            Op::Fclass(fmt) => {
+                let value = fmt.classify(self.read_fp(rs1, fmt));
+                self.write_reg(rd, value);
+            }
+            _ => unreachable!(),
+        }
+
+        Ok(())
+    }
+
+    // 命令の丸めモードで計算するSoftfloatを作る
+    // 予約されている丸めモードの場合は不正な命令になる
+    fn softfloat(&self, rm: u32) -> Result<Softfloat> {
+        let rm = if rm == RM_DYN { self.csr.frm } else { rm };
+
+        match RoundingMode::from_bits(rm) {
+            Some(rm) => Ok(Softfloat::new(rm)),
+            None => illegal!(),
+        }
+    }
+
+    // 正しくNaN-boxingされていない単精度の値は正規化されたNaNとして扱う
+    #[inline]
+    fn read_fp(&self, reg: u32, fmt: Format) -> u64 {
+        let value = self.fregs[reg as usize];
+
+        match fmt {
+            Format::Single if value & NAN_BOX != NAN_BOX => Format::Single.canonical_nan(),
+            Format::Single => value & !NAN_BOX,
+            Format::Double => value,
+        }
+    }
+
+    #[inline]
+    fn write_fp(&mut self, reg: u32, fmt: Format, value: u64) {
+        let value = match fmt {
+            Format::Single => value | NAN_BOX,
+            Format::Double => value,
+        };
+
+        self.write_freg(reg, value);
+        self.csr.set_fs_dirty();
+    }
+
+    #[inline]
+    fn write_fp_result(&mut self, reg: u32, fmt: Format, value: u64, sf: &Softfloat) {
+        self.csr.accrue_fflags(sf.flags());
+        self.write_fp(reg, fmt, value);
+    }
+}
@@ -1,4 +1,8 @@
-use crate::{Priv, Result, Trap, illegal};
+use crate::{
+    Priv, Result, Trap, illegal,
+    pmp::Pmp,
+    snapshot::{self, Snapshot, SnapshotReader, SnapshotWriter},
+};
 
 // デバッグ用マクロ
 macro_rules! unimplemented {
@@ -22,7 +26,8 @@ const MIE: u32 = 0x304;
 const MTVEC: u32 = 0x305;
 
 const MEDELEG_SUPPORTED: u32 = 0xcbbff;
-const MIDELEG_SUPPORTED: u32 = IP_SSIP | IP_MSIP | IP_STIP | IP_MTIP | IP_MEIP | IP_SEIP;
+// Mモードの割り込みは委譲できない
+const MIDELEG_SUPPORTED: u32 = IP_SSIP | IP_STIP | IP_SEIP;
 
 const TVEC_MODE: u32 = 0x3;
 
@@ -37,19 +42,61 @@ const MENVCFGH: u32 = 0x31a;
 
 const MIP_SUPPORTED: u32 = IP_SEIP | IP_SSIP;
 
+// 同時に起こった割り込みの優先度順
+const INTERRUPT_PRIORITY: [Trap; 6] = [
+    Trap::MachineExternalInterrupt,
+    Trap::MachineSoftwareInterrupt,
+    Trap::MachineTimerInterrupt,
+    Trap::SupervisorExternalInterrupt,
+    Trap::SupervisorSoftwareInterrupt,
+    Trap::SupervisorTimerInterrupt,
+];
+
 const MISA_MXL_SUPPORTED: u32 = 0x1 << 30; // 32bit
 const MISA_A: u32 = 1 << ('A' as u32 - 'A' as u32);
+const MISA_B: u32 = 1 << ('B' as u32 - 'A' as u32);
+const MISA_C: u32 = 1 << ('C' as u32 - 'A' as u32);
+const MISA_D: u32 = 1 << ('D' as u32 - 'A' as u32);
+const MISA_F: u32 = 1 << ('F' as u32 - 'A' as u32);
 const MISA_I: u32 = 1 << ('I' as u32 - 'A' as u32);
 const MISA_M: u32 = 1 << ('M' as u32 - 'A' as u32);
 
 const MISA_U: u32 = 1 << ('U' as u32 - 'A' as u32);
 const MISA_S: u32 = 1 << ('S' as u32 - 'A' as u32);
 
-const MISA_SUPPORTED_VALUE: u32 = MISA_MXL_SUPPORTED | MISA_A | MISA_I | MISA_M | MISA_U | MISA_S;
+const MISA_SUPPORTED_VALUE: u32 = MISA_MXL_SUPPORTED
+    | MISA_A
+    | MISA_B
+    | MISA_C
+    | MISA_D
+    | MISA_F
+    | MISA_I
+    | MISA_M
+    | MISA_U
+    | MISA_S;
+
+// misaで表せない対応済みの拡張
+const ISA_Z_EXTENSIONS: [&str; 14] = [
+    "zicbom", "zicbop", "zicboz", "zicntr", "zicsr", "zifencei", "zmmul", "zaamo", "zalrsc", "zba",
+    "zbb", "zbs", "sstc", "svadu",
+];
+
+// デバイスツリー上のtimebase-frequency
+pub const TIMEBASE_FREQ: u32 = 10_000_000;
+
+// デバイスツリー上のriscv,cbom-block-sizeとriscv,cboz-block-size
+pub const CACHE_BLOCK_SIZE: u32 = 64;
 
 const MENVCFGH_POS: u64 = 32;
 const MENVCFG_FIOM: u32 = 1;
+// menvcfgとsenvcfgで共通のフィールド
+pub const ENVCFG_CBIE: u32 = 0x3 << 4;
+pub const ENVCFG_CBCFE: u32 = 1 << 6;
+pub const ENVCFG_CBZE: u32 = 1 << 7;
+const ENVCFG_CBIE_RESERVED: u32 = 0b10 << 4;
+const ENVCFG_SUPPORTED: u32 = MENVCFG_FIOM | ENVCFG_CBIE | ENVCFG_CBCFE | ENVCFG_CBZE;
 const MENVCFG_ADUE: u32 = 1 << 29;
+const MENVCFG_STCE: u32 = 1 << 31;
 
 #[allow(unused)]
 const MCYCLE: u32 = 0xb00;
@@ -60,6 +107,10 @@ const MHPMCOUNTER31: u32 = 0xb1f;
 const MHPMCOUNTER3H: u32 = 0xb83;
 const MHPMCOUNTER31H: u32 = 0xb9f;
 const MCOUNTINHIBIT: u32 = 0x320;
+const PMPCFG0: u32 = 0x3a0;
+const PMPCFG15: u32 = 0x3af;
+const PMPADDR0: u32 = 0x3b0;
+const PMPADDR63: u32 = 0x3ef;
 
 const MINSTRET_MASK: u64 = 0xffffffff;
 const MINSTRETH_POS: u64 = 31;
@@ -90,6 +141,7 @@ const STATUS_SPIE_POS: u32 = 5;
 const STATUS_MPIE_POS: u32 = 7;
 const STATUS_SPP_POS: u32 = 8;
 const STATUS_MPP_POS: u32 = 11;
+const STATUS_FS_POS: u32 = 13;
 
 const STATUS_SIE: u32 = 1 << STATUS_SIE_POS;
 const STATUS_MIE: u32 = 1 << STATUS_MIE_POS;
@@ -97,12 +149,16 @@ const STATUS_SPIE: u32 = 1 << STATUS_SPIE_POS;
 const STATUS_MPIE: u32 = 1 << STATUS_MPIE_POS;
 const STATUS_SPP: u32 = 1 << STATUS_SPP_POS;
 const STATUS_MPP: u32 = 0x3 << STATUS_MPP_POS;
+const STATUS_FS: u32 = 0x3 << STATUS_FS_POS;
 const STATUS_MPRV: u32 = 1 << 17;
 const STATUS_SUM: u32 = 1 << 18;
-const STATUS_MXR: u32 = 1 << 19; //[todo] implement when virtual address implemented
+const STATUS_MXR: u32 = 1 << 19;
 const STATUS_TVM: u32 = 1 << 20; //[todo] implement when supervisor mode implemented
-const STATUS_TW: u32 = 1 << 21; //[todo] implement when wfi instruction implemented
+const STATUS_TW: u32 = 1 << 21;
 const STATUS_TSR: u32 = 1 << 22; //[todo] implement when sret instruction implemented
+const STATUS_SD: u32 = 1 << 31; // 読み出し専用でFSから求める
+
+const FS_DIRTY: u32 = 0x3;
 
 const MSTATUS_SUPPORTED: u32 = STATUS_SIE
     | STATUS_MIE
@@ -110,10 +166,13 @@ const MSTATUS_SUPPORTED: u32 = STATUS_SIE
     | STATUS_MPIE
     | STATUS_SPP
     | STATUS_MPP
+    | STATUS_FS
     | STATUS_TVM
+    | STATUS_TW
     | STATUS_TSR
     | STATUS_MPRV
-    | STATUS_SUM;
+    | STATUS_SUM
+    | STATUS_MXR;
 
 const COUNTEREN_CY: u32 = 1;
 const COUNTEREN_TM: u32 = 1 << 1;
@@ -128,6 +187,7 @@ const SSTATUS: u32 = 0x100;
 const SIE: u32 = 0x104;
 const STVEC: u32 = 0x105;
 const SCOUNTEREN: u32 = 0x106;
+const SENVCFG: u32 = 0x10a;
 const SSCRATCH: u32 = 0x140;
 const SEPC: u32 = 0x141;
 const SCAUSE: u32 = 0x142;
@@ -139,13 +199,23 @@ const STIMECMPH: u32 = 0x15d;
 
 const STIMECMPH_POS: u32 = 32;
 
-const SATP_ASID: u32 = 0x1ff << 22;
+const SATP_ASID_POS: u32 = 22;
+const SATP_ASID: u32 = 0x1ff << SATP_ASID_POS;
 const SATP_PPN: u32 = 0x3fffff;
 
-const SSTATUS_SUPPORTED: u32 = STATUS_SIE | STATUS_SPIE | STATUS_SPP | STATUS_MXR | STATUS_SUM;
+const SSTATUS_SUPPORTED: u32 =
+    STATUS_SIE | STATUS_SPIE | STATUS_SPP | STATUS_FS | STATUS_MXR | STATUS_SUM;
 const SIE_SUPPORTED: u32 = IE_SSIE | IE_STIE | IE_SEIE;
 
 // Unprivileged
+const FFLAGS: u32 = 0x001;
+const FRM: u32 = 0x002;
+const FCSR: u32 = 0x003;
+
+const FFLAGS_MASK: u32 = 0x1f;
+const FRM_MASK: u32 = 0x7;
+const FCSR_FRM_POS: u32 = 5;
+
 const CYCLE: u32 = 0xc00;
 const CYCLEH: u32 = 0xc80;
 const TIME: u32 = 0xc01;
@@ -157,8 +227,125 @@ const CYCLEH_POS: u64 = 32;
 const TIMEH_POS: u64 = 32;
 const INSTRETH_POS: u64 = 32;
 
+// 実装しているCSRの名前の一覧
+// デバッガやトレースでCSRを名前で表示する際に使用する
+pub const CSR_NAMES: [(u32, &str); 64] = [
+    (FFLAGS, "fflags"),
+    (FRM, "frm"),
+    (FCSR, "fcsr"),
+    (SSTATUS, "sstatus"),
+    (SIE, "sie"),
+    (STVEC, "stvec"),
+    (SCOUNTEREN, "scounteren"),
+    (SENVCFG, "senvcfg"),
+    (SSCRATCH, "sscratch"),
+    (SEPC, "sepc"),
+    (SCAUSE, "scause"),
+    (STVAL, "stval"),
+    (SIP, "sip"),
+    (STIMECMP, "stimecmp"),
+    (STIMECMPH, "stimecmph"),
+    (SATP, "satp"),
+    (MSTATUS, "mstatus"),
+    (MISA, "misa"),
+    (MEDELEG, "medeleg"),
+    (MIDELEG, "mideleg"),
+    (MIE, "mie"),
+    (MTVEC, "mtvec"),
+    (MCOUNTEREN, "mcounteren"),
+    (MENVCFG, "menvcfg"),
+    (MSTATUSH, "mstatush"),
+    (MENVCFGH, "menvcfgh"),
+    (MCOUNTINHIBIT, "mcountinhibit"),
+    (MSCRATCH, "mscratch"),
+    (MEPC, "mepc"),
+    (MCAUSE, "mcause"),
+    (MTVAL, "mtval"),
+    (MIP, "mip"),
+    (PMPCFG0, "pmpcfg0"),
+    (PMPCFG0 + 1, "pmpcfg1"),
+    (PMPCFG0 + 2, "pmpcfg2"),
+    (PMPCFG0 + 3, "pmpcfg3"),
+    (PMPADDR0, "pmpaddr0"),
+    (PMPADDR0 + 1, "pmpaddr1"),
+    (PMPADDR0 + 2, "pmpaddr2"),
+    (PMPADDR0 + 3, "pmpaddr3"),
+    (PMPADDR0 + 4, "pmpaddr4"),
+    (PMPADDR0 + 5, "pmpaddr5"),
+    (PMPADDR0 + 6, "pmpaddr6"),
+    (PMPADDR0 + 7, "pmpaddr7"),
+    (PMPADDR0 + 8, "pmpaddr8"),
+    (PMPADDR0 + 9, "pmpaddr9"),
+    (PMPADDR0 + 10, "pmpaddr10"),
+    (PMPADDR0 + 11, "pmpaddr11"),
+    (PMPADDR0 + 12, "pmpaddr12"),
+    (PMPADDR0 + 13, "pmpaddr13"),
+    (PMPADDR0 + 14, "pmpaddr14"),
+    (PMPADDR0 + 15, "pmpaddr15"),
+    (MINSTRET, "minstret"),
+    (MINSTRETH, "minstreth"),
+    (CYCLE, "cycle"),
+    (TIME, "time"),
+    (INSTRET, "instret"),
+    (CYCLEH, "cycleh"),
+    (TIMEH, "timeh"),
+    (INSTRETH, "instreth"),
+    (MVENDORID, "mvendorid"),
+    (MARCHID, "marchid"),
+    (MIMPID, "mimpid"),
+    (MHARTID, "mhartid"),
+];
+
+// 逆アセンブル用のCSRの名前
+pub fn csr_name(csr: u32) -> Option<&'static str> {
+    CSR_NAMES
+        .iter()
+        .find(|(addr, _)| *addr == csr)
+        .map(|(_, name)| *name)
+}
+
+// menvcfgとsenvcfgに書き込む値を正規化する関数
+// CBIE=10は予約されているので00として扱う
+fn legalize_envcfg(value: u32) -> u32 {
+    let value = value & ENVCFG_SUPPORTED;
+
+    if value & ENVCFG_CBIE == ENVCFG_CBIE_RESERVED {
+        value & !ENVCFG_CBIE
+    } else {
+        value
+    }
+}
+
+// misaと対応済みの拡張からriscv,isa-extensionsの一覧を作る関数
+pub fn isa_extensions() -> Vec<String> {
+    let letters = "imafdqc"
+        .chars()
+        .filter(|c| MISA_SUPPORTED_VALUE & (1 << (*c as u32 - 'a' as u32)) != 0)
+        .map(String::from);
+
+    letters
+        .chain(ISA_Z_EXTENSIONS.iter().map(|s| s.to_string()))
+        .collect()
+}
+
+// riscv,isaの文字列(例: rv32ima_zicsr)を作る関数
+pub fn isa_string() -> String {
+    let mut isa = "rv32".to_string();
+
+    for extension in isa_extensions() {
+        if extension.len() > 1 {
+            isa.push('_');
+        }
+
+        isa.push_str(&extension);
+    }
+
+    isa
+}
+
 #[derive(Default, Debug)]
 pub struct Csr {
+    pub mhartid: u32,
     pub mstatus: u32,
     pub mscratch: u32,
     pub mtvec: u32,
@@ -175,12 +362,15 @@ pub struct Csr {
     pub mcountinhibit: u32,
     pub mtimecmp: u64,
 
+    pub pmp: Pmp,
+
     pub cycle: u64,
     pub instret: u64, // 64bitのinstret 0-31がminstretで32-63がminstreth
     pub time: u64,
 
     pub satp: u32,
     pub scounteren: u32,
+    pub senvcfg: u32,
     pub stvec: u32,
     pub scause: u32,
     pub stval: u32,
@@ -188,6 +378,9 @@ pub struct Csr {
     pub sscratch: u32,
     pub stimecmp: u64,
 
+    pub fflags: u32,
+    pub frm: u32,
+
     pub suppress_minsret: bool, // CSR命令でminsretが書き込まれた時にretireするときにincしないためのフラグ
 }
 
@@ -197,13 +390,13 @@ impl Csr {
         self.check_csr_access(csr, prv, false)?;
 
         match csr {
-            MHARTID => Ok(0),
+            MHARTID => Ok(self.mhartid),
             MISA => Ok(MISA_SUPPORTED_VALUE),
             MIMPID => Ok(1), //とりあえずバージョンは1
             MARCHID => Ok(1),
             MVENDORID => Ok(0),
 
-            MSTATUS => Ok(self.mstatus),
+            MSTATUS => Ok(self.mstatus | self.status_sd()),
             MCAUSE => Ok(self.mcause),
             MTVEC => Ok(self.mtvec),
             MIE => Ok(self.mie),
@@ -224,8 +417,9 @@ impl Csr {
             MCOUNTINHIBIT => Ok(self.mcountinhibit | MCOUNTINHIBIT_INITIAL),
 
             SCOUNTEREN => Ok(self.scounteren),
+            SENVCFG => Ok(self.senvcfg),
 
-            SSTATUS => Ok(self.mstatus & SSTATUS_SUPPORTED),
+            SSTATUS => Ok((self.mstatus & SSTATUS_SUPPORTED) | self.status_sd()),
             SEPC => Ok(self.sepc),
             SATP => {
                 // mstatus.TVM && Supervisorのときは例外を起こすべきらしいけどなぜかそれだとテストが通らなかったので仕様変更されている？
@@ -241,8 +435,19 @@ impl Csr {
             STVAL => Ok(self.stval),
             SIE => Ok(self.mie & SIE_SUPPORTED),
             SIP => Ok(self.mip & SIE_SUPPORTED),
-            STIMECMP => Ok(self.stimecmp as u32),
-            STIMECMPH => Ok((self.stimecmp >> STIMECMPH_POS) as u32),
+            STIMECMP | STIMECMPH => {
+                self.check_stimecmp_access(prv)?;
+
+                match csr {
+                    STIMECMP => Ok(self.stimecmp as u32),
+                    _ => Ok((self.stimecmp >> STIMECMPH_POS) as u32),
+                }
+            }
+
+            FFLAGS | FRM | FCSR if !self.is_fs_enabled() => illegal!(),
+            FFLAGS => Ok(self.fflags),
+            FRM => Ok(self.frm),
+            FCSR => Ok((self.frm << FCSR_FRM_POS) | self.fflags),
 
             CYCLE => {
                 self.chceck_cycle_access(prv)?;
@@ -278,9 +483,10 @@ impl Csr {
                 Ok((self.instret >> INSTRETH_POS) as u32)
             }
 
-            0x3b0 | 0x7a5 | 0x744 | 0x3a0 | 0xda0 | 0xfb0 | 0x30c | 0x10c | 0x321 | 0x7a0 => {
-                illegal!()
-            } // 未実装CSR
+            PMPCFG0..=PMPCFG15 => Ok(self.pmp.read_cfg((csr - PMPCFG0) as usize)),
+            PMPADDR0..=PMPADDR63 => Ok(self.pmp.read_addr((csr - PMPADDR0) as usize)),
+
+            0x7a5 | 0x744 | 0xda0 | 0xfb0 | 0x30c | 0x10c | 0x321 | 0x7a0 => illegal!(), // 未実装CSR
             _ => unimplemented!(),
         }
     }
@@ -294,8 +500,18 @@ impl Csr {
             MSTATUS => self.mstatus = value & MSTATUS_SUPPORTED,
             MTVEC => self.mtvec = 0xfffffffd & value,
             MIE => self.mie = value & MIE_SUPPORTED,
-            MIP => self.mip = value & MIP_SUPPORTED, // MEIP, MTIPの直接書き込みは無視する。
-            MEPC => self.mepc = value & !0x3,
+            // MEIP, MTIP, MSIPの直接書き込みは無視する。
+            // menvcfg.STCE=0の場合はSTIPをMモードのソフトウェアが設定する
+            MIP => {
+                let writable = if self.is_stce_enabled() {
+                    MIP_SUPPORTED
+                } else {
+                    MIP_SUPPORTED | IP_STIP
+                };
+
+                self.mip = (self.mip & !writable) | (value & writable);
+            }
+            MEPC => self.mepc = value & !0x1,
             MSCRATCH => self.mscratch = value,
             MCOUNTEREN => {
                 // 今のところはCYとTMのみサポートしているが必要である場合は追加する。
@@ -308,8 +524,13 @@ impl Csr {
             MTVAL => self.mtval = value,
             MEDELEG => self.medeleg = value & MEDELEG_SUPPORTED,
             MIDELEG => self.mideleg = value & MIDELEG_SUPPORTED,
-            MENVCFG => self.menvcfg = (self.menvcfg & 0xffff0000) | (value & MENVCFG_FIOM) as u64,
-            MENVCFGH => self.menvcfg = self.menvcfg | ((value & MENVCFG_ADUE) << 31) as u64,
+            MENVCFG => self.menvcfg = (self.menvcfg & !0xffffffff) | legalize_envcfg(value) as u64,
+            MENVCFGH => {
+                let value = value & (MENVCFG_ADUE | MENVCFG_STCE);
+
+                self.menvcfg = (self.menvcfg & 0xffffffff) | (value as u64) << MENVCFGH_POS;
+                self.update_timer_pending();
+            }
             MINSTRET => {
                 self.instret = (self.instret & !MINSTRET_MASK) | (value as u64);
 
@@ -336,7 +557,7 @@ impl Csr {
                 }
 
                 // ASID[8:7]=3 && BAREの場合はカスタムユースらしいが無視する。
-                self.satp = value & !SATP_ASID
+                self.satp = value
             }
             SCOUNTEREN => {
                 // 今のところはCYとTMのみサポートしているが必要である場合は追加する。
@@ -346,20 +567,17 @@ impl Csr {
 
                 self.scounteren = value & MCOUNTEREN_SUPPORTED;
             }
+            SENVCFG => self.senvcfg = legalize_envcfg(value),
 
             SSTATUS => {
-                if value & !SSTATUS_SUPPORTED != 0 {
+                if value & !(SSTATUS_SUPPORTED | STATUS_SD) != 0 {
                     unimplemented!();
                 }
 
-                if value & STATUS_MXR != 0 {
-                    panic!("[WARNING]: sstatus.MXR is not supported.");
-                }
-
                 self.mstatus = (self.mstatus & !SSTATUS_SUPPORTED) | (value & SSTATUS_SUPPORTED);
             }
 
-            SEPC => self.sepc = value & !0x3,
+            SEPC => self.sepc = value & !0x1,
             STVEC => self.stvec = 0xfffffffd & value,
             SSCRATCH => self.sscratch = value,
             SIE => self.mie = (self.mie & !SIE_SUPPORTED) | (value & SIE_SUPPORTED),
@@ -385,34 +603,38 @@ impl Csr {
                 }
             }
             STIMECMP => {
-                self.chceck_time_access(prv)?;
-
-                let stimecmp = (self.stimecmp & (0xffffffff << 32)) | (value as u64);
-
-                if stimecmp > self.time {
-                    self.mip = self.mip & !IP_STIP;
-                } else {
-                    self.mip = self.mip | IP_STIP;
-                }
+                self.check_stimecmp_access(prv)?;
 
-                self.stimecmp = stimecmp;
+                self.stimecmp = (self.stimecmp & (0xffffffff << 32)) | (value as u64);
+                self.update_timer_pending();
             }
 
             STIMECMPH => {
-                self.chceck_time_access(prv)?;
+                self.check_stimecmp_access(prv)?;
 
-                let stimecmp = (self.stimecmp & 0xffffffff) | ((value as u64) << STIMECMPH_POS);
-
-                if stimecmp > self.time {
-                    self.mip = self.mip & !IP_STIP;
-                } else {
-                    self.mip = self.mip | IP_STIP;
-                }
+                self.stimecmp = (self.stimecmp & 0xffffffff) | ((value as u64) << STIMECMPH_POS);
+                self.update_timer_pending();
+            }
 
-                self.stimecmp = stimecmp;
+            FFLAGS | FRM | FCSR if !self.is_fs_enabled() => illegal!(),
+            FFLAGS => {
+                self.fflags = value & FFLAGS_MASK;
+                self.set_fs_dirty();
+            }
+            FRM => {
+                self.frm = value & FRM_MASK;
+                self.set_fs_dirty();
             }
+            FCSR => {
+                self.fflags = value & FFLAGS_MASK;
+                self.frm = (value >> FCSR_FRM_POS) & FRM_MASK;
+                self.set_fs_dirty();
+            }
+
+            PMPCFG0..=PMPCFG15 => self.pmp.write_cfg((csr - PMPCFG0) as usize, value),
+            PMPADDR0..=PMPADDR63 => self.pmp.write_addr((csr - PMPADDR0) as usize, value),
 
-            0x3b0 | 0x7a5 | 0x744 | 0x3a0 => illegal!(), // 未実装CSR
+            0x7a5 | 0x744 => illegal!(), // 未実装CSR
             _ => unimplemented!(),
         }
 
@@ -472,6 +694,17 @@ impl Csr {
         illegal!();
     }
 
+    // menvcfg.STCE=0の場合とmcounteren.TM=0の場合はMモード以外からアクセスできない
+    #[inline]
+    fn check_stimecmp_access(&self, prv: Priv) -> Result<()> {
+        if prv != Priv::Machine && (!self.is_stce_enabled() || self.mcounteren & COUNTEREN_TM == 0)
+        {
+            illegal!();
+        }
+
+        Ok(())
+    }
+
     #[inline]
     fn chceck_instret_access(&self, prv: Priv) -> Result<()> {
         if prv == Priv::Machine {
@@ -497,13 +730,50 @@ impl Csr {
     #[inline]
     pub fn progress_time(&mut self) {
         self.time = self.time.wrapping_add(1);
+        self.update_timer_pending();
+    }
 
+    // ホストの時計に合わせてtimeを設定する関数
+    // timeが戻ることはない
+    #[inline]
+    pub fn set_time(&mut self, time: u64) {
+        self.time = self.time.max(time);
+        self.update_timer_pending();
+    }
+
+    // CLINTのmtimeへの書き込みでtimeを設定する関数
+    // set_timeと異なり時間を戻すこともできる
+    #[inline]
+    pub fn write_time(&mut self, time: u64) {
+        self.time = time;
+        self.update_timer_pending();
+    }
+
+    // 次にタイマー割り込みが起こるtimeを返す関数
+    // WFIで待っている間はこの値まで時間を進める
+    #[inline]
+    pub fn next_timer_event(&self) -> Option<u64> {
+        let stimecmp = self.is_stce_enabled().then_some(self.stimecmp);
+
+        [Some(self.mtimecmp), stimecmp]
+            .into_iter()
+            .flatten()
+            .filter(|cmp| *cmp > self.time)
+            .min()
+    }
+
+    #[inline]
+    fn update_timer_pending(&mut self) {
         if self.time >= self.mtimecmp {
             self.mip = self.mip | IP_MTIP;
         } else {
             self.mip = self.mip & !IP_MTIP;
         }
 
+        if !self.is_stce_enabled() {
+            return;
+        }
+
         if self.time >= self.stimecmp {
             self.mip = self.mip | IP_STIP;
         } else {
@@ -527,6 +797,11 @@ impl Csr {
         self.satp & SATP_PPN
     }
 
+    #[inline]
+    pub fn get_satp_asid(&self) -> u32 {
+        (self.satp & SATP_ASID) >> SATP_ASID_POS
+    }
+
     #[inline]
     pub fn get_mstatus_mpp(&self) -> u32 {
         (self.mstatus & STATUS_MPP) >> STATUS_MPP_POS
@@ -552,30 +827,17 @@ impl Csr {
         self.mip = (self.mip & !IP_SEIP) | ((seip & 0x1) << IP_SEIP_POS);
     }
 
+    // SSWIへの書き込みでmip.SSIPを立てる関数
     #[inline]
-    pub fn set_mtimecmp(&mut self, mtimecmp: u32) {
-        let mtimecmp = (self.mtimecmp & (0xffffffff << 32)) | (mtimecmp as u64);
-
-        if mtimecmp > self.time {
-            self.mip = self.mip & !IP_MTIP;
-        } else {
-            self.mip = self.mip | IP_MTIP;
-        }
-
-        self.mtimecmp = mtimecmp;
+    pub fn set_mip_ssip(&mut self) {
+        self.mip |= IP_SSIP;
     }
 
+    // CLINTのmtimecmpの値を設定する関数
     #[inline]
-    pub fn set_mtimecmph(&mut self, mtimecmph: u32) {
-        let mtimecmp = (self.mtimecmp & 0xffffffff) | ((mtimecmph as u64) << 32);
-
-        if mtimecmp > self.time {
-            self.mip = self.mip & !IP_MTIP;
-        } else {
-            self.mip = self.mip & IP_MTIP;
-        }
-
+    pub fn set_mtimecmp(&mut self, mtimecmp: u64) {
         self.mtimecmp = mtimecmp;
+        self.update_timer_pending();
     }
 
     // mstatus.TWが有効かどうかを判定する関数
@@ -601,19 +863,77 @@ impl Csr {
         self.mstatus & STATUS_SUM != 0
     }
 
+    // mstatus.MXRが有効な場合は実行のみ可能なページも読み込める
+    #[inline]
+    pub fn is_enabled_mstatus_mxr(&self) -> bool {
+        self.mstatus & STATUS_MXR != 0
+    }
+
     #[inline]
     pub fn is_enabled_mstatus_tsr(&self) -> bool {
         self.mstatus & STATUS_TSR != 0
     }
 
+    // mstatus.FSがOffでないかを判定する関数
+    // Offの場合は浮動小数点命令とfcsrへのアクセスがillegal-instructionになる
+    #[inline]
+    pub fn is_fs_enabled(&self) -> bool {
+        self.mstatus & STATUS_FS != 0
+    }
+
+    // 浮動小数点の状態を変更した時に呼ぶ関数
+    #[inline]
+    pub fn set_fs_dirty(&mut self) {
+        self.mstatus |= FS_DIRTY << STATUS_FS_POS;
+    }
+
+    // 浮動小数点命令で発生した例外フラグを加える関数
+    #[inline]
+    pub fn accrue_fflags(&mut self, flags: u32) {
+        if flags != 0 {
+            self.fflags |= flags;
+            self.set_fs_dirty();
+        }
+    }
+
+    #[inline]
+    fn status_sd(&self) -> u32 {
+        if (self.mstatus & STATUS_FS) >> STATUS_FS_POS == FS_DIRTY {
+            STATUS_SD
+        } else {
+            0
+        }
+    }
+
     #[inline]
     pub fn is_paging_enabled(&self) -> bool {
         self.satp >> 31 == 1
     }
 
+    // menvcfg.ADUE=1の場合はページテーブルウォーカーがA/Dビットを更新する
+    #[inline]
+    pub fn is_adue_enabled(&self) -> bool {
+        (self.menvcfg >> MENVCFGH_POS) & MENVCFG_ADUE as u64 != 0
+    }
+
+    // Zicbom/Zicbozの命令が許可されているかを確認する関数
+    // Mモード以外ではmenvcfg、Uモードではsenvcfgでもfieldが0以外である必要がある
+    pub fn check_cbo_access(&self, field: u32, prv: Priv) -> Result<()> {
+        let menvcfg = self.menvcfg as u32;
+
+        if (prv != Priv::Machine && menvcfg & field == 0)
+            || (prv == Priv::User && self.senvcfg & field == 0)
+        {
+            illegal!();
+        }
+
+        Ok(())
+    }
+
+    // menvcfg.STCE=1の場合はstimecmpでSTIPが決まる
     #[inline]
-    pub fn is_svadu_enabled(&self) -> bool {
-        (self.menvcfg >> MENVCFGH_POS) & MENVCFG_ADUE as u64 == 0
+    pub fn is_stce_enabled(&self) -> bool {
+        (self.menvcfg >> MENVCFGH_POS) & MENVCFG_STCE as u64 != 0
     }
 
     // トラップを処理する関数
@@ -647,7 +967,7 @@ impl Csr {
                 | (mie << STATUS_MPIE_POS)
                 | ((from_prv as u32) << STATUS_MPP_POS);
 
-            self.mepc = va & !0x3;
+            self.mepc = va & !0x1;
 
             if is_interrupt && self.mtvec & TVEC_MODE != 0 {
                 ((self.mtvec & !TVEC_MODE) + cause * 4, Priv::Machine)
@@ -674,7 +994,7 @@ impl Csr {
                 | (0 << STATUS_SIE)
                 | (spp << STATUS_SPP_POS);
 
-            self.sepc = va & !0x3;
+            self.sepc = va & !0x1;
 
             if is_interrupt && self.stvec & TVEC_MODE != 0 {
                 ((self.stvec & !TVEC_MODE) + cause * 4, Priv::Supervisor)
@@ -684,76 +1004,42 @@ impl Csr {
         }
     }
 
+    // 起こすことのできる割り込みのうち最も優先度の高いものを返す関数
+    // 行き先の権限が高い割り込みから順に、同じ権限の中ではINTERRUPT_PRIORITYの順に処理する
     #[inline]
-    pub fn can_external_interrupt(&self, from_prv: Priv) -> bool {
-        match from_prv {
-            Priv::Machine => {
-                if self.mstatus & STATUS_MIE == 0 {
-                    return false;
-                }
-            }
-            Priv::Supervisor => {
-                if self.mideleg & IP_SEIP != 0 {
-                    if self.mstatus & STATUS_SIE == 0 {
-                        return false;
-                    }
-                }
-            }
-            Priv::User => {}
-        }
-
-        true
-    }
-
-    // [todo]: 複数割り込みの順番の実装
-    #[inline]
-    pub fn resolve_pending(&mut self, from_prv: Priv) -> Option<Trap> {
+    pub fn resolve_pending(&self, from_prv: Priv) -> Option<Trap> {
         let active_bit = self.mip & self.mie;
 
         if active_bit == 0 {
             return None;
         }
 
-        let active_bit = {
-            match from_prv {
-                Priv::Machine => {
-                    if self.mstatus & STATUS_MIE == 0 {
-                        return None;
-                    }
-                }
-                Priv::Supervisor => {
-                    if active_bit & self.mideleg != 0 {
-                        // 委譲
-                        if self.mstatus & STATUS_SIE == 0 {
-                            return None;
-                        }
-                    }
-                }
-                Priv::User => {}
-            }
-            active_bit
-        };
+        // Mモードへの割り込みはMモード未満の場合かmstatus.MIE=1の場合に起こる
+        let is_m_enabled = from_prv != Priv::Machine || self.mstatus & STATUS_MIE != 0;
 
-        if active_bit == 0 {
-            return None;
-        }
-
-        if active_bit & 0x200 != 0 {
-            return Some(Trap::SupervisorExternalInterrupt);
-        }
-
-        if active_bit & 0x2 != 0 {
-            return Some(Trap::SupervisorSoftwareInterrupt);
-        }
+        // Sモードに委譲された割り込みはUモードの場合かSモードでmstatus.SIE=1の場合に起こる
+        let is_s_enabled = match from_prv {
+            Priv::Machine => false,
+            Priv::Supervisor => self.mstatus & STATUS_SIE != 0,
+            Priv::User => true,
+        };
 
-        if active_bit & 0x20 != 0 {
-            return Some(Trap::SupervisorTimerInterrupt);
-        }
+        let m_pending = if is_m_enabled {
+            active_bit & !self.mideleg
+        } else {
+            0
+        };
+        let s_pending = if is_s_enabled {
+            active_bit & self.mideleg
+        } else {
+            0
+        };
 
-        panic!(
-            "[ERROR]: Unknown or invalid interrupt({}) occured.",
-            active_bit
-        );
+        [m_pending, s_pending].into_iter().find_map(|pending| {
+            INTERRUPT_PRIORITY
+                .into_iter()
+                .find(|e| pending & (1 << e.cause()) != 0)
+        })
     }
 
     // mrmetのCSRでの処理を行う関数
@@ -794,3 +1080,79 @@ impl Csr {
         Ok(spp)
     }
 }
+
+impl Snapshot for Csr {
+    fn save(&self, w: &mut SnapshotWriter) {
+        w.write_u32(self.mstatus);
+        w.write_u32(self.mscratch);
+        w.write_u32(self.mtvec);
+        w.write_u32(self.mie);
+        w.write_u32(self.mip);
+        w.write_u32(self.mepc);
+        w.write_u32(self.mtval);
+        w.write_u32(self.mcause);
+        w.write_u32(self.medeleg);
+        w.write_u32(self.mideleg);
+        w.write_u32(self.mcounteren);
+        w.write_u32(self.mcountinhibit);
+        w.write_u32(self.satp);
+        w.write_u32(self.scounteren);
+        w.write_u32(self.senvcfg);
+        w.write_u32(self.stvec);
+        w.write_u32(self.scause);
+        w.write_u32(self.stval);
+        w.write_u32(self.sepc);
+        w.write_u32(self.sscratch);
+        w.write_u32(self.fflags);
+        w.write_u32(self.frm);
+
+        w.write_u64(self.menvcfg);
+        w.write_u64(self.mtimecmp);
+        w.write_u64(self.cycle);
+        w.write_u64(self.instret);
+        w.write_u64(self.time);
+        w.write_u64(self.stimecmp);
+
+        self.pmp.save(w);
+
+        w.write_bool(self.suppress_minsret);
+    }
+
+    fn restore(&mut self, r: &mut SnapshotReader) -> snapshot::Result<()> {
+        self.mstatus = r.read_u32()?;
+        self.mscratch = r.read_u32()?;
+        self.mtvec = r.read_u32()?;
+        self.mie = r.read_u32()?;
+        self.mip = r.read_u32()?;
+        self.mepc = r.read_u32()?;
+        self.mtval = r.read_u32()?;
+        self.mcause = r.read_u32()?;
+        self.medeleg = r.read_u32()?;
+        self.mideleg = r.read_u32()?;
+        self.mcounteren = r.read_u32()?;
+        self.mcountinhibit = r.read_u32()?;
+        self.satp = r.read_u32()?;
+        self.scounteren = r.read_u32()?;
+        self.senvcfg = r.read_u32()?;
+        self.stvec = r.read_u32()?;
+        self.scause = r.read_u32()?;
+        self.stval = r.read_u32()?;
+        self.sepc = r.read_u32()?;
+        self.sscratch = r.read_u32()?;
+        self.fflags = r.read_u32()? & FFLAGS_MASK;
+        self.frm = r.read_u32()? & FRM_MASK;
+
+        self.menvcfg = r.read_u64()?;
+        self.mtimecmp = r.read_u64()?;
+        self.cycle = r.read_u64()?;
+        self.instret = r.read_u64()?;
+        self.time = r.read_u64()?;
+        self.stimecmp = r.read_u64()?;
+
+        self.pmp.restore(r)?;
+
+        self.suppress_minsret = r.read_bool()?;
+
+        Ok(())
+    }
+}
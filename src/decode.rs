@@ -0,0 +1,680 @@
+use crate::softfloat::Format;
+
+// 命令をデコードした結果
+// 命令キャッシュに保存しておき、同じ命令を何度もデコードしないようにする
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Lb,
+    Lh,
+    Lw,
+    Lbu,
+    Lhu,
+    Fence,
+    FenceI,
+    CboInval,
+    CboClean,
+    CboFlush,
+    CboZero,
+    Addi,
+    Slli,
+    Slti,
+    Sltiu,
+    Xori,
+    Srli,
+    Srai,
+    Ori,
+    Andi,
+    Auipc,
+    Sb,
+    Sh,
+    Sw,
+    Add,
+    Sub,
+    Sll,
+    Slt,
+    Sltu,
+    Xor,
+    Srl,
+    Sra,
+    Or,
+    And,
+    Mul,
+    Mulh,
+    Mulhsu,
+    Mulhu,
+    Div,
+    Divu,
+    Rem,
+    Remu,
+    LrW,
+    ScW,
+    AmoswapW,
+    AmoaddW,
+    AmoxorW,
+    AmoandW,
+    AmoorW,
+    AmominW,
+    AmomaxW,
+    AmominuW,
+    AmomaxuW,
+    // Zba拡張
+    Sh1add,
+    Sh2add,
+    Sh3add,
+    // Zbb拡張
+    Andn,
+    Orn,
+    Xnor,
+    Clz,
+    Ctz,
+    Cpop,
+    Max,
+    Maxu,
+    Min,
+    Minu,
+    SextB,
+    SextH,
+    ZextH,
+    Rol,
+    Ror,
+    Rori,
+    OrcB,
+    Rev8,
+    // Zbs拡張
+    Bclr,
+    Bclri,
+    Bext,
+    Bexti,
+    Binv,
+    Binvi,
+    Bset,
+    Bseti,
+    Lui,
+    Beq,
+    Bne,
+    Blt,
+    Bge,
+    Bltu,
+    Bgeu,
+    Jalr,
+    Jal,
+    Csrrw,
+    Csrrs,
+    Csrrc,
+    Csrrwi,
+    Csrrsi,
+    Csrrci,
+    Ecall,
+    Ebreak,
+    Wfi,
+    Sret,
+    Mret,
+    SfenceVma,
+    Flw,
+    Fsw,
+    Fld,
+    Fsd,
+    Fmadd(Format),
+    Fmsub(Format),
+    Fnmsub(Format),
+    Fnmadd(Format),
+    Fadd(Format),
+    Fsub(Format),
+    Fmul(Format),
+    Fdiv(Format),
+    Fsqrt(Format),
+    Fsgnj(Format),
+    Fsgnjn(Format),
+    Fsgnjx(Format),
+    Fmin(Format),
+    Fmax(Format),
+    FcvtW(Format),  // fcvt.w.s, fcvt.w.d
+    FcvtWu(Format), // fcvt.wu.s, fcvt.wu.d
+    FcvtFromW(Format),
+    FcvtFromWu(Format),
+    FcvtSD,
+    FcvtDS,
+    FmvXW,
+    FmvWX,
+    Feq(Format),
+    Flt(Format),
+    Fle(Format),
+    Fclass(Format),
+    Illegal,
+    Unimplemented,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Decoded {
+    pub op: Op,
+    pub rd: u32,
+    pub rs1: u32,
+    pub rs2: u32,
+    pub rs3: u32,
+    // 符号拡張済みの即値
+    // CSR命令の場合はCSRの番号、浮動小数点の演算命令の場合は丸めモード
+    pub imm: u32,
+    pub inst: u32,
+}
+
+#[inline]
+fn imm_i(inst: u32) -> u32 {
+    ((inst as i32) >> 20) as u32
+}
+
+#[inline]
+fn imm_s(inst: u32) -> u32 {
+    let imm = ((inst >> (25 - 5)) & 0xfe0) | ((inst >> 7) & 0x1f);
+    (((imm << 20) as i32) >> 20) as u32
+}
+
+#[inline]
+fn imm_b(inst: u32) -> u32 {
+    let imm = ((inst >> 19) & 0x1000)
+        | ((inst << 4) & 0x800)
+        | ((inst >> 20) & 0x7e0)
+        | ((inst >> 7) & 0x1e);
+    (((imm << 19) as i32) >> 19) as u32
+}
+
+#[inline]
+fn imm_j(inst: u32) -> u32 {
+    let imm = ((inst >> (31 - 20)) & (1 << 20))
+        | ((inst >> (21 - 1)) & 0x7fe)
+        | ((inst >> (20 - 11)) & (1 << 11))
+        | (inst & 0xff000);
+    (((imm << 11) as i32) >> 11) as u32
+}
+
+// 命令長を返す関数
+// 下位2bitが0b11以外の場合は圧縮命令
+#[inline]
+pub fn inst_len(inst: u32) -> u32 {
+    if inst & 0x3 == 0x3 { 4 } else { 2 }
+}
+
+pub fn decode(inst: u32) -> Decoded {
+    if inst_len(inst) == 2 {
+        return decode_compressed(inst & 0xffff);
+    }
+
+    let opcode = inst & 0x7f;
+    let funct3 = (inst >> 12) & 0x7;
+    let funct7 = inst >> 25;
+
+    let (op, imm) = match opcode {
+        0b0000011 => {
+            let op = match funct3 {
+                0b000 => Op::Lb,
+                0b001 => Op::Lh,
+                0b010 => Op::Lw,
+                0b100 => Op::Lbu,
+                0b101 => Op::Lhu,
+                _ => Op::Unimplemented,
+            };
+
+            (op, imm_i(inst))
+        }
+        0b0000111 => {
+            let op = match funct3 {
+                0b010 => Op::Flw,
+                0b011 => Op::Fld,
+                _ => Op::Illegal,
+            };
+
+            (op, imm_i(inst))
+        }
+        0b0001111 => {
+            let op = match funct3 {
+                0 => match inst {
+                    0x8330000f => Op::Illegal, // FENCE.TSO
+                    _ => Op::Fence,            // PAUSE Zinhintpause拡張もここに含まれる
+                },
+                1 => Op::FenceI,
+                // Zicbom, Zicboz拡張 rdは0である必要がある
+                2 if (inst >> 7) & 0x1f == 0 => match inst >> 20 {
+                    0 => Op::CboInval,
+                    1 => Op::CboClean,
+                    2 => Op::CboFlush,
+                    4 => Op::CboZero,
+                    _ => Op::Illegal,
+                },
+                _ => Op::Illegal,
+            };
+
+            (op, 0)
+        }
+        0b0010011 => {
+            let op = match funct3 {
+                0b000 => Op::Addi,
+                0b001 => match funct7 {
+                    0b0000000 => Op::Slli,
+                    0b0110000 => match (inst >> 20) & 0x1f {
+                        0 => Op::Clz,
+                        1 => Op::Ctz,
+                        2 => Op::Cpop,
+                        4 => Op::SextB,
+                        5 => Op::SextH,
+                        _ => Op::Illegal,
+                    },
+                    0b0100100 => Op::Bclri,
+                    0b0110100 => Op::Binvi,
+                    0b0010100 => Op::Bseti,
+                    _ => Op::Illegal,
+                },
+                0b010 => Op::Slti,
+                0b011 => Op::Sltiu,
+                0b100 => Op::Xori,
+                0b101 => match (funct7, inst >> 20) {
+                    (_, 0x287) => Op::OrcB,
+                    (_, 0x698) => Op::Rev8,
+                    (0b0000000, _) => Op::Srli,
+                    (0b0100000, _) => Op::Srai,
+                    (0b0110000, _) => Op::Rori,
+                    (0b0100100, _) => Op::Bexti,
+                    _ => Op::Illegal,
+                },
+                0b110 => Op::Ori,
+                0b111 => Op::Andi,
+                _ => unreachable!(),
+            };
+
+            let imm = match op {
+                Op::Slli
+                | Op::Srli
+                | Op::Srai
+                | Op::Rori
+                | Op::Bclri
+                | Op::Bexti
+                | Op::Binvi
+                | Op::Bseti => (inst >> 20) & 0x1f,
+                _ => imm_i(inst),
+            };
+
+            (op, imm)
+        }
+        0b0010111 => (Op::Auipc, inst & 0xfffff000),
+        0b0100011 => {
+            let op = match funct3 {
+                0b000 => Op::Sb,
+                0b001 => Op::Sh,
+                0b010 => Op::Sw,
+                _ => Op::Unimplemented,
+            };
+
+            (op, imm_s(inst))
+        }
+        0b0100111 => {
+            let op = match funct3 {
+                0b010 => Op::Fsw,
+                0b011 => Op::Fsd,
+                _ => Op::Illegal,
+            };
+
+            (op, imm_s(inst))
+        }
+        0b0110011 => {
+            let op = match (funct3, funct7) {
+                (0b000, 0b0000000) => Op::Add,
+                (0b000, 0b0000001) => Op::Mul,
+                (0b000, 0b0100000) => Op::Sub,
+                (0b001, 0b0000000) => Op::Sll,
+                (0b001, 0b0000001) => Op::Mulh,
+                (0b010, 0b0000000) => Op::Slt,
+                (0b010, 0b0000001) => Op::Mulhsu,
+                (0b011, 0b0000000) => Op::Sltu,
+                (0b011, 0b0000001) => Op::Mulhu,
+                (0b100, 0b0000000) => Op::Xor,
+                (0b100, 0b0000001) => Op::Div,
+                (0b101, 0b0000000) => Op::Srl,
+                (0b101, 0b0000001) => Op::Divu,
+                (0b101, 0b0100000) => Op::Sra,
+                (0b110, 0b0000000) => Op::Or,
+                (0b110, 0b0000001) => Op::Rem,
+                (0b111, 0b0000000) => Op::And,
+                (0b111, 0b0000001) => Op::Remu,
+                (0b010, 0b0010000) => Op::Sh1add,
+                (0b100, 0b0010000) => Op::Sh2add,
+                (0b110, 0b0010000) => Op::Sh3add,
+                (0b111, 0b0100000) => Op::Andn,
+                (0b110, 0b0100000) => Op::Orn,
+                (0b100, 0b0100000) => Op::Xnor,
+                (0b100, 0b0000101) => Op::Min,
+                (0b101, 0b0000101) => Op::Minu,
+                (0b110, 0b0000101) => Op::Max,
+                (0b111, 0b0000101) => Op::Maxu,
+                (0b100, 0b0000100) if (inst >> 20) & 0x1f == 0 => Op::ZextH,
+                (0b001, 0b0110000) => Op::Rol,
+                (0b101, 0b0110000) => Op::Ror,
+                (0b001, 0b0100100) => Op::Bclr,
+                (0b101, 0b0100100) => Op::Bext,
+                (0b001, 0b0110100) => Op::Binv,
+                (0b001, 0b0010100) => Op::Bset,
+                _ => Op::Illegal,
+            };
+
+            (op, 0)
+        }
+        0b0101111 => {
+            // aq, rlは無視する
+            let op = match (funct3, funct7 >> 2) {
+                (0b010, 0b00010) => Op::LrW,
+                (0b010, 0b00011) => Op::ScW,
+                (0b010, 0b00000) => Op::AmoaddW,
+                (0b010, 0b00001) => Op::AmoswapW,
+                (0b010, 0b00100) => Op::AmoxorW,
+                (0b010, 0b01000) => Op::AmoorW,
+                (0b010, 0b01100) => Op::AmoandW,
+                (0b010, 0b10000) => Op::AmominW,
+                (0b010, 0b10100) => Op::AmomaxW,
+                (0b010, 0b11000) => Op::AmominuW,
+                (0b010, 0b11100) => Op::AmomaxuW,
+                _ => Op::Unimplemented,
+            };
+
+            (op, 0)
+        }
+        0b0110111 => (Op::Lui, inst & 0xfffff000),
+        0b1100011 => {
+            let op = match funct3 {
+                0b000 => Op::Beq,
+                0b001 => Op::Bne,
+                0b100 => Op::Blt,
+                0b101 => Op::Bge,
+                0b110 => Op::Bltu,
+                0b111 => Op::Bgeu,
+                _ => Op::Unimplemented,
+            };
+
+            (op, imm_b(inst))
+        }
+        0b1100111 => {
+            // funct3の検証
+            // これは検証すべきかはわからない。
+            // tinyemuでは無視してた。
+            let op = if funct3 == 0 {
+                Op::Jalr
+            } else {
+                Op::Unimplemented
+            };
+
+            (op, imm_i(inst))
+        }
+        0b1101111 => (Op::Jal, imm_j(inst)),
+        0b1000011 | 0b1000111 | 0b1001011 | 0b1001111 => {
+            let op = match (opcode, fp_format(funct7 & 0x3)) {
+                (_, None) => Op::Illegal,
+                (0b1000011, Some(fmt)) => Op::Fmadd(fmt),
+                (0b1000111, Some(fmt)) => Op::Fmsub(fmt),
+                (0b1001011, Some(fmt)) => Op::Fnmsub(fmt),
+                (_, Some(fmt)) => Op::Fnmadd(fmt),
+            };
+
+            (op, funct3)
+        }
+        0b1010011 => (decode_op_fp(inst), funct3),
+        0b1110011 => {
+            let op = match funct3 {
+                0b001 => Op::Csrrw,
+                0b010 => Op::Csrrs,
+                0b011 => Op::Csrrc,
+                0b101 => Op::Csrrwi,
+                0b110 => Op::Csrrsi,
+                0b111 => Op::Csrrci,
+                0b000 if funct7 == 0b0001001 => Op::SfenceVma,
+                0b000 => match inst {
+                    0x00000073 => Op::Ecall,
+                    0x00100073 => Op::Ebreak,
+                    0x10500073 => Op::Wfi,
+                    0x10200073 => Op::Sret,
+                    0x30200073 => Op::Mret,
+                    _ => Op::Unimplemented,
+                },
+                _ => Op::Unimplemented,
+            };
+
+            (op, inst >> 20)
+        }
+        _ => (Op::Unimplemented, 0),
+    };
+
+    Decoded {
+        op,
+        rd: (inst >> 7) & 0x1f,
+        rs1: (inst >> 15) & 0x1f,
+        rs2: (inst >> 20) & 0x1f,
+        rs3: inst >> 27,
+        imm,
+        inst,
+    }
+}
+
+#[inline]
+fn fp_format(fmt: u32) -> Option<Format> {
+    match fmt {
+        0b00 => Some(Format::Single),
+        0b01 => Some(Format::Double),
+        _ => None,
+    }
+}
+
+// OP-FPの命令をデコードする関数
+// 丸めモードが不正かどうかは実行時に検証する
+fn decode_op_fp(inst: u32) -> Op {
+    let funct3 = (inst >> 12) & 0x7;
+    let funct5 = inst >> 27;
+    let rs2 = (inst >> 20) & 0x1f;
+
+    let Some(fmt) = fp_format((inst >> 25) & 0x3) else {
+        return Op::Illegal;
+    };
+
+    match (funct5, funct3, rs2) {
+        (0b00000, _, _) => Op::Fadd(fmt),
+        (0b00001, _, _) => Op::Fsub(fmt),
+        (0b00010, _, _) => Op::Fmul(fmt),
+        (0b00011, _, _) => Op::Fdiv(fmt),
+        (0b01011, _, 0) => Op::Fsqrt(fmt),
+        (0b00100, 0b000, _) => Op::Fsgnj(fmt),
+        (0b00100, 0b001, _) => Op::Fsgnjn(fmt),
+        (0b00100, 0b010, _) => Op::Fsgnjx(fmt),
+        (0b00101, 0b000, _) => Op::Fmin(fmt),
+        (0b00101, 0b001, _) => Op::Fmax(fmt),
+        (0b01000, _, 1) if fmt == Format::Single => Op::FcvtSD,
+        (0b01000, _, 0) if fmt == Format::Double => Op::FcvtDS,
+        (0b11000, _, 0) => Op::FcvtW(fmt),
+        (0b11000, _, 1) => Op::FcvtWu(fmt),
+        (0b11010, _, 0) => Op::FcvtFromW(fmt),
+        (0b11010, _, 1) => Op::FcvtFromWu(fmt),
+        (0b11100, 0b000, 0) if fmt == Format::Single => Op::FmvXW,
+        (0b11100, 0b001, 0) => Op::Fclass(fmt),
+        (0b11110, 0b000, 0) if fmt == Format::Single => Op::FmvWX,
+        (0b10100, 0b010, _) => Op::Feq(fmt),
+        (0b10100, 0b001, _) => Op::Flt(fmt),
+        (0b10100, 0b000, _) => Op::Fle(fmt),
+        _ => Op::Illegal,
+    }
+}
+
+// 圧縮命令のレジスタ(rd', rs1', rs2')はx8からx15を表す
+#[inline]
+fn creg(bits: u32) -> u32 {
+    (bits & 0x7) + 8
+}
+
+// 圧縮命令の6bitの即値(imm[5]がinst[12]、imm[4:0]がinst[6:2])
+#[inline]
+fn cimm6(inst: u32) -> u32 {
+    let imm = ((inst >> 7) & 0x20) | ((inst >> 2) & 0x1f);
+    (((imm << 26) as i32) >> 26) as u32
+}
+
+#[inline]
+fn cimm_j(inst: u32) -> u32 {
+    let imm = ((inst >> 1) & 0x800)
+        | ((inst << 2) & 0x400)
+        | ((inst >> 1) & 0x300)
+        | ((inst << 1) & 0x80)
+        | ((inst >> 1) & 0x40)
+        | ((inst << 3) & 0x20)
+        | ((inst >> 7) & 0x10)
+        | ((inst >> 2) & 0xe);
+    (((imm << 20) as i32) >> 20) as u32
+}
+
+#[inline]
+fn cimm_b(inst: u32) -> u32 {
+    let imm = ((inst >> 4) & 0x100)
+        | ((inst << 1) & 0xc0)
+        | ((inst << 3) & 0x20)
+        | ((inst >> 7) & 0x18)
+        | ((inst >> 2) & 0x6);
+    (((imm << 23) as i32) >> 23) as u32
+}
+
+// 圧縮命令を対応する32bit命令の操作に展開する関数
+pub fn decode_compressed(inst: u32) -> Decoded {
+    let funct3 = inst >> 13;
+    let bit12 = (inst >> 12) & 0x1;
+    let rd = (inst >> 7) & 0x1f;
+    let rs2 = (inst >> 2) & 0x1f;
+
+    let (op, rd, rs1, rs2, imm) = match (inst & 0x3, funct3) {
+        (0b00, 0b000) => {
+            // C.ADDI4SPN
+            let imm = ((inst >> 1) & 0x3c0)
+                | ((inst >> 7) & 0x30)
+                | ((inst >> 2) & 0x8)
+                | ((inst >> 4) & 0x4);
+            let op = if imm == 0 { Op::Illegal } else { Op::Addi };
+
+            (op, creg(inst >> 2), 2, 0, imm)
+        }
+        (0b00, 0b001 | 0b101) => {
+            // C.FLD, C.FSD
+            let imm = ((inst >> 7) & 0x38) | ((inst << 1) & 0xc0);
+            let op = if funct3 == 0b001 { Op::Fld } else { Op::Fsd };
+
+            (op, creg(inst >> 2), creg(inst >> 7), creg(inst >> 2), imm)
+        }
+        (0b00, 0b010 | 0b110 | 0b011 | 0b111) => {
+            // C.LW, C.SW, C.FLW, C.FSW
+            let imm = ((inst >> 7) & 0x38) | ((inst << 1) & 0x40) | ((inst >> 4) & 0x4);
+            let op = match funct3 {
+                0b010 => Op::Lw,
+                0b110 => Op::Sw,
+                0b011 => Op::Flw,
+                _ => Op::Fsw,
+            };
+
+            (op, creg(inst >> 2), creg(inst >> 7), creg(inst >> 2), imm)
+        }
+        (0b01, 0b000) => (Op::Addi, rd, rd, 0, cimm6(inst)), // C.ADDI, C.NOP
+        (0b01, 0b001) => (Op::Jal, 1, 0, 0, cimm_j(inst)),   // C.JAL
+        (0b01, 0b010) => (Op::Addi, rd, 0, 0, cimm6(inst)),  // C.LI
+        (0b01, 0b011) if rd == 2 => {
+            // C.ADDI16SP
+            let imm = ((inst >> 3) & 0x200)
+                | ((inst >> 2) & 0x10)
+                | ((inst << 1) & 0x40)
+                | ((inst << 4) & 0x180)
+                | ((inst << 3) & 0x20);
+            let imm = (((imm << 22) as i32) >> 22) as u32;
+            let op = if imm == 0 { Op::Illegal } else { Op::Addi };
+
+            (op, 2, 2, 0, imm)
+        }
+        (0b01, 0b011) => {
+            // C.LUI
+            let imm = cimm6(inst) << 12;
+            let op = if imm == 0 { Op::Illegal } else { Op::Lui };
+
+            (op, rd, 0, 0, imm)
+        }
+        (0b01, 0b100) => {
+            let rd = creg(inst >> 7);
+
+            match (inst >> 10) & 0x3 {
+                // RV32ではshamt[5]が1の場合は予約されている
+                0b00 | 0b01 if bit12 == 1 => (Op::Illegal, rd, rd, 0, 0),
+                0b00 => (Op::Srli, rd, rd, 0, rs2), // C.SRLI
+                0b01 => (Op::Srai, rd, rd, 0, rs2), // C.SRAI
+                0b10 => (Op::Andi, rd, rd, 0, cimm6(inst)), // C.ANDI
+                _ => {
+                    let op = match (bit12, (inst >> 5) & 0x3) {
+                        (0, 0b00) => Op::Sub,
+                        (0, 0b01) => Op::Xor,
+                        (0, 0b10) => Op::Or,
+                        (0, 0b11) => Op::And,
+                        _ => Op::Illegal,
+                    };
+
+                    (op, rd, rd, creg(inst >> 2), 0)
+                }
+            }
+        }
+        (0b01, 0b101) => (Op::Jal, 0, 0, 0, cimm_j(inst)), // C.J
+        (0b01, 0b110) => (Op::Beq, 0, creg(inst >> 7), 0, cimm_b(inst)), // C.BEQZ
+        (0b01, 0b111) => (Op::Bne, 0, creg(inst >> 7), 0, cimm_b(inst)), // C.BNEZ
+        (0b10, 0b000) => {
+            // C.SLLI
+            let op = if bit12 == 1 { Op::Illegal } else { Op::Slli };
+
+            (op, rd, rd, 0, rs2)
+        }
+        (0b10, 0b001) => {
+            // C.FLDSP
+            let imm = ((inst >> 7) & 0x20) | ((inst >> 2) & 0x18) | ((inst << 4) & 0x1c0);
+
+            (Op::Fld, rd, 2, 0, imm)
+        }
+        (0b10, 0b010) => {
+            // C.LWSP
+            let imm = ((inst >> 7) & 0x20) | ((inst >> 2) & 0x1c) | ((inst << 4) & 0xc0);
+            let op = if rd == 0 { Op::Illegal } else { Op::Lw };
+
+            (op, rd, 2, 0, imm)
+        }
+        (0b10, 0b011) => {
+            // C.FLWSP
+            let imm = ((inst >> 7) & 0x20) | ((inst >> 2) & 0x1c) | ((inst << 4) & 0xc0);
+
+            (Op::Flw, rd, 2, 0, imm)
+        }
+        (0b10, 0b100) => match (bit12, rd, rs2) {
+            (0, 0, 0) => (Op::Illegal, 0, 0, 0, 0),
+            (0, _, 0) => (Op::Jalr, 0, rd, 0, 0),  // C.JR
+            (0, _, _) => (Op::Add, rd, 0, rs2, 0), // C.MV
+            (1, 0, 0) => (Op::Ebreak, 0, 0, 0, 0), // C.EBREAK
+            (1, _, 0) => (Op::Jalr, 1, rd, 0, 0),  // C.JALR
+            _ => (Op::Add, rd, rd, rs2, 0),        // C.ADD
+        },
+        (0b10, 0b101) => {
+            // C.FSDSP
+            let imm = ((inst >> 7) & 0x38) | ((inst >> 1) & 0x1c0);
+
+            (Op::Fsd, 0, 2, rs2, imm)
+        }
+        (0b10, 0b110 | 0b111) => {
+            // C.SWSP, C.FSWSP
+            let imm = ((inst >> 7) & 0x3c) | ((inst >> 1) & 0xc0);
+            let op = if funct3 == 0b110 { Op::Sw } else { Op::Fsw };
+
+            (op, 0, 2, rs2, imm)
+        }
+        _ => (Op::Illegal, 0, 0, 0, 0),
+    };
+
+    // 全て0の命令は不正な命令として扱う
+    let op = if inst == 0 { Op::Illegal } else { op };
+
+    Decoded {
+        op,
+        rd,
+        rs1,
+        rs2,
+        rs3: 0,
+        imm,
+        inst,
+    }
+}
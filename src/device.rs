@@ -1,14 +1,65 @@
-use std::fmt::Debug;
+use std::fmt::{Debug, Display};
 
-use crate::{IRQ, host_device::GpuMessage, memory::Memory};
+use crate::{fdt::FdtBuilder, host_device::GpuMessage, memory::Memory, snapshot::Snapshot};
 
-pub type DeviceResult<T> = crate::Result<DeviceResponse<T>>;
+pub type BusResult<T> = std::result::Result<T, BusError>;
+pub type DeviceResult<T> = BusResult<DeviceResponse<T>>;
+
+// バスやデバイスへのアクセスが失敗した理由
+// Busでログを出力し、アクセスの種類に応じたアクセスフォルトにする
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BusError {
+    // 何も接続されていないアドレス
+    Unmapped,
+    // 対応していないアクセスサイズ
+    Size(u32),
+    // 存在しないか実装していないレジスタ
+    Register(u32),
+    // レジスタに書き込めない値
+    Value { offset: u32, value: u32 },
+    // デバイスが読み書きするゲストのメモリが範囲外
+    Memory { addr: usize, len: usize },
+    // 実装していないデバイスへの要求
+    Unsupported(&'static str),
+}
+
+impl Display for BusError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unmapped => write!(f, "no device is mapped"),
+            Self::Size(size) => write!(f, "{}-byte access is not supported", size),
+            Self::Register(offset) => {
+                write!(f, "register at offset 0x{:x} is not implemented", offset)
+            }
+            Self::Value { offset, value } => write!(
+                f,
+                "0x{:x} cannot be written to register at offset 0x{:x}",
+                value, offset
+            ),
+            Self::Memory { addr, len } => write!(
+                f,
+                "0x{:x} bytes at 0x{:08x} are out of guest memory",
+                len, addr
+            ),
+            Self::Unsupported(request) => write!(f, "{} is not supported", request),
+        }
+    }
+}
 
 pub struct DeviceResponse<T> {
     pub value: T,
     pub is_interrupting: bool,
 }
 
+// PLICのゲートウェイでの割り込みの信号の扱い
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trigger {
+    // is_interruptingがtrueになるたびに割り込みを起こす
+    Edge,
+    // is_interruptingを信号の状態として扱い、trueの間は割り込みを起こし続ける
+    Level,
+}
+
 // 仮想デバイスとホストデバイスとの通信に使用する列挙体
 pub enum DeviceMessage {
     Uart(char),
@@ -31,8 +82,22 @@ pub trait DeviceRecieverTrait: Default {
     fn try_recv_from_host(&self) -> Result<DeviceMessage, Self::E>;
 }
 
+// ホストデバイスと繋がっていない仮想デバイスのためのreciever
+// 何も受け取らない
+#[derive(Default)]
+pub struct NullReciever;
+
+impl DeviceRecieverTrait for NullReciever {
+    type E = ();
+
+    fn try_recv_from_host(&self) -> Result<DeviceMessage, Self::E> {
+        Err(())
+    }
+}
+
 // 仮想デバイスについてのトレイト
-pub trait DeviceTrait {
+// スナップショットにはゲストから見える状態のみ保存し、ホストとの通信路は保存しない
+pub trait DeviceTrait: Snapshot {
     fn read(&mut self, offset: u32, size: u32, memory: &mut Memory) -> DeviceResult<u32>;
     fn write(
         &mut self,
@@ -42,9 +107,20 @@ pub trait DeviceTrait {
         memory: &mut Memory,
     ) -> DeviceResult<()>;
 
-    fn irq(&self) -> IRQ;
+    fn trigger(&self) -> Trigger {
+        Trigger::Edge
+    }
+
+    // デバイスツリーのノード名(serial@10000000のserialの部分)
+    fn fdt_name(&self) -> &'static str;
+
+    fn fdt_compatible(&self) -> &'static [&'static str];
+
+    // reg, interrupts以外のプロパティが必要な場合に書き込む関数
+    fn fdt_properties(&self, _: &mut FdtBuilder) {}
 
     // 割り込みが起こったときのみ行う必要があるもののフラグの切り替えに使用する関数
+    // PLICで割り込みがclaimされたときに呼び出される
     fn take_interrupt(&mut self) {}
 
     // イベントループからメッセージをデバイスに通知するときに使われる関数
@@ -53,7 +129,7 @@ pub trait DeviceTrait {
     fn handle_incoming(&mut self, message: &DeviceMessage) {}
 
     // tickごとに実行される関数
-    // 外部割り込みが有効な場合に実行される
+    // 戻り値はreadやwriteのis_interruptingと同じように扱われる
     fn tick(&mut self, _: &mut Memory) -> bool {
         false
     }
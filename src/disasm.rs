@@ -0,0 +1,552 @@
+use crate::{
+    csr::csr_name,
+    decode::{Decoded, Op, decode, inst_len},
+    softfloat::Format,
+};
+
+const REG_NAMES: [&str; 32] = [
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1", "a0", "a1", "a2", "a3", "a4",
+    "a5", "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4",
+    "t5", "t6",
+];
+
+const FREG_NAMES: [&str; 32] = [
+    "ft0", "ft1", "ft2", "ft3", "ft4", "ft5", "ft6", "ft7", "fs0", "fs1", "fa0", "fa1", "fa2",
+    "fa3", "fa4", "fa5", "fa6", "fa7", "fs2", "fs3", "fs4", "fs5", "fs6", "fs7", "fs8", "fs9",
+    "fs10", "fs11", "ft8", "ft9", "ft10", "ft11",
+];
+
+// 命令に書かれる丸めモードの名前
+// dynの場合は省略する
+const ROUNDING_MODES: [Option<&str>; 8] = [
+    Some("rne"),
+    Some("rtz"),
+    Some("rdn"),
+    Some("rup"),
+    Some("rmm"),
+    None,
+    None,
+    None,
+];
+const RM_DYN: u32 = 0b111;
+
+// 逆アセンブルできない場合
+const UNKNOWN: &str = "unknown";
+
+pub fn reg_name(reg: u32) -> &'static str {
+    REG_NAMES[(reg & 0x1f) as usize]
+}
+
+pub fn freg_name(reg: u32) -> &'static str {
+    FREG_NAMES[(reg & 0x1f) as usize]
+}
+
+// Spikeと同じくニーモニックを8文字に揃えてオペランドを並べる
+fn format(name: &str, operands: &[String]) -> String {
+    if operands.is_empty() {
+        return name.to_string();
+    }
+
+    format!("{:<7} {}", name, operands.join(", "))
+}
+
+macro_rules! asm {
+    ($name:expr) => {
+        format($name, &[])
+    };
+    ($name:expr, $($operand:expr),+) => {
+        format($name, &[$($operand.to_string()),+])
+    };
+}
+
+fn csr_operand(csr: u32) -> String {
+    match csr_name(csr) {
+        Some(name) => name.to_string(),
+        None => format!("0x{:03x}", csr),
+    }
+}
+
+fn fence_set(bits: u32) -> String {
+    ["i", "o", "r", "w"]
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| bits & (8 >> i) != 0)
+        .map(|(_, c)| *c)
+        .collect()
+}
+
+// 命令を逆アセンブルする関数
+// pcは分岐先やジャンプ先のアドレスを求めるために使用する
+pub fn disassemble(inst: u32, pc: u32) -> String {
+    if inst_len(inst) == 2 {
+        return disassemble_compressed(inst & 0xffff, pc);
+    }
+
+    let opcode = inst & 0x7f;
+    let rd = (inst >> 7) & 0x1f;
+    let rs1 = (inst >> 15) & 0x1f;
+    let rs2 = (inst >> 20) & 0x1f;
+    let funct3 = (inst >> 12) & 0x7;
+    let funct7 = inst >> 25;
+
+    let (rd_name, rs1_name, rs2_name) = (reg_name(rd), reg_name(rs1), reg_name(rs2));
+
+    let imm_i = (inst as i32) >> 20;
+    let imm_s = ((inst as i32) >> 25 << 5) | ((inst >> 7) & 0x1f) as i32;
+
+    match opcode {
+        0b0000011 => {
+            let name = match funct3 {
+                0b000 => "lb",
+                0b001 => "lh",
+                0b010 => "lw",
+                0b100 => "lbu",
+                0b101 => "lhu",
+                _ => return UNKNOWN.to_string(),
+            };
+
+            asm!(name, rd_name, format!("{}({})", imm_i, rs1_name))
+        }
+        0b0001111 => match (funct3, inst) {
+            (0, 0x8330000f) => asm!("fence.tso"),
+            (0, 0x0100000f) => asm!("pause"),
+            (0, _) => {
+                let pred = (inst >> 24) & 0xf;
+                let succ = (inst >> 20) & 0xf;
+
+                if pred == 0xf && succ == 0xf {
+                    asm!("fence")
+                } else {
+                    asm!("fence", fence_set(pred), fence_set(succ))
+                }
+            }
+            (1, _) => asm!("fence.i"),
+            (2, _) if rd == 0 => {
+                let name = match inst >> 20 {
+                    0 => "cbo.inval",
+                    1 => "cbo.clean",
+                    2 => "cbo.flush",
+                    4 => "cbo.zero",
+                    _ => return UNKNOWN.to_string(),
+                };
+
+                asm!(name, format!("({})", rs1_name))
+            }
+            _ => UNKNOWN.to_string(),
+        },
+        0b0010011 => match funct3 {
+            0b000 => match (rd, rs1, imm_i) {
+                (0, 0, 0) => asm!("nop"),
+                (_, 0, _) => asm!("li", rd_name, imm_i),
+                (_, _, 0) => asm!("mv", rd_name, rs1_name),
+                _ => asm!("addi", rd_name, rs1_name, imm_i),
+            },
+            0b001 if funct7 == 0 => asm!("slli", rd_name, rs1_name, rs2),
+            0b001 if funct7 == 0b0110000 => {
+                let name = match rs2 {
+                    0 => "clz",
+                    1 => "ctz",
+                    2 => "cpop",
+                    4 => "sext.b",
+                    5 => "sext.h",
+                    _ => return UNKNOWN.to_string(),
+                };
+
+                asm!(name, rd_name, rs1_name)
+            }
+            0b001 if funct7 == 0b0100100 => asm!("bclri", rd_name, rs1_name, rs2),
+            0b001 if funct7 == 0b0110100 => asm!("binvi", rd_name, rs1_name, rs2),
+            0b001 if funct7 == 0b0010100 => asm!("bseti", rd_name, rs1_name, rs2),
+            0b010 => asm!("slti", rd_name, rs1_name, imm_i),
+            0b011 if imm_i == 1 => asm!("seqz", rd_name, rs1_name),
+            0b011 => asm!("sltiu", rd_name, rs1_name, imm_i),
+            0b100 if imm_i == -1 => asm!("not", rd_name, rs1_name),
+            0b100 => asm!("xori", rd_name, rs1_name, imm_i),
+            0b101 if funct7 == 0 => asm!("srli", rd_name, rs1_name, rs2),
+            0b101 if funct7 == 0b0100000 => asm!("srai", rd_name, rs1_name, rs2),
+            0b101 if imm_i == 0x287 => asm!("orc.b", rd_name, rs1_name),
+            0b101 if imm_i == 0x698 => asm!("rev8", rd_name, rs1_name),
+            0b101 if funct7 == 0b0110000 => asm!("rori", rd_name, rs1_name, rs2),
+            0b101 if funct7 == 0b0100100 => asm!("bexti", rd_name, rs1_name, rs2),
+            // Zicbop拡張のprefetchはrd=0のoriとして表される
+            0b110 if rd == 0 && matches!(rs2, 0 | 1 | 3) => {
+                let name = match rs2 {
+                    0 => "prefetch.i",
+                    1 => "prefetch.r",
+                    _ => "prefetch.w",
+                };
+
+                asm!(name, format!("{}({})", imm_i & !0x1f, rs1_name))
+            }
+            0b110 => asm!("ori", rd_name, rs1_name, imm_i),
+            0b111 => asm!("andi", rd_name, rs1_name, imm_i),
+            _ => UNKNOWN.to_string(),
+        },
+        0b0010111 => asm!("auipc", rd_name, format!("0x{:x}", inst >> 12)),
+        0b0100011 => {
+            let name = match funct3 {
+                0b000 => "sb",
+                0b001 => "sh",
+                0b010 => "sw",
+                _ => return UNKNOWN.to_string(),
+            };
+
+            asm!(name, rs2_name, format!("{}({})", imm_s, rs1_name))
+        }
+        0b0110011 => {
+            let name = match (funct3, funct7) {
+                (0b000, 0b0000000) => "add",
+                (0b000, 0b0000001) => "mul",
+                (0b000, 0b0100000) if rs1 == 0 => return asm!("neg", rd_name, rs2_name),
+                (0b000, 0b0100000) => "sub",
+                (0b001, 0b0000000) => "sll",
+                (0b001, 0b0000001) => "mulh",
+                (0b010, 0b0000000) if rs2 == 0 => return asm!("sltz", rd_name, rs1_name),
+                (0b010, 0b0000000) if rs1 == 0 => return asm!("sgtz", rd_name, rs2_name),
+                (0b010, 0b0000000) => "slt",
+                (0b010, 0b0000001) => "mulhsu",
+                (0b011, 0b0000000) if rs1 == 0 => return asm!("snez", rd_name, rs2_name),
+                (0b011, 0b0000000) => "sltu",
+                (0b011, 0b0000001) => "mulhu",
+                (0b100, 0b0000000) => "xor",
+                (0b100, 0b0000001) => "div",
+                (0b101, 0b0000000) => "srl",
+                (0b101, 0b0000001) => "divu",
+                (0b101, 0b0100000) => "sra",
+                (0b110, 0b0000000) => "or",
+                (0b110, 0b0000001) => "rem",
+                (0b111, 0b0000000) => "and",
+                (0b111, 0b0000001) => "remu",
+                (0b010, 0b0010000) => "sh1add",
+                (0b100, 0b0010000) => "sh2add",
+                (0b110, 0b0010000) => "sh3add",
+                (0b111, 0b0100000) => "andn",
+                (0b110, 0b0100000) => "orn",
+                (0b100, 0b0100000) => "xnor",
+                (0b100, 0b0000101) => "min",
+                (0b101, 0b0000101) => "minu",
+                (0b110, 0b0000101) => "max",
+                (0b111, 0b0000101) => "maxu",
+                (0b100, 0b0000100) if rs2 == 0 => return asm!("zext.h", rd_name, rs1_name),
+                (0b001, 0b0110000) => "rol",
+                (0b101, 0b0110000) => "ror",
+                (0b001, 0b0100100) => "bclr",
+                (0b101, 0b0100100) => "bext",
+                (0b001, 0b0110100) => "binv",
+                (0b001, 0b0010100) => "bset",
+                _ => return UNKNOWN.to_string(),
+            };
+
+            asm!(name, rd_name, rs1_name, rs2_name)
+        }
+        0b0101111 if funct3 == 0b010 => {
+            let name = match funct7 >> 2 {
+                0b00010 if rs2 == 0 => "lr.w",
+                0b00011 => "sc.w",
+                0b00000 => "amoadd.w",
+                0b00001 => "amoswap.w",
+                0b00100 => "amoxor.w",
+                0b01000 => "amoor.w",
+                0b01100 => "amoand.w",
+                0b10000 => "amomin.w",
+                0b10100 => "amomax.w",
+                0b11000 => "amominu.w",
+                0b11100 => "amomaxu.w",
+                _ => return UNKNOWN.to_string(),
+            };
+
+            let name = match funct7 & 0x3 {
+                0b10 => format!("{}.aq", name),
+                0b01 => format!("{}.rl", name),
+                0b11 => format!("{}.aqrl", name),
+                _ => name.to_string(),
+            };
+
+            let addr = format!("({})", rs1_name);
+
+            if name.starts_with("lr.w") {
+                asm!(&name, rd_name, addr)
+            } else {
+                asm!(&name, rd_name, rs2_name, addr)
+            }
+        }
+        0b0110111 => asm!("lui", rd_name, format!("0x{:x}", inst >> 12)),
+        0b1100011 => {
+            let imm = ((inst >> (31 - 12)) & (1 << 12))
+                | ((inst >> (25 - 5)) & 0x7e0)
+                | ((inst >> (8 - 1)) & 0x1e)
+                | ((inst << (11 - 7)) & (1 << 11));
+            let imm = ((imm << 19) as i32) >> 19;
+            let target = format!("0x{:x}", pc.wrapping_add(imm as u32));
+
+            match (funct3, rs1, rs2) {
+                (0b000, _, 0) => asm!("beqz", rs1_name, target),
+                (0b001, _, 0) => asm!("bnez", rs1_name, target),
+                (0b100, _, 0) => asm!("bltz", rs1_name, target),
+                (0b100, 0, _) => asm!("bgtz", rs2_name, target),
+                (0b101, _, 0) => asm!("bgez", rs1_name, target),
+                (0b101, 0, _) => asm!("blez", rs2_name, target),
+                _ => {
+                    let name = match funct3 {
+                        0b000 => "beq",
+                        0b001 => "bne",
+                        0b100 => "blt",
+                        0b101 => "bge",
+                        0b110 => "bltu",
+                        0b111 => "bgeu",
+                        _ => return UNKNOWN.to_string(),
+                    };
+
+                    asm!(name, rs1_name, rs2_name, target)
+                }
+            }
+        }
+        0b1100111 if funct3 == 0 => match (rd, rs1, imm_i) {
+            (0, 1, 0) => asm!("ret"),
+            (0, _, 0) => asm!("jr", rs1_name),
+            (1, _, 0) => asm!("jalr", rs1_name),
+            _ => asm!("jalr", rd_name, format!("{}({})", imm_i, rs1_name)),
+        },
+        0b1101111 => {
+            let imm = ((inst >> (31 - 20)) & (1 << 20))
+                | ((inst >> (21 - 1)) & 0x7fe)
+                | ((inst >> (20 - 11)) & (1 << 11))
+                | (inst & 0xff000);
+            let imm = ((imm << 11) as i32) >> 11;
+            let target = format!("0x{:x}", pc.wrapping_add(imm as u32));
+
+            match rd {
+                0 => asm!("j", target),
+                1 => asm!("jal", target),
+                _ => asm!("jal", rd_name, target),
+            }
+        }
+        0b1110011 => disassemble_system(inst, rd, rs1, rs2, funct3, funct7),
+        0b0000111 | 0b0100111 | 0b1000011 | 0b1000111 | 0b1001011 | 0b1001111 | 0b1010011 => {
+            disassemble_fp(inst)
+        }
+        _ => UNKNOWN.to_string(),
+    }
+}
+
+// 浮動小数点命令はデコードした結果から逆アセンブルする
+fn disassemble_fp(inst: u32) -> String {
+    let Decoded {
+        op,
+        rd,
+        rs1,
+        rs2,
+        rs3,
+        imm,
+        ..
+    } = decode(inst);
+
+    let (frd, frs1, frs2, frs3) = (
+        freg_name(rd),
+        freg_name(rs1),
+        freg_name(rs2),
+        freg_name(rs3),
+    );
+    let offset = format!("{}({})", imm as i32, reg_name(rs1));
+
+    let suffix = |fmt: Format| match fmt {
+        Format::Single => "s",
+        Format::Double => "d",
+    };
+
+    // 丸めモードがある命令は最後のオペランドとして出力する
+    let with_rm = |name: String, operands: &[&str]| {
+        let mut operands: Vec<String> = operands.iter().map(|o| o.to_string()).collect();
+
+        if imm != RM_DYN {
+            match ROUNDING_MODES[imm as usize] {
+                Some(rm) => operands.push(rm.to_string()),
+                None => return UNKNOWN.to_string(),
+            }
+        }
+
+        format(&name, &operands)
+    };
+
+    match op {
+        Op::Flw => asm!("flw", frd, offset),
+        Op::Fld => asm!("fld", frd, offset),
+        Op::Fsw => asm!("fsw", frs2, offset),
+        Op::Fsd => asm!("fsd", frs2, offset),
+        Op::Fmadd(fmt) | Op::Fmsub(fmt) | Op::Fnmsub(fmt) | Op::Fnmadd(fmt) => {
+            let name = match op {
+                Op::Fmadd(_) => "fmadd",
+                Op::Fmsub(_) => "fmsub",
+                Op::Fnmsub(_) => "fnmsub",
+                _ => "fnmadd",
+            };
+
+            with_rm(
+                format!("{}.{}", name, suffix(fmt)),
+                &[frd, frs1, frs2, frs3],
+            )
+        }
+        Op::Fadd(fmt) | Op::Fsub(fmt) | Op::Fmul(fmt) | Op::Fdiv(fmt) => {
+            let name = match op {
+                Op::Fadd(_) => "fadd",
+                Op::Fsub(_) => "fsub",
+                Op::Fmul(_) => "fmul",
+                _ => "fdiv",
+            };
+
+            with_rm(format!("{}.{}", name, suffix(fmt)), &[frd, frs1, frs2])
+        }
+        Op::Fsqrt(fmt) => with_rm(format!("fsqrt.{}", suffix(fmt)), &[frd, frs1]),
+        Op::Fsgnj(fmt) | Op::Fsgnjn(fmt) | Op::Fsgnjx(fmt) => {
+            let (name, pseudo) = match op {
+                Op::Fsgnj(_) => ("fsgnj", "fmv"),
+                Op::Fsgnjn(_) => ("fsgnjn", "fneg"),
+                _ => ("fsgnjx", "fabs"),
+            };
+
+            if rs1 == rs2 {
+                asm!(&format!("{}.{}", pseudo, suffix(fmt)), frd, frs1)
+            } else {
+                asm!(&format!("{}.{}", name, suffix(fmt)), frd, frs1, frs2)
+            }
+        }
+        Op::Fmin(fmt) => asm!(&format!("fmin.{}", suffix(fmt)), frd, frs1, frs2),
+        Op::Fmax(fmt) => asm!(&format!("fmax.{}", suffix(fmt)), frd, frs1, frs2),
+        Op::FcvtW(fmt) => with_rm(format!("fcvt.w.{}", suffix(fmt)), &[reg_name(rd), frs1]),
+        Op::FcvtWu(fmt) => with_rm(format!("fcvt.wu.{}", suffix(fmt)), &[reg_name(rd), frs1]),
+        // 倍精度への変換は常に正確なので丸めモードを省略する
+        Op::FcvtFromW(Format::Double) => asm!("fcvt.d.w", frd, reg_name(rs1)),
+        Op::FcvtFromWu(Format::Double) => asm!("fcvt.d.wu", frd, reg_name(rs1)),
+        Op::FcvtFromW(fmt) => with_rm(format!("fcvt.{}.w", suffix(fmt)), &[frd, reg_name(rs1)]),
+        Op::FcvtFromWu(fmt) => with_rm(format!("fcvt.{}.wu", suffix(fmt)), &[frd, reg_name(rs1)]),
+        Op::FcvtSD => with_rm("fcvt.s.d".to_string(), &[frd, frs1]),
+        Op::FcvtDS => asm!("fcvt.d.s", frd, frs1),
+        Op::FmvXW => asm!("fmv.x.w", reg_name(rd), frs1),
+        Op::FmvWX => asm!("fmv.w.x", frd, reg_name(rs1)),
+        Op::Feq(fmt) => asm!(&format!("feq.{}", suffix(fmt)), reg_name(rd), frs1, frs2),
+        Op::Flt(fmt) => asm!(&format!("flt.{}", suffix(fmt)), reg_name(rd), frs1, frs2),
+        Op::Fle(fmt) => asm!(&format!("fle.{}", suffix(fmt)), reg_name(rd), frs1, frs2),
+        Op::Fclass(fmt) => asm!(&format!("fclass.{}", suffix(fmt)), reg_name(rd), frs1),
+        _ => UNKNOWN.to_string(),
+    }
+}
+
+fn disassemble_system(inst: u32, rd: u32, rs1: u32, rs2: u32, funct3: u32, funct7: u32) -> String {
+    let csr = inst >> 20;
+    let (rd_name, rs1_name) = (reg_name(rd), reg_name(rs1));
+    let csr_name = csr_operand(csr);
+
+    match funct3 {
+        0b000 => match inst {
+            0x00000073 => asm!("ecall"),
+            0x00100073 => asm!("ebreak"),
+            0x10500073 => asm!("wfi"),
+            0x10200073 => asm!("sret"),
+            0x30200073 => asm!("mret"),
+            _ if funct7 == 0b0001001 && rd == 0 => match (rs1, rs2) {
+                (0, 0) => asm!("sfence.vma"),
+                (_, 0) => asm!("sfence.vma", rs1_name),
+                _ => asm!("sfence.vma", rs1_name, reg_name(rs2)),
+            },
+            _ => UNKNOWN.to_string(),
+        },
+        0b010 if rs1 == 0 => {
+            // カウンタの読み込みは専用の疑似命令がある
+            let name = match csr_name.as_str() {
+                "cycle" | "time" | "instret" | "cycleh" | "timeh" | "instreth" => {
+                    format!("rd{}", csr_name)
+                }
+                _ => return asm!("csrr", rd_name, csr_name),
+            };
+
+            asm!(&name, rd_name)
+        }
+        0b001..=0b011 => {
+            let name = ["csrrw", "csrrs", "csrrc"][funct3 as usize - 1];
+
+            if rd == 0 {
+                asm!(&name.replace("csrr", "csr"), csr_name, rs1_name)
+            } else {
+                asm!(name, rd_name, csr_name, rs1_name)
+            }
+        }
+        0b101..=0b111 => {
+            let name = ["csrrwi", "csrrsi", "csrrci"][funct3 as usize - 5];
+
+            if rd == 0 {
+                asm!(&name.replace("csrr", "csr"), csr_name, rs1)
+            } else {
+                asm!(name, rd_name, csr_name, rs1)
+            }
+        }
+        _ => UNKNOWN.to_string(),
+    }
+}
+
+// 圧縮命令はSpikeと同じくc.から始まる名前で出力する
+// オペランドはデコードした結果から求める
+fn disassemble_compressed(inst: u32, pc: u32) -> String {
+    let decoded = decode(inst);
+
+    if decoded.op == Op::Illegal {
+        return UNKNOWN.to_string();
+    }
+
+    let (rd, rs1, rs2) = (
+        reg_name(decoded.rd),
+        reg_name(decoded.rs1),
+        reg_name(decoded.rs2),
+    );
+    let imm = decoded.imm as i32;
+    let target = format!("0x{:x}", pc.wrapping_add(decoded.imm));
+    let offset = format!("{}({})", imm, rs1);
+    let bit12 = (inst >> 12) & 0x1;
+
+    match (inst & 0x3, inst >> 13) {
+        (0b00, 0b000) => asm!("c.addi4spn", rd, rs1, imm),
+        (0b00, 0b001) => asm!("c.fld", freg_name(decoded.rd), offset),
+        (0b00, 0b010) => asm!("c.lw", rd, offset),
+        (0b00, 0b011) => asm!("c.flw", freg_name(decoded.rd), offset),
+        (0b00, 0b101) => asm!("c.fsd", freg_name(decoded.rs2), offset),
+        (0b00, 0b110) => asm!("c.sw", rs2, offset),
+        (0b00, 0b111) => asm!("c.fsw", freg_name(decoded.rs2), offset),
+        (0b01, 0b000) if decoded.rd == 0 => asm!("c.nop"),
+        (0b01, 0b000) => asm!("c.addi", rd, imm),
+        (0b01, 0b001) => asm!("c.jal", target),
+        (0b01, 0b010) => asm!("c.li", rd, imm),
+        (0b01, 0b011) if decoded.rd == 2 => asm!("c.addi16sp", rd, imm),
+        (0b01, 0b011) => asm!(
+            "c.lui",
+            rd,
+            format!("0x{:x}", (decoded.imm >> 12) & 0xfffff)
+        ),
+        (0b01, 0b100) => match decoded.op {
+            Op::Srli => asm!("c.srli", rd, imm),
+            Op::Srai => asm!("c.srai", rd, imm),
+            Op::Andi => asm!("c.andi", rd, imm),
+            Op::Sub => asm!("c.sub", rd, rs2),
+            Op::Xor => asm!("c.xor", rd, rs2),
+            Op::Or => asm!("c.or", rd, rs2),
+            Op::And => asm!("c.and", rd, rs2),
+            _ => UNKNOWN.to_string(),
+        },
+        (0b01, 0b101) => asm!("c.j", target),
+        (0b01, 0b110) => asm!("c.beqz", rs1, target),
+        (0b01, 0b111) => asm!("c.bnez", rs1, target),
+        (0b10, 0b000) => asm!("c.slli", rd, imm),
+        (0b10, 0b001) => asm!("c.fldsp", freg_name(decoded.rd), offset),
+        (0b10, 0b010) => asm!("c.lwsp", rd, offset),
+        (0b10, 0b011) => asm!("c.flwsp", freg_name(decoded.rd), offset),
+        (0b10, 0b100) => match (bit12, decoded.op) {
+            (0, Op::Jalr) => asm!("c.jr", rs1),
+            (0, _) => asm!("c.mv", rd, rs2),
+            (1, Op::Ebreak) => asm!("c.ebreak"),
+            (1, Op::Jalr) => asm!("c.jalr", rs1),
+            _ => asm!("c.add", rd, rs2),
+        },
+        (0b10, 0b101) => asm!("c.fsdsp", freg_name(decoded.rs2), offset),
+        (0b10, 0b110) => asm!("c.swsp", rs2, offset),
+        (0b10, 0b111) => asm!("c.fswsp", freg_name(decoded.rs2), offset),
+        _ => UNKNOWN.to_string(),
+    }
+}
@@ -1,3 +1,5 @@
+use std::{fmt::Display, ops::Range};
+
 type Elf32Half = u16;
 type Elf32Word = u32;
 type Elf32Addr = u32;
@@ -6,11 +8,26 @@ type Elf32Off = u32;
 const EI_NIDENT: usize = 16;
 const ELF_MAGIC: [u8; 4] = [0x7f, b'E', b'L', b'F'];
 
+const EI_CLASS: usize = 4;
+const EI_DATA: usize = 5;
+const ELFCLASS32: u8 = 1;
+const ELFDATA2LSB: u8 = 1;
+
+const ET_EXEC: Elf32Half = 2;
+const EM_RISCV: Elf32Half = 243;
+
 const PT_LOAD: Elf32Word = 1;
 
+const SHT_SYMTAB: Elf32Word = 2;
+const SHN_UNDEF: Elf32Half = 0;
+
+const STT_NOTYPE: u8 = 0;
+const STT_OBJECT: u8 = 1;
+const STT_FUNC: u8 = 2;
+
 #[allow(unused)]
 #[derive(Debug, Clone, Copy)]
-#[repr(packed)]
+#[repr(C, packed)]
 pub struct Elf32Ehdr {
     pub e_ident: [u8; EI_NIDENT],
     pub e_type: Elf32Half,
@@ -30,13 +47,13 @@ pub struct Elf32Ehdr {
 
 impl Elf32Ehdr {
     pub fn is_valid(&self) -> bool {
-        self.e_ident.starts_with(&ELF_MAGIC) && self.e_ident[4] == 1 /* ELFCLASS32 */
+        self.e_ident.starts_with(&ELF_MAGIC) && self.e_ident[EI_CLASS] == ELFCLASS32
     }
 }
 
 #[allow(unused)]
 #[derive(Debug, Clone, Copy)]
-#[repr(packed)]
+#[repr(C, packed)]
 pub struct Elf32Phdr {
     pub p_type: Elf32Word,
     pub p_offset: Elf32Off,
@@ -53,3 +70,278 @@ impl Elf32Phdr {
         self.p_type == PT_LOAD
     }
 }
+
+#[allow(unused)]
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+pub struct Elf32Shdr {
+    pub sh_name: Elf32Word,
+    pub sh_type: Elf32Word,
+    pub sh_flags: Elf32Word,
+    pub sh_addr: Elf32Addr,
+    pub sh_offset: Elf32Off,
+    pub sh_size: Elf32Word,
+    pub sh_link: Elf32Word,
+    pub sh_info: Elf32Word,
+    pub sh_addralign: Elf32Word,
+    pub sh_entsize: Elf32Word,
+}
+
+#[allow(unused)]
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+pub struct Elf32Sym {
+    pub st_name: Elf32Word,
+    pub st_value: Elf32Addr,
+    pub st_size: Elf32Word,
+    pub st_info: u8,
+    pub st_other: u8,
+    pub st_shndx: Elf32Half,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ElfError {
+    InvalidMagic,
+    UnsupportedClass,
+    UnsupportedEndian,
+    UnsupportedType(u16),
+    UnsupportedMachine(u16),
+    // ヘッダやセグメントがファイルの範囲外を指している場合
+    Truncated { offset: usize, size: usize },
+    // p_memsz < p_filesz の場合やアドレスがオーバーフローする場合
+    InvalidSegment { index: usize },
+    InvalidSymbolTable,
+}
+
+impl Display for ElfError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidMagic => write!(f, "not an ELF file"),
+            Self::UnsupportedClass => write!(f, "not an ELF32 file"),
+            Self::UnsupportedEndian => write!(f, "not a little-endian ELF file"),
+            Self::UnsupportedType(t) => write!(f, "unsupported ELF type {}", t),
+            Self::UnsupportedMachine(m) => write!(f, "unsupported machine {} (not RISC-V)", m),
+            Self::Truncated { offset, size } => write!(
+                f,
+                "0x{:x} bytes at offset 0x{:x} are outside of the file",
+                size, offset
+            ),
+            Self::InvalidSegment { index } => write!(f, "program header {} is invalid", index),
+            Self::InvalidSymbolTable => write!(f, "symbol table is invalid"),
+        }
+    }
+}
+
+impl std::error::Error for ElfError {}
+
+type Result<T> = std::result::Result<T, ElfError>;
+
+// 範囲を確認した上でファイル中の構造体を読み込む
+fn read_struct<T: Copy>(array: &[u8], offset: usize) -> Result<T> {
+    let size = size_of::<T>();
+
+    if offset.checked_add(size).is_none_or(|end| end > array.len()) {
+        return Err(ElfError::Truncated { offset, size });
+    }
+
+    Ok(unsafe { std::ptr::read_unaligned(array[offset..].as_ptr() as *const T) })
+}
+
+fn slice(array: &[u8], offset: usize, size: usize) -> Result<&[u8]> {
+    array
+        .get(offset..offset.saturating_add(size))
+        .ok_or(ElfError::Truncated { offset, size })
+}
+
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    pub name: String,
+    pub addr: u32,
+    pub size: u32,
+}
+
+// ELFの.symtabから読み込んだシンボル
+// アドレス順に並べておく
+#[derive(Debug, Clone, Default)]
+pub struct SymbolTable {
+    symbols: Vec<Symbol>,
+}
+
+impl SymbolTable {
+    pub fn lookup(&self, name: &str) -> Option<u32> {
+        self.symbols.iter().find(|s| s.name == name).map(|s| s.addr)
+    }
+
+    // addrを含むシンボルとシンボルの先頭からのオフセットを返す関数
+    pub fn find(&self, addr: u32) -> Option<(&str, u32)> {
+        let idx = self.symbols.partition_point(|s| s.addr <= addr);
+        let symbol = &self.symbols[..idx].last()?;
+        let offset = addr - symbol.addr;
+
+        if symbol.size != 0 && offset >= symbol.size {
+            return None;
+        }
+
+        Some((&symbol.name, offset))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Symbol> {
+        self.symbols.iter()
+    }
+}
+
+// 読み込み対象のセグメント
+pub struct ElfSegment<'a> {
+    pub paddr: u32,
+    pub data: &'a [u8],
+    pub memsz: u32,
+}
+
+pub struct ElfFile<'a> {
+    pub entry: u32,
+    pub segments: Vec<ElfSegment<'a>>,
+    pub symbols: SymbolTable,
+}
+
+impl<'a> ElfFile<'a> {
+    pub fn parse(array: &'a [u8]) -> Result<Self> {
+        if !is_elf(array) {
+            return Err(ElfError::InvalidMagic);
+        }
+
+        let ehdr: Elf32Ehdr = read_struct(array, 0)?;
+
+        if !ehdr.is_valid() {
+            return Err(ElfError::UnsupportedClass);
+        }
+
+        if ehdr.e_ident[EI_DATA] != ELFDATA2LSB {
+            return Err(ElfError::UnsupportedEndian);
+        }
+
+        if ehdr.e_type != ET_EXEC {
+            return Err(ElfError::UnsupportedType(ehdr.e_type));
+        }
+
+        if ehdr.e_machine != EM_RISCV {
+            return Err(ElfError::UnsupportedMachine(ehdr.e_machine));
+        }
+
+        let phoff = ehdr.e_phoff as usize;
+        let phentsize = (ehdr.e_phentsize as usize).max(size_of::<Elf32Phdr>());
+
+        let mut segments = Vec::new();
+
+        for i in 0..ehdr.e_phnum as usize {
+            let phdr: Elf32Phdr = read_struct(array, phoff + i * phentsize)?;
+
+            if !phdr.is_load_seg() {
+                continue;
+            }
+
+            if phdr.p_memsz < phdr.p_filesz || phdr.p_paddr.checked_add(phdr.p_memsz).is_none() {
+                return Err(ElfError::InvalidSegment { index: i });
+            }
+
+            let data = slice(array, phdr.p_offset as usize, phdr.p_filesz as usize)?;
+
+            segments.push(ElfSegment {
+                paddr: phdr.p_paddr,
+                data,
+                memsz: phdr.p_memsz,
+            });
+        }
+
+        let symbols = Self::parse_symbols(array, &ehdr)?;
+
+        Ok(Self {
+            entry: ehdr.e_entry,
+            segments,
+            symbols,
+        })
+    }
+
+    fn parse_symbols(array: &[u8], ehdr: &Elf32Ehdr) -> Result<SymbolTable> {
+        let shoff = ehdr.e_shoff as usize;
+        let shnum = ehdr.e_shnum as usize;
+        let shentsize = (ehdr.e_shentsize as usize).max(size_of::<Elf32Shdr>());
+
+        let mut symbols = Vec::new();
+
+        if shoff == 0 {
+            return Ok(SymbolTable { symbols });
+        }
+
+        for i in 0..shnum {
+            let shdr: Elf32Shdr = read_struct(array, shoff + i * shentsize)?;
+
+            if shdr.sh_type != SHT_SYMTAB {
+                continue;
+            }
+
+            let link = shdr.sh_link as usize;
+
+            if link >= shnum {
+                return Err(ElfError::InvalidSymbolTable);
+            }
+
+            let strtab: Elf32Shdr = read_struct(array, shoff + link * shentsize)?;
+            let strtab = slice(array, strtab.sh_offset as usize, strtab.sh_size as usize)?;
+
+            let entsize = (shdr.sh_entsize as usize).max(size_of::<Elf32Sym>());
+            let table = slice(array, shdr.sh_offset as usize, shdr.sh_size as usize)?;
+
+            for j in 0..table.len() / entsize {
+                let sym: Elf32Sym = read_struct(table, j * entsize)?;
+
+                let kind = sym.st_info & 0xf;
+
+                if sym.st_shndx == SHN_UNDEF || !matches!(kind, STT_NOTYPE | STT_OBJECT | STT_FUNC)
+                {
+                    continue;
+                }
+
+                let name = strtab
+                    .get(sym.st_name as usize..)
+                    .and_then(|s| s.split(|c| *c == 0).next())
+                    .ok_or(ElfError::InvalidSymbolTable)?;
+
+                // $xなどのマッピングシンボルは除く
+                if name.is_empty() || name.starts_with(b"$") {
+                    continue;
+                }
+
+                symbols.push(Symbol {
+                    name: String::from_utf8_lossy(name).into_owned(),
+                    addr: sym.st_value,
+                    size: sym.st_size,
+                });
+            }
+        }
+
+        symbols.sort_by_key(|s| s.addr);
+
+        Ok(SymbolTable { symbols })
+    }
+
+    // 読み込まれる物理アドレスの範囲
+    pub fn range(&self) -> Range<u32> {
+        let start = self.segments.iter().map(|s| s.paddr).min().unwrap_or(0);
+        let end = self
+            .segments
+            .iter()
+            .map(|s| s.paddr + s.memsz)
+            .max()
+            .unwrap_or(0);
+
+        start..end
+    }
+}
+
+pub fn is_elf(array: &[u8]) -> bool {
+    array.starts_with(&ELF_MAGIC)
+}
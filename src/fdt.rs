@@ -0,0 +1,271 @@
+use std::ops::Range;
+
+use crate::{
+    bus::{Bus, MEMORY_BASE},
+    csr::{CACHE_BLOCK_SIZE, TIMEBASE_FREQ, isa_extensions, isa_string},
+};
+
+const FDT_MAGIC: u32 = 0xd00dfeed;
+const FDT_VERSION: u32 = 17;
+const FDT_LAST_COMP_VERSION: u32 = 16;
+
+const FDT_HEADER_SIZE: usize = 40;
+const FDT_RSVMAP_SIZE: usize = 16; // 終端のエントリのみ
+
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_END: u32 = 9;
+
+pub const DEFAULT_BOOTARGS: &str =
+    "earlycon=uart8250,mmio,0x10000000 console=tty0 console=ttyS0 rw init=/init";
+
+// デバイスツリーを生成する際の設定
+#[derive(Debug, Clone)]
+pub struct FdtConfig {
+    pub bootargs: String,
+    pub initrd: Option<Range<u32>>,
+}
+
+impl Default for FdtConfig {
+    fn default() -> Self {
+        Self {
+            bootargs: DEFAULT_BOOTARGS.to_string(),
+            initrd: None,
+        }
+    }
+}
+
+// Flattened Device Treeのblobを組み立てる構造体
+#[derive(Default)]
+pub struct FdtBuilder {
+    structure: Vec<u8>,
+    strings: Vec<u8>,
+    depth: usize,
+    next_phandle: u32,
+}
+
+impl FdtBuilder {
+    fn push_u32(&mut self, value: u32) {
+        self.structure.extend_from_slice(&value.to_be_bytes());
+    }
+
+    fn align(&mut self) {
+        while !self.structure.len().is_multiple_of(4) {
+            self.structure.push(0);
+        }
+    }
+
+    // 同じ名前は使い回す
+    fn string_offset(&mut self, name: &str) -> u32 {
+        let mut offset = 0;
+
+        for s in self.strings.split(|c| *c == 0) {
+            if s == name.as_bytes() {
+                return offset as u32;
+            }
+
+            offset += s.len() + 1;
+        }
+
+        let offset = self.strings.len();
+
+        self.strings.extend_from_slice(name.as_bytes());
+        self.strings.push(0);
+
+        offset as u32
+    }
+
+    pub fn alloc_phandle(&mut self) -> u32 {
+        self.next_phandle += 1;
+
+        self.next_phandle
+    }
+
+    pub fn begin_node(&mut self, name: &str) -> &mut Self {
+        self.push_u32(FDT_BEGIN_NODE);
+        self.structure.extend_from_slice(name.as_bytes());
+        self.structure.push(0);
+        self.align();
+
+        self.depth += 1;
+
+        self
+    }
+
+    pub fn end_node(&mut self) -> &mut Self {
+        self.push_u32(FDT_END_NODE);
+
+        self.depth -= 1;
+
+        self
+    }
+
+    pub fn property(&mut self, name: &str, value: &[u8]) -> &mut Self {
+        let nameoff = self.string_offset(name);
+
+        self.push_u32(FDT_PROP);
+        self.push_u32(value.len() as u32);
+        self.push_u32(nameoff);
+        self.structure.extend_from_slice(value);
+        self.align();
+
+        self
+    }
+
+    pub fn property_null(&mut self, name: &str) -> &mut Self {
+        self.property(name, &[])
+    }
+
+    pub fn property_u32(&mut self, name: &str, value: u32) -> &mut Self {
+        self.property(name, &value.to_be_bytes())
+    }
+
+    pub fn property_cells(&mut self, name: &str, cells: &[u32]) -> &mut Self {
+        let value: Vec<u8> = cells.iter().flat_map(|c| c.to_be_bytes()).collect();
+
+        self.property(name, &value)
+    }
+
+    pub fn property_string(&mut self, name: &str, value: &str) -> &mut Self {
+        self.property_strings(name, &[value])
+    }
+
+    pub fn property_strings<S: AsRef<str>>(&mut self, name: &str, values: &[S]) -> &mut Self {
+        let mut value = Vec::new();
+
+        for s in values {
+            value.extend_from_slice(s.as_ref().as_bytes());
+            value.push(0);
+        }
+
+        self.property(name, &value)
+    }
+
+    pub fn finish(mut self) -> Vec<u8> {
+        assert_eq!(self.depth, 0, "[ERROR]: unbalanced fdt nodes.");
+
+        self.push_u32(FDT_END);
+
+        let off_mem_rsvmap = FDT_HEADER_SIZE;
+        let off_dt_struct = off_mem_rsvmap + FDT_RSVMAP_SIZE;
+        let off_dt_strings = off_dt_struct + self.structure.len();
+        let totalsize = off_dt_strings + self.strings.len();
+
+        let header = [
+            FDT_MAGIC,
+            totalsize as u32,
+            off_dt_struct as u32,
+            off_dt_strings as u32,
+            off_mem_rsvmap as u32,
+            FDT_VERSION,
+            FDT_LAST_COMP_VERSION,
+            0, // boot_cpuid_phys
+            self.strings.len() as u32,
+            self.structure.len() as u32,
+        ];
+
+        let mut blob = Vec::with_capacity(totalsize);
+
+        for value in header {
+            blob.extend_from_slice(&value.to_be_bytes());
+        }
+
+        blob.extend_from_slice(&[0; FDT_RSVMAP_SIZE]);
+        blob.extend_from_slice(&self.structure);
+        blob.extend_from_slice(&self.strings);
+
+        blob
+    }
+}
+
+// Busの構成からデバイスツリーを生成する関数
+pub fn generate(bus: &Bus, config: &FdtConfig) -> Vec<u8> {
+    let mut fdt = FdtBuilder::default();
+
+    let intc_phandles: Vec<u32> = (0..bus.hart_count()).map(|_| fdt.alloc_phandle()).collect();
+    let plic_phandle = fdt.alloc_phandle();
+
+    let stdout_path = bus
+        .find_device("serial")
+        .map(|base| format!("/soc/serial@{:x}", base));
+
+    fdt.begin_node("")
+        .property_u32("#address-cells", 1)
+        .property_u32("#size-cells", 1)
+        .property_string("compatible", "tiny-rv32ima-sim")
+        .property_string("model", "tiny-rv32ima-sim");
+
+    fdt.begin_node("chosen")
+        .property_string("bootargs", &config.bootargs);
+
+    if let Some(path) = &stdout_path {
+        fdt.property_string("stdout-path", path);
+    }
+
+    if let Some(initrd) = &config.initrd {
+        fdt.property_u32("linux,initrd-start", initrd.start)
+            .property_u32("linux,initrd-end", initrd.end);
+    }
+
+    fdt.end_node();
+
+    if let Some(path) = &stdout_path {
+        fdt.begin_node("aliases")
+            .property_string("serial0", path)
+            .end_node();
+    }
+
+    fdt.begin_node(&format!("memory@{:x}", MEMORY_BASE))
+        .property_string("device_type", "memory")
+        .property_cells("reg", &[MEMORY_BASE, bus.memory_size() as u32])
+        .end_node();
+
+    fdt.begin_node("cpus")
+        .property_u32("#address-cells", 1)
+        .property_u32("#size-cells", 0)
+        .property_u32("timebase-frequency", TIMEBASE_FREQ);
+
+    for (hart, intc_phandle) in intc_phandles.iter().enumerate() {
+        write_cpu(&mut fdt, hart as u32, *intc_phandle);
+    }
+
+    fdt.end_node(); // cpus
+
+    fdt.begin_node("soc")
+        .property_u32("#address-cells", 1)
+        .property_u32("#size-cells", 1)
+        .property_string("compatible", "simple-bus")
+        .property_null("ranges");
+
+    bus.write_fdt(&mut fdt, &intc_phandles, plic_phandle);
+
+    fdt.end_node(); // soc
+    fdt.end_node(); // root
+
+    fdt.finish()
+}
+
+// cpusの下にhartのノードを書き込む関数
+fn write_cpu(fdt: &mut FdtBuilder, hart: u32, intc_phandle: u32) {
+    fdt.begin_node(&format!("cpu@{}", hart))
+        .property_string("device_type", "cpu")
+        .property_u32("reg", hart)
+        .property_string("status", "okay")
+        .property_string("compatible", "riscv")
+        .property_string("riscv,isa-base", "rv32i")
+        .property_string("riscv,isa", &isa_string())
+        .property_strings("riscv,isa-extensions", &isa_extensions())
+        .property_string("mmu-type", "riscv,sv32")
+        .property_u32("riscv,cbom-block-size", CACHE_BLOCK_SIZE)
+        .property_u32("riscv,cboz-block-size", CACHE_BLOCK_SIZE);
+
+    fdt.begin_node("interrupt-controller")
+        .property_u32("#interrupt-cells", 1)
+        .property_null("interrupt-controller")
+        .property_string("compatible", "riscv,cpu-intc")
+        .property_u32("phandle", intc_phandle)
+        .end_node();
+
+    fdt.end_node(); // cpu
+}
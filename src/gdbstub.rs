@@ -0,0 +1,864 @@
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    io::{self, BufRead, BufReader, ErrorKind, Read, Write},
+    net::{TcpListener, TcpStream},
+};
+
+use crate::{
+    Priv,
+    bus::Bus,
+    cpu::{Cpu, WatchKind, Watchpoint},
+    csr::CSR_NAMES,
+    disasm::freg_name,
+};
+
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+
+const PC_REGNUM: u32 = 32;
+const FPR_REGNUM_BASE: u32 = 33;
+const CSR_REGNUM_BASE: u32 = 65; // gdbではf0-f31とfcsr等の後にCSRが並ぶ
+const PRIV_REGNUM: u32 = CSR_REGNUM_BASE + 4096;
+
+// fpuのfeatureに載せるCSR
+const FP_CSRS: [(u32, &str); 3] = [(0x001, "fflags"), (0x002, "frm"), (0x003, "fcsr")];
+
+const MAX_PACKET_SIZE: usize = 0x4000;
+const MAX_MEMORY_ACCESS: usize = 0x1000;
+
+const REG_NAMES: [&str; 32] = [
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "fp", "s1", "a0", "a1", "a2", "a3", "a4",
+    "a5", "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4",
+    "t5", "t6",
+];
+
+// 停止した理由
+#[derive(Debug, Clone, Copy)]
+pub enum StopReason {
+    Interrupt,
+    Breakpoint(BreakpointKind),
+    Step,
+    Watch(WatchKind, u32),
+}
+
+// gdbから指示された再開の方法
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resume {
+    Continue,
+    Step,
+    Detach,
+    Kill,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakpointKind {
+    Software,
+    Hardware,
+}
+
+// GDB Remote Serial Protocolのスタブ
+// ブレークポイントは命令を書き換えずにPCとの比較で実現する
+pub struct GdbStub {
+    reader: BufReader<TcpStream>,
+    breakpoints: HashMap<u32, BreakpointKind>,
+    last_stop: Option<StopReason>,
+}
+
+impl GdbStub {
+    // addrで待ち受けてgdbが接続するまでブロックする
+    pub fn listen(addr: &str) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+
+        eprintln!("[INFO]: waiting for gdb on {}", listener.local_addr()?);
+
+        let (stream, peer) = listener.accept()?;
+
+        eprintln!("[INFO]: gdb connected from {}", peer);
+
+        Self::new(stream)
+    }
+
+    fn new(stream: TcpStream) -> io::Result<Self> {
+        stream.set_nodelay(true)?;
+
+        Ok(Self {
+            reader: BufReader::new(stream),
+            breakpoints: HashMap::new(),
+            last_stop: None,
+        })
+    }
+
+    pub fn breakpoint(&self, pc: u32) -> Option<BreakpointKind> {
+        self.breakpoints.get(&pc).copied()
+    }
+
+    // 実行中にCtrl-C(0x03)が送られたかを確認する関数
+    pub fn poll_interrupt(&mut self) -> io::Result<bool> {
+        self.reader.get_ref().set_nonblocking(true)?;
+
+        let result = match self.reader.fill_buf() {
+            Ok([]) => Err(ErrorKind::UnexpectedEof.into()),
+            Ok(buf) => {
+                let is_interrupted = buf.contains(&0x03);
+                let len = buf.len();
+
+                self.reader.consume(len);
+
+                Ok(is_interrupted)
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => Ok(false),
+            Err(e) => Err(e),
+        };
+
+        self.reader.get_ref().set_nonblocking(false)?;
+
+        result
+    }
+
+    // 停止を通知し、再開が指示されるまでパケットを処理する関数
+    pub fn stop(&mut self, reason: StopReason, cpu: &mut Cpu, bus: &mut Bus) -> io::Result<Resume> {
+        self.last_stop = Some(reason);
+
+        let reply = self.stop_reply();
+        self.send_packet(&reply)?;
+
+        self.serve(cpu, bus)
+    }
+
+    // 接続直後の最初のパケットから処理する関数
+    pub fn attach(&mut self, cpu: &mut Cpu, bus: &mut Bus) -> io::Result<Resume> {
+        self.last_stop = None;
+
+        self.serve(cpu, bus)
+    }
+
+    fn serve(&mut self, cpu: &mut Cpu, bus: &mut Bus) -> io::Result<Resume> {
+        loop {
+            let Some(packet) = self.read_packet()? else {
+                // 切断された場合はそのまま実行を続ける
+                return Ok(Resume::Detach);
+            };
+
+            let (reply, resume) = self.handle_packet(&packet, cpu, bus);
+
+            if let Some(reply) = reply {
+                self.send_packet(&reply)?;
+            }
+
+            if let Some(resume) = resume {
+                return Ok(resume);
+            }
+        }
+    }
+
+    fn stop_reply(&self) -> String {
+        match self.last_stop {
+            Some(StopReason::Interrupt) => format!("T{:02x}", SIGINT),
+            Some(StopReason::Step) | None => format!("T{:02x}", SIGTRAP),
+            Some(StopReason::Breakpoint(BreakpointKind::Software)) => {
+                format!("T{:02x}swbreak:;", SIGTRAP)
+            }
+            Some(StopReason::Breakpoint(BreakpointKind::Hardware)) => {
+                format!("T{:02x}hwbreak:;", SIGTRAP)
+            }
+            Some(StopReason::Watch(kind, addr)) => {
+                let name = match kind {
+                    WatchKind::Write => "watch",
+                    WatchKind::Read => "rwatch",
+                    WatchKind::Access => "awatch",
+                };
+
+                format!("T{:02x}{}:{:x};", SIGTRAP, name, addr)
+            }
+        }
+    }
+
+    // 返信するパケットと再開の方法を返す
+    fn handle_packet(
+        &mut self,
+        packet: &str,
+        cpu: &mut Cpu,
+        bus: &mut Bus,
+    ) -> (Option<String>, Option<Resume>) {
+        let ok = || Some("OK".to_string());
+        let error = || Some("E01".to_string());
+
+        let Some(command) = packet.chars().next() else {
+            return (Some(String::new()), None);
+        };
+        // 不正なバイトはU+FFFDに置き換えられているので、1文字目の長さで切り出す
+        let args = &packet[command.len_utf8()..];
+
+        let reply = match command {
+            '?' => Some(self.stop_reply()),
+            'g' => {
+                let mut reply = String::new();
+
+                for reg in 0..32 {
+                    push_u32(&mut reply, cpu.read_reg(reg));
+                }
+
+                push_u32(&mut reply, cpu.pc());
+
+                Some(reply)
+            }
+            'G' => match parse_u32_list(args) {
+                Some(values) if values.len() >= 33 => {
+                    for (reg, value) in values.iter().enumerate().take(32).skip(1) {
+                        cpu.write_reg(reg as u32, *value);
+                    }
+
+                    cpu.set_pc(values[32]);
+
+                    ok()
+                }
+                _ => error(),
+            },
+            'p' => match u32::from_str_radix(args, 16)
+                .ok()
+                .and_then(|n| read_register(cpu, n))
+            {
+                Some(reply) => Some(reply),
+                None => error(),
+            },
+            'P' => {
+                let parsed = args.split_once('=').and_then(|(n, v)| {
+                    let n = u32::from_str_radix(n, 16).ok()?;
+
+                    Some((n, parse_hex_bytes(v)?))
+                });
+
+                match parsed {
+                    Some((n, value)) if write_register(cpu, n, &value) => ok(),
+                    _ => error(),
+                }
+            }
+            'm' => match parse_addr_len(args) {
+                Some((addr, len)) => {
+                    let mut buf = vec![0; len.min(MAX_MEMORY_ACCESS)];
+
+                    if cpu.debug_read_memory(addr, &mut buf, bus) {
+                        Some(buf.iter().fold(String::new(), |mut s, b| {
+                            let _ = write!(s, "{:02x}", b);
+                            s
+                        }))
+                    } else {
+                        Some("E14".to_string()) // EFAULT
+                    }
+                }
+                None => error(),
+            },
+            'M' => {
+                let parsed = args.split_once(':').and_then(|(addr_len, data)| {
+                    let (addr, len) = parse_addr_len(addr_len)?;
+                    let data = parse_hex_bytes(data)?;
+
+                    (data.len() == len).then_some((addr, data))
+                });
+
+                match parsed {
+                    Some((addr, data)) if cpu.debug_write_memory(addr, &data, bus) => ok(),
+                    Some(_) => Some("E14".to_string()),
+                    None => error(),
+                }
+            }
+            'Z' | 'z' => self.handle_breakpoint(command == 'Z', args, cpu),
+            'c' | 's' => {
+                if let Some(addr) = parse_hex(args) {
+                    cpu.set_pc(addr);
+                }
+
+                let resume = if command == 'c' {
+                    Resume::Continue
+                } else {
+                    Resume::Step
+                };
+
+                return (None, Some(resume));
+            }
+            'D' => return (ok(), Some(Resume::Detach)),
+            'k' => return (None, Some(Resume::Kill)),
+            'H' | 'T' => ok(),
+            'q' => self.handle_query(args, cpu),
+            _ => None,
+        };
+
+        (Some(reply.unwrap_or_default()), None)
+    }
+
+    fn handle_breakpoint(&mut self, is_insert: bool, args: &str, cpu: &mut Cpu) -> Option<String> {
+        let mut fields = args.split(',');
+
+        let kind = fields.next()?;
+        let addr = parse_hex(fields.next()?)?;
+        let len = fields.next().and_then(parse_hex).unwrap_or(4);
+
+        let watch_kind = match kind {
+            "0" | "1" => {
+                let kind = if kind == "0" {
+                    BreakpointKind::Software
+                } else {
+                    BreakpointKind::Hardware
+                };
+
+                if is_insert {
+                    self.breakpoints.insert(addr, kind);
+                } else {
+                    self.breakpoints.remove(&addr);
+                }
+
+                return Some("OK".to_string());
+            }
+            "2" => WatchKind::Write,
+            "3" => WatchKind::Read,
+            "4" => WatchKind::Access,
+            _ => return None,
+        };
+
+        let watchpoint = Watchpoint {
+            addr,
+            len,
+            kind: watch_kind,
+        };
+
+        if is_insert {
+            cpu.add_watchpoint(watchpoint);
+        } else {
+            cpu.remove_watchpoint(watchpoint);
+        }
+
+        Some("OK".to_string())
+    }
+
+    fn handle_query(&self, args: &str, cpu: &Cpu) -> Option<String> {
+        if args.starts_with("Supported") {
+            return Some(format!(
+                "PacketSize={:x};qXfer:features:read+;swbreak+;hwbreak+",
+                MAX_PACKET_SIZE
+            ));
+        }
+
+        if let Some(annex) = args.strip_prefix("Xfer:features:read:") {
+            let (name, range) = annex.split_once(':')?;
+
+            if name != "target.xml" {
+                return Some("E00".to_string());
+            }
+
+            let (offset, len) = parse_addr_len(range)?;
+            let xml = target_xml(cpu);
+
+            let offset = (offset as usize).min(xml.len());
+            let end = (offset + len).min(xml.len());
+
+            let prefix = if end == xml.len() { 'l' } else { 'm' };
+
+            return Some(format!("{}{}", prefix, &xml[offset..end]));
+        }
+
+        match args {
+            "Attached" => Some("1".to_string()),
+            "C" => Some("QC1".to_string()),
+            "fThreadInfo" => Some("m1".to_string()),
+            "sThreadInfo" => Some("l".to_string()),
+            _ => None,
+        }
+    }
+
+    // 受信したパケットの中身を返す
+    // 切断された場合はNoneを返す
+    fn read_packet(&mut self) -> io::Result<Option<String>> {
+        loop {
+            let mut byte = [0];
+
+            if self.reader.read(&mut byte)? == 0 {
+                return Ok(None);
+            }
+
+            // +/-や停止中のCtrl-Cは読み飛ばす
+            if byte[0] != b'$' {
+                continue;
+            }
+
+            // qSupportedで伝えたPacketSizeを超える分は読み込まない
+            let mut data = Vec::new();
+            let limit = MAX_PACKET_SIZE as u64 + 1;
+
+            (&mut self.reader).take(limit).read_until(b'#', &mut data)?;
+
+            if data.pop() != Some(b'#') {
+                if data.len() < MAX_PACKET_SIZE {
+                    // '#'の前に切断された
+                    return Ok(None);
+                }
+
+                // 大きすぎるパケットは残りを'$'まで読み飛ばして再送させる
+                self.reader.get_mut().write_all(b"-")?;
+                continue;
+            }
+
+            let mut checksum = [0; 2];
+            self.reader.read_exact(&mut checksum)?;
+
+            let expected = std::str::from_utf8(&checksum)
+                .ok()
+                .and_then(|c| u8::from_str_radix(c, 16).ok());
+
+            let actual = data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+
+            if expected != Some(actual) {
+                self.reader.get_mut().write_all(b"-")?;
+                continue;
+            }
+
+            self.reader.get_mut().write_all(b"+")?;
+
+            return Ok(Some(String::from_utf8_lossy(&unescape(&data)).into_owned()));
+        }
+    }
+
+    fn send_packet(&mut self, data: &str) -> io::Result<()> {
+        let mut escaped = Vec::with_capacity(data.len());
+
+        for b in data.bytes() {
+            if matches!(b, b'#' | b'$' | b'}' | b'*') {
+                escaped.push(b'}');
+                escaped.push(b ^ 0x20);
+            } else {
+                escaped.push(b);
+            }
+        }
+
+        let checksum = escaped.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+
+        let stream = self.reader.get_mut();
+
+        stream.write_all(b"$")?;
+        stream.write_all(&escaped)?;
+        stream.write_all(format!("#{:02x}", checksum).as_bytes())?;
+
+        // '+'の受信はread_packetで読み飛ばす
+        stream.flush()
+    }
+}
+
+fn unescape(data: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(data.len());
+    let mut iter = data.iter();
+
+    while let Some(b) = iter.next() {
+        match b {
+            b'}' => {
+                if let Some(b) = iter.next() {
+                    result.push(b ^ 0x20);
+                }
+            }
+            _ => result.push(*b),
+        }
+    }
+
+    result
+}
+
+// gdbに渡すレジスタの値はターゲットのエンディアンの16進数
+fn push_u32(s: &mut String, value: u32) {
+    for b in value.to_le_bytes() {
+        let _ = write!(s, "{:02x}", b);
+    }
+}
+
+fn push_u64(s: &mut String, value: u64) {
+    for b in value.to_le_bytes() {
+        let _ = write!(s, "{:02x}", b);
+    }
+}
+
+fn parse_hex(s: &str) -> Option<u32> {
+    u32::from_str_radix(s, 16).ok()
+}
+
+fn parse_hex_bytes(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn parse_u32_list(s: &str) -> Option<Vec<u32>> {
+    let bytes = parse_hex_bytes(s)?;
+
+    Some(
+        bytes
+            .chunks_exact(4)
+            .map(|c| u32::from_le_bytes(c.try_into().unwrap()))
+            .collect(),
+    )
+}
+
+fn parse_addr_len(s: &str) -> Option<(u32, usize)> {
+    let (addr, len) = s.split_once(',')?;
+
+    Some((parse_hex(addr)?, parse_hex(len)? as usize))
+}
+
+// 浮動小数点レジスタは64bit、それ以外は32bit
+fn read_register(cpu: &Cpu, n: u32) -> Option<String> {
+    let mut reply = String::new();
+
+    match n {
+        0..32 => push_u32(&mut reply, cpu.read_reg(n)),
+        PC_REGNUM => push_u32(&mut reply, cpu.pc()),
+        FPR_REGNUM_BASE..CSR_REGNUM_BASE => {
+            push_u64(&mut reply, cpu.read_freg(n - FPR_REGNUM_BASE))
+        }
+        PRIV_REGNUM => push_u32(&mut reply, cpu.prv() as u32),
+        _ => {
+            let csr = n.checked_sub(CSR_REGNUM_BASE)?;
+
+            push_u32(&mut reply, cpu.csr().read(csr, Priv::Machine).ok()?);
+        }
+    }
+
+    Some(reply)
+}
+
+fn write_register(cpu: &mut Cpu, n: u32, bytes: &[u8]) -> bool {
+    if (FPR_REGNUM_BASE..CSR_REGNUM_BASE).contains(&n) {
+        let Ok(bytes) = bytes.try_into() else {
+            return false;
+        };
+
+        cpu.write_freg(n - FPR_REGNUM_BASE, u64::from_le_bytes(bytes));
+        return true;
+    }
+
+    let Ok(bytes) = bytes.try_into() else {
+        return false;
+    };
+    let value = u32::from_le_bytes(bytes);
+
+    match n {
+        0 => true,
+        1..32 => {
+            cpu.write_reg(n, value);
+            true
+        }
+        PC_REGNUM => {
+            cpu.set_pc(value);
+            true
+        }
+        _ => match n.checked_sub(CSR_REGNUM_BASE) {
+            Some(csr) => cpu.mut_csr().write(csr, value, Priv::Machine).is_ok(),
+            None => false,
+        },
+    }
+}
+
+// Machineモードで読み込めるCSRのみ載せる
+fn target_xml(cpu: &Cpu) -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\"?>\
+         <!DOCTYPE target SYSTEM \"gdb-target.dtd\">\
+         <target version=\"1.0\">\
+         <architecture>riscv:rv32</architecture>\
+         <feature name=\"org.gnu.gdb.riscv.cpu\">",
+    );
+
+    for (i, name) in REG_NAMES.iter().enumerate() {
+        let ty = match *name {
+            "ra" => "code_ptr",
+            "sp" | "fp" => "data_ptr",
+            _ => "int",
+        };
+
+        let _ = write!(
+            xml,
+            "<reg name=\"{}\" bitsize=\"32\" type=\"{}\" regnum=\"{}\"/>",
+            name, ty, i
+        );
+    }
+
+    let _ = write!(
+        xml,
+        "<reg name=\"pc\" bitsize=\"32\" type=\"code_ptr\" regnum=\"{}\"/>\
+         </feature>\
+         <feature name=\"org.gnu.gdb.riscv.fpu\">",
+        PC_REGNUM
+    );
+
+    for i in 0..32 {
+        let _ = write!(
+            xml,
+            "<reg name=\"{}\" bitsize=\"64\" type=\"ieee_double\" regnum=\"{}\"/>",
+            freg_name(i),
+            FPR_REGNUM_BASE + i
+        );
+    }
+
+    // mstatus.FSがOffの間は読み込めないが常に載せる
+    for (csr, name) in FP_CSRS {
+        let _ = write!(
+            xml,
+            "<reg name=\"{}\" bitsize=\"32\" type=\"int\" regnum=\"{}\"/>",
+            name,
+            CSR_REGNUM_BASE + csr
+        );
+    }
+
+    xml.push_str(
+        "</feature>\
+         <feature name=\"org.gnu.gdb.riscv.csr\">",
+    );
+
+    for (csr, name) in CSR_NAMES {
+        if FP_CSRS.iter().all(|(fp_csr, _)| *fp_csr != csr)
+            && cpu.csr().read(csr, Priv::Machine).is_ok()
+        {
+            let _ = write!(
+                xml,
+                "<reg name=\"{}\" bitsize=\"32\" type=\"int\" regnum=\"{}\" group=\"csr\"/>",
+                name,
+                CSR_REGNUM_BASE + csr
+            );
+        }
+    }
+
+    let _ = write!(
+        xml,
+        "</feature>\
+         <feature name=\"org.gnu.gdb.riscv.virtual\">\
+         <reg name=\"priv\" bitsize=\"32\" type=\"int\" regnum=\"{}\" group=\"general\"/>\
+         </feature>\
+         </target>",
+        PRIV_REGNUM
+    );
+
+    xml
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulator::{MEMORY_BASE, Simulator};
+
+    // ループバックで接続したスタブとgdb側のソケットを返す
+    fn connect() -> (GdbStub, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+
+        (GdbStub::new(stream).unwrap(), client)
+    }
+
+    fn read_bytes(client: &mut TcpStream, len: usize) -> Vec<u8> {
+        let mut buf = vec![0; len];
+        client.read_exact(&mut buf).unwrap();
+
+        buf
+    }
+
+    #[test]
+    fn test_read_packet() {
+        let (mut stub, mut client) = connect();
+
+        // チェックサムが合わないパケットには'-'を返して読み直す
+        client.write_all(b"+$g#00$m0,4#fd").unwrap();
+
+        assert_eq!(stub.read_packet().unwrap().as_deref(), Some("m0,4"));
+        assert_eq!(read_bytes(&mut client, 2), b"-+");
+
+        // エスケープされたバイトは戻してから返す
+        client.write_all(b"$X}\x03}]#b2").unwrap();
+
+        assert_eq!(stub.read_packet().unwrap().as_deref(), Some("X#}"));
+        assert_eq!(read_bytes(&mut client, 1), b"+");
+
+        // PacketSizeを超えるパケットには'-'を返す
+        let mut oversize = vec![b'$'];
+        oversize.resize(MAX_PACKET_SIZE + 2, b'a');
+        oversize.extend_from_slice(b"#00$g#67");
+        client.write_all(&oversize).unwrap();
+
+        assert_eq!(stub.read_packet().unwrap().as_deref(), Some("g"));
+        assert_eq!(read_bytes(&mut client, 2), b"-+");
+
+        // '#'の前に切断された場合は途中までのパケットを返さない
+        client.write_all(b"$abc").unwrap();
+        drop(client);
+
+        assert_eq!(stub.read_packet().unwrap(), None);
+    }
+
+    #[test]
+    fn test_send_packet() {
+        let (mut stub, mut client) = connect();
+
+        stub.send_packet("a#b$c}d*").unwrap();
+
+        let sent = read_bytes(&mut client, 16);
+
+        assert_eq!(&sent[..13], b"$a}\x03b}\x04c}]d}\x0a");
+        assert_eq!(&sent[13..], b"#ec");
+        assert_eq!(unescape(&sent[1..13]), b"a#b$c}d*");
+    }
+
+    #[test]
+    fn test_parse_hex() {
+        assert_eq!(parse_hex_bytes("0aff"), Some(vec![0x0a, 0xff]));
+        assert_eq!(parse_hex_bytes(""), Some(vec![]));
+        assert_eq!(parse_hex_bytes("abc"), None);
+        assert_eq!(parse_hex_bytes("zz"), None);
+
+        assert_eq!(parse_addr_len("80000000,10"), Some((0x80000000, 0x10)));
+        assert_eq!(parse_addr_len("80000000"), None);
+        assert_eq!(parse_addr_len("x,1"), None);
+        assert_eq!(parse_addr_len("100000000,1"), None);
+    }
+
+    #[test]
+    fn test_register_mapping() {
+        let mut cpu = Cpu::new(0);
+
+        assert!(write_register(&mut cpu, 1, &[0x78, 0x56, 0x34, 0x12]));
+        assert_eq!(cpu.read_reg(1), 0x12345678);
+        assert_eq!(read_register(&cpu, 1).as_deref(), Some("78563412"));
+
+        // x0への書き込みは無視する
+        assert!(write_register(&mut cpu, 0, &[1, 0, 0, 0]));
+        assert_eq!(cpu.read_reg(0), 0);
+
+        assert!(write_register(&mut cpu, PC_REGNUM, &[0, 0x10, 0, 0x80]));
+        assert_eq!(cpu.pc(), 0x80001000);
+
+        // f0は64bit
+        assert!(write_register(&mut cpu, 33, &[1, 2, 3, 4, 5, 6, 7, 8]));
+        assert_eq!(cpu.read_freg(0), 0x0807060504030201);
+        assert!(!write_register(&mut cpu, 33, &[1, 2, 3, 4]));
+        assert_eq!(read_register(&cpu, 33).as_deref(), Some("0102030405060708"));
+
+        // CSRは65から始まる
+        assert!(write_register(
+            &mut cpu,
+            65 + 0x340,
+            &[0xef, 0xbe, 0xad, 0xde]
+        ));
+        assert_eq!(cpu.csr().read(0x340, Priv::Machine).ok(), Some(0xdeadbeef));
+        assert_eq!(read_register(&cpu, 65 + 0x340).as_deref(), Some("efbeadde"));
+        assert_eq!(read_register(&cpu, 65 + 0xfff), None);
+
+        assert_eq!(read_register(&cpu, 4161).as_deref(), Some("03000000"));
+    }
+
+    #[test]
+    fn test_handle_packet() {
+        let (mut stub, _client) = connect();
+
+        let program: Vec<u8> = [
+            0x00100093u32, // li ra, 1
+            0x00200113,    // li sp, 2
+            0x0000006f,    // j .
+        ]
+        .iter()
+        .flat_map(|i| i.to_le_bytes())
+        .collect();
+
+        let mut simulator = Simulator::new().setup_headless();
+        simulator.load_flat(&program, MEMORY_BASE).unwrap();
+
+        let mut simulator = simulator.set_entry_point(MEMORY_BASE);
+
+        let (cpu, bus) = simulator.gdb_target();
+
+        let (reply, resume) = stub.handle_packet("g", cpu, bus);
+        let reply = reply.unwrap();
+
+        assert_eq!(resume, None);
+        assert_eq!(reply.len(), 33 * 8);
+        assert_eq!(&reply[8..16], "00000000"); // ra
+        assert_eq!(&reply[32 * 8..], "00000080"); // pc
+
+        assert_eq!(
+            stub.handle_packet("m80000000,4", cpu, bus),
+            (Some("93001000".to_string()), None)
+        );
+        assert_eq!(
+            stub.handle_packet("m0,4", cpu, bus),
+            (Some("E14".to_string()), None)
+        );
+
+        assert_eq!(
+            stub.handle_packet("Z0,80000004,4", cpu, bus),
+            (Some("OK".to_string()), None)
+        );
+        assert_eq!(
+            stub.handle_packet("s", cpu, bus),
+            (None, Some(Resume::Step))
+        );
+
+        simulator.step();
+
+        let (cpu, bus) = simulator.gdb_target();
+
+        assert_eq!(stub.breakpoint(cpu.pc()), Some(BreakpointKind::Software));
+        assert_eq!(
+            stub.handle_packet("p1", cpu, bus),
+            (Some("01000000".to_string()), None)
+        );
+        assert_eq!(
+            stub.handle_packet("p20", cpu, bus),
+            (Some("04000080".to_string()), None)
+        );
+
+        assert_eq!(
+            stub.handle_packet("z0,80000004,4", cpu, bus),
+            (Some("OK".to_string()), None)
+        );
+        assert_eq!(stub.breakpoint(cpu.pc()), None);
+    }
+
+    #[test]
+    fn test_memory_write_breaks_reservation() {
+        // gdbから書き換えたアドレスに対するSC.Wは失敗する
+        let (mut stub, _client) = connect();
+
+        let program: Vec<u8> = [
+            0x80001537u32, // lui a0, 0x80001
+            0x100525af,    // lr.w a1, (a0)
+            0x18d5262f,    // sc.w a2, a3, (a0)
+            0x0000006f,    // j .
+        ]
+        .iter()
+        .flat_map(|i| i.to_le_bytes())
+        .collect();
+
+        let mut simulator = Simulator::new().setup_headless();
+        simulator.load_flat(&program, MEMORY_BASE).unwrap();
+
+        let mut simulator = simulator.set_entry_point(MEMORY_BASE);
+
+        simulator.step();
+        simulator.step();
+
+        let (cpu, bus) = simulator.gdb_target();
+
+        assert_eq!(
+            stub.handle_packet("M80001000,4:2a000000", cpu, bus),
+            (Some("OK".to_string()), None)
+        );
+
+        simulator.step();
+
+        let (cpu, bus) = simulator.gdb_target();
+
+        assert_eq!(cpu.read_reg(12), 1); // a2
+        assert_eq!(
+            stub.handle_packet("m80001000,4", cpu, bus),
+            (Some("2a000000".to_string()), None)
+        );
+    }
+}
@@ -15,6 +15,13 @@ pub trait HostDevice: Send {
 #[cfg(target_arch = "wasm32")]
 pub trait HostDevice: Debug {}
 
+// ホストデバイスからシミュレータ本体への要求
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug)]
+pub enum ControlMessage {
+    SaveSnapshot(std::path::PathBuf),
+}
+
 #[derive(Default)]
 pub struct HostDeviceManager {
     devices: Vec<Box<dyn HostDevice>>,
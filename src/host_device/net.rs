@@ -21,6 +21,7 @@ type Result<T> = std::result::Result<T, Box<dyn Error>>;
 pub struct HostNet {
     net_rx: NativeHostReciever,
     net_tx: NativeHostSender,
+    if_name: String,
 }
 
 #[derive(Default)]
@@ -31,13 +32,19 @@ struct Ifreq {
 
 impl HostDevice for HostNet {
     fn run(self: Box<Self>) {
-        HostNet::run(*self, "tap0").unwrap();
+        let if_name = self.if_name.clone();
+
+        HostNet::run(*self, &if_name).unwrap();
     }
 }
 
 impl HostNet {
-    pub fn new(net_rx: NativeHostReciever, net_tx: NativeHostSender) -> Self {
-        Self { net_rx, net_tx }
+    pub fn new(net_rx: NativeHostReciever, net_tx: NativeHostSender, if_name: String) -> Self {
+        Self {
+            net_rx,
+            net_tx,
+            if_name,
+        }
     }
 
     pub fn run(self, if_name: &str) -> Result<()> {
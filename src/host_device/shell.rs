@@ -1,18 +1,26 @@
 use std::{
     error::Error,
     io::{Write, stdin, stdout},
+    path::PathBuf,
     process::exit,
+    sync::mpsc::Sender,
 };
 
 use termion::{event::Key, input::TermRead, raw::IntoRawMode, screen::ToMainScreen};
 
-use crate::{device::DeviceMessage, host_device::HostDevice, native::NativeHostSender};
+use crate::{
+    device::DeviceMessage,
+    host_device::{ControlMessage, HostDevice},
+    native::NativeHostSender,
+};
 
 type Result<T> = std::result::Result<T, Box<dyn Error>>;
 
 #[derive(Debug)]
 pub struct Shell {
     uart_tx: NativeHostSender,
+    control_tx: Sender<ControlMessage>,
+    snapshot: Option<PathBuf>,
 }
 
 impl HostDevice for Shell {
@@ -26,8 +34,16 @@ fn make_message(c: char) -> DeviceMessage {
 }
 
 impl Shell {
-    pub fn new(uart_tx: NativeHostSender) -> Self {
-        Self { uart_tx }
+    pub fn new(
+        uart_tx: NativeHostSender,
+        control_tx: Sender<ControlMessage>,
+        snapshot: Option<PathBuf>,
+    ) -> Self {
+        Self {
+            uart_tx,
+            control_tx,
+            snapshot,
+        }
     }
 
     pub fn run(self) -> Result<()> {
@@ -47,6 +63,13 @@ impl Shell {
                     drop(stdout);
                     exit(0);
                 }
+                Key::Ctrl('s') => {
+                    // 保存先が指定されていない場合は無視する
+                    if let Some(path) = &self.snapshot {
+                        self.control_tx
+                            .send(ControlMessage::SaveSnapshot(path.clone()))?;
+                    }
+                }
                 Key::Ctrl('a') => self.uart_tx.send(make_message('\x01'))?,
                 Key::Ctrl('c') => self.uart_tx.send(make_message('\x03'))?,
                 Key::Ctrl('e') => self.uart_tx.send(make_message('\x05'))?,
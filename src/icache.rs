@@ -0,0 +1,87 @@
+use crate::{
+    bus::MEMORY_BASE,
+    decode::{Decoded, decode, inst_len},
+    memory::{Memory, PAGE_SIZE},
+};
+
+// 1ページに入る命令の数
+// 圧縮命令があるので2バイトごとに保存する
+const SLOTS_PER_PAGE: usize = PAGE_SIZE / 2;
+
+struct CachedPage {
+    // デコードした時点でのページのバージョン
+    version: u32,
+    // デコードした時点でのキャッシュ全体の世代
+    generation: u32,
+    insts: Box<[Option<Decoded>]>,
+}
+
+// デコード済みの命令を物理ページ単位で保存するキャッシュ
+// ページへの書き込みはMemoryのページのバージョンで検出する
+#[derive(Default)]
+pub struct ICache {
+    pages: Vec<Option<CachedPage>>, // RAMのページ番号がキーになる
+    // FENCE.IやSFENCE.VMAで全体を無効化するたびに増やす
+    generation: u32,
+}
+
+impl ICache {
+    // paの命令をデコードして返す関数
+    // RAMの範囲外の場合とページやRAMの末尾をまたぐ命令の場合はNoneを返す
+    #[inline]
+    pub fn fetch(&mut self, pa: u32, memory: &Memory) -> Option<Decoded> {
+        let offset = pa.wrapping_sub(MEMORY_BASE) as usize;
+
+        if offset + 2 > memory.size() {
+            return None;
+        }
+
+        let idx = offset / PAGE_SIZE;
+        let version = memory.page_version(idx);
+
+        if self.pages.len() != memory.page_count() {
+            self.pages.clear();
+            self.pages.resize_with(memory.page_count(), || None);
+        }
+
+        let page = self.pages[idx].get_or_insert_with(|| CachedPage {
+            version,
+            generation: self.generation,
+            insts: vec![None; SLOTS_PER_PAGE].into_boxed_slice(),
+        });
+
+        if page.version != version || page.generation != self.generation {
+            page.version = version;
+            page.generation = self.generation;
+            page.insts.fill(None);
+        }
+
+        let slot = &mut page.insts[(offset % PAGE_SIZE) / 2];
+
+        if let Some(decoded) = slot {
+            return Some(*decoded);
+        }
+
+        let low = u16::from_le_bytes([memory.array[offset], memory.array[offset + 1]]) as u32;
+
+        let inst = if inst_len(low) == 2 {
+            low
+        } else {
+            // 後半が別のページにある場合は変換後のアドレスが連続しているとは限らない
+            // RAMの末尾で後半が足りない場合も遅いパスでアクセスフォルトにする
+            if offset % PAGE_SIZE == PAGE_SIZE - 2 || offset + 4 > memory.size() {
+                return None;
+            }
+
+            let high = u16::from_le_bytes([memory.array[offset + 2], memory.array[offset + 3]]);
+
+            low | (high as u32) << 16
+        };
+
+        Some(*slot.insert(decode(inst)))
+    }
+
+    pub fn clear(&mut self) {
+        self.generation = self.generation.wrapping_add(1);
+    }
+}
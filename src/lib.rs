@@ -1,13 +1,25 @@
 mod bus;
+#[cfg(not(target_arch = "wasm32"))]
+mod clock;
 mod cpu;
 mod csr;
+mod decode;
 mod device;
+pub mod disasm;
 mod elf;
+mod fdt;
+#[cfg(not(target_arch = "wasm32"))]
+mod gdbstub;
 mod host_device;
+mod icache;
 mod memory;
 mod native;
+mod pmp;
 pub mod simulator;
+mod snapshot;
+mod softfloat;
 mod tlb;
+mod trace;
 
 #[cfg(target_arch = "wasm32")]
 pub mod wasm;
@@ -45,7 +57,7 @@ impl AccessType {
             }
         } else {
             match self {
-                Self::Fetch => todo!(),
+                Self::Fetch => Trap::InstructionAccessFault,
                 Self::Read => Trap::LoadAccessFault,
                 Self::Write => Trap::StoreOrAMOAccessFault,
             }
@@ -78,8 +90,10 @@ impl From<u32> for Priv {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
 pub enum Trap {
     InstructionAddressMisaligned = 0,
+    InstructionAccessFault = 1,
     IlligalInstruction = 2,
     BreakPoint = 3,
     LoadAddressMisaligned = 4,
@@ -94,8 +108,11 @@ pub enum Trap {
     StoreOrAMOPageFault = 15,
 
     SupervisorSoftwareInterrupt = 1 << 31 | 1,
+    MachineSoftwareInterrupt = 1 << 31 | 3,
     SupervisorTimerInterrupt = 1 << 31 | 5,
+    MachineTimerInterrupt = 1 << 31 | 7,
     SupervisorExternalInterrupt = 1 << 31 | 9,
+    MachineExternalInterrupt = 1 << 31 | 11,
 
     UnimplementedInstruction, // デバッグ用
     UnimplementedCSR,         // デバッグ用
@@ -115,26 +132,6 @@ impl Trap {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum IRQ {
-    None = 0,
-    VirtioNet = 1,
-    VirtioGpu = 2,
-    Uart = 0xa,
-}
-
-impl From<usize> for IRQ {
-    fn from(value: usize) -> Self {
-        match value {
-            0 => Self::None,
-            1 => Self::VirtioNet,
-            2 => Self::VirtioGpu,
-            0xa => Self::Uart,
-            _ => unreachable!(),
-        }
-    }
-}
-
 #[macro_export]
 macro_rules! illegal {
     () => {
@@ -1,35 +1,419 @@
 use std::{
-    fs::File,
-    io::{BufReader, Read},
+    env,
+    fmt::Display,
+    fs::{self, File},
+    io::BufWriter,
+    ops::Range,
+    path::PathBuf,
+    process::exit,
 };
 
-use tiny_rv32ima_sim::simulator::Simulator;
+use tiny_rv32ima_sim::{
+    Priv,
+    simulator::{
+        ClintLayout, DEFAULT_BOOTARGS, FdtConfig, MAX_HART_COUNT, MAX_MEMORY_SIZE, MEMORY_BASE,
+        NativeConfig, NativeLoaded, Simulator, TimerMode, TraceConfig, TraceFormat, Tracer, is_elf,
+    },
+};
+
+const USAGE: &str = "\
+Usage: tiny-rv32ima-sim [OPTIONS]
+
+Options:
+  --firmware <FILE>       firmware image, raw binary or ELF (default: statics/fw_jump.bin)
+  --fw-addr <ADDR>        firmware load address, ignored for ELF (default: 0x80000000)
+  --kernel <FILE>         kernel image (default: statics/Image)
+  --kernel-addr <ADDR>    kernel load address (default: 0x80400000)
+  --no-kernel             do not load a kernel image
+  --initrd <FILE>         initrd image
+  --initrd-addr <ADDR>    initrd load address (default: 0x84000000)
+  --dtb <FILE>            device tree blob (default: generated from the machine)
+  --dtb-addr <ADDR>       device tree load address, passed in a1 (default: 0x80100000)
+  --append <ARGS>         kernel command line of the generated device tree
+  --ram-size <SIZE>       RAM size, e.g. 128M (default: 128M)
+  --harts <N>             number of harts, all starting at the entry point
+                          with a0 = mhartid (default: 1)
+  --aclint                split the CLINT into ACLINT MSWI, MTIMER and SSWI
+  --pmp-permissive        allow S/U-mode accesses while every PMP entry is off,
+                          for firmware that does not set up PMP
+  --entry <ADDR>          entry point (default: ELF entry or firmware load address)
+  --no-net                do not attach virtio-net
+  --tap <NAME>            tap device used by virtio-net (default: tap0)
+  --no-gpu                do not attach virtio-gpu
+  --gdb <[HOST:]PORT>     wait for gdb on the given port before running
+  --timer <MODE>          instret to advance time by one per instruction, or
+                          wallclock to follow the host clock (default: instret)
+  --snapshot <FILE>       save a snapshot of the machine to FILE on Ctrl-S
+  --restore <FILE>        resume from a snapshot instead of loading images
+                          (use the same --ram-size, --harts, --no-net and --no-gpu)
+  --trace <FILE>          write a Spike-compatible commit log of every instruction
+                          on hart 0
+  --trace-format <FMT>    commit, or full to also log the instruction (default: commit)
+  --trace-range <S-E>     only trace instructions with S <= pc < E
+  --trace-priv <MODES>    only trace the given modes, e.g. su (default: msu)
+  -h, --help              print this help";
+
+struct Options {
+    firmware: String,
+    fw_addr: u32,
+    kernel: Option<String>,
+    kernel_addr: u32,
+    initrd: Option<String>,
+    initrd_addr: u32,
+    dtb: Option<String>,
+    dtb_addr: u32,
+    bootargs: String,
+    ram_size: usize,
+    harts: usize,
+    clint_layout: ClintLayout,
+    pmp_permissive: bool,
+    entry: Option<u32>,
+    net: bool,
+    tap: String,
+    gpu: bool,
+    gdb: Option<String>,
+    timer: TimerMode,
+    snapshot: Option<String>,
+    restore: Option<String>,
+    trace: Option<String>,
+    trace_config: TraceConfig,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            firmware: "statics/fw_jump.bin".to_string(),
+            fw_addr: 0x80000000,
+            kernel: Some("statics/Image".to_string()),
+            kernel_addr: 0x80400000,
+            initrd: None,
+            initrd_addr: 0x84000000,
+            dtb: None,
+            dtb_addr: 0x80100000,
+            bootargs: DEFAULT_BOOTARGS.to_string(),
+            ram_size: 128 * 1024 * 1024,
+            harts: 1,
+            clint_layout: ClintLayout::default(),
+            pmp_permissive: false,
+            entry: None,
+            net: true,
+            tap: "tap0".to_string(),
+            gpu: true,
+            gdb: None,
+            timer: TimerMode::default(),
+            snapshot: None,
+            restore: None,
+            trace: None,
+            trace_config: TraceConfig::default(),
+        }
+    }
+}
+
+fn fail<E: Display>(message: E) -> ! {
+    eprintln!("error: {}", message);
+    exit(1);
+}
+
+// 0xで始まる場合は16進数として解釈する
+fn parse_u32(value: &str) -> Option<u32> {
+    let value = value.replace('_', "");
+
+    match value.strip_prefix("0x").or(value.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+// K, M, Gの接尾辞を受け付ける
+fn parse_size(value: &str) -> Option<usize> {
+    let (number, unit) = match value.chars().last()?.to_ascii_uppercase() {
+        'K' => (&value[..value.len() - 1], 1024),
+        'M' => (&value[..value.len() - 1], 1024 * 1024),
+        'G' => (&value[..value.len() - 1], 1024 * 1024 * 1024),
+        _ => (value, 1),
+    };
+
+    (parse_u32(number)? as usize).checked_mul(unit)
+}
+
+fn next_value(args: &mut impl Iterator<Item = String>, name: &str) -> String {
+    args.next()
+        .unwrap_or_else(|| fail(format!("missing value for '{}'", name)))
+}
+
+fn next_addr(args: &mut impl Iterator<Item = String>, name: &str) -> u32 {
+    let value = next_value(args, name);
+
+    parse_u32(&value).unwrap_or_else(|| fail(format!("invalid address '{}' for '{}'", value, name)))
+}
+
+// START-ENDの形式
+fn parse_range(value: &str) -> Option<Range<u32>> {
+    let (start, end) = value.split_once('-')?;
+
+    Some(parse_u32(start)?..parse_u32(end)?)
+}
+
+// m, s, uの組み合わせ
+fn parse_privs(value: &str) -> Option<Vec<Priv>> {
+    value
+        .chars()
+        .map(|c| match c.to_ascii_lowercase() {
+            'm' => Some(Priv::Machine),
+            's' => Some(Priv::Supervisor),
+            'u' => Some(Priv::User),
+            _ => None,
+        })
+        .collect()
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Options {
+    let mut options = Options::default();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--firmware" => options.firmware = next_value(&mut args, &arg),
+            "--fw-addr" => options.fw_addr = next_addr(&mut args, &arg),
+            "--kernel" => options.kernel = Some(next_value(&mut args, &arg)),
+            "--kernel-addr" => options.kernel_addr = next_addr(&mut args, &arg),
+            "--no-kernel" => options.kernel = None,
+            "--initrd" => options.initrd = Some(next_value(&mut args, &arg)),
+            "--initrd-addr" => options.initrd_addr = next_addr(&mut args, &arg),
+            "--dtb" => options.dtb = Some(next_value(&mut args, &arg)),
+            "--dtb-addr" => options.dtb_addr = next_addr(&mut args, &arg),
+            "--append" => options.bootargs = next_value(&mut args, &arg),
+            "--ram-size" => {
+                let value = next_value(&mut args, &arg);
+
+                options.ram_size = parse_size(&value)
+                    .unwrap_or_else(|| fail(format!("invalid size '{}' for '{}'", value, arg)));
+            }
+            "--harts" => {
+                let value = next_value(&mut args, &arg);
+
+                options.harts = parse_u32(&value)
+                    .map(|n| n as usize)
+                    .filter(|n| (1..=MAX_HART_COUNT).contains(n))
+                    .unwrap_or_else(|| {
+                        fail(format!(
+                            "'{}' must be between 1 and {}",
+                            arg, MAX_HART_COUNT
+                        ))
+                    });
+            }
+            "--aclint" => options.clint_layout = ClintLayout::Aclint,
+            "--pmp-permissive" => options.pmp_permissive = true,
+            "--entry" => options.entry = Some(next_addr(&mut args, &arg)),
+            "--no-net" => options.net = false,
+            "--tap" => options.tap = next_value(&mut args, &arg),
+            "--no-gpu" => options.gpu = false,
+            "--gdb" => {
+                let value = next_value(&mut args, &arg);
+
+                // ポート番号のみの場合はlocalhostで待ち受ける
+                options.gdb = Some(if value.contains(':') {
+                    value
+                } else {
+                    format!("127.0.0.1:{}", value)
+                });
+            }
+            "--timer" => {
+                let value = next_value(&mut args, &arg);
 
-const FW_SIZE: usize = 1024 * 1024;
-const DTB_SIZE: usize = 64 * 1024;
-const KERNEL_SIZE: usize = 36 * 1024 * 1024;
+                options.timer = match value.as_str() {
+                    "instret" => TimerMode::Instret,
+                    "wallclock" => TimerMode::WallClock,
+                    _ => fail(format!("invalid mode '{}' for '{}'", value, arg)),
+                };
+            }
+            "--snapshot" => options.snapshot = Some(next_value(&mut args, &arg)),
+            "--restore" => options.restore = Some(next_value(&mut args, &arg)),
+            "--trace" => options.trace = Some(next_value(&mut args, &arg)),
+            "--trace-format" => {
+                let value = next_value(&mut args, &arg);
 
-fn read_file(filename: &str, size: usize) -> Vec<u8> {
-    let file = File::open(filename).unwrap();
-    let mut reader = BufReader::new(file);
+                options.trace_config.format = match value.as_str() {
+                    "commit" => TraceFormat::Commit,
+                    "full" => TraceFormat::Full,
+                    _ => fail(format!("invalid format '{}' for '{}'", value, arg)),
+                };
+            }
+            "--trace-range" => {
+                let value = next_value(&mut args, &arg);
 
-    let mut buf = vec![0; size];
-    reader.read(&mut buf).unwrap();
+                options.trace_config.range =
+                    Some(parse_range(&value).unwrap_or_else(|| {
+                        fail(format!("invalid range '{}' for '{}'", value, arg))
+                    }));
+            }
+            "--trace-priv" => {
+                let value = next_value(&mut args, &arg);
 
-    buf
+                options.trace_config.privs = parse_privs(&value)
+                    .unwrap_or_else(|| fail(format!("invalid modes '{}' for '{}'", value, arg)));
+            }
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                exit(0);
+            }
+            _ => fail(format!("unknown option '{}'\n\n{}", arg, USAGE)),
+        }
+    }
+
+    if options.ram_size == 0 || options.ram_size > MAX_MEMORY_SIZE {
+        fail(format!(
+            "RAM size must be between 1 and 0x{:x} bytes",
+            MAX_MEMORY_SIZE
+        ));
+    }
+
+    if options.tap.len() >= 16 {
+        fail(format!("tap name '{}' is too long", options.tap));
+    }
+
+    options
 }
 
 fn main() {
-    let mut simulator = Simulator::new().setup_native_devices();
+    let options = parse_args(env::args().skip(1));
+
+    let config = NativeConfig {
+        net: options.net.then_some(options.tap.clone()),
+        gpu: options.gpu,
+        snapshot: options.snapshot.as_ref().map(PathBuf::from),
+        timer: options.timer,
+    };
+
+    let mut simulator = Simulator::new()
+        .set_memory_size(options.ram_size)
+        .set_hart_count(options.harts)
+        .set_clint_layout(options.clint_layout)
+        .set_pmp_permissive(options.pmp_permissive)
+        .setup_native_devices(config);
+
+    // スナップショットから再開する場合はイメージを読み込まない
+    if let Some(path) = &options.restore {
+        let buf = fs::read(path)
+            .unwrap_or_else(|e| fail(format!("failed to read snapshot '{}': {}", path, e)));
+
+        let mut simulator = simulator.set_entry_point(MEMORY_BASE);
+
+        simulator
+            .restore_snapshot(&buf)
+            .unwrap_or_else(|e| fail(format!("failed to restore '{}': {}", path, e)));
+
+        run(simulator, &options);
+    }
+
+    let files = [
+        ("firmware", Some(&options.firmware), options.fw_addr),
+        ("dtb", options.dtb.as_ref(), options.dtb_addr),
+        ("kernel", options.kernel.as_ref(), options.kernel_addr),
+        ("initrd", options.initrd.as_ref(), options.initrd_addr),
+    ];
+
+    let mut images = Vec::new();
+
+    for (name, path, addr) in files {
+        let Some(path) = path else {
+            continue;
+        };
+
+        let buf = fs::read(path)
+            .unwrap_or_else(|e| fail(format!("failed to read {} '{}': {}", name, path, e)));
+
+        images.push((name, buf, addr));
+    }
+
+    if options.dtb.is_none() {
+        let initrd = images
+            .iter()
+            .find(|(name, _, _)| *name == "initrd")
+            .map(|(_, buf, addr)| *addr..addr.wrapping_add(buf.len() as u32));
+
+        let config = FdtConfig {
+            bootargs: options.bootargs.clone(),
+            initrd,
+        };
+
+        images.push(("dtb", simulator.generate_fdt(&config), options.dtb_addr));
+    }
+
+    let mut loaded: Vec<(&str, Range<u64>)> = Vec::new();
+    let mut elf_entry = None;
+
+    // ELFのファームウェアはセグメントの物理アドレスに読み込む
+    if let Some(pos) = images
+        .iter()
+        .position(|(name, buf, _)| *name == "firmware" && is_elf(buf))
+    {
+        let (name, buf, _) = images.remove(pos);
+
+        let image = simulator.load_elf(&buf).unwrap_or_else(|e| {
+            fail(format!(
+                "failed to load {}: {} (RAM is 0x{:08x}-0x{:08x})",
+                name,
+                e,
+                MEMORY_BASE,
+                MEMORY_BASE as u64 + options.ram_size as u64
+            ))
+        });
+
+        elf_entry = Some(image.entry);
+        loaded.push((name, image.range.start as u64..image.range.end as u64));
+    }
+
+    for (name, buf, addr) in &images {
+        let range = *addr as u64..*addr as u64 + buf.len() as u64;
+
+        if let Some((other, _)) = loaded
+            .iter()
+            .find(|(_, r)| r.start < range.end && range.start < r.end)
+        {
+            fail(format!(
+                "{} (0x{:08x}-0x{:08x}) overlaps {}",
+                name, range.start, range.end, other
+            ));
+        }
+
+        if let Err(e) = simulator.load_flat(buf, *addr) {
+            fail(format!(
+                "failed to load {}: {} (RAM is 0x{:08x}-0x{:08x})",
+                name,
+                e,
+                MEMORY_BASE,
+                MEMORY_BASE as u64 + options.ram_size as u64
+            ));
+        }
+
+        loaded.push((name, range));
+    }
+
+    simulator.set_dtb_address(options.dtb_addr);
+
+    let entry = options.entry.or(elf_entry).unwrap_or(options.fw_addr);
+
+    run(simulator.set_entry_point(entry), &options);
+}
+
+fn run(mut simulator: Simulator<NativeLoaded>, options: &Options) -> ! {
+    if let Some(path) = &options.trace {
+        let file = File::create(path)
+            .unwrap_or_else(|e| fail(format!("failed to create trace '{}': {}", path, e)));
 
-    let buf = read_file("statics/fw_jump.bin", FW_SIZE);
-    simulator.load_flat(&buf, 0x80000000);
+        let tracer = Tracer::new(Box::new(BufWriter::new(file)), options.trace_config.clone());
 
-    let buf = read_file("statics/platform.dtb", DTB_SIZE);
-    simulator.load_flat(&buf, 0x80100000);
+        simulator.set_tracer(Some(tracer));
+    }
 
-    let buf = read_file("statics/Image", KERNEL_SIZE);
-    simulator.load_flat(&buf, 0x80400000);
+    match &options.gdb {
+        Some(addr) => {
+            if let Err(e) = simulator.run_with_gdb(addr) {
+                fail(format!("gdb connection on {}: {}", addr, e));
+            }
+        }
+        None => simulator.run(),
+    }
 
-    simulator.set_entry_point(0x80000000).run();
+    exit(0);
 }
@@ -1,23 +1,111 @@
-use std::mem::transmute;
+use std::{fmt::Display, ops::Range};
 
 use crate::{
     AccessType, Result,
     bus::MEMORY_BASE,
-    elf::{Elf32Ehdr, Elf32Phdr},
+    device::{BusError, BusResult},
+    elf::{ElfError, ElfFile},
+    snapshot::{self, Snapshot, SnapshotError, SnapshotReader, SnapshotWriter},
 };
 
 // pub const MEMORY_SIZE: usize = 1024 * 1024 * 512;
 pub const MEMORY_SIZE: usize = 1024 * 1024 * 128;
 
+pub const PAGE_SIZE: usize = 4096;
+
+// スナップショットではこの単位で0ではない部分のみ保存する
+const SNAPSHOT_PAGE_SIZE: usize = 4096;
+const SNAPSHOT_PAGE_END: u32 = u32::MAX;
+
 #[derive(Debug)]
 pub struct Memory {
     pub array: Vec<u8>,
+    // 書き込まれるたびに増えるページごとのバージョン
+    // 命令キャッシュが書き換えられたページを検出するために使う
+    page_versions: Vec<u32>,
+    // デバイスが書き込んだ可能性がある物理アドレスの範囲
+    // LR.Wの予約を取り消すためにBusが取り出す
+    device_writes: Vec<Range<usize>>,
+}
+
+// イメージをメモリに読み込む際のエラー
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LoadError {
+    // 読み込み先がメモリの範囲外の場合
+    OutOfRange { addr: u32, len: usize },
+    Elf(ElfError),
+}
+
+impl Display for LoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::OutOfRange { addr, len } => write!(
+                f,
+                "0x{:x} bytes at 0x{:08x} does not fit in memory",
+                len, addr
+            ),
+            Self::Elf(e) => write!(f, "invalid ELF: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+impl From<ElfError> for LoadError {
+    fn from(e: ElfError) -> Self {
+        Self::Elf(e)
+    }
 }
 
 impl Default for Memory {
     fn default() -> Self {
+        Self::new(MEMORY_SIZE)
+    }
+}
+
+impl Memory {
+    pub fn new(size: usize) -> Self {
         Self {
-            array: vec![0; MEMORY_SIZE],
+            array: vec![0; size],
+            page_versions: vec![0; size.div_ceil(PAGE_SIZE)],
+            device_writes: Vec::new(),
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.array.len()
+    }
+
+    pub fn page_count(&self) -> usize {
+        self.page_versions.len()
+    }
+
+    #[inline]
+    pub fn page_version(&self, idx: usize) -> u32 {
+        self.page_versions[idx]
+    }
+
+    // raw_mut_ptrで渡した範囲を取り出す関数
+    #[inline]
+    pub fn take_device_writes(&mut self) -> Vec<Range<usize>> {
+        std::mem::take(&mut self.device_writes)
+    }
+
+    #[inline]
+    pub fn has_device_writes(&self) -> bool {
+        !self.device_writes.is_empty()
+    }
+
+    // offsetからlenバイトが書き換えられたことを記録する
+    #[inline]
+    fn touch(&mut self, offset: usize, len: usize) {
+        if len == 0 {
+            return;
+        }
+
+        for version in &mut self.page_versions[offset / PAGE_SIZE..=(offset + len - 1) / PAGE_SIZE]
+        {
+            *version = version.wrapping_add(1);
         }
     }
 }
@@ -87,101 +175,176 @@ impl Memory {
     #[inline]
     pub fn raw_write(&mut self, offset: usize, array: &[u8]) -> () {
         self.array[offset..offset + array.len()].copy_from_slice(array);
+        self.touch(offset, array.len());
 
         ()
     }
 
+    // デバイスがゲストの物理アドレスaddrからsizeバイトを参照する関数
+    // ゲストが渡したアドレスなので、メモリの範囲外の場合はエラーを返す
     #[inline]
-    pub fn raw_ptr(&self, addr: usize, size: usize) -> &[u8] {
-        let offset = addr - MEMORY_BASE as usize;
-        &self.array[offset..offset + size]
+    pub fn raw_ptr(&self, addr: usize, size: usize) -> BusResult<&[u8]> {
+        let offset = self.device_offset(addr, size)?;
+
+        Ok(&self.array[offset..offset + size])
     }
 
+    // ゲストの物理アドレスaddrからTを読み出す関数
+    // ゲストが渡したアドレスはアラインされているとは限らないので、参照ではなくコピーを返す
     #[inline]
-    pub fn view_as<T>(&self, addr: usize, len: usize) -> &T {
+    pub fn view_as<T: Copy>(&self, addr: usize, len: usize) -> BusResult<T> {
         let size = size_of::<T>();
 
         if size > len {
-            panic!("[ERROR] len(0x{:x}) > size(0x{:x})", len, size);
+            return Err(BusError::Memory { addr, len });
         }
 
-        let ptr = self.raw_ptr(addr, size);
+        let ptr = self.raw_ptr(addr, size)?;
 
-        unsafe { transmute(ptr.as_ptr()) }
+        Ok(unsafe { ptr.as_ptr().cast::<T>().read_unaligned() })
     }
 
     #[inline]
-    pub fn raw_mut_ptr(&mut self, addr: usize, size: usize) -> &mut [u8] {
-        let offset = addr - MEMORY_BASE as usize;
-        &mut self.array[offset..offset + size]
+    pub fn raw_mut_ptr(&mut self, addr: usize, size: usize) -> BusResult<&mut [u8]> {
+        let offset = self.device_offset(addr, size)?;
+
+        // デバイスから書き込まれる可能性がある
+        self.touch(offset, size);
+        self.device_writes.push(addr..addr + size);
+
+        Ok(&mut self.array[offset..offset + size])
     }
 
+    // デバイスが書き込むためにゲストのメモリを&mut Tとして参照する関数
+    // アラインされていない参照は作れないので、その場合もエラーを返す
     #[inline]
-    pub fn mut_view_as<T>(&mut self, addr: usize, len: usize) -> &mut T {
+    pub fn mut_view_as<T>(&mut self, addr: usize, len: usize) -> BusResult<&mut T> {
         let size = size_of::<T>();
 
         if size > len {
-            panic!("[ERROR] len(0x{:x}) > size(0x{:x})", len, size);
+            return Err(BusError::Memory { addr, len });
+        }
+
+        let ptr = self.raw_mut_ptr(addr, size)?.as_mut_ptr().cast::<T>();
+
+        if !ptr.is_aligned() {
+            return Err(BusError::Memory { addr, len });
         }
 
-        let ptr = self.raw_mut_ptr(addr, size);
+        Ok(unsafe { &mut *ptr })
+    }
 
-        unsafe { transmute(ptr.as_ptr()) }
+    #[inline]
+    fn device_offset(&self, addr: usize, size: usize) -> BusResult<usize> {
+        addr.checked_sub(MEMORY_BASE as usize)
+            .filter(|offset| {
+                offset
+                    .checked_add(size)
+                    .is_some_and(|end| end <= self.array.len())
+            })
+            .ok_or(BusError::Memory { addr, len: size })
     }
+
     fn is_invalid_range(&self, address: usize, size: usize) -> bool {
-        let is_over_memory = address + size > MEMORY_SIZE;
+        let is_over_memory = address + size > self.array.len();
 
         is_over_memory
     }
 
-    pub fn load_flat_binary(&mut self, array: &[u8], addr: u32) {
-        let addr = addr as usize;
-
-        if array.len() > MEMORY_SIZE {
-            panic!("[Error]: the program is too big.");
+    pub fn load_flat_binary(
+        &mut self,
+        array: &[u8],
+        addr: u32,
+    ) -> std::result::Result<(), LoadError> {
+        let offset = (addr as usize).wrapping_sub(MEMORY_BASE as usize);
+
+        if addr < MEMORY_BASE || offset + array.len() > self.array.len() {
+            return Err(LoadError::OutOfRange {
+                addr,
+                len: array.len(),
+            });
         }
 
-        let offset = addr - MEMORY_BASE as usize;
-
         self.raw_write(offset, array);
+
+        Ok(())
     }
 
     // [todo] lazy_load_flat_program
 
-    pub fn load_elf_binary(&mut self, array: &[u8]) -> u32 {
-        let ehdr_size = core::mem::size_of::<Elf32Ehdr>();
-        let ehdr = unsafe { *(&array[..ehdr_size] as *const _ as *const Elf32Ehdr) };
+    // セグメントを全て確認してから書き込む
+    pub fn load_elf_binary<'a>(
+        &mut self,
+        array: &'a [u8],
+    ) -> std::result::Result<ElfFile<'a>, LoadError> {
+        let elf = ElfFile::parse(array)?;
+
+        for segment in &elf.segments {
+            let offset = (segment.paddr as usize).wrapping_sub(MEMORY_BASE as usize);
+
+            if segment.paddr < MEMORY_BASE || offset + segment.memsz as usize > self.array.len() {
+                return Err(LoadError::OutOfRange {
+                    addr: segment.paddr,
+                    len: segment.memsz as usize,
+                });
+            }
+        }
+
+        for segment in &elf.segments {
+            let offset = (segment.paddr - MEMORY_BASE) as usize;
+            let data_end = offset + segment.data.len();
+            let bss_end = offset + segment.memsz as usize;
 
-        if !ehdr.is_valid() {
-            panic!("invalid ELF32");
+            self.array[offset..data_end].copy_from_slice(segment.data);
+            self.array[data_end..bss_end].fill(0);
+            self.touch(offset, segment.memsz as usize);
         }
 
-        let phnum = ehdr.e_phnum as usize;
-        let phoff = ehdr.e_phoff as usize;
-        let phentsize = ehdr.e_phentsize as usize;
-        let phdr_size = core::mem::size_of::<Elf32Phdr>();
+        Ok(elf)
+    }
+}
 
-        for i in 0..phnum {
-            let offset = phoff + i * phentsize;
-            let phdr: Elf32Phdr =
-                unsafe { *(&array[offset..offset + phdr_size] as *const _ as *const Elf32Phdr) };
+impl Snapshot for Memory {
+    fn save(&self, w: &mut SnapshotWriter) {
+        w.write_len(self.array.len());
 
-            if !phdr.is_load_seg() {
+        for (idx, page) in self.array.chunks(SNAPSHOT_PAGE_SIZE).enumerate() {
+            if page.iter().all(|b| *b == 0) {
                 continue;
             }
 
-            let file_off = phdr.p_offset as usize;
-            let file_end = file_off + phdr.p_filesz as usize;
+            w.write_u32(idx as u32);
+            w.write_bytes(page);
+        }
 
-            let mem_addr = (phdr.p_paddr as u32 - MEMORY_BASE) as usize;
-            let mem_end = mem_addr + phdr.p_filesz as usize;
+        w.write_u32(SNAPSHOT_PAGE_END);
+    }
 
-            self.array[mem_addr..mem_end].copy_from_slice(&array[file_off..file_end]);
+    fn restore(&mut self, r: &mut SnapshotReader) -> snapshot::Result<()> {
+        let size = r.read_u32()? as usize;
 
-            let bss_end = mem_addr + phdr.p_memsz as usize;
-            self.array[mem_end..bss_end].fill(0);
+        if size != self.array.len() {
+            return Err(SnapshotError::MemorySizeMismatch {
+                expected: size,
+                actual: self.array.len(),
+            });
         }
 
-        ehdr.e_entry
+        self.array.fill(0);
+        self.touch(0, self.array.len());
+
+        loop {
+            let idx = r.read_u32()?;
+
+            if idx == SNAPSHOT_PAGE_END {
+                return Ok(());
+            }
+
+            let Some(page) = self.array.chunks_mut(SNAPSHOT_PAGE_SIZE).nth(idx as usize) else {
+                return Err(SnapshotError::InvalidValue("memory page"));
+            };
+
+            page.copy_from_slice(r.read_bytes(page.len())?);
+        }
     }
 }
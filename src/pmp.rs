@@ -0,0 +1,185 @@
+use std::ops::Range;
+
+use crate::{
+    AccessType, Priv,
+    snapshot::{self, Snapshot, SnapshotReader, SnapshotWriter},
+};
+
+// 実装しているエントリの数
+// 残りのエントリのCSRは0固定になる
+const PMP_COUNT: usize = 16;
+
+const PMP_R: u8 = 1 << 0;
+const PMP_W: u8 = 1 << 1;
+const PMP_X: u8 = 1 << 2;
+const PMP_A: u8 = 0x3 << 3;
+const PMP_L: u8 = 1 << 7;
+const PMP_CFG_MASK: u8 = PMP_R | PMP_W | PMP_X | PMP_A | PMP_L;
+
+// Aフィールドの値
+const PMP_A_TOR: u8 = 1 << 3;
+const PMP_A_NA4: u8 = 2 << 3;
+const PMP_A_NAPOT: u8 = 3 << 3;
+
+// 粒度は4バイト(G=0)
+#[derive(Default, Debug)]
+pub struct Pmp {
+    cfg: [u8; PMP_COUNT],
+    addr: [u32; PMP_COUNT], // 物理アドレスの33-2bit
+
+    // 有効なエントリが一つもない場合にS/Uモードのアクセスを全て許可するか
+    // PMPを設定しないソフトウェアを動かすためのもので、仕様通りではない
+    is_permissive: bool,
+}
+
+impl Pmp {
+    pub fn is_permissive(&self) -> bool {
+        self.is_permissive
+    }
+
+    pub fn set_permissive(&mut self, is_permissive: bool) {
+        self.is_permissive = is_permissive;
+    }
+
+    // pmpcfgN(N=0..3)の値を返す関数
+    pub fn read_cfg(&self, index: usize) -> u32 {
+        if index * 4 >= PMP_COUNT {
+            return 0;
+        }
+
+        u32::from_le_bytes(self.cfg[index * 4..index * 4 + 4].try_into().unwrap())
+    }
+
+    pub fn write_cfg(&mut self, index: usize, value: u32) {
+        if index * 4 >= PMP_COUNT {
+            return;
+        }
+
+        for (i, cfg) in value.to_le_bytes().into_iter().enumerate() {
+            let entry = index * 4 + i;
+
+            if self.is_locked(entry) {
+                continue;
+            }
+
+            let mut cfg = cfg & PMP_CFG_MASK;
+
+            // R=0, W=1は予約されているのでW=0にする
+            if cfg & PMP_R == 0 {
+                cfg &= !PMP_W;
+            }
+
+            self.cfg[entry] = cfg;
+        }
+    }
+
+    pub fn read_addr(&self, index: usize) -> u32 {
+        self.addr.get(index).copied().unwrap_or(0)
+    }
+
+    pub fn write_addr(&mut self, index: usize, value: u32) {
+        // ロックされたエントリと、ロックされたTORのエントリの下限になるアドレスは書き換えられない
+        let is_tor_base_locked = index + 1 < PMP_COUNT
+            && self.is_locked(index + 1)
+            && self.cfg[index + 1] & PMP_A == PMP_A_TOR;
+
+        if index >= PMP_COUNT || self.is_locked(index) || is_tor_base_locked {
+            return;
+        }
+
+        self.addr[index] = value;
+    }
+
+    // paからsizeバイトのアクセスが許可されているかを確認する関数
+    pub fn is_permitted(&self, pa: u32, size: u32, access_type: AccessType, prv: Priv) -> bool {
+        // ロックされたエントリがない場合はMモードのアクセスは常に許可される
+        if prv == Priv::Machine && self.cfg.iter().all(|cfg| cfg & PMP_L == 0) {
+            return true;
+        }
+
+        let start = pa as u64;
+        let end = start + size as u64;
+
+        // 番号の小さいエントリが優先される
+        for (i, cfg) in self.cfg.iter().enumerate() {
+            let Some(range) = self.range(i) else {
+                continue;
+            };
+
+            if end <= range.start || range.end <= start {
+                continue;
+            }
+
+            // 一部のバイトのみが一致する場合は失敗する
+            if start < range.start || range.end < end {
+                return false;
+            }
+
+            if prv == Priv::Machine && cfg & PMP_L == 0 {
+                return true;
+            }
+
+            let permission = match access_type {
+                AccessType::Read => PMP_R,
+                AccessType::Write => PMP_W,
+                AccessType::Fetch => PMP_X,
+            };
+
+            return cfg & permission != 0;
+        }
+
+        // 一致するエントリがない場合はMモードのみ許可される
+        // is_permissiveの場合は、有効なエントリが一つもなければS/Uモードも許可する
+        prv == Priv::Machine || (self.is_permissive && self.cfg.iter().all(|cfg| cfg & PMP_A == 0))
+    }
+
+    #[inline]
+    fn is_locked(&self, index: usize) -> bool {
+        self.cfg[index] & PMP_L != 0
+    }
+
+    // エントリが表すバイト単位のアドレスの範囲を返す関数
+    fn range(&self, index: usize) -> Option<Range<u64>> {
+        let addr = (self.addr[index] as u64) << 2;
+
+        match self.cfg[index] & PMP_A {
+            PMP_A_TOR => {
+                let start = match index {
+                    0 => 0,
+                    _ => (self.addr[index - 1] as u64) << 2,
+                };
+
+                Some(start..addr)
+            }
+            PMP_A_NA4 => Some(addr..addr + 4),
+            PMP_A_NAPOT => {
+                // 下位に連続する1の数で大きさが決まる
+                let size = 1u64 << (self.addr[index].trailing_ones() + 3);
+                let start = addr & !(size - 1);
+
+                Some(start..start + size)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Snapshot for Pmp {
+    fn save(&self, w: &mut SnapshotWriter) {
+        w.write_bytes(&self.cfg);
+
+        for addr in self.addr {
+            w.write_u32(addr);
+        }
+    }
+
+    fn restore(&mut self, r: &mut SnapshotReader) -> snapshot::Result<()> {
+        self.cfg.copy_from_slice(r.read_bytes(PMP_COUNT)?);
+
+        for addr in &mut self.addr {
+            *addr = r.read_u32()?;
+        }
+
+        Ok(())
+    }
+}
@@ -1,20 +1,60 @@
-use std::marker::PhantomData;
+use std::{marker::PhantomData, ops::Range};
 
 use crate::{
+    AccessType,
     bus::{
-        Bus, BusDevice, UART_BASE, UART_END, VIRTIO_GPU_BASE, VIRTIO_GPU_END, VIRTIO_NET_BASE,
-        VIRTIO_NET_END, uart::Uart, virtio_gpu::VirtioGpu, virtio_net::VirtioNet,
+        Bus, BusDevice, UART_BASE, UART_END, UART_IRQ, VIRTIO_GPU_BASE, VIRTIO_GPU_END,
+        VIRTIO_GPU_IRQ, VIRTIO_NET_BASE, VIRTIO_NET_END, VIRTIO_NET_IRQ, uart::Uart,
+        virtio_gpu::VirtioGpu, virtio_net::VirtioNet,
     },
     cpu::Cpu,
+    csr::TIMEBASE_FREQ,
+    device::NullReciever,
+    fdt,
     host_device::HostDeviceManager,
     native::{NativeReciever, NativeSender},
+    snapshot::{Snapshot, SnapshotReader, SnapshotWriter},
 };
 
+pub use crate::{
+    bus::{AddDeviceError, ClintLayout, MAX_HART_COUNT, MAX_MEMORY_SIZE, MEMORY_BASE},
+    elf::{ElfError, Symbol, SymbolTable, is_elf},
+    fdt::{DEFAULT_BOOTARGS, FdtConfig},
+    memory::LoadError,
+    snapshot::SnapshotError,
+    trace::{TraceConfig, TraceFormat, Tracer},
+};
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::{
+    fs,
+    io::{self, Write},
+    path::PathBuf,
+    sync::mpsc::{self, Receiver},
+    thread,
+    time::Duration,
+};
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use crate::clock::TimerMode;
+
+#[cfg(not(target_arch = "wasm32"))]
+use crate::{
+    clock::WallClock,
+    gdbstub::{GdbStub, Resume, StopReason},
+    host_device::{ControlMessage, gpu::HostGpu, net::HostNet, shell::Shell},
+};
+
+// WFIで待っている間に1回で進める時間(1ms)
+const IDLE_TICKS: u64 = TIMEBASE_FREQ as u64 / 1000;
+
+// WFIで待っている間にホストのスレッドを止める時間
 #[cfg(not(target_arch = "wasm32"))]
-use std::{sync::mpsc, thread};
+const IDLE_SLEEP: Duration = Duration::from_millis(1);
 
+// gdbからのCtrl-Cやホストからの要求を確認する間隔(命令数)
 #[cfg(not(target_arch = "wasm32"))]
-use crate::host_device::{gpu::HostGpu, net::HostNet, shell::Shell};
+const POLL_INTERVAL: u64 = 0x4000;
 
 #[cfg(target_arch = "wasm32")]
 use crate::{device::DeviceMessage, wasm::WasmGpuSender};
@@ -22,9 +62,14 @@ use crate::{device::DeviceMessage, wasm::WasmGpuSender};
 use web_sys::CanvasRenderingContext2d;
 
 pub struct Simulator<T> {
-    cpu: Cpu,
+    harts: Vec<Cpu>, // インデックスがmhartidになる
     bus: Bus,
     host_device_manager: Option<HostDeviceManager>,
+    symbols: SymbolTable,
+    #[cfg(not(target_arch = "wasm32"))]
+    control: Option<Receiver<ControlMessage>>,
+    #[cfg(not(target_arch = "wasm32"))]
+    clock: Option<WallClock>, // TimerMode::WallClockの場合のみ
     _marker: PhantomData<T>,
 }
 
@@ -35,78 +80,512 @@ pub struct WasmSetup;
 pub struct NativeLoaded;
 pub struct WasmLoaded;
 
+// デバイスを接続せずにCPUとメモリだけで実行する
+pub struct HeadlessSetup;
+pub struct HeadlessLoaded;
+
+// ヘッドレス実行の終了条件
+#[derive(Debug, Clone)]
+pub struct TestConfig {
+    pub tohost: Option<u32>,       // 0以外が書き込まれたら終了する
+    pub exit_address: Option<u32>, // PCが到達したら終了する
+    pub max_steps: u64,
+}
+
+impl Default for TestConfig {
+    fn default() -> Self {
+        Self {
+            tohost: None,
+            exit_address: None,
+            max_steps: 10_000_000,
+        }
+    }
+}
+
+// riscv-testsの規約に従った実行結果
+// Failには失敗したテストの番号が入る
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TestResult {
+    Pass,
+    Fail(u32),
+    Timeout,
+}
+
+impl TestResult {
+    // tohostやgpの値は (テスト番号 << 1) | 1 で、成功の場合は1になる
+    fn from_code(code: u32) -> Self {
+        match code {
+            1 => Self::Pass,
+            _ => Self::Fail(code >> 1),
+        }
+    }
+}
+
+// ネイティブで接続するデバイスの設定
+#[cfg(not(target_arch = "wasm32"))]
+pub struct NativeConfig {
+    pub net: Option<String>, // 接続するtapデバイスの名前 Noneの場合はvirtio-netを接続しない
+    pub gpu: bool,
+    pub snapshot: Option<PathBuf>, // Ctrl-Sでスナップショットを保存するファイル
+    pub timer: TimerMode,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Default for NativeConfig {
+    fn default() -> Self {
+        Self {
+            net: Some("tap0".to_string()),
+            gpu: true,
+            snapshot: None,
+            timer: TimerMode::default(),
+        }
+    }
+}
+
+// 読み込んだELFの情報
+#[derive(Debug, Clone)]
+pub struct ElfImage {
+    pub entry: u32,
+    pub range: Range<u32>,
+}
+
 impl<T> Simulator<T> {
-    pub fn load_flat(&mut self, array: &[u8], addr: u32) {
-        self.bus.memory().load_flat_binary(array, addr);
+    pub fn load_flat(&mut self, array: &[u8], addr: u32) -> Result<(), LoadError> {
+        self.bus.memory().load_flat_binary(array, addr)
+    }
+
+    // ELFのセグメントを読み込み、PCをe_entryに設定する
+    // 読み込み先の範囲とエントリポイントを返す
+    pub fn load_elf(&mut self, array: &[u8]) -> Result<ElfImage, LoadError> {
+        let elf = self.bus.memory().load_elf_binary(array)?;
+
+        let image = ElfImage {
+            entry: elf.entry,
+            range: elf.range(),
+        };
+
+        self.set_pc(elf.entry);
+        self.symbols = elf.symbols;
+
+        Ok(image)
+    }
+
+    // 読み込んだELFのシンボル
+    pub fn symbols(&self) -> &SymbolTable {
+        &self.symbols
+    }
+
+    pub fn memory_size(&self) -> usize {
+        self.bus.memory_size()
+    }
+
+    // 全てのhartのa1にdtbのアドレスを設定する
+    pub fn set_dtb_address(&mut self, addr: u32) {
+        for hart in &mut self.harts {
+            hart.set_dtb_addr(addr);
+        }
+    }
+
+    // 接続されているデバイスからデバイスツリーを生成する
+    pub fn generate_fdt(&self, config: &FdtConfig) -> Vec<u8> {
+        fdt::generate(&self.bus, config)
+    }
+
+    // デバイスツリーを生成してaddrに配置し、a1にaddrを設定する
+    pub fn load_fdt(&mut self, config: &FdtConfig, addr: u32) -> Result<(), LoadError> {
+        let fdt = self.generate_fdt(config);
+
+        self.load_flat(&fdt, addr)?;
+        self.set_dtb_address(addr);
+
+        Ok(())
     }
 
+    // hart 0を返す
     pub fn cpu(&self) -> &Cpu {
-        &self.cpu
+        &self.harts[0]
+    }
+
+    pub fn harts(&self) -> &[Cpu] {
+        &self.harts
+    }
+
+    // CPU、メモリ、デバイスの状態を全て保存する
+    pub fn save_snapshot(&self) -> Vec<u8> {
+        let mut w = SnapshotWriter::new();
+
+        w.write_len(self.harts.len());
+        for hart in &self.harts {
+            hart.save(&mut w);
+        }
+
+        self.bus.save(&mut w);
+
+        w.finish()
+    }
+
+    // save_snapshotで保存した状態を復元する
+    // 同じ構成(メモリサイズと接続しているデバイス)で作ったSimulatorでなければならない
+    // エラーの場合は復元する前の状態に戻すので、そのまま実行を続けられる
+    pub fn restore_snapshot(&mut self, data: &[u8]) -> Result<(), SnapshotError> {
+        // 途中まで読み込んでから失敗した場合に戻せるように現在の状態を保存しておく
+        let backup = self.save_snapshot();
+
+        if let Err(e) = self.restore_snapshot_unchecked(data) {
+            self.restore_snapshot_unchecked(&backup)
+                .expect("the current state must be restorable");
+
+            return Err(e);
+        }
+
+        // 実時間の場合は復元したtimeから数え直す
+        #[cfg(not(target_arch = "wasm32"))]
+        self.start_clock();
+
+        Ok(())
+    }
+
+    // 読み込みながら状態を書き換えるので、エラーの場合は途中まで復元された状態になる
+    fn restore_snapshot_unchecked(&mut self, data: &[u8]) -> Result<(), SnapshotError> {
+        let mut r = SnapshotReader::new(data)?;
+
+        let hart_count = r.read_len()?;
+        if hart_count != self.harts.len() {
+            return Err(SnapshotError::HartCountMismatch {
+                expected: hart_count,
+                actual: self.harts.len(),
+            });
+        }
+
+        for hart in &mut self.harts {
+            hart.restore(&mut r)?;
+        }
+
+        self.bus.restore(&mut r)?;
+
+        if !r.is_end() {
+            return Err(SnapshotError::InvalidValue("trailing data"));
+        }
+
+        Ok(())
+    }
+
+    // hart 0の命令の実行とトラップを記録する
+    // Noneの場合は記録を止める
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) {
+        self.harts[0].flush_trace();
+        self.harts[0].set_tracer(tracer);
+    }
+
+    // 全てのhartのPCを設定する
+    // SBIのHSMの想定で、全てのhartが同じアドレスから実行を始める
+    fn set_pc(&mut self, pc: u32) {
+        for hart in &mut self.harts {
+            hart.set_pc(pc);
+        }
+    }
+
+    // スナップショットから再開した場合もtimeが戻らないように、現在のtimeから数え始める
+    #[cfg(not(target_arch = "wasm32"))]
+    fn start_clock(&mut self) {
+        if let Some(clock) = &mut self.clock {
+            *clock = WallClock::new(self.harts[0].csr().time);
+        }
+    }
+
+    // gdbからはhart 0のみを扱う
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) fn gdb_target(&mut self) -> (&mut Cpu, &mut Bus) {
+        (&mut self.harts[0], &mut self.bus)
+    }
+
+    #[inline]
+    fn is_all_waiting(&self) -> bool {
+        self.harts.iter().all(|hart| hart.is_waiting())
+    }
+
+    fn into_state<U>(self) -> Simulator<U> {
+        Simulator {
+            harts: self.harts,
+            bus: self.bus,
+            host_device_manager: self.host_device_manager,
+            symbols: self.symbols,
+            #[cfg(not(target_arch = "wasm32"))]
+            control: self.control,
+            #[cfg(not(target_arch = "wasm32"))]
+            clock: self.clock,
+            _marker: PhantomData,
+        }
+    }
+
+    // 全てのhartで1命令分実行を進める関数
+    // hartは番号順に1命令ずつ実行し、timeは全てのhartで共通になる
+    fn step_once(&mut self) {
+        self.bus.tick();
+        self.sync_harts();
+
+        for hart in 0..self.harts.len() {
+            self.step_hart(hart);
+        }
+
+        if self.is_all_waiting() {
+            self.progress_idle_time();
+        } else {
+            self.progress_time();
+        }
+    }
+
+    // 1つのhartで1命令分実行を進める関数
+    // 割り込みとトラップの処理もここで行う
+    fn step_hart(&mut self, hart: usize) {
+        let cpu = &mut self.harts[hart];
+
+        // 割り込みが起こるまでWFIで待つ
+        if cpu.is_waiting() {
+            if !cpu.has_pending_interrupt() {
+                return;
+            }
+
+            cpu.wake();
+        }
+
+        if let Some(e) = cpu.check_local_intrrupt_active() {
+            cpu.handle_trap(e);
+        }
+
+        match cpu.step(&mut self.bus) {
+            Err(e) => {
+                cpu.handle_trap(e);
+            }
+            Ok(is_jump) => {
+                cpu.mut_csr().progress_instret();
+
+                if !is_jump {
+                    cpu.progress_pc();
+                }
+            }
+        }
+
+        cpu.mut_csr().progress_cycle();
+
+        self.sync_harts();
+    }
+
+    // CLINTとPLICの状態を各hartのCSRに反映する関数
+    #[inline]
+    fn sync_harts(&mut self) {
+        let mtime = self
+            .bus
+            .sync_harts(self.harts.iter_mut().map(|hart| hart.mut_csr()));
+
+        // mtimeが書き込まれた場合は実時間をその値から数え直す
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(time) = mtime
+            && let Some(clock) = &mut self.clock
+        {
+            *clock = WallClock::new(time);
+        }
+
+        #[cfg(target_arch = "wasm32")]
+        let _ = mtime;
+    }
+
+    // 実時間の場合はホストの時計に合わせ、それ以外の場合は1命令分timeを進める関数
+    #[inline]
+    fn progress_time(&mut self) {
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(clock) = &mut self.clock {
+            if let Some(time) = clock.tick() {
+                self.set_time(time);
+            }
+
+            return;
+        }
+
+        for hart in &mut self.harts {
+            hart.mut_csr().progress_time();
+        }
+    }
+
+    // 全てのhartがWFIで待っている間に時間を進める関数
+    // 次のタイマー割り込みまで進めるが、実時間の場合はホストの時計より先には進めない
+    fn progress_idle_time(&mut self) {
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(clock) = &self.clock {
+            self.set_time(clock.now());
+
+            return;
+        }
+
+        let time = self.harts[0].csr().time;
+
+        let time = self
+            .harts
+            .iter()
+            .filter_map(|hart| hart.csr().next_timer_event())
+            .fold(time.saturating_add(IDLE_TICKS), u64::min);
+
+        self.set_time(time);
+    }
+
+    #[inline]
+    fn set_time(&mut self, time: u64) {
+        for hart in &mut self.harts {
+            hart.mut_csr().set_time(time);
+        }
+    }
+
+    // 物理アドレスから4バイト読み込む関数
+    // メモリの範囲外の場合はNoneを返す
+    fn read_physical_u32(&mut self, addr: u32) -> Option<u32> {
+        let offset = addr.checked_sub(MEMORY_BASE)?;
+
+        self.bus
+            .memory()
+            .read(offset, 4, AccessType::Read, false)
+            .ok()
     }
 }
 
 impl Simulator<Initial> {
     pub fn new() -> Self {
         Self {
-            cpu: Cpu::default(),
+            harts: vec![Cpu::new(0)],
             bus: Bus::default(),
             host_device_manager: None,
+            symbols: SymbolTable::default(),
+            #[cfg(not(target_arch = "wasm32"))]
+            control: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            clock: None,
             _marker: PhantomData,
         }
     }
 
-    // native
+    // メモリサイズを変更する関数
+    // MAX_MEMORY_SIZEを超える場合はMAX_MEMORY_SIZEになる
+    pub fn set_memory_size(mut self, size: usize) -> Self {
+        self.rebuild_bus(size.min(MAX_MEMORY_SIZE), self.harts.len());
+
+        self
+    }
+
+    // hartの数を変更する関数
+    // 1からMAX_HART_COUNTの範囲に収める
+    pub fn set_hart_count(mut self, count: usize) -> Self {
+        let count = count.clamp(1, MAX_HART_COUNT);
+        let is_permissive = self.harts[0].csr().pmp.is_permissive();
+
+        self.harts = (0..count).map(|hart| Cpu::new(hart as u32)).collect();
+        self.rebuild_bus(self.bus.memory_size(), count);
+
+        self.set_pmp_permissive(is_permissive)
+    }
+
+    // PMPのエントリが一つも有効でない場合に、S/Uモードのアクセスを許可するかを変更する関数
+    // 仕様では失敗するので、PMPを設定しないファームウェアを使う場合のみtrueにする
+    pub fn set_pmp_permissive(mut self, is_permissive: bool) -> Self {
+        for cpu in &mut self.harts {
+            cpu.set_pmp_permissive(is_permissive);
+        }
+
+        self
+    }
+
+    // timeの進め方を変更する関数
     #[cfg(not(target_arch = "wasm32"))]
-    pub fn setup_native_devices(mut self) -> Simulator<NativeSetup> {
-        let (uart_tx, uart_rx) = mpsc::channel();
+    pub fn set_timer_mode(mut self, mode: TimerMode) -> Self {
+        self.clock = (mode == TimerMode::WallClock).then(|| WallClock::new(0));
 
-        let uart = BusDevice::new(
-            Box::new(Uart::new(NativeReciever::new(uart_rx))),
-            UART_BASE..UART_END,
-        );
+        self
+    }
 
-        let shell = Box::new(Shell::new(uart_tx));
+    // CLINTの構成を変更する関数
+    pub fn set_clint_layout(mut self, layout: ClintLayout) -> Self {
+        self.bus.set_clint_layout(layout);
 
-        let (net_host_tx, net_guest_rx) = mpsc::channel();
-        let (net_guest_tx, net_host_rx) = mpsc::channel();
+        self
+    }
 
-        let virtio_net = BusDevice::new(
-            Box::new(VirtioNet::new(
-                NativeReciever::new(net_guest_rx),
-                NativeSender::new(net_guest_tx),
-            )),
-            VIRTIO_NET_BASE..VIRTIO_NET_END,
-        );
+    // CLINTの構成は引き継いでBusを作り直す関数
+    fn rebuild_bus(&mut self, memory_size: usize, hart_count: usize) {
+        let layout = self.bus.clint_layout();
 
-        let host_net = Box::new(HostNet::new(net_host_rx, net_host_tx));
+        self.bus = Bus::new(memory_size, hart_count);
+        self.bus.set_clint_layout(layout);
+    }
 
-        let (gpu_tx, gpu_rx) = mpsc::channel();
+    // テスト向けにデバイスを接続しない
+    pub fn setup_headless(self) -> Simulator<HeadlessSetup> {
+        self.into_state()
+    }
 
-        let virtio_gpu = BusDevice::new(
-            Box::new(VirtioGpu::new(NativeSender::new(gpu_tx))),
-            VIRTIO_GPU_BASE..VIRTIO_GPU_END,
-        );
+    // native
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn setup_native_devices(mut self, config: NativeConfig) -> Simulator<NativeSetup> {
+        let mut device_manager = HostDeviceManager::default();
 
-        let host_gpu = Box::new(HostGpu::new(gpu_rx));
+        let (uart_tx, uart_rx) = mpsc::channel();
+        let (control_tx, control_rx) = mpsc::channel();
+
+        let uart = BusDevice::new(
+            Box::new(Uart::new(NativeReciever::new(uart_rx))),
+            UART_BASE..UART_END,
+        )
+        .set_irq(UART_IRQ);
+
+        let shell = Box::new(Shell::new(uart_tx, control_tx, config.snapshot));
+        self.control = Some(control_rx);
+
+        // 実行を始めるときにtimeの値に合わせ直す
+        self = self.set_timer_mode(config.timer);
 
         self.bus
             .add_device(uart)
-            .add_device(virtio_net)
-            .add_device(virtio_gpu);
+            .expect("standard devices do not conflict");
+        device_manager.add_device(shell);
+
+        if let Some(if_name) = config.net {
+            let (net_host_tx, net_guest_rx) = mpsc::channel();
+            let (net_guest_tx, net_host_rx) = mpsc::channel();
+
+            let virtio_net = BusDevice::new(
+                Box::new(VirtioNet::new(
+                    NativeReciever::new(net_guest_rx),
+                    NativeSender::new(net_guest_tx),
+                )),
+                VIRTIO_NET_BASE..VIRTIO_NET_END,
+            )
+            .set_irq(VIRTIO_NET_IRQ);
+
+            let host_net = Box::new(HostNet::new(net_host_rx, net_host_tx, if_name));
+
+            self.bus
+                .add_device(virtio_net)
+                .expect("standard devices do not conflict");
+            device_manager.add_device(host_net);
+        }
 
-        let mut device_manager = HostDeviceManager::default();
+        if config.gpu {
+            let (gpu_tx, gpu_rx) = mpsc::channel();
 
-        device_manager
-            .add_device(shell)
-            .add_device(host_net)
-            .add_device(host_gpu);
+            let virtio_gpu = BusDevice::new(
+                Box::new(VirtioGpu::new(NativeSender::new(gpu_tx))),
+                VIRTIO_GPU_BASE..VIRTIO_GPU_END,
+            )
+            .set_irq(VIRTIO_GPU_IRQ);
 
-        Simulator {
-            cpu: self.cpu,
-            bus: self.bus,
-            host_device_manager: Some(device_manager),
-            _marker: PhantomData,
+            let host_gpu = Box::new(HostGpu::new(gpu_rx));
+
+            self.bus
+                .add_device(virtio_gpu)
+                .expect("standard devices do not conflict");
+            device_manager.add_device(host_gpu);
         }
+
+        self.host_device_manager = Some(device_manager);
+
+        self.into_state()
     }
 
     // wasm
@@ -118,113 +597,245 @@ impl Simulator<Initial> {
         use crate::wasm::WasmUartReciever;
 
         let uart_reciever = WasmUartReciever::default();
-        let uart = BusDevice::new(Box::new(Uart::new(uart_reciever)), UART_BASE..UART_END);
+        let uart = BusDevice::new(Box::new(Uart::new(uart_reciever)), UART_BASE..UART_END)
+            .set_irq(UART_IRQ);
 
         let virtio_sender = WasmGpuSender::new(canvas_ctx);
 
         let virtio_gpu = BusDevice::new(
             Box::new(VirtioGpu::new(virtio_sender)),
             VIRTIO_GPU_BASE..VIRTIO_GPU_END,
-        );
+        )
+        .set_irq(VIRTIO_GPU_IRQ);
 
-        self.bus.add_device(uart).add_device(virtio_gpu);
+        self.bus
+            .add_device(uart)
+            .expect("standard devices do not conflict");
+        self.bus
+            .add_device(virtio_gpu)
+            .expect("standard devices do not conflict");
 
-        Simulator {
-            cpu: self.cpu,
-            bus: self.bus,
-            host_device_manager: self.host_device_manager,
-            _marker: PhantomData,
-        }
+        self.into_state()
     }
 }
 
 #[cfg(not(target_arch = "wasm32"))]
 impl Simulator<NativeSetup> {
     pub fn set_entry_point(mut self, entry_point: u32) -> Simulator<NativeLoaded> {
-        self.cpu.set_pc(entry_point);
+        self.set_pc(entry_point);
 
-        Simulator {
-            cpu: self.cpu,
-            bus: self.bus,
-            host_device_manager: self.host_device_manager,
-            _marker: PhantomData,
-        }
+        self.into_state()
+    }
+}
+
+impl Simulator<HeadlessSetup> {
+    pub fn set_entry_point(mut self, entry_point: u32) -> Simulator<HeadlessLoaded> {
+        self.set_pc(entry_point);
+
+        #[cfg(not(target_arch = "wasm32"))]
+        self.start_clock();
+
+        self.into_state()
+    }
+
+    // baseにホストと繋がっていないUARTを接続し、割り込み源の番号を返す関数
+    // irqがNoneの場合は使われていない番号を割り当てる
+    pub fn add_uart(&mut self, base: u32, irq: Option<u32>) -> Result<u32, AddDeviceError> {
+        let size = UART_END - UART_BASE;
+        let end = base
+            .checked_add(size)
+            .ok_or(AddDeviceError::InvalidRange { base, size })?;
+
+        let uart = BusDevice::new(Box::new(Uart::new(NullReciever)), base..end);
+
+        self.bus.add_device(match irq {
+            Some(irq) => uart.set_irq(irq),
+            None => uart,
+        })
     }
 }
 
 #[cfg(target_arch = "wasm32")]
 impl Simulator<WasmSetup> {
     pub fn set_entry_point(mut self, entry_point: u32) -> Simulator<WasmLoaded> {
-        self.cpu.set_pc(entry_point);
+        self.set_pc(entry_point);
 
-        Simulator {
-            cpu: self.cpu,
-            bus: self.bus,
-            host_device_manager: self.host_device_manager,
-            _marker: PhantomData,
-        }
+        self.into_state()
     }
 }
 
 #[cfg(not(target_arch = "wasm32"))]
 impl Simulator<NativeLoaded> {
     pub fn run(mut self) {
-        let device_manager = self.host_device_manager.take().unwrap();
+        self.spawn_host_devices();
+        self.start_clock();
 
-        for device in device_manager.devices() {
-            thread::spawn(move || device.run());
+        let mut steps: u64 = 0;
+
+        loop {
+            self.step_once();
+            steps += 1;
+
+            if self.should_poll(steps) {
+                self.handle_control();
+            }
+
+            self.sleep_if_waiting();
         }
+    }
+
+    // addrでgdbの接続を待ってから停止した状態で実行を始める
+    pub fn run_with_gdb(mut self, addr: &str) -> std::io::Result<()> {
+        let mut gdb = GdbStub::listen(addr)?;
+
+        self.spawn_host_devices();
+        self.start_clock();
+
+        let (cpu, bus) = self.gdb_target();
+        let mut resume = gdb.attach(cpu, bus)?;
+        let mut steps: u64 = 0;
 
         loop {
-            self.bus.tick(self.cpu.prv(), self.cpu.mut_csr());
+            match resume {
+                Resume::Detach => break,
+                Resume::Kill => return Ok(()),
+                Resume::Continue | Resume::Step => {}
+            }
 
-            if let Some(e) = self.cpu.check_local_intrrupt_active() {
-                self.cpu.handle_trap(e, &mut self.bus);
+            self.step_once();
+            steps += 1;
+
+            let reason = if let Some((kind, addr)) = self.harts[0].take_watch_hit() {
+                Some(StopReason::Watch(kind, addr))
+            } else if resume == Resume::Step {
+                Some(StopReason::Step)
+            } else if let Some(kind) = gdb.breakpoint(self.harts[0].pc()) {
+                Some(StopReason::Breakpoint(kind))
+            } else if self.should_poll(steps) && gdb.poll_interrupt()? {
+                Some(StopReason::Interrupt)
+            } else {
+                None
+            };
+
+            if self.should_poll(steps) {
+                self.handle_control();
             }
 
-            match self.cpu.step(&mut self.bus) {
-                Err(e) => {
-                    self.cpu.handle_trap(e, &mut self.bus);
-                }
-                Ok(is_jump) => {
-                    self.cpu.mut_csr().progress_instret();
+            if let Some(reason) = reason {
+                let (cpu, bus) = self.gdb_target();
+                resume = gdb.stop(reason, cpu, bus)?;
+            } else {
+                self.sleep_if_waiting();
+            }
+        }
 
-                    if !is_jump {
-                        self.cpu.progress_pc();
-                    }
-                }
+        // デタッチされた後は通常通り実行する
+        loop {
+            self.step_once();
+            steps += 1;
+
+            if self.should_poll(steps) {
+                self.handle_control();
             }
 
-            self.cpu.mut_csr().progress_cycle();
-            self.cpu.mut_csr().progress_time();
+            self.sleep_if_waiting();
         }
     }
-}
 
-#[cfg(target_arch = "wasm32")]
-impl Simulator<WasmLoaded> {
-    pub fn step(&mut self) {
-        self.bus.tick(self.cpu.prv(), self.cpu.mut_csr());
+    // WFIで待っている間は1回ごとにスレッドを止めるので毎回確認する
+    #[inline]
+    fn should_poll(&self, steps: u64) -> bool {
+        steps.is_multiple_of(POLL_INTERVAL) || self.is_all_waiting()
+    }
 
-        if let Some(e) = self.cpu.check_local_intrrupt_active() {
-            self.cpu.handle_trap(e, &mut self.bus);
+    // WFIで待っている間はホストのCPUを使わないようにスレッドを止める
+    #[inline]
+    fn sleep_if_waiting(&self) {
+        if self.is_all_waiting() {
+            thread::sleep(IDLE_SLEEP);
         }
+    }
 
-        match self.cpu.step(&mut self.bus) {
-            Err(e) => {
-                self.cpu.handle_trap(e, &mut self.bus);
+    // ホストデバイスからの要求を処理する関数
+    // シェルからプロセスが終了されることがあるのでトレースもここで書き出す
+    fn handle_control(&mut self) {
+        self.harts[0].flush_trace();
+
+        let Some(control) = &self.control else {
+            return;
+        };
+
+        let Ok(message) = control.try_recv() else {
+            return;
+        };
+
+        match message {
+            ControlMessage::SaveSnapshot(path) => {
+                // 標準エラーは捨てられることが多いのでコンソールに出力する
+                // シェルがrawモードなので行頭に戻してから出力する
+                match fs::write(&path, self.save_snapshot()) {
+                    Ok(()) => print!("\r\n[INFO]: snapshot saved to {}\r\n", path.display()),
+                    Err(e) => print!(
+                        "\r\n[ERROR]: failed to save snapshot to {}: {}\r\n",
+                        path.display(),
+                        e
+                    ),
+                }
+
+                io::stdout().flush().unwrap();
             }
-            Ok(is_jump) => {
-                self.cpu.mut_csr().progress_instret();
+        }
+    }
 
-                if !is_jump {
-                    self.cpu.progress_pc();
+    fn spawn_host_devices(&mut self) {
+        let device_manager = self.host_device_manager.take().unwrap();
+
+        for device in device_manager.devices() {
+            thread::spawn(move || device.run());
+        }
+    }
+}
+
+impl Simulator<HeadlessLoaded> {
+    // 終了条件を満たすかmax_stepsだけ実行するまで進める関数
+    pub fn run_test(&mut self, config: &TestConfig) -> TestResult {
+        for _ in 0..config.max_steps {
+            if config.exit_address == Some(self.harts[0].pc()) {
+                return TestResult::from_code(self.harts[0].read_reg(3)); // gp
+            }
+
+            self.step_once();
+
+            if let Some(tohost) = config.tohost {
+                match self.read_physical_u32(tohost) {
+                    Some(0) | None => {}
+                    Some(code) => return TestResult::from_code(code),
                 }
             }
         }
 
-        self.cpu.mut_csr().progress_cycle();
-        self.cpu.mut_csr().progress_time();
+        TestResult::Timeout
+    }
+
+    pub fn step(&mut self) {
+        self.step_once();
+    }
+
+    // PLICのレベルトリガの割り込み源の信号を変える関数
+    pub fn set_irq_level(&mut self, irq: u32, level: bool) {
+        self.bus.set_irq_level(irq, level);
+    }
+
+    // PLICのエッジトリガの割り込み源に信号を入れる関数
+    pub fn trigger_irq(&mut self, irq: u32) {
+        self.bus.trigger_irq(irq);
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl Simulator<WasmLoaded> {
+    pub fn step(&mut self) {
+        self.step_once();
     }
 
     pub fn send_key(&mut self, key: char) {
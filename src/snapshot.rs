@@ -0,0 +1,220 @@
+use std::fmt::Display;
+
+use crate::Priv;
+
+const SNAPSHOT_MAGIC: [u8; 8] = *b"RV32SNAP";
+
+// 形式を変更した場合は上げる
+pub const SNAPSHOT_VERSION: u32 = 8;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SnapshotError {
+    InvalidMagic,
+    UnsupportedVersion(u32),
+    UnexpectedEof,
+    // スナップショットを取った時と構成が異なる場合
+    MemorySizeMismatch { expected: usize, actual: usize },
+    HartCountMismatch { expected: usize, actual: usize },
+    DeviceMismatch { expected: String, actual: String },
+    InvalidValue(&'static str),
+}
+
+impl Display for SnapshotError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidMagic => write!(f, "not a snapshot file"),
+            Self::UnsupportedVersion(v) => write!(
+                f,
+                "snapshot version {} is not supported (expected {})",
+                v, SNAPSHOT_VERSION
+            ),
+            Self::UnexpectedEof => write!(f, "snapshot is truncated"),
+            Self::MemorySizeMismatch { expected, actual } => write!(
+                f,
+                "snapshot has 0x{:x} bytes of RAM but the machine has 0x{:x}",
+                expected, actual
+            ),
+            Self::HartCountMismatch { expected, actual } => write!(
+                f,
+                "snapshot has {} harts but the machine has {}",
+                expected, actual
+            ),
+            Self::DeviceMismatch { expected, actual } => write!(
+                f,
+                "snapshot has devices [{}] but the machine has [{}]",
+                expected, actual
+            ),
+            Self::InvalidValue(name) => write!(f, "invalid value for {}", name),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+pub type Result<T> = std::result::Result<T, SnapshotError>;
+
+// スナップショットに状態を保存・復元する型が実装するトレイト
+pub trait Snapshot {
+    fn save(&self, w: &mut SnapshotWriter);
+    fn restore(&mut self, r: &mut SnapshotReader) -> Result<()>;
+}
+
+// 値はすべてリトルエンディアンで書き込む
+#[derive(Default)]
+pub struct SnapshotWriter {
+    buf: Vec<u8>,
+}
+
+impl SnapshotWriter {
+    pub fn new() -> Self {
+        let mut w = Self::default();
+
+        w.write_bytes(&SNAPSHOT_MAGIC);
+        w.write_u32(SNAPSHOT_VERSION);
+
+        w
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.buf
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.buf.push(value);
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        self.write_u8(value as u8);
+    }
+
+    pub fn write_u16(&mut self, value: u16) {
+        self.write_bytes(&value.to_le_bytes());
+    }
+
+    pub fn write_u32(&mut self, value: u32) {
+        self.write_bytes(&value.to_le_bytes());
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.write_bytes(&value.to_le_bytes());
+    }
+
+    pub fn write_len(&mut self, len: usize) {
+        self.write_u32(len as u32);
+    }
+
+    pub fn write_option_u32(&mut self, value: Option<u32>) {
+        self.write_bool(value.is_some());
+        self.write_u32(value.unwrap_or(0));
+    }
+
+    pub fn write_string(&mut self, value: &str) {
+        self.write_len(value.len());
+        self.write_bytes(value.as_bytes());
+    }
+}
+
+pub struct SnapshotReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> SnapshotReader<'a> {
+    pub fn new(data: &'a [u8]) -> Result<Self> {
+        let mut r = Self { data, pos: 0 };
+
+        if r.read_bytes(SNAPSHOT_MAGIC.len())
+            .map_err(|_| SnapshotError::InvalidMagic)?
+            != SNAPSHOT_MAGIC
+        {
+            return Err(SnapshotError::InvalidMagic);
+        }
+
+        let version = r.read_u32()?;
+
+        if version != SNAPSHOT_VERSION {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+
+        Ok(r)
+    }
+
+    pub fn read_bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos.saturating_add(len))
+            .ok_or(SnapshotError::UnexpectedEof)?;
+
+        self.pos += len;
+
+        Ok(bytes)
+    }
+
+    fn read_array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        Ok(self.read_bytes(N)?.try_into().unwrap())
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8> {
+        Ok(self.read_array::<1>()?[0])
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool> {
+        match self.read_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(SnapshotError::InvalidValue("bool")),
+        }
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.read_array()?))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.read_array()?))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.read_array()?))
+    }
+
+    pub fn read_len(&mut self) -> Result<usize> {
+        let len = self.read_u32()? as usize;
+
+        // 残りのデータより長い場合は壊れている
+        if len > self.data.len() - self.pos {
+            return Err(SnapshotError::UnexpectedEof);
+        }
+
+        Ok(len)
+    }
+
+    pub fn read_option_u32(&mut self) -> Result<Option<u32>> {
+        let is_some = self.read_bool()?;
+        let value = self.read_u32()?;
+
+        Ok(is_some.then_some(value))
+    }
+
+    pub fn read_string(&mut self) -> Result<String> {
+        let len = self.read_len()?;
+        let bytes = self.read_bytes(len)?;
+
+        String::from_utf8(bytes.to_vec()).map_err(|_| SnapshotError::InvalidValue("string"))
+    }
+
+    pub fn read_priv(&mut self) -> Result<Priv> {
+        match self.read_u32()? {
+            v @ (0 | 1 | 3) => Ok(v.into()),
+            _ => Err(SnapshotError::InvalidValue("privilege mode")),
+        }
+    }
+
+    pub fn is_end(&self) -> bool {
+        self.pos == self.data.len()
+    }
+}
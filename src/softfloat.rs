@@ -0,0 +1,681 @@
+use std::cmp::Ordering;
+
+// IEEE 754の二進浮動小数点数をソフトウェアで計算するモジュール
+// 丸めモード、例外フラグ、NaNの扱いはRISC-Vに合わせている
+
+pub const FLAG_NX: u32 = 1; // 不正確
+pub const FLAG_UF: u32 = 1 << 1; // アンダーフロー
+pub const FLAG_OF: u32 = 1 << 2; // オーバーフロー
+pub const FLAG_DZ: u32 = 1 << 3; // ゼロ除算
+pub const FLAG_NV: u32 = 1 << 4; // 無効演算
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RoundingMode {
+    NearestEven = 0,
+    TowardZero = 1,
+    Down = 2,
+    Up = 3,
+    NearestMaxMagnitude = 4,
+}
+
+impl RoundingMode {
+    pub fn from_bits(bits: u32) -> Option<Self> {
+        match bits {
+            0 => Some(Self::NearestEven),
+            1 => Some(Self::TowardZero),
+            2 => Some(Self::Down),
+            3 => Some(Self::Up),
+            4 => Some(Self::NearestMaxMagnitude),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Single,
+    Double,
+}
+
+// 展開した値
+// 有限の値はsig * 2^expを表す
+#[derive(Debug, Clone, Copy)]
+enum Value {
+    Zero(bool),
+    Finite(bool, i32, u64),
+    Inf(bool),
+    Nan,
+}
+
+impl Format {
+    #[inline]
+    fn exp_bits(self) -> u32 {
+        match self {
+            Self::Single => 8,
+            Self::Double => 11,
+        }
+    }
+
+    #[inline]
+    fn frac_bits(self) -> u32 {
+        match self {
+            Self::Single => 23,
+            Self::Double => 52,
+        }
+    }
+
+    #[inline]
+    fn bias(self) -> i32 {
+        (1 << (self.exp_bits() - 1)) - 1
+    }
+
+    #[inline]
+    fn exp_max(self) -> u64 {
+        (1 << self.exp_bits()) - 1
+    }
+
+    #[inline]
+    fn frac_mask(self) -> u64 {
+        (1 << self.frac_bits()) - 1
+    }
+
+    #[inline]
+    pub fn sign_bit(self) -> u64 {
+        1 << (self.exp_bits() + self.frac_bits())
+    }
+
+    // 非正規化数のLSBの重み
+    #[inline]
+    fn min_exp(self) -> i32 {
+        1 - self.bias() - self.frac_bits() as i32
+    }
+
+    #[inline]
+    fn sign(self, bits: u64) -> bool {
+        bits & self.sign_bit() != 0
+    }
+
+    #[inline]
+    fn with_sign(self, sign: bool) -> u64 {
+        if sign { self.sign_bit() } else { 0 }
+    }
+
+    pub fn canonical_nan(self) -> u64 {
+        (self.exp_max() << self.frac_bits()) | (1 << (self.frac_bits() - 1))
+    }
+
+    fn zero(self, sign: bool) -> u64 {
+        self.with_sign(sign)
+    }
+
+    fn inf(self, sign: bool) -> u64 {
+        self.with_sign(sign) | (self.exp_max() << self.frac_bits())
+    }
+
+    fn max_finite(self, sign: bool) -> u64 {
+        self.inf(sign) - 1
+    }
+
+    pub fn is_nan(self, bits: u64) -> bool {
+        (bits >> self.frac_bits()) & self.exp_max() == self.exp_max()
+            && bits & self.frac_mask() != 0
+    }
+
+    pub fn is_signaling_nan(self, bits: u64) -> bool {
+        self.is_nan(bits) && bits & (1 << (self.frac_bits() - 1)) == 0
+    }
+
+    fn unpack(self, bits: u64) -> Value {
+        let sign = self.sign(bits);
+        let exp = (bits >> self.frac_bits()) & self.exp_max();
+        let frac = bits & self.frac_mask();
+
+        match (exp, frac) {
+            (0, 0) => Value::Zero(sign),
+            (0, _) => Value::Finite(sign, self.min_exp(), frac),
+            (e, 0) if e == self.exp_max() => Value::Inf(sign),
+            (e, _) if e == self.exp_max() => Value::Nan,
+            (e, _) => Value::Finite(
+                sign,
+                e as i32 - self.bias() - self.frac_bits() as i32,
+                frac | (1 << self.frac_bits()),
+            ),
+        }
+    }
+
+    // fclass命令の結果
+    pub fn classify(self, bits: u64) -> u32 {
+        let index = match self.unpack(bits) {
+            Value::Inf(true) => 0,
+            Value::Finite(true, _, sig) if sig >> self.frac_bits() != 0 => 1,
+            Value::Finite(true, _, _) => 2,
+            Value::Zero(true) => 3,
+            Value::Zero(false) => 4,
+            Value::Finite(false, _, sig) if sig >> self.frac_bits() == 0 => 5,
+            Value::Finite(false, _, _) => 6,
+            Value::Inf(false) => 7,
+            Value::Nan if self.is_signaling_nan(bits) => 8,
+            Value::Nan => 9,
+        };
+
+        1 << index
+    }
+}
+
+#[inline]
+fn msb(sig: u128) -> i32 {
+    127 - sig.leading_zeros() as i32
+}
+
+// 右にシフトし、落ちたbitが0でなければLSBを立てる
+#[inline]
+fn shift_right_jam(sig: u128, shift: u32) -> u128 {
+    if shift >= 128 {
+        (sig != 0) as u128
+    } else {
+        (sig >> shift) | ((sig & ((1 << shift) - 1)) != 0) as u128
+    }
+}
+
+#[inline]
+fn shift_to(sig: u128, shift: i32) -> u128 {
+    if shift >= 0 {
+        sig << shift
+    } else {
+        shift_right_jam(sig, -shift as u32)
+    }
+}
+
+fn isqrt(n: u128) -> u128 {
+    let mut n = n;
+    let mut x = 0;
+    let mut bit = 1 << 126;
+
+    while bit > n {
+        bit >>= 2;
+    }
+
+    while bit != 0 {
+        if n >= x + bit {
+            n -= x + bit;
+            x = (x >> 1) + bit;
+        } else {
+            x >>= 1;
+        }
+
+        bit >>= 2;
+    }
+
+    x
+}
+
+// 丸めモードと発生した例外フラグを持って計算する
+pub struct Softfloat {
+    rm: RoundingMode,
+    flags: u32,
+}
+
+impl Softfloat {
+    pub fn new(rm: RoundingMode) -> Self {
+        Self { rm, flags: 0 }
+    }
+
+    pub fn flags(&self) -> u32 {
+        self.flags
+    }
+
+    fn invalid(&mut self, fmt: Format) -> u64 {
+        self.flags |= FLAG_NV;
+        fmt.canonical_nan()
+    }
+
+    // 入力にNaNがある場合は正規化されたNaNを返す
+    fn propagate_nan(&mut self, fmt: Format, inputs: &[u64]) -> Option<u64> {
+        if inputs.iter().any(|x| fmt.is_signaling_nan(*x)) {
+            self.flags |= FLAG_NV;
+        }
+
+        inputs
+            .iter()
+            .any(|x| fmt.is_nan(*x))
+            .then(|| fmt.canonical_nan())
+    }
+
+    // sigをshiftだけ右にシフトして丸める
+    // 丸めた値と不正確かどうかを返す
+    fn round_shift(&self, sign: bool, sig: u128, shift: i32) -> (u128, bool) {
+        if shift <= 0 {
+            return (sig << -shift, false);
+        }
+
+        let (kept, half) = match shift {
+            1..128 => {
+                let rem = sig & ((1 << shift) - 1);
+                (sig >> shift, rem.cmp(&(1 << (shift - 1))))
+            }
+            128 => (0, sig.cmp(&(1 << 127))),
+            _ => (0, Ordering::Less),
+        };
+
+        let inexact = shift >= 128 || sig & ((1 << shift) - 1) != 0;
+
+        let round_up = match self.rm {
+            RoundingMode::NearestEven => {
+                half == Ordering::Greater || (half == Ordering::Equal && kept & 1 == 1)
+            }
+            RoundingMode::TowardZero => false,
+            RoundingMode::Down => sign && inexact,
+            RoundingMode::Up => !sign && inexact,
+            RoundingMode::NearestMaxMagnitude => half != Ordering::Less,
+        };
+
+        (kept + round_up as u128, inexact)
+    }
+
+    // sig * 2^expを丸めてfmtの形式にする
+    // sigのLSBは下位のbitが0でないことを表すstickyでもよい
+    fn round_pack(&mut self, fmt: Format, sign: bool, exp: i32, sig: u128) -> u64 {
+        if sig == 0 {
+            return fmt.zero(sign);
+        }
+
+        let frac_bits = fmt.frac_bits() as i32;
+        let top = exp + msb(sig);
+        let mut lsb = (top - frac_bits).max(fmt.min_exp());
+
+        let (mut mant, inexact) = self.round_shift(sign, sig, lsb - exp);
+
+        if mant >> (frac_bits + 1) != 0 {
+            mant >>= 1;
+            lsb += 1;
+        }
+
+        // 丸めた後で判定する
+        // 指数の範囲が無制限として丸めた場合に最小の正規化数になるなら小さすぎない
+        let min_top = 1 - fmt.bias();
+        let tiny = top < min_top
+            && !(top == min_top - 1 && {
+                let (mant, _) = self.round_shift(sign, sig, top - frac_bits - exp);
+                mant >> (frac_bits + 1) != 0
+            });
+
+        if inexact {
+            self.flags |= FLAG_NX;
+
+            if tiny {
+                self.flags |= FLAG_UF;
+            }
+        }
+
+        if mant >> frac_bits == 0 {
+            // 非正規化数
+            return fmt.with_sign(sign) | mant as u64;
+        }
+
+        let biased = (lsb + frac_bits + fmt.bias()) as u64;
+
+        if biased >= fmt.exp_max() {
+            self.flags |= FLAG_OF | FLAG_NX;
+
+            return match (self.rm, sign) {
+                (RoundingMode::NearestEven | RoundingMode::NearestMaxMagnitude, _) => fmt.inf(sign),
+                (RoundingMode::Down, true) | (RoundingMode::Up, false) => fmt.inf(sign),
+                _ => fmt.max_finite(sign),
+            };
+        }
+
+        fmt.with_sign(sign) | (biased << frac_bits) | (mant as u64 & fmt.frac_mask())
+    }
+
+    // 符号が異なる場合は引き算になる
+    #[allow(clippy::too_many_arguments)]
+    fn add_finite(
+        &mut self,
+        fmt: Format,
+        sign_a: bool,
+        exp_a: i32,
+        sig_a: u128,
+        sign_b: bool,
+        exp_b: i32,
+        sig_b: u128,
+    ) -> u64 {
+        // 大きい方の最上位bitを125bit目に揃える
+        let base = (exp_a + msb(sig_a)).max(exp_b + msb(sig_b)) - 125;
+        let a = shift_to(sig_a, exp_a - base);
+        let b = shift_to(sig_b, exp_b - base);
+
+        let (sign, sig) = if sign_a == sign_b {
+            (sign_a, a + b)
+        } else if a >= b {
+            (sign_a, a - b)
+        } else {
+            (sign_b, b - a)
+        };
+
+        if sig == 0 {
+            return fmt.zero(self.rm == RoundingMode::Down);
+        }
+
+        self.round_pack(fmt, sign, base, sig)
+    }
+
+    pub fn add(&mut self, fmt: Format, a: u64, b: u64) -> u64 {
+        if let Some(nan) = self.propagate_nan(fmt, &[a, b]) {
+            return nan;
+        }
+
+        match (fmt.unpack(a), fmt.unpack(b)) {
+            (Value::Inf(sa), Value::Inf(sb)) if sa != sb => self.invalid(fmt),
+            (Value::Inf(s), _) | (_, Value::Inf(s)) => fmt.inf(s),
+            (Value::Zero(sa), Value::Zero(sb)) => fmt.zero(if sa == sb {
+                sa
+            } else {
+                self.rm == RoundingMode::Down
+            }),
+            (Value::Zero(_), _) => b,
+            (_, Value::Zero(_)) => a,
+            (Value::Finite(sa, ea, ma), Value::Finite(sb, eb, mb)) => {
+                self.add_finite(fmt, sa, ea, ma as u128, sb, eb, mb as u128)
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn sub(&mut self, fmt: Format, a: u64, b: u64) -> u64 {
+        self.add(fmt, a, b ^ fmt.sign_bit())
+    }
+
+    pub fn mul(&mut self, fmt: Format, a: u64, b: u64) -> u64 {
+        if let Some(nan) = self.propagate_nan(fmt, &[a, b]) {
+            return nan;
+        }
+
+        let sign = fmt.sign(a) ^ fmt.sign(b);
+
+        match (fmt.unpack(a), fmt.unpack(b)) {
+            (Value::Inf(_), Value::Zero(_)) | (Value::Zero(_), Value::Inf(_)) => self.invalid(fmt),
+            (Value::Inf(_), _) | (_, Value::Inf(_)) => fmt.inf(sign),
+            (Value::Zero(_), _) | (_, Value::Zero(_)) => fmt.zero(sign),
+            (Value::Finite(_, ea, ma), Value::Finite(_, eb, mb)) => {
+                self.round_pack(fmt, sign, ea + eb, ma as u128 * mb as u128)
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn div(&mut self, fmt: Format, a: u64, b: u64) -> u64 {
+        if let Some(nan) = self.propagate_nan(fmt, &[a, b]) {
+            return nan;
+        }
+
+        let sign = fmt.sign(a) ^ fmt.sign(b);
+
+        match (fmt.unpack(a), fmt.unpack(b)) {
+            (Value::Inf(_), Value::Inf(_)) | (Value::Zero(_), Value::Zero(_)) => self.invalid(fmt),
+            (Value::Inf(_), _) => fmt.inf(sign),
+            (_, Value::Inf(_)) | (Value::Zero(_), _) => fmt.zero(sign),
+            (_, Value::Zero(_)) => {
+                self.flags |= FLAG_DZ;
+                fmt.inf(sign)
+            }
+            (Value::Finite(_, ea, ma), Value::Finite(_, eb, mb)) => {
+                // 最上位bitを63bit目に揃えてから割る
+                let (za, zb) = (ma.leading_zeros(), mb.leading_zeros());
+                let n = ((ma << za) as u128) << 64;
+                let d = (mb << zb) as u128;
+
+                let sig = (n / d) | !n.is_multiple_of(d) as u128;
+                let exp = (ea - za as i32) - (eb - zb as i32) - 64;
+
+                self.round_pack(fmt, sign, exp, sig)
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn sqrt(&mut self, fmt: Format, a: u64) -> u64 {
+        if let Some(nan) = self.propagate_nan(fmt, &[a]) {
+            return nan;
+        }
+
+        match fmt.unpack(a) {
+            Value::Zero(sign) => fmt.zero(sign),
+            Value::Inf(false) => fmt.inf(false),
+            Value::Inf(true) | Value::Finite(true, _, _) => self.invalid(fmt),
+            Value::Finite(false, exp, sig) => {
+                // 最上位bitを62bit目に揃えて、指数を偶数にする
+                let z = sig.leading_zeros() - 1;
+                let mut n = ((sig << z) as u128) << 64;
+                let mut exp = exp - z as i32 - 64;
+
+                if exp & 1 != 0 {
+                    n <<= 1;
+                    exp -= 1;
+                }
+
+                let root = isqrt(n);
+                let sig = root | (root * root != n) as u128;
+
+                self.round_pack(fmt, false, exp / 2, sig)
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    // (-1)^negate_product * a * b + (-1)^negate_c * cを一度だけ丸める
+    pub fn fma(
+        &mut self,
+        fmt: Format,
+        a: u64,
+        b: u64,
+        c: u64,
+        negate_product: bool,
+        negate_c: bool,
+    ) -> u64 {
+        let (va, vb, vc) = (fmt.unpack(a), fmt.unpack(b), fmt.unpack(c));
+
+        // 0 * ∞はcがNaNでも無効演算になる
+        let is_invalid_product = matches!(
+            (va, vb),
+            (Value::Inf(_), Value::Zero(_)) | (Value::Zero(_), Value::Inf(_))
+        );
+
+        if let Some(nan) = self.propagate_nan(fmt, &[a, b, c]) {
+            if is_invalid_product {
+                self.flags |= FLAG_NV;
+            }
+
+            return nan;
+        }
+
+        if is_invalid_product {
+            return self.invalid(fmt);
+        }
+
+        let sign_p = fmt.sign(a) ^ fmt.sign(b) ^ negate_product;
+        let sign_c = fmt.sign(c) ^ negate_c;
+
+        match (va, vb, vc) {
+            (Value::Inf(_), _, _) | (_, Value::Inf(_), _) => match vc {
+                Value::Inf(s) if s != sign_p => self.invalid(fmt),
+                _ => fmt.inf(sign_p),
+            },
+            (_, _, Value::Inf(_)) => fmt.inf(sign_c),
+            (Value::Zero(_), _, Value::Zero(_)) | (_, Value::Zero(_), Value::Zero(_)) => {
+                fmt.zero(if sign_p == sign_c {
+                    sign_p
+                } else {
+                    self.rm == RoundingMode::Down
+                })
+            }
+            (Value::Zero(_), _, _) | (_, Value::Zero(_), _) => {
+                (c & !fmt.sign_bit()) | fmt.with_sign(sign_c)
+            }
+            (Value::Finite(_, ea, ma), Value::Finite(_, eb, mb), Value::Zero(_)) => {
+                self.round_pack(fmt, sign_p, ea + eb, ma as u128 * mb as u128)
+            }
+            (Value::Finite(_, ea, ma), Value::Finite(_, eb, mb), Value::Finite(_, ec, mc)) => self
+                .add_finite(
+                    fmt,
+                    sign_p,
+                    ea + eb,
+                    ma as u128 * mb as u128,
+                    sign_c,
+                    ec,
+                    mc as u128,
+                ),
+            _ => unreachable!(),
+        }
+    }
+
+    // 大小比較のための値
+    // -0と+0は同じ値になる
+    fn order_key(fmt: Format, bits: u64) -> i128 {
+        let magnitude = (bits & !fmt.sign_bit()) as i128;
+
+        if fmt.sign(bits) {
+            -magnitude
+        } else {
+            magnitude
+        }
+    }
+
+    pub fn min(&mut self, fmt: Format, a: u64, b: u64) -> u64 {
+        self.min_max(fmt, a, b, true)
+    }
+
+    pub fn max(&mut self, fmt: Format, a: u64, b: u64) -> u64 {
+        self.min_max(fmt, a, b, false)
+    }
+
+    // 片方のみがNaNの場合はもう片方を返す
+    // -0は+0より小さいとして扱う
+    fn min_max(&mut self, fmt: Format, a: u64, b: u64, is_min: bool) -> u64 {
+        if fmt.is_signaling_nan(a) || fmt.is_signaling_nan(b) {
+            self.flags |= FLAG_NV;
+        }
+
+        match (fmt.is_nan(a), fmt.is_nan(b)) {
+            (true, true) => return fmt.canonical_nan(),
+            (true, false) => return b,
+            (false, true) => return a,
+            _ => {}
+        }
+
+        let a_is_less = match Self::order_key(fmt, a).cmp(&Self::order_key(fmt, b)) {
+            Ordering::Less => true,
+            Ordering::Greater => false,
+            Ordering::Equal => fmt.sign(a),
+        };
+
+        if a_is_less == is_min { a } else { b }
+    }
+
+    // feqはsignaling NaNの場合のみ無効演算になる
+    pub fn eq(&mut self, fmt: Format, a: u64, b: u64) -> bool {
+        if fmt.is_nan(a) || fmt.is_nan(b) {
+            if fmt.is_signaling_nan(a) || fmt.is_signaling_nan(b) {
+                self.flags |= FLAG_NV;
+            }
+
+            return false;
+        }
+
+        Self::order_key(fmt, a) == Self::order_key(fmt, b)
+    }
+
+    pub fn lt(&mut self, fmt: Format, a: u64, b: u64) -> bool {
+        if fmt.is_nan(a) || fmt.is_nan(b) {
+            self.flags |= FLAG_NV;
+            return false;
+        }
+
+        Self::order_key(fmt, a) < Self::order_key(fmt, b)
+    }
+
+    pub fn le(&mut self, fmt: Format, a: u64, b: u64) -> bool {
+        if fmt.is_nan(a) || fmt.is_nan(b) {
+            self.flags |= FLAG_NV;
+            return false;
+        }
+
+        Self::order_key(fmt, a) <= Self::order_key(fmt, b)
+    }
+
+    // 整数に変換する
+    // 範囲外の場合は無効演算になり、飽和した値を返す
+    pub fn float_to_int(&mut self, fmt: Format, a: u64, signed: bool) -> u32 {
+        let saturated = |sign: bool| match (signed, sign) {
+            (true, false) => i32::MAX as u32,
+            (true, true) => i32::MIN as u32,
+            (false, false) => u32::MAX,
+            (false, true) => 0,
+        };
+
+        let (sign, exp, sig) = match fmt.unpack(a) {
+            Value::Nan => {
+                self.flags |= FLAG_NV;
+                return saturated(false);
+            }
+            Value::Inf(sign) => {
+                self.flags |= FLAG_NV;
+                return saturated(sign);
+            }
+            Value::Zero(_) => return 0,
+            Value::Finite(sign, exp, sig) => (sign, exp, sig),
+        };
+
+        // 2^64以上は確実に範囲外
+        let (magnitude, inexact) = if exp > 64 {
+            (u128::MAX, false)
+        } else {
+            self.round_shift(sign, sig as u128, -exp)
+        };
+
+        let is_in_range = match (signed, sign) {
+            (true, false) => magnitude <= i32::MAX as u128,
+            (true, true) => magnitude <= 1 << 31,
+            (false, false) => magnitude <= u32::MAX as u128,
+            (false, true) => magnitude == 0,
+        };
+
+        if !is_in_range {
+            self.flags |= FLAG_NV;
+            return saturated(sign);
+        }
+
+        if inexact {
+            self.flags |= FLAG_NX;
+        }
+
+        if sign {
+            (magnitude as u32).wrapping_neg()
+        } else {
+            magnitude as u32
+        }
+    }
+
+    pub fn int_to_float(&mut self, fmt: Format, value: u32, signed: bool) -> u64 {
+        let sign = signed && (value as i32) < 0;
+        let magnitude = if sign {
+            (value as i32).unsigned_abs()
+        } else {
+            value
+        };
+
+        self.round_pack(fmt, sign, 0, magnitude as u128)
+    }
+
+    pub fn convert(&mut self, from: Format, to: Format, a: u64) -> u64 {
+        if self.propagate_nan(from, &[a]).is_some() {
+            return to.canonical_nan();
+        }
+
+        match from.unpack(a) {
+            Value::Zero(sign) => to.zero(sign),
+            Value::Inf(sign) => to.inf(sign),
+            Value::Finite(sign, exp, sig) => self.round_pack(to, sign, exp, sig as u128),
+            Value::Nan => unreachable!(),
+        }
+    }
+}
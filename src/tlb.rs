@@ -1,23 +1,30 @@
-use crate::Priv;
-
 const TLB_SIZE: usize = 4096;
 const TLB_MASK: u32 = (TLB_SIZE - 1) as u32;
 
+// Sv32のVPN[1]は10bitなのでメガページは全て別のエントリに入る
+const SUPERPAGE_TLB_SIZE: usize = 1024;
+
+const PTE_G: u32 = 1 << 5;
+
 pub struct Tlb {
     entries: [Option<TlbEntry>; TLB_SIZE], // vpnがキーに成る。
+    superpages: [Option<TlbEntry>; SUPERPAGE_TLB_SIZE], // VPN[1]がキーに成る。
 }
 
 #[derive(Default, Clone, Copy)]
 pub struct TlbEntry {
-    prv: Priv,
-    ppn: u32,
-    vpn: u32,
+    vpn: u32, // メガページの場合はVPN[1]
+    ppn: u32, // 物理アドレスの上位(下位bitは0)
+    asid: u32,
+    pte: u32, // PTEの権限やA/Dビット
+    is_superpage: bool,
 }
 
 impl Default for Tlb {
     fn default() -> Self {
         Self {
             entries: [None; TLB_SIZE],
+            superpages: [None; SUPERPAGE_TLB_SIZE],
         }
     }
 }
@@ -25,39 +32,115 @@ impl Default for Tlb {
 impl Tlb {
     pub fn register_entry(&mut self, entry: TlbEntry) {
         let vpn = entry.vpn;
-        let i = (vpn & TLB_MASK) as usize;
 
-        self.entries[i] = Some(entry);
+        if entry.is_superpage {
+            self.superpages[vpn as usize] = Some(entry);
+        } else {
+            self.entries[(vpn & TLB_MASK) as usize] = Some(entry);
+        }
     }
 
-    pub fn lookup_ppn(&self, va: u32, prv: Priv) -> Option<&TlbEntry> {
+    pub fn lookup(&self, va: u32, asid: u32) -> Option<&TlbEntry> {
         let vpn = va >> 12;
-        let i = (vpn & TLB_MASK) as usize;
 
-        if let Some(ref entry) = self.entries[i] {
-            if entry.vpn == vpn && entry.prv == prv {
-                return Some(entry);
-            }
+        if let Some(ref entry) = self.entries[(vpn & TLB_MASK) as usize]
+            && entry.vpn == vpn
+            && entry.matches_asid(asid)
+        {
+            return Some(entry);
+        }
+
+        let vpn1 = va >> 22;
+
+        if let Some(ref entry) = self.superpages[vpn1 as usize]
+            && entry.vpn == vpn1
+            && entry.matches_asid(asid)
+        {
+            return Some(entry);
         }
 
         None
     }
 
+    // sfence.vmaで使う関数
+    // vaがNoneの場合は全てのアドレス、asidがNoneの場合は全てのASIDが対象になる
+    // asidを指定した場合はグローバルなエントリは消さない
+    pub fn flush(&mut self, va: Option<u32>, asid: Option<u32>) {
+        let Some(va) = va else {
+            for slot in self.entries.iter_mut().chain(self.superpages.iter_mut()) {
+                flush_slot(slot, None, asid);
+            }
+
+            return;
+        };
+
+        // vaを含むエントリはvaから決まる位置にしか入らない
+        flush_slot(
+            &mut self.entries[((va >> 12) & TLB_MASK) as usize],
+            Some(va),
+            asid,
+        );
+        flush_slot(&mut self.superpages[(va >> 22) as usize], Some(va), asid);
+    }
+
     pub fn clear(&mut self) {
         self.entries = [None; TLB_SIZE];
+        self.superpages = [None; SUPERPAGE_TLB_SIZE];
+    }
+}
+
+// 条件に一致するエントリを消す関数
+#[inline]
+fn flush_slot(slot: &mut Option<TlbEntry>, va: Option<u32>, asid: Option<u32>) {
+    if let Some(entry) = slot {
+        let va_matched = va.is_none_or(|va| entry.contains(va));
+        let asid_matched = asid.is_none_or(|asid| !entry.is_global() && entry.asid == asid);
+
+        if va_matched && asid_matched {
+            *slot = None;
+        }
     }
 }
 
 impl TlbEntry {
-    pub fn new(va: u32, ppn: u32, prv: Priv) -> Self {
+    pub fn new(va: u32, ppn: u32, asid: u32, pte: u32, is_superpage: bool) -> Self {
         Self {
-            prv,
+            vpn: if is_superpage { va >> 22 } else { va >> 12 },
             ppn,
-            vpn: va >> 12,
+            asid,
+            pte,
+            is_superpage,
         }
     }
 
-    pub fn ppn(&self) -> u32 {
-        self.ppn
+    // 仮想アドレスを物理アドレスに変換する関数
+    pub fn translate(&self, va: u32) -> u32 {
+        self.ppn | (va & self.offset_mask())
+    }
+
+    pub fn pte(&self) -> u32 {
+        self.pte
+    }
+
+    #[inline]
+    fn offset_mask(&self) -> u32 {
+        if self.is_superpage { 0x3fffff } else { 0xfff }
+    }
+
+    #[inline]
+    fn contains(&self, va: u32) -> bool {
+        let shift = if self.is_superpage { 22 } else { 12 };
+
+        va >> shift == self.vpn
+    }
+
+    #[inline]
+    fn is_global(&self) -> bool {
+        self.pte & PTE_G != 0
+    }
+
+    #[inline]
+    fn matches_asid(&self, asid: u32) -> bool {
+        self.is_global() || self.asid == asid
     }
 }
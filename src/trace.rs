@@ -0,0 +1,230 @@
+use std::{
+    io::{self, Write},
+    ops::Range,
+};
+
+use crate::{Priv, Trap, decode::inst_len, disasm::disassemble};
+
+// 出力の形式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+    // Spikeの--log-commitsと同じ形式
+    Commit,
+    // Spikeの-l --log-commitsと同じく逆アセンブルした命令の行を前に出力する
+    Full,
+}
+
+#[derive(Debug, Clone)]
+pub struct TraceConfig {
+    pub format: TraceFormat,
+    pub range: Option<Range<u32>>, // PCがこの範囲内の場合のみ出力する
+    pub privs: Vec<Priv>,          // 出力する特権モード
+}
+
+impl Default for TraceConfig {
+    fn default() -> Self {
+        Self {
+            format: TraceFormat::Commit,
+            range: None,
+            privs: vec![Priv::Machine, Priv::Supervisor, Priv::User],
+        }
+    }
+}
+
+// 1命令分の記録
+#[derive(Debug, Default)]
+struct Record {
+    pc: u32,
+    inst: u32,
+    prv: u32,
+    rd: Option<(u32, u32)>,
+    frd: Option<(u32, u64)>,
+    mem_read: Option<u32>,
+    mem_write: Option<(u32, u64, u32)>, // アドレス、値、サイズ
+}
+
+// 命令の実行とトラップを記録する構造体
+// 命令の実行中に書き込まれたレジスタとメモリを記録し、リタイアした時に出力する
+pub struct Tracer {
+    writer: Box<dyn Write>,
+    config: TraceConfig,
+
+    current: Option<Record>,
+}
+
+impl Tracer {
+    pub fn new(writer: Box<dyn Write>, config: TraceConfig) -> Self {
+        Self {
+            writer,
+            config,
+            current: None,
+        }
+    }
+
+    fn is_target(&self, pc: u32, prv: Priv) -> bool {
+        self.config.privs.contains(&prv)
+            && self
+                .config
+                .range
+                .as_ref()
+                .is_none_or(|range| range.contains(&pc))
+    }
+
+    // フェッチが終わった時に呼ぶ関数
+    pub fn begin(&mut self, pc: u32, inst: u32, prv: Priv) {
+        self.current = self.is_target(pc, prv).then(|| Record {
+            pc,
+            inst,
+            prv: prv as u32,
+            ..Default::default()
+        });
+    }
+
+    #[inline]
+    pub fn write_reg(&mut self, reg: u32, value: u32) {
+        if reg == 0 {
+            return;
+        }
+
+        if let Some(record) = &mut self.current {
+            record.rd = Some((reg, value));
+        }
+    }
+
+    #[inline]
+    pub fn write_freg(&mut self, reg: u32, value: u64) {
+        if let Some(record) = &mut self.current {
+            record.frd = Some((reg, value));
+        }
+    }
+
+    // fldのように複数回に分けて読む命令では最初のアドレスを記録する
+    #[inline]
+    pub fn read_memory(&mut self, addr: u32) {
+        if let Some(record) = &mut self.current {
+            record.mem_read.get_or_insert(addr);
+        }
+    }
+
+    #[inline]
+    pub fn write_memory(&mut self, addr: u32, value: u64, size: u32) {
+        if let Some(record) = &mut self.current {
+            record.mem_write = Some((addr, value, size));
+        }
+    }
+
+    // 命令がリタイアした時に呼ぶ関数
+    pub fn commit(&mut self) {
+        let Some(record) = self.current.take() else {
+            return;
+        };
+
+        if let Err(e) = self.write_record(&record) {
+            eprintln!("[ERROR]: failed to write trace: {}", e);
+        }
+    }
+
+    // 命令がトラップを起こした場合は記録を破棄する
+    pub fn abort(&mut self) {
+        self.current = None;
+    }
+
+    // トラップが起こった時に呼ぶ関数
+    // prvとepcはトラップが起こった時点のもの
+    pub fn trap(&mut self, e: Trap, epc: u32, prv: Priv, tval: Option<u32>) {
+        if !self.is_target(epc, prv) {
+            return;
+        }
+
+        if let Err(e) = self.write_trap(e, epc, tval) {
+            eprintln!("[ERROR]: failed to write trace: {}", e);
+        }
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    fn write_record(&mut self, record: &Record) -> io::Result<()> {
+        let w = &mut self.writer;
+
+        // Spikeと同じく圧縮命令は4桁で出力する
+        let width = inst_len(record.inst) as usize * 2;
+
+        if self.config.format == TraceFormat::Full {
+            writeln!(
+                w,
+                "core   0: 0x{:08x} (0x{:0width$x}) {}",
+                record.pc,
+                record.inst,
+                disassemble(record.inst, record.pc)
+            )?;
+        }
+
+        write!(
+            w,
+            "core   0: {} 0x{:08x} (0x{:0width$x})",
+            record.prv, record.pc, record.inst
+        )?;
+
+        if let Some((reg, value)) = record.rd {
+            write!(w, " x{:<2} 0x{:08x}", reg, value)?;
+        }
+
+        if let Some((reg, value)) = record.frd {
+            write!(w, " f{:<2} 0x{:016x}", reg, value)?;
+        }
+
+        if let Some(addr) = record.mem_read {
+            write!(w, " mem 0x{:08x}", addr)?;
+        }
+
+        if let Some((addr, value, size)) = record.mem_write {
+            write!(
+                w,
+                " mem 0x{:08x} 0x{:0width$x}",
+                addr,
+                value,
+                width = size as usize * 2
+            )?;
+        }
+
+        writeln!(w)
+    }
+
+    fn write_trap(&mut self, e: Trap, epc: u32, tval: Option<u32>) -> io::Result<()> {
+        let w = &mut self.writer;
+
+        writeln!(w, "core   0: exception {}, epc 0x{:08x}", trap_name(e), epc)?;
+
+        if let Some(tval) = tval {
+            writeln!(w, "core   0:           tval 0x{:08x}", tval)?;
+        }
+
+        Ok(())
+    }
+}
+
+// Spikeでの名前
+fn trap_name(e: Trap) -> String {
+    let name = match e {
+        Trap::InstructionAddressMisaligned => "trap_instruction_address_misaligned",
+        Trap::InstructionAccessFault => "trap_instruction_access_fault",
+        Trap::IlligalInstruction => "trap_illegal_instruction",
+        Trap::BreakPoint => "trap_breakpoint",
+        Trap::LoadAddressMisaligned => "trap_load_address_misaligned",
+        Trap::LoadAccessFault => "trap_load_access_fault",
+        Trap::StoreOrAMOAddressMisaligned => "trap_store_address_misaligned",
+        Trap::StoreOrAMOAccessFault => "trap_store_access_fault",
+        Trap::EnvCallFromUser => "trap_user_ecall",
+        Trap::EnvCallFromSupervisor => "trap_supervisor_ecall",
+        Trap::EnvCallFromMachine => "trap_machine_ecall",
+        Trap::InstructionPageFault => "trap_instruction_page_fault",
+        Trap::LoadPageFault => "trap_load_page_fault",
+        Trap::StoreOrAMOPageFault => "trap_store_page_fault",
+        _ if e.is_interrupt() => return format!("interrupt #{}", e.cause()),
+        _ => return format!("{:?}", e),
+    };
+
+    name.to_string()
+}
@@ -1,12 +1,12 @@
 use std::mem::transmute;
 
-use wasm_bindgen::{Clamped, JsCast, prelude::wasm_bindgen};
+use wasm_bindgen::{Clamped, JsCast, JsValue, prelude::wasm_bindgen};
 use web_sys::{CanvasRenderingContext2d, ImageData, Window, console, window};
 
 use crate::{
     device::{DeviceMessage, DeviceRecieverTrait, DeviceSenderTrait},
     host_device::{GpuMessage, GpuOperation},
-    simulator::{self, Simulator, WasmLoaded},
+    simulator::{self, FdtConfig, Simulator, WasmLoaded},
 };
 
 #[wasm_bindgen]
@@ -104,13 +104,14 @@ impl WasmSimulator {
         let mut simulator = Simulator::new().setup_wasm_devices(context);
 
         let buf = include_bytes!("../statics/fw_jump.bin");
-        simulator.load_flat(buf, 0x80000000);
+        simulator.load_flat(buf, 0x80000000).unwrap();
 
-        let buf = include_bytes!("../statics/platform.dtb");
-        simulator.load_flat(&buf.as_slice(), 0x80100000);
+        simulator
+            .load_fdt(&FdtConfig::default(), 0x80100000)
+            .unwrap();
 
         let buf = include_bytes!("../statics/Image");
-        simulator.load_flat(&buf.as_slice(), 0x80400000);
+        simulator.load_flat(&buf.as_slice(), 0x80400000).unwrap();
 
         Self {
             simulator: simulator.set_entry_point(0x80000000),
@@ -126,4 +127,15 @@ impl WasmSimulator {
     pub fn send_key(&mut self, key: u8) {
         self.simulator.send_key(key as char);
     }
+
+    // JS側ではUint8Arrayとして受け渡す
+    pub fn save_snapshot(&self) -> Vec<u8> {
+        self.simulator.save_snapshot()
+    }
+
+    pub fn restore_snapshot(&mut self, data: &[u8]) -> Result<(), JsValue> {
+        self.simulator
+            .restore_snapshot(data)
+            .map_err(|e| JsValue::from_str(&e.to_string()))
+    }
 }
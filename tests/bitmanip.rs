@@ -0,0 +1,71 @@
+use crate::common::{load_program, run};
+use tiny_rv32ima_sim::simulator::{MEMORY_BASE, Simulator};
+
+mod common;
+
+const MISA: u32 = 0x301;
+const MISA_B: u32 = 1 << 1;
+
+#[test]
+fn test_bitmanip() {
+    let program = [
+        0x80f005b7u32, // lui a1, 0x80f00
+        0x01258593,    // addi a1, a1, 0x12
+        0x00400613,    // li a2, 4
+        0x20b64433,    // sh2add s0, a2, a1
+        0x60061493,    // clz s1, a2
+        0x60159913,    // ctz s2, a1
+        0x60259993,    // cpop s3, a1
+        0x0ac5ca33,    // min s4, a1, a2
+        0x0ac5dab3,    // minu s5, a1, a2
+        0x6985db13,    // rev8 s6, a1
+        0x2875db93,    // orc.b s7, a1
+        0x60c5dc33,    // ror s8, a1, a2
+        0x6085dc93,    // rori s9, a1, 8
+        0x48c5dd33,    // bext s10, a1, a2
+        0x29f01d93,    // bseti s11, zero, 31
+        0x68c59e33,    // binv t3, a1, a2
+        0x49f59e93,    // bclri t4, a1, 31
+        0x40b5cf33,    // xnor t5, a1, a1
+        0x605b1f93,    // sext.h t6, s6
+        0x080b46b3,    // zext.h a3, s6
+        0x60c59733,    // rol a4, a1, a2
+        0x200667b3,    // sh3add a5, a2, zero
+        0x0000006f,    // j .
+    ];
+
+    let simulator = run(
+        load_program(Simulator::new().setup_headless(), &program),
+        30,
+    );
+    let cpu = simulator.cpu();
+
+    assert_eq!(cpu.pc(), MEMORY_BASE + 0x58);
+    assert_eq!(cpu.read_csr(MISA).unwrap() & MISA_B, MISA_B);
+
+    let expected = [
+        (8, 0x80f00022),  // s0
+        (9, 29),          // s1
+        (18, 1),          // s2
+        (19, 7),          // s3
+        (20, 0x80f00012), // s4
+        (21, 4),          // s5
+        (22, 0x1200f080), // s6
+        (23, 0xffff00ff), // s7
+        (24, 0x280f0001), // s8
+        (25, 0x1280f000), // s9
+        (26, 1),          // s10
+        (27, 0x80000000), // s11
+        (28, 0x80f00002), // t3
+        (29, 0x00f00012), // t4
+        (30, 0xffffffff), // t5
+        (31, 0xfffff080), // t6
+        (13, 0xf080),     // a3
+        (14, 0x0f000128), // a4
+        (15, 32),         // a5
+    ];
+
+    for (reg, value) in expected {
+        assert_eq!(cpu.read_reg(reg), value, "x{}", reg);
+    }
+}
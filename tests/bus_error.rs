@@ -0,0 +1,139 @@
+use crate::common::{load_program, run};
+use tiny_rv32ima_sim::simulator::{HeadlessLoaded, MEMORY_BASE, Simulator};
+
+mod common;
+
+const MCAUSE: u32 = 0x342;
+const MEPC: u32 = 0x341;
+const MTVAL: u32 = 0x343;
+
+const UART_BASE: u32 = 0x10000000;
+
+const PROLOGUE: [u32; 3] = [
+    0x800002b7, // lui t0, 0x80000
+    0x10028293, // addi t0, t0, 0x100
+    0x30529073, // csrw mtvec, t0
+];
+
+// UARTを接続し、トラップした場合は0x80000100で止まる
+fn load(program: &[u32]) -> Simulator<HeadlessLoaded> {
+    let mut simulator = Simulator::new().setup_headless();
+    simulator.add_uart(UART_BASE, None).unwrap();
+
+    let program: Vec<u32> = PROLOGUE.iter().chain(program).copied().collect();
+
+    load_program(simulator, &program)
+}
+
+#[test]
+fn test_bus_error_device_size() {
+    // UARTへの1バイト以外のアクセスはアクセスフォルトになり、tvalはアクセスしたアドレスになる
+    let read = [
+        0x10000337, // lui t1, 0x10000
+        0x00131503, // lh a0, 1(t1)
+    ];
+
+    let simulator = run(load(&read), 20);
+    let cpu = simulator.cpu();
+
+    assert_eq!(cpu.pc(), MEMORY_BASE + 0x100);
+    assert_eq!(cpu.read_csr(MCAUSE).unwrap(), 5); // Load access fault
+    assert_eq!(cpu.read_csr(MEPC).unwrap(), MEMORY_BASE + 0x10);
+    assert_eq!(cpu.read_csr(MTVAL).unwrap(), UART_BASE + 1);
+
+    let store = [
+        0x10000337, // lui t1, 0x10000
+        0x00731023, // sh t2, 0(t1)
+    ];
+
+    let simulator = run(load(&store), 20);
+    let cpu = simulator.cpu();
+
+    assert_eq!(cpu.pc(), MEMORY_BASE + 0x100);
+    assert_eq!(cpu.read_csr(MCAUSE).unwrap(), 7); // Store/AMO access fault
+    assert_eq!(cpu.read_csr(MTVAL).unwrap(), UART_BASE);
+}
+
+#[test]
+fn test_bus_error_unmapped() {
+    // 何も接続されていないアドレスへのアクセスと命令フェッチはアクセスフォルトになる
+    let read = [
+        0x10001337, // lui t1, 0x10001
+        0x00032503, // lw a0, 0(t1)
+    ];
+
+    let simulator = run(load(&read), 20);
+    let cpu = simulator.cpu();
+
+    assert_eq!(cpu.pc(), MEMORY_BASE + 0x100);
+    assert_eq!(cpu.read_csr(MCAUSE).unwrap(), 5); // Load access fault
+    assert_eq!(cpu.read_csr(MTVAL).unwrap(), 0x10001000);
+
+    let fetch = [
+        0x10001337, // lui t1, 0x10001
+        0x00030067, // jr t1
+    ];
+
+    let simulator = run(load(&fetch), 20);
+    let cpu = simulator.cpu();
+
+    assert_eq!(cpu.pc(), MEMORY_BASE + 0x100);
+    assert_eq!(cpu.read_csr(MCAUSE).unwrap(), 1); // Instruction access fault
+    assert_eq!(cpu.read_csr(MEPC).unwrap(), 0x10001000);
+    assert_eq!(cpu.read_csr(MTVAL).unwrap(), 0x10001000);
+}
+
+#[test]
+fn test_bus_error_page_walk() {
+    // ページテーブルが何も接続されていないアドレスにある場合は、元のアクセスのアクセスフォルトになる
+    let program = [
+        0xfff00313, // li t1, -1
+        0x3b031073, // csrw pmpaddr0, t1
+        0x00f00313, // li t1, 0xf
+        0x3a031073, // csrw pmpcfg0, t1
+        0x80010337, // lui t1, 0x80010
+        0x00130313, // addi t1, t1, 1
+        0x18031073, // csrw satp, t1
+        0x00021337, // lui t1, 0x21
+        0x80030313, // addi t1, t1, -2048
+        0x30032073, // csrs mstatus, t1
+        0x000013b7, // lui t2, 0x1
+        0x2343a503, // lw a0, 0x234(t2)
+    ];
+
+    let simulator = run(load(&program), 30);
+    let cpu = simulator.cpu();
+
+    assert_eq!(cpu.pc(), MEMORY_BASE + 0x100);
+    assert_eq!(cpu.read_csr(MCAUSE).unwrap(), 5); // Load access fault
+    assert_eq!(cpu.read_csr(MEPC).unwrap(), MEMORY_BASE + 0x38);
+    assert_eq!(cpu.read_csr(MTVAL).unwrap(), 0x1234);
+}
+
+#[test]
+fn test_unimplemented_instruction() {
+    // 実装していない命令やCSRはホストを止めずに不正命令例外になり、tvalは命令になる
+    let custom = [
+        0x0000000b, // custom-0
+    ];
+
+    let simulator = run(load(&custom), 20);
+    let cpu = simulator.cpu();
+
+    assert_eq!(cpu.pc(), MEMORY_BASE + 0x100);
+    assert_eq!(cpu.read_csr(MCAUSE).unwrap(), 2); // Illegal instruction
+    assert_eq!(cpu.read_csr(MEPC).unwrap(), MEMORY_BASE + 0xc);
+    assert_eq!(cpu.read_csr(MTVAL).unwrap(), 0x0000000b);
+
+    let csr = [
+        0x7c002573, // csrr a0, 0x7c0
+    ];
+
+    let simulator = run(load(&csr), 20);
+    let cpu = simulator.cpu();
+
+    assert_eq!(cpu.pc(), MEMORY_BASE + 0x100);
+    assert_eq!(cpu.read_csr(MCAUSE).unwrap(), 2); // Illegal instruction
+    assert_eq!(cpu.read_csr(MEPC).unwrap(), MEMORY_BASE + 0xc);
+    assert_eq!(cpu.read_csr(MTVAL).unwrap(), 0x7c002573);
+}
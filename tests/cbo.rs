@@ -0,0 +1,170 @@
+use crate::common::{load_program, load_words, run};
+use tiny_rv32ima_sim::simulator::{HeadlessLoaded, MEMORY_BASE, Simulator};
+
+mod common;
+
+const MCAUSE: u32 = 0x342;
+const MEPC: u32 = 0x341;
+const MTVAL: u32 = 0x343;
+
+// 0x80001000から0x100バイトを0xffで埋めておく
+// トラップした場合は0x80000100で止まる
+fn load(program: &[u32]) -> Simulator<HeadlessLoaded> {
+    // PMPを設定せずにS/Uモードで実行する
+    let mut simulator = Simulator::new().set_pmp_permissive(true).setup_headless();
+    load_words(&mut simulator, &[0xffffffff; 0x40], MEMORY_BASE + 0x1000);
+
+    load_program(simulator, program)
+}
+
+#[test]
+fn test_cbo_zero() {
+    // rs1を含む64バイトのブロックを0にする
+    // menvcfg.CBZE=0の場合はSモードからは実行できない
+    let program = [
+        0x800002b7, // lui t0, 0x80000
+        0x10028293, // addi t0, t0, 0x100
+        0x30529073, // csrw mtvec, t0
+        0x800017b7, // lui a5, 0x80001
+        0x04878513, // addi a0, a5, 0x48
+        0x0045200f, // cbo.zero (a0)
+        0x0407a583, // lw a1, 0x40(a5)
+        0x07c7a603, // lw a2, 0x7c(a5)
+        0x03c7a683, // lw a3, 0x3c(a5)
+        0x0807a703, // lw a4, 0x80(a5)
+        0x000012b7, // lui t0, 0x1
+        0x80028293, // addi t0, t0, -0x800
+        0x3002a073, // csrs mstatus, t0
+        0x00000297, // auipc t0, 0
+        0x01028293, // addi t0, t0, 16
+        0x34129073, // csrw mepc, t0
+        0x30200073, // mret
+        0x0045200f, // cbo.zero (a0)
+        0x0000006f, // j .
+    ];
+
+    let simulator = run(load(&program), 30);
+    let cpu = simulator.cpu();
+
+    assert_eq!(cpu.read_reg(11), 0); // a1
+    assert_eq!(cpu.read_reg(12), 0); // a2
+    assert_eq!(cpu.read_reg(13), 0xffffffff); // a3
+    assert_eq!(cpu.read_reg(14), 0xffffffff); // a4
+
+    assert_eq!(cpu.pc(), MEMORY_BASE + 0x100);
+    assert_eq!(cpu.read_csr(MCAUSE).unwrap(), 2);
+    assert_eq!(cpu.read_csr(MEPC).unwrap(), MEMORY_BASE + 0x44);
+}
+
+#[test]
+fn test_cbo_menvcfg() {
+    // CBIE=10は予約されているので00になる
+    // CBIE, CBCFE, CBZEを設定するとSモードからも実行できる
+    let program = [
+        0x800002b7, // lui t0, 0x80000
+        0x10028293, // addi t0, t0, 0x100
+        0x30529073, // csrw mtvec, t0
+        0x0a000293, // li t0, 0xa0
+        0x30a29073, // csrw menvcfg, t0
+        0x30a02473, // csrr s0, menvcfg
+        0x0f000293, // li t0, 0xf0
+        0x30a29073, // csrw menvcfg, t0
+        0x30a024f3, // csrr s1, menvcfg
+        0x800017b7, // lui a5, 0x80001
+        0x000012b7, // lui t0, 0x1
+        0x80028293, // addi t0, t0, -0x800
+        0x3002a073, // csrs mstatus, t0
+        0x00000297, // auipc t0, 0
+        0x01028293, // addi t0, t0, 16
+        0x34129073, // csrw mepc, t0
+        0x30200073, // mret
+        0x0047a00f, // cbo.zero (a5)
+        0x0017a00f, // cbo.clean (a5)
+        0x0027a00f, // cbo.flush (a5)
+        0x0007a583, // lw a1, 0(a5)
+        0x0007a00f, // cbo.inval (a5)
+        0x0417e013, // prefetch.r 64(a5)
+        0x0000006f, // j .
+    ];
+
+    let simulator = run(load(&program), 40);
+    let cpu = simulator.cpu();
+
+    assert_eq!(cpu.read_reg(8), 0x80); // s0
+    assert_eq!(cpu.read_reg(9), 0xf0); // s1
+    assert_eq!(cpu.pc(), MEMORY_BASE + 0x5c);
+    assert_eq!(cpu.read_reg(11), 0); // a1
+}
+
+#[test]
+fn test_cbo_illegal() {
+    // rdが0でないCBO命令と、MISC-MEMのfunct3が3以上の命令は不正命令になる
+    let prologue = [
+        0x800002b7, // lui t0, 0x80000
+        0x10028293, // addi t0, t0, 0x100
+        0x30529073, // csrw mtvec, t0
+        0x800017b7, // lui a5, 0x80001
+    ];
+
+    for inst in [0x0047a08f, 0x0007b00f, 0x0007f00f] {
+        let program: Vec<u32> = prologue.iter().copied().chain([inst]).collect();
+
+        let simulator = run(load(&program), 20);
+        let cpu = simulator.cpu();
+
+        assert_eq!(cpu.pc(), MEMORY_BASE + 0x100);
+        assert_eq!(cpu.read_csr(MCAUSE).unwrap(), 2);
+        assert_eq!(cpu.read_csr(MEPC).unwrap(), MEMORY_BASE + 0x10);
+        assert_eq!(cpu.read_csr(MTVAL).unwrap(), inst);
+    }
+}
+
+#[test]
+fn test_cbo_zero_pmp_boundary() {
+    // 0x80000000-0x80001020だけをSモードからアクセスできるようにし、境界を含むブロックを0にする
+    // ブロックの一部にアクセスできない場合は何も書き込まずにフォルトし、tvalはrs1の値になる
+    let program = [
+        0x800002b7, // lui t0, 0x80000
+        0x10028293, // addi t0, t0, 0x100
+        0x30529073, // csrw mtvec, t0
+        0x08000293, // li t0, 0x80
+        0x30a29073, // csrw menvcfg, t0
+        0x200002b7, // lui t0, 0x20000
+        0x40828293, // addi t0, t0, 0x408
+        0x3b029073, // csrw pmpaddr0, t0
+        0x00f00293, // li t0, 0xf
+        0x3a029073, // csrw pmpcfg0, t0
+        0x800017b7, // lui a5, 0x80001
+        0x00878513, // addi a0, a5, 8
+        0x000012b7, // lui t0, 0x1
+        0x80028293, // addi t0, t0, -0x800
+        0x3002a073, // csrs mstatus, t0
+        0x00000297, // auipc t0, 0
+        0x01028293, // addi t0, t0, 16
+        0x34129073, // csrw mepc, t0
+        0x30200073, // mret
+        0x0045200f, // cbo.zero (a0)
+        0x0000006f, // j .
+    ];
+
+    let mut simulator = load(&program);
+
+    // トラップした後にブロックの先頭を読む
+    let handler: Vec<u8> = [
+        0x0007a583u32, // lw a1, 0(a5)
+        0x0000006f,    // j .
+    ]
+    .iter()
+    .flat_map(|i| i.to_le_bytes())
+    .collect();
+    simulator.load_flat(&handler, MEMORY_BASE + 0x100).unwrap();
+
+    let simulator = run(simulator, 40);
+    let cpu = simulator.cpu();
+
+    assert_eq!(cpu.pc(), MEMORY_BASE + 0x104);
+    assert_eq!(cpu.read_csr(MCAUSE).unwrap(), 7); // Store/AMO access fault
+    assert_eq!(cpu.read_csr(MEPC).unwrap(), MEMORY_BASE + 0x4c);
+    assert_eq!(cpu.read_csr(MTVAL).unwrap(), MEMORY_BASE + 0x1008);
+    assert_eq!(cpu.read_reg(11), 0xffffffff); // a1
+}
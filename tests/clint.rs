@@ -0,0 +1,140 @@
+use crate::common::{load_program, run};
+use tiny_rv32ima_sim::simulator::{ClintLayout, FdtConfig, HeadlessLoaded, MEMORY_BASE, Simulator};
+
+mod common;
+
+const MCAUSE: u32 = 0x342;
+const MEPC: u32 = 0x341;
+
+// トラップした場合は0x80000100で止まる
+fn load(layout: ClintLayout, program: &[u32]) -> Simulator<HeadlessLoaded> {
+    load_program(
+        Simulator::new().set_clint_layout(layout).setup_headless(),
+        program,
+    )
+}
+
+#[test]
+fn test_clint_mtime_and_mtimecmp() {
+    // mtimeはtimeと同じ値になり、書き込むとtimeも変わる
+    // mtimecmpは書き込んだ値を読み出せる
+    let program = [
+        0x0200c337, // lui t1, 0x200c
+        0xff832503, // lw a0, -8(t1)
+        0xffc32583, // lw a1, -4(t1)
+        0xc0102673, // csrr a2, time
+        0xfe032c23, // sw zero, -8(t1)
+        0x00100393, // li t2, 1
+        0xfe732e23, // sw t2, -4(t1)
+        0xc01026f3, // csrr a3, time
+        0xc8102773, // csrr a4, timeh
+        0x02004e37, // lui t3, 0x2004
+        0x12300e93, // li t4, 0x123
+        0x01de2023, // sw t4, 0(t3)
+        0x007e2223, // sw t2, 4(t3)
+        0x000e2783, // lw a5, 0(t3)
+        0x004e2803, // lw a6, 4(t3)
+        0x344028f3, // csrr a7, mip
+        0x0000006f, // j .
+    ];
+
+    let simulator = run(load(ClintLayout::Clint, &program), 30);
+    let cpu = simulator.cpu();
+
+    assert_eq!(cpu.pc(), MEMORY_BASE + 0x40);
+    assert_eq!(cpu.read_reg(12) - cpu.read_reg(10), 2); // a2 - a0
+    assert_eq!(cpu.read_reg(11), 0); // a1
+
+    // mtimeを0x1_00000000付近に書き換えた
+    assert!(cpu.read_reg(13) < 0x10); // a3
+    assert_eq!(cpu.read_reg(14), 1); // a4
+
+    assert_eq!(cpu.read_reg(15), 0x123); // a5
+    assert_eq!(cpu.read_reg(16), 1); // a6
+    assert_eq!(cpu.read_reg(17) & 0x80, 0); // a7 MTIP
+}
+
+#[test]
+fn test_clint_access_fault() {
+    // 定義されていないオフセットと4バイト以外のアクセスはアクセスフォルトになる
+    let prologue = [
+        0x800002b7, // lui t0, 0x80000
+        0x10028293, // addi t0, t0, 0x100
+        0x30529073, // csrw mtvec, t0
+    ];
+
+    let undefined = [
+        0x02008337, // lui t1, 0x2008
+        0x00032503, // lw a0, 0(t1)
+    ];
+
+    let program: Vec<u32> = prologue.iter().chain(&undefined).copied().collect();
+    let simulator = run(load(ClintLayout::Clint, &program), 20);
+    let cpu = simulator.cpu();
+
+    assert_eq!(cpu.pc(), MEMORY_BASE + 0x100);
+    assert_eq!(cpu.read_csr(MCAUSE).unwrap(), 5); // Load access fault
+    assert_eq!(cpu.read_csr(MEPC).unwrap(), MEMORY_BASE + 0x10);
+
+    let byte_store = [
+        0x02000337, // lui t1, 0x2000
+        0x00730023, // sb t2, 0(t1)
+    ];
+
+    let program: Vec<u32> = prologue.iter().chain(&byte_store).copied().collect();
+    let simulator = run(load(ClintLayout::Clint, &program), 20);
+    let cpu = simulator.cpu();
+
+    assert_eq!(cpu.pc(), MEMORY_BASE + 0x100);
+    assert_eq!(cpu.read_csr(MCAUSE).unwrap(), 7); // Store/AMO access fault
+    assert_eq!(cpu.read_csr(MEPC).unwrap(), MEMORY_BASE + 0x10);
+}
+
+#[test]
+fn test_aclint_sswi() {
+    // SSWIのsetssipに書き込むとmip.SSIPが立ち、読み込むと0になる
+    let program = [
+        0x800002b7, // lui t0, 0x80000
+        0x10028293, // addi t0, t0, 0x100
+        0x30529073, // csrw mtvec, t0
+        0x02f00337, // lui t1, 0x2f00
+        0x00100393, // li t2, 1
+        0x00732023, // sw t2, 0(t1)
+        0x00032503, // lw a0, 0(t1)
+        0x344025f3, // csrr a1, mip
+        0x0000006f, // j .
+    ];
+
+    let simulator = run(load(ClintLayout::Aclint, &program), 20);
+    let cpu = simulator.cpu();
+
+    assert_eq!(cpu.pc(), MEMORY_BASE + 0x20);
+    assert_eq!(cpu.read_reg(10), 0); // a0
+    assert_eq!(cpu.read_reg(11) & 0x2, 0x2); // a1 SSIP
+
+    // CLINTの場合はSSWIは存在しない
+    let simulator = run(load(ClintLayout::Clint, &program), 20);
+    let cpu = simulator.cpu();
+
+    assert_eq!(cpu.pc(), MEMORY_BASE + 0x100);
+    assert_eq!(cpu.read_csr(MCAUSE).unwrap(), 7); // Store/AMO access fault
+}
+
+#[test]
+fn test_aclint_fdt() {
+    let contains = |blob: &[u8], needle: &[u8]| blob.windows(needle.len()).any(|w| w == needle);
+
+    let blob = Simulator::new().generate_fdt(&FdtConfig::default());
+
+    assert!(contains(&blob, b"sifive,clint0\0"));
+    assert!(!contains(&blob, b"riscv,aclint-mtimer\0"));
+
+    let blob = Simulator::new()
+        .set_clint_layout(ClintLayout::Aclint)
+        .generate_fdt(&FdtConfig::default());
+
+    assert!(!contains(&blob, b"sifive,clint0\0"));
+    assert!(contains(&blob, b"riscv,aclint-mswi\0"));
+    assert!(contains(&blob, b"riscv,aclint-mtimer\0"));
+    assert!(contains(&blob, b"riscv,aclint-sswi\0"));
+}
@@ -1,80 +1,65 @@
-pub(crate) use std::{
-    fs::{self, File},
-    io::{BufReader, Read},
-    path::Path,
-};
+#![allow(dead_code)]
+
+pub(crate) use std::{fs, path::Path};
 
-use tiny_rv32ima_sim::cpu::Cpu;
+use tiny_rv32ima_sim::simulator::{
+    HeadlessLoaded, HeadlessSetup, MEMORY_BASE, Simulator, TestConfig, TestResult,
+};
 
-pub const TEST_DIR: &str = "tests/isa/flats";
 pub const TEST_ELVES_DIR: &str = "tests/isa/elves";
 
+// トラップハンドラとして置く`j .`
+pub const TRAP_HANDLER: u32 = MEMORY_BASE + 0x100;
+pub const JUMP_SELF: u32 = 0x0000006f;
+
 pub struct RiscvTest<'a> {
     pub filename: &'a str,
     pub exit_address: u32,
 }
 
-pub fn run_test<P: AsRef<Path>>(cpu: &mut Cpu, file_path: P, exit_address: u32) {
-    let file = File::open(file_path).unwrap();
-    let mut reader = BufReader::new(file);
-
-    let mut buf = vec![0; 1024 * 1024];
-    reader.read(&mut buf).unwrap();
-
-    cpu.load_flat_program(&buf);
-    assert!(cpu.debug_run(exit_address));
+// riscv-testsのテストはscripts/riscv-tests.shで用意したバイナリが必要なので#[ignore]にしている
+// `cargo test -- --ignored`で明示的に実行した場合は、バイナリが無ければ失敗させる
+pub fn require_dir<P: AsRef<Path>>(dir_path: P) {
+    assert!(
+        dir_path.as_ref().is_dir(),
+        "{} does not exist. Run scripts/riscv-tests.sh to build the riscv-tests ELFs.",
+        dir_path.as_ref().display()
+    );
 }
 
-pub fn run_elf_test<P: AsRef<Path>>(cpu: &mut Cpu, file_path: P, exit_address: u32) {
-    let file = File::open(file_path).unwrap();
-    let mut reader = BufReader::new(file);
-
-    let mut buf = vec![0; 1024 * 1024];
-    reader.read(&mut buf).unwrap();
+// tohostのシンボルが見つからない場合はexit_addressをtohostとして扱う
+pub fn run_elf_test<P: AsRef<Path>>(file_path: P, exit_address: u32) {
+    let buf = fs::read(&file_path).unwrap();
 
-    cpu.set_memory_base_address(0x80000000);
-    cpu.load_elf_program(&buf);
-    assert!(cpu.debug_run(exit_address));
-}
+    let mut simulator = Simulator::new().setup_headless();
 
-pub fn run_tests<P: AsRef<Path>>(
-    cpu: &mut Cpu,
-    dir_path: P,
-    default_exit_address: u32,
-    excludes: Vec<RiscvTest>,
-) {
-    let dir = fs::read_dir(dir_path).unwrap();
-    for file in dir.into_iter() {
-        let file_path = file.unwrap().path();
+    let image = simulator.load_elf(&buf).unwrap();
+    let tohost = simulator.symbols().lookup("tohost").unwrap_or(exit_address);
 
-        if file_path.extension().unwrap() == "bin" {
-            let filename = file_path
-                .file_name()
-                .unwrap()
-                .to_string_lossy()
-                .into_owned();
+    let mut simulator = simulator.set_entry_point(image.entry);
 
-            let mut exit_address = default_exit_address;
-            for exclude in &excludes {
-                if filename == exclude.filename {
-                    exit_address = exclude.exit_address;
-                }
-            }
+    let config = TestConfig {
+        tohost: Some(tohost),
+        ..Default::default()
+    };
 
-            println!("TRY: {}", filename);
-            run_test(cpu, file_path, exit_address);
-            println!("PASS: {}", filename);
-        }
+    match simulator.run_test(&config) {
+        TestResult::Pass => {}
+        TestResult::Fail(n) => panic!("{}: test #{} failed", file_path.as_ref().display(), n),
+        TestResult::Timeout => panic!("{}: timed out", file_path.as_ref().display()),
     }
 }
 
 pub fn run_elf_tests<P: AsRef<Path>>(
-    cpu: &mut Cpu,
     dir_path: P,
     default_exit_address: u32,
     excludes: Vec<RiscvTest>,
 ) {
-    let dir = fs::read_dir(dir_path).unwrap();
+    require_dir(&dir_path);
+
+    let mut count = 0;
+
+    let dir = fs::read_dir(&dir_path).unwrap();
     for file in dir.into_iter() {
         let file_path = file.unwrap().path();
 
@@ -93,8 +78,53 @@ pub fn run_elf_tests<P: AsRef<Path>>(
             }
 
             println!("TRY: {}", filename);
-            run_elf_test(cpu, file_path, exit_address);
+            run_elf_test(file_path, exit_address);
             println!("PASS: {}", filename);
+
+            count += 1;
         }
     }
+
+    assert!(count > 0, "{} has no tests", dir_path.as_ref().display());
+}
+
+// 命令やデータの列をaddrに読み込む関数
+pub fn load_words(simulator: &mut Simulator<HeadlessSetup>, words: &[u32], addr: u32) {
+    let bytes: Vec<u8> = words.iter().flat_map(|i| i.to_le_bytes()).collect();
+    simulator.load_flat(&bytes, addr).unwrap();
+}
+
+// programをMEMORY_BASEから実行する
+// トラップした場合は0x80000100の`j .`で止まる
+pub fn load_program(
+    simulator: Simulator<HeadlessSetup>,
+    program: &[u32],
+) -> Simulator<HeadlessLoaded> {
+    let mut simulator = simulator;
+
+    load_words(&mut simulator, program, MEMORY_BASE);
+    load_words(&mut simulator, &[JUMP_SELF], TRAP_HANDLER);
+
+    simulator.set_entry_point(MEMORY_BASE)
+}
+
+// stepsだけ実行する関数
+// プログラムはtohostを使わずに`j .`で止まるので、途中で終了した場合は失敗させる
+pub fn run(simulator: Simulator<HeadlessLoaded>, steps: u64) -> Simulator<HeadlessLoaded> {
+    let mut simulator = simulator;
+
+    let config = TestConfig {
+        max_steps: steps,
+        ..Default::default()
+    };
+
+    let result = simulator.run_test(&config);
+    assert_eq!(
+        result,
+        TestResult::Timeout,
+        "stopped before {} steps",
+        steps
+    );
+
+    simulator
 }
@@ -0,0 +1,84 @@
+use crate::common::{load_program, load_words, run};
+use tiny_rv32ima_sim::simulator::{MEMORY_BASE, Simulator};
+
+mod common;
+
+const MCAUSE: u32 = 0x342;
+const MEPC: u32 = 0x341;
+const MTVAL: u32 = 0x343;
+const MISA: u32 = 0x301;
+
+#[test]
+fn test_compressed_program() {
+    // 32bit命令が2バイト境界に置かれている
+    let program = [
+        0x450du16, // c.li a0, 3
+        0x0511,    // c.addi a0, 4
+        0x2809,    // c.jal func
+        0x8406,    // c.mv s0, ra
+        0x0405,    // c.addi s0, 1
+        0x55b7,    // lui a1, 0x12345
+        0x1234,    //
+        0x8593,    // addi a1, a1, 0x678
+        0x6785,    //
+        0x050a,    // c.slli a0, 2
+        0xa019,    // c.j end
+        0x952a,    // func: c.add a0, a0
+        0x8082,    // c.jr ra
+        0xa001,    // end: c.j end
+    ];
+
+    let mut simulator = Simulator::new().setup_headless();
+
+    let program: Vec<u8> = program.iter().flat_map(|i| i.to_le_bytes()).collect();
+    simulator.load_flat(&program, MEMORY_BASE).unwrap();
+
+    let simulator = run(simulator.set_entry_point(MEMORY_BASE), 20);
+    let cpu = simulator.cpu();
+
+    assert_eq!(cpu.pc(), MEMORY_BASE + 0x1a);
+    assert_eq!(cpu.read_reg(10), 56); // a0
+    assert_eq!(cpu.read_reg(11), 0x12345678); // a1
+    assert_eq!(cpu.read_reg(8), MEMORY_BASE + 7); // s0
+    assert_ne!(cpu.read_csr(MISA).unwrap() & (1 << 2), 0);
+}
+
+#[test]
+fn test_fetch_across_page_boundary() {
+    // 0x0のページのみをマップして、0xffeに置いた32bit命令を実行する
+    let program = [
+        0x800002b7u32, // lui t0, 0x80000
+        0x10028293,    // addi t0, t0, 0x100
+        0x30529073,    // csrw mtvec, t0
+        0x800802b7,    // lui t0, 0x80080
+        0x00228293,    // addi t0, t0, 2
+        0x18029073,    // csrw satp, t0
+        0x000012b7,    // lui t0, 0x1
+        0x80028293,    // addi t0, t0, -0x800
+        0x3002a073,    // csrs mstatus, t0
+        0x000012b7,    // lui t0, 0x1
+        0xffe28293,    // addi t0, t0, -2
+        0x34129073,    // csrw mepc, t0
+        0x30200073,    // mret
+    ];
+
+    // PMPを設定せずにS/Uモードで実行する
+    let mut simulator = Simulator::new().set_pmp_permissive(true).setup_headless();
+
+    // 0x80002000に1段目、0x80003000に2段目のページテーブルを置く
+    load_words(&mut simulator, &[0x20000c01], MEMORY_BASE + 0x2000);
+    load_words(&mut simulator, &[0x2000104b], MEMORY_BASE + 0x3000); // V|R|X|A
+
+    // addi a0, a0, 1の前半のみがマップされたページにある
+    simulator
+        .load_flat(&0x0513u16.to_le_bytes(), MEMORY_BASE + 0x4ffe)
+        .unwrap();
+
+    let simulator = run(load_program(simulator, &program), 20);
+    let cpu = simulator.cpu();
+
+    assert_eq!(cpu.pc(), MEMORY_BASE + 0x100);
+    assert_eq!(cpu.read_csr(MCAUSE).unwrap(), 12); // Instruction page fault
+    assert_eq!(cpu.read_csr(MEPC).unwrap(), 0xffe);
+    assert_eq!(cpu.read_csr(MTVAL).unwrap(), 0x1000);
+}
@@ -0,0 +1,173 @@
+use tiny_rv32ima_sim::disasm::disassemble;
+
+const BASE: u32 = 0x80000000;
+
+// 各命令を先頭から4バイトずつ並べたものとして逆アセンブルする
+const INSTRUCTIONS: [(u32, &str); 99] = [
+    (0x00000013, "nop"),
+    (0xffb00513, "li      a0, -5"),
+    (0x00010413, "mv      s0, sp"),
+    (0x00c58513, "addi    a0, a1, 12"),
+    (0x00331293, "slli    t0, t1, 3"),
+    (0x41f35293, "srai    t0, t1, 31"),
+    (0x00155513, "srli    a0, a0, 1"),
+    (0x0015b513, "seqz    a0, a1"),
+    (0xfff5c513, "not     a0, a1"),
+    (0x0055b513, "sltiu   a0, a1, 5"),
+    (0x0035c513, "xori    a0, a1, 3"),
+    (0x0ff57513, "andi    a0, a0, 255"),
+    (0x80001537, "lui     a0, 0x80001"),
+    (0x00000297, "auipc   t0, 0x0"),
+    (0xffc12503, "lw      a0, -4(sp)"),
+    (0x00054583, "lbu     a1, 0(a0)"),
+    (0x00112623, "sw      ra, 12(sp)"),
+    (0xfea58fa3, "sb      a0, -1(a1)"),
+    (0x00c58533, "add     a0, a1, a2"),
+    (0x40b00533, "neg     a0, a1"),
+    (0x00b03533, "snez    a0, a1"),
+    (0x0005a533, "sltz    a0, a1"),
+    (0x00b02533, "sgtz    a0, a1"),
+    (0x02c5a533, "mulhsu  a0, a1, a2"),
+    (0x02c5f533, "remu    a0, a1, a2"),
+    (0x40c5d533, "sra     a0, a1, a2"),
+    (0x1005a52f, "lr.w    a0, (a1)"),
+    (0x1ac5a52f, "sc.w.rl a0, a2, (a1)"),
+    (0x0ec5a52f, "amoswap.w.aqrl a0, a2, (a1)"),
+    (0x00c5a52f, "amoadd.w a0, a2, (a1)"),
+    (0x00050063, "beqz    a0, 0x80000078"),
+    (0xfeb51ee3, "bne     a0, a1, 0x80000078"),
+    (0xfea05ce3, "blez    a0, 0x80000078"),
+    (0xfea04ae3, "bgtz    a0, 0x80000078"),
+    (0xfeb568e3, "bltu    a0, a1, 0x80000078"),
+    (0xfedff06f, "j       0x80000078"),
+    (0xfe9ff0ef, "jal     0x80000078"),
+    (0xfe5ff2ef, "jal     t0, 0x80000078"),
+    (0x00008067, "ret"),
+    (0x00050067, "jr      a0"),
+    (0x000500e7, "jalr    a0"),
+    (0x004502e7, "jalr    t0, 4(a0)"),
+    (0x00000073, "ecall"),
+    (0x00100073, "ebreak"),
+    (0x10500073, "wfi"),
+    (0x10200073, "sret"),
+    (0x30200073, "mret"),
+    (0x12000073, "sfence.vma"),
+    (0x12050073, "sfence.vma a0"),
+    (0x12b50073, "sfence.vma a0, a1"),
+    (0x0ff0000f, "fence"),
+    (0x0310000f, "fence   rw, w"),
+    (0x8330000f, "fence.tso"),
+    (0x0100000f, "pause"),
+    (0x0000100f, "fence.i"),
+    (0x0045200f, "cbo.zero (a0)"),
+    (0x0027a00f, "cbo.flush (a5)"),
+    (0x0417e013, "prefetch.r 64(a5)"),
+    (0xfe056013, "prefetch.i -32(a0)"),
+    (0x0015e513, "ori     a0, a1, 1"),
+    (0x20c5a533, "sh1add  a0, a1, a2"),
+    (0x40c5f533, "andn    a0, a1, a2"),
+    (0x60259513, "cpop    a0, a1"),
+    (0x0805c533, "zext.h  a0, a1"),
+    (0x6985d513, "rev8    a0, a1"),
+    (0x4845d513, "bexti   a0, a1, 4"),
+    (0x30002573, "csrr    a0, mstatus"),
+    (0x18051073, "csrw    satp, a0"),
+    (0x3045a073, "csrs    mie, a1"),
+    (0x34059573, "csrrw   a0, mscratch, a1"),
+    (0x1005b573, "csrrc   a0, sstatus, a1"),
+    (0x30045073, "csrwi   mstatus, 8"),
+    (0x30416573, "csrrsi  a0, mie, 2"),
+    (0xc0102573, "rdtime  a0"),
+    (0xc80025f3, "rdcycleh a1"),
+    (0x7c002573, "csrr    a0, 0x7c0"),
+    (0x0020f053, "fadd.s  ft0, ft1, ft2"),
+    (0x00209053, "fadd.s  ft0, ft1, ft2, rtz"),
+    (0x02c58553, "fadd.d  fa0, fa1, fa2, rne"),
+    (0xd2050053, "fcvt.d.w ft0, a0"),
+    (0x42008053, "fcvt.d.s ft0, ft1"),
+    (0x4010b053, "fcvt.s.d ft0, ft1, rup"),
+    (0xc0001553, "fcvt.w.s a0, ft0, rtz"),
+    (0x9b24f443, "fmadd.d fs0, fs1, fs2, fs3"),
+    (0x1820c04b, "fnmsub.s ft0, ft1, ft2, ft3, rmm"),
+    (0x20108053, "fmv.s   ft0, ft1"),
+    (0x22109053, "fneg.d  ft0, ft1"),
+    (0x2010a053, "fabs.s  ft0, ft1"),
+    (0x22208053, "fsgnj.d ft0, ft1, ft2"),
+    (0xe0000553, "fmv.x.w a0, ft0"),
+    (0xf0050053, "fmv.w.x ft0, a0"),
+    (0xa2102553, "feq.d   a0, ft0, ft1"),
+    (0xe0051553, "fclass.s a0, fa0"),
+    (0xffc12007, "flw     ft0, -4(sp)"),
+    (0x00853427, "fsd     fs0, 8(a0)"),
+    (0x5a00f053, "fsqrt.d ft0, ft1"),
+    (0x28208053, "fmin.s  ft0, ft1, ft2"),
+    (0x0020d053, "unknown"),
+    (0xffffffff, "unknown"),
+];
+
+// 圧縮命令とそのアドレスのオフセット
+const COMPRESSED_INSTRUCTIONS: [(u32, u32, &str); 33] = [
+    (0x0040, 0x00, "c.addi4spn s0, sp, 4"),
+    (0x4188, 0x06, "c.lw    a0, 0(a1)"),
+    (0xc1c8, 0x0c, "c.sw    a0, 4(a1)"),
+    (0x0001, 0x10, "c.nop"),
+    (0x0505, 0x12, "c.addi  a0, 1"),
+    (0x2009, 0x18, "c.jal   0x8000001a"),
+    (0x557d, 0x1a, "c.li    a0, -1"),
+    (0x7101, 0x20, "c.addi16sp sp, -512"),
+    (0x6505, 0x26, "c.lui   a0, 0x1"),
+    (0x72fd, 0x28, "c.lui   t0, 0xfffff"),
+    (0x8105, 0x2e, "c.srli  a0, 1"),
+    (0x80fd, 0x30, "c.srli  s1, 31"),
+    (0x997d, 0x34, "c.andi  a0, -1"),
+    (0x8d0d, 0x38, "c.sub   a0, a1"),
+    (0x8f7d, 0x3e, "c.and   a4, a5"),
+    (0xa009, 0x40, "c.j     0x80000042"),
+    (0xc7e9, 0x46, "c.beqz  a5, 0x80000110"),
+    (0x0506, 0x112, "c.slli  a0, 1"),
+    (0x50fe, 0x118, "c.lwsp  ra, 252(sp)"),
+    (0x8502, 0x11c, "c.jr    a0"),
+    (0x852e, 0x120, "c.mv    a0, a1"),
+    (0x9002, 0x124, "c.ebreak"),
+    (0x9502, 0x126, "c.jalr  a0"),
+    (0x9fee, 0x12c, "c.add   t6, s11"),
+    (0xd296, 0x132, "c.swsp  t0, 100(sp)"),
+    (0x2500, 0x134, "c.fld   fs0, 8(a0)"),
+    (0xa826, 0x136, "c.fsdsp fs1, 16(sp)"),
+    (0x6012, 0x138, "c.flwsp ft0, 4(sp)"),
+    (0xe406, 0x13a, "c.fswsp ft1, 8(sp)"),
+    (0x61c8, 0x13c, "c.flw   fa0, 4(a1)"),
+    (0xe1c8, 0x13e, "c.fsw   fa0, 4(a1)"),
+    (0xa588, 0x140, "c.fsd   fa0, 8(a1)"),
+    (0x2022, 0x142, "c.fldsp ft0, 8(sp)"),
+];
+
+#[test]
+fn test_disassemble() {
+    for (i, (inst, expected)) in INSTRUCTIONS.iter().enumerate() {
+        let pc = BASE + i as u32 * 4;
+
+        assert_eq!(
+            disassemble(*inst, pc),
+            *expected,
+            "0x{:08x} at 0x{:08x}",
+            inst,
+            pc
+        );
+    }
+}
+
+#[test]
+fn test_disassemble_compressed() {
+    for (inst, offset, expected) in COMPRESSED_INSTRUCTIONS {
+        let pc = BASE + offset;
+
+        assert_eq!(
+            disassemble(inst, pc),
+            expected,
+            "0x{:04x} at 0x{:08x}",
+            inst,
+            pc
+        );
+    }
+}
@@ -0,0 +1,114 @@
+use tiny_rv32ima_sim::simulator::{ElfError, LoadError, Simulator};
+
+const ENTRY: u32 = 0x80000000;
+const TOHOST: u32 = 0x80001000;
+
+fn push_u16(buf: &mut Vec<u8>, value: u16) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+fn push_u32(buf: &mut Vec<u8>, value: u32) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+// PT_LOADが1つと.symtab/.strtabを持つ最小のELFを組み立てる
+fn build_elf(machine: u16, memsz: u32) -> Vec<u8> {
+    let code = [0x13, 0x00, 0x00, 0x00, 0x6f, 0x00, 0x00, 0x00]; // nop; j .
+    let strtab = b"\0_start\0tohost\0\0";
+
+    let phoff = 52;
+    let code_off = phoff + 32;
+    let strtab_off = code_off + code.len() as u32;
+    let symtab_off = strtab_off + strtab.len() as u32;
+    let shoff = symtab_off + 16 * 3;
+
+    let mut buf = Vec::new();
+
+    buf.extend_from_slice(&[0x7f, b'E', b'L', b'F', 1, 1, 1, 0]);
+    buf.extend_from_slice(&[0; 8]);
+    push_u16(&mut buf, 2); // ET_EXEC
+    push_u16(&mut buf, machine);
+    push_u32(&mut buf, 1);
+    push_u32(&mut buf, ENTRY);
+    push_u32(&mut buf, phoff);
+    push_u32(&mut buf, shoff);
+    push_u32(&mut buf, 0);
+    push_u16(&mut buf, 52);
+    push_u16(&mut buf, 32);
+    push_u16(&mut buf, 1);
+    push_u16(&mut buf, 40);
+    push_u16(&mut buf, 3);
+    push_u16(&mut buf, 0);
+
+    for value in [1, code_off, ENTRY, ENTRY, code.len() as u32, memsz, 5, 4] {
+        push_u32(&mut buf, value);
+    }
+
+    buf.extend_from_slice(&code);
+    buf.extend_from_slice(strtab);
+
+    for (name, value, info) in [(0, 0, 0), (1, ENTRY, 0x12), (8, TOHOST, 0x11)] {
+        push_u32(&mut buf, name);
+        push_u32(&mut buf, value);
+        push_u32(&mut buf, 0);
+        buf.push(info);
+        buf.push(0);
+        push_u16(&mut buf, if name == 0 { 0 } else { 1 });
+    }
+
+    buf.extend_from_slice(&[0; 40]);
+
+    let sections = [
+        (2, symtab_off, 16 * 3, 2, 16),             // .symtab
+        (3, strtab_off, strtab.len() as u32, 0, 0), // .strtab
+    ];
+
+    for (sh_type, offset, size, link, entsize) in sections {
+        for value in [0, sh_type, 0, 0, offset, size, link, 0, 1, entsize] {
+            push_u32(&mut buf, value);
+        }
+    }
+
+    buf
+}
+
+#[test]
+fn test_load_elf() {
+    let mut simulator = Simulator::new();
+
+    let image = simulator.load_elf(&build_elf(243, 0x2000)).unwrap();
+
+    assert_eq!(image.entry, ENTRY);
+    assert_eq!(image.range, ENTRY..ENTRY + 0x2000);
+
+    assert_eq!(simulator.symbols().lookup("_start"), Some(ENTRY));
+    assert_eq!(simulator.symbols().lookup("tohost"), Some(TOHOST));
+    assert_eq!(simulator.symbols().find(ENTRY + 4), Some(("_start", 4)));
+}
+
+#[test]
+fn test_load_invalid_elf() {
+    let mut simulator = Simulator::new();
+
+    assert_eq!(
+        simulator.load_elf(&build_elf(62, 0x2000)).unwrap_err(),
+        LoadError::Elf(ElfError::UnsupportedMachine(62))
+    );
+
+    assert!(matches!(
+        simulator.load_elf(&build_elf(243, 4)),
+        Err(LoadError::Elf(ElfError::InvalidSegment { index: 0 }))
+    ));
+
+    assert!(matches!(
+        simulator.load_elf(&build_elf(243, 0x10000000)),
+        Err(LoadError::OutOfRange { .. })
+    ));
+
+    let elf = build_elf(243, 0x2000);
+
+    assert!(matches!(
+        simulator.load_elf(&elf[..100]),
+        Err(LoadError::Elf(ElfError::Truncated { .. }))
+    ));
+}
@@ -0,0 +1,202 @@
+use tiny_rv32ima_sim::simulator::{FdtConfig, MEMORY_BASE, Simulator};
+
+const FDT_MAGIC: u32 = 0xd00dfeed;
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_NOP: u32 = 4;
+const FDT_END: u32 = 9;
+
+// 構造ブロックを読んで組み立てたノード
+struct Node {
+    name: String,
+    props: Vec<(String, Vec<u8>)>,
+    children: Vec<Node>,
+}
+
+impl Node {
+    fn prop(&self, name: &str) -> &[u8] {
+        self.props
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_slice())
+            .unwrap_or_else(|| panic!("{} has no property {}", self.name, name))
+    }
+
+    fn prop_u32s(&self, name: &str) -> Vec<u32> {
+        let value = self.prop(name);
+        assert_eq!(
+            value.len() % 4,
+            0,
+            "{}/{} is not a cell array",
+            self.name,
+            name
+        );
+
+        value
+            .chunks(4)
+            .map(|c| u32::from_be_bytes(c.try_into().unwrap()))
+            .collect()
+    }
+
+    fn prop_str(&self, name: &str) -> &str {
+        let value = self.prop(name);
+        assert_eq!(
+            value.last(),
+            Some(&0),
+            "{}/{} is not terminated",
+            self.name,
+            name
+        );
+
+        std::str::from_utf8(&value[..value.len() - 1]).unwrap()
+    }
+
+    fn child(&self, name: &str) -> &Node {
+        self.children
+            .iter()
+            .find(|c| c.name == name)
+            .unwrap_or_else(|| panic!("{} has no child {}", self.name, name))
+    }
+}
+
+fn read_be(blob: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(blob[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_cstr(blob: &[u8], offset: usize) -> String {
+    let end = offset + blob[offset..].iter().position(|&b| b == 0).unwrap();
+
+    String::from_utf8(blob[offset..end].to_vec()).unwrap()
+}
+
+// ヘッダを確認し、構造ブロックをたどってルートノードを返す
+fn parse_fdt(blob: &[u8]) -> Node {
+    assert_eq!(read_be(blob, 0), FDT_MAGIC);
+    assert_eq!(read_be(blob, 4) as usize, blob.len());
+
+    let off_struct = read_be(blob, 8) as usize;
+    let off_strings = read_be(blob, 12) as usize;
+    let size_struct = read_be(blob, 36) as usize;
+    let end_struct = off_struct + size_struct;
+
+    let mut stack: Vec<Node> = Vec::new();
+    let mut root = None;
+    let mut pos = off_struct;
+
+    while pos < end_struct {
+        let token = read_be(blob, pos);
+        pos += 4;
+
+        match token {
+            FDT_BEGIN_NODE => {
+                let name = read_cstr(blob, pos);
+                pos = (pos + name.len() + 1).next_multiple_of(4);
+                stack.push(Node {
+                    name,
+                    props: Vec::new(),
+                    children: Vec::new(),
+                });
+            }
+            FDT_END_NODE => {
+                let node = stack.pop().expect("unbalanced FDT_END_NODE");
+                match stack.last_mut() {
+                    Some(parent) => parent.children.push(node),
+                    None => root = Some(node),
+                }
+            }
+            FDT_PROP => {
+                let len = read_be(blob, pos) as usize;
+                let name = read_cstr(blob, off_strings + read_be(blob, pos + 4) as usize);
+                let value = blob[pos + 8..pos + 8 + len].to_vec();
+                pos = (pos + 8 + len).next_multiple_of(4);
+                stack
+                    .last_mut()
+                    .expect("property outside of a node")
+                    .props
+                    .push((name, value));
+            }
+            FDT_NOP => {}
+            FDT_END => {
+                assert!(stack.is_empty(), "FDT_END inside a node");
+                assert_eq!(pos, end_struct);
+                return root.expect("no root node");
+            }
+            _ => panic!("unknown token 0x{:x} at 0x{:x}", token, pos - 4),
+        }
+    }
+
+    panic!("structure block has no FDT_END");
+}
+
+#[test]
+fn test_generated_fdt() {
+    let memory_size = 64 * 1024 * 1024;
+    let hart_count = 2;
+    let simulator = Simulator::new()
+        .set_memory_size(memory_size)
+        .set_hart_count(hart_count);
+
+    let config = FdtConfig {
+        bootargs: "console=ttyS0".to_string(),
+        initrd: Some(0x84000000..0x84100000),
+    };
+
+    let root = parse_fdt(&simulator.generate_fdt(&config));
+
+    let chosen = root.child("chosen");
+    assert_eq!(chosen.prop_str("bootargs"), "console=ttyS0");
+    assert_eq!(chosen.prop_u32s("linux,initrd-start"), [0x84000000]);
+    assert_eq!(chosen.prop_u32s("linux,initrd-end"), [0x84100000]);
+
+    let memory = root.child(&format!("memory@{:x}", MEMORY_BASE));
+    assert_eq!(memory.prop_str("device_type"), "memory");
+    assert_eq!(memory.prop_u32s("reg"), [MEMORY_BASE, memory_size as u32]);
+
+    // 設定したhartの数だけcpuノードがあり、それぞれが割り込みコントローラを持つ
+    let cpus = root.child("cpus");
+    let cpu_nodes: Vec<&Node> = cpus
+        .children
+        .iter()
+        .filter(|c| c.name.starts_with("cpu@"))
+        .collect();
+    assert_eq!(cpu_nodes.len(), hart_count);
+
+    let mut intc_phandles = Vec::new();
+    for (hart, cpu) in cpu_nodes.iter().enumerate() {
+        assert_eq!(cpu.name, format!("cpu@{}", hart));
+        assert_eq!(cpu.prop_u32s("reg"), [hart as u32]);
+        assert_eq!(
+            cpu.prop_str("riscv,isa"),
+            "rv32imafdc_zicbom_zicbop_zicboz_zicntr_zicsr_zifencei_zmmul_zaamo_zalrsc_zba_zbb_zbs_sstc_svadu"
+        );
+        assert_eq!(cpu.prop_u32s("riscv,cbom-block-size"), [64]);
+        assert_eq!(cpu.prop_u32s("riscv,cboz-block-size"), [64]);
+
+        let intc = cpu.child("interrupt-controller");
+        intc_phandles.push(intc.prop_u32s("phandle")[0]);
+    }
+
+    // phandleは重複しない
+    let mut unique = intc_phandles.clone();
+    unique.sort();
+    unique.dedup();
+    assert_eq!(unique.len(), hart_count);
+
+    // CLINTはhartごとにM-mode software(3)とM-mode timer(7)を送る
+    let soc = root.child("soc");
+    let clint = soc.child("clint@2000000");
+    let clint_irqs: Vec<u32> = intc_phandles
+        .iter()
+        .flat_map(|&ph| [ph, 3, ph, 7])
+        .collect();
+    assert_eq!(clint.prop_u32s("interrupts-extended"), clint_irqs);
+
+    // PLICはhartごとにM-mode external(11)とS-mode external(9)のコンテキストを持つ
+    let plic = soc.child("plic@c000000");
+    let plic_irqs: Vec<u32> = intc_phandles
+        .iter()
+        .flat_map(|&ph| [ph, 11, ph, 9])
+        .collect();
+    assert_eq!(plic.prop_u32s("interrupts-extended"), plic_irqs);
+}
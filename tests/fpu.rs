@@ -0,0 +1,132 @@
+use crate::common::{load_program, run};
+use tiny_rv32ima_sim::simulator::{HeadlessLoaded, MEMORY_BASE, Simulator};
+
+mod common;
+
+const FFLAGS: u32 = 0x001;
+const MCAUSE: u32 = 0x342;
+const MEPC: u32 = 0x341;
+const MTVAL: u32 = 0x343;
+const MISA: u32 = 0x301;
+
+const FLAG_NX: u32 = 1;
+
+const NAN_BOX: u64 = 0xffffffff_00000000;
+
+// トラップした場合は0x80000100で止まる
+fn load(program: &[u32]) -> Simulator<HeadlessLoaded> {
+    let setup = [
+        0x800002b7u32, // lui t0, 0x80000
+        0x10028293,    // addi t0, t0, 0x100
+        0x30529073,    // csrw mtvec, t0
+    ];
+
+    let program: Vec<u32> = setup.iter().chain(program).copied().collect();
+
+    load_program(Simulator::new().setup_headless(), &program)
+}
+
+#[test]
+fn test_double_arithmetic() {
+    let program = [
+        0x000022b7, // lui t0, 0x2
+        0x3002a073, // csrs mstatus, t0
+        0x00100513, // li a0, 1
+        0xd2050053, // fcvt.d.w ft0, a0
+        0x00300593, // li a1, 3
+        0xd20580d3, // fcvt.d.w ft1, a1
+        0x1a107153, // fdiv.d ft2, ft0, ft1
+        0x401171d3, // fcvt.s.d ft3, ft2
+        0x0210f243, // fmadd.d ft4, ft1, ft1, ft0
+        0xc2027653, // fcvt.w.d a2, ft4
+        0xa20026d3, // feq.d a3, ft0, ft0
+        0xe2011753, // fclass.d a4, ft2
+        0x001027f3, // csrr a5, fflags
+        0x30002873, // csrr a6, mstatus
+        0x0000006f, // j .
+    ];
+
+    let simulator = run(load(&program), 30);
+    let cpu = simulator.cpu();
+
+    let third = 1.0f64 / 3.0;
+
+    assert_eq!(cpu.pc(), MEMORY_BASE + 0x44);
+    assert_eq!(cpu.read_freg(2), third.to_bits());
+    assert_eq!(cpu.read_freg(3), NAN_BOX | (third as f32).to_bits() as u64);
+    assert_eq!(cpu.read_freg(4), 10.0f64.to_bits());
+    assert_eq!(cpu.read_reg(12), 10); // a2
+    assert_eq!(cpu.read_reg(13), 1); // a3
+    assert_eq!(cpu.read_reg(14), 1 << 6); // a4 正の正規化数
+    assert_eq!(cpu.read_reg(15), FLAG_NX); // a5
+    assert_eq!(cpu.read_reg(16) & 0x80006000, 0x80006000); // a6 SD=1, FS=Dirty
+
+    let misa = cpu.read_csr(MISA).unwrap();
+    assert_ne!(misa & (1 << 3), 0); // D
+    assert_ne!(misa & (1 << 5), 0); // F
+}
+
+#[test]
+fn test_rounding_modes() {
+    let program = [
+        0x000022b7, // lui t0, 0x2
+        0x3002a073, // csrs mstatus, t0
+        0xc0200537, // lui a0, 0xc0200
+        0xf0050053, // fmv.w.x ft0, a0 (-2.5)
+        0xc00015d3, // fcvt.w.s a1, ft0, rtz
+        0xc0002653, // fcvt.w.s a2, ft0, rdn
+        0xc00036d3, // fcvt.w.s a3, ft0, rup
+        0xc0004753, // fcvt.w.s a4, ft0, rmm
+        0xc00007d3, // fcvt.w.s a5, ft0, rne
+        0x00300293, // li t0, 3
+        0x00229073, // csrw frm, t0
+        0xc0007853, // fcvt.w.s a6, ft0 (dyn)
+        0x000070d3, // fadd.s ft1, ft0, ft0
+        0xe00088d3, // fmv.x.w a7, ft1
+        0xd2000153, // fcvt.d.w ft2, zero
+        0x000171d3, // fadd.s ft3, ft2, ft0
+        0xe0018953, // fmv.x.w s2, ft3
+        0x001029f3, // csrr s3, fflags
+        0x00302a73, // csrr s4, fcsr
+        0x00500293, // li t0, 5
+        0x00229073, // csrw frm, t0
+        0x00007253, // fadd.s ft4, ft0, ft0
+    ];
+
+    let simulator = run(load(&program), 40);
+    let cpu = simulator.cpu();
+
+    assert_eq!(cpu.read_reg(11), -2i32 as u32); // a1
+    assert_eq!(cpu.read_reg(12), -3i32 as u32); // a2
+    assert_eq!(cpu.read_reg(13), -2i32 as u32); // a3
+    assert_eq!(cpu.read_reg(14), -3i32 as u32); // a4
+    assert_eq!(cpu.read_reg(15), -2i32 as u32); // a5
+    assert_eq!(cpu.read_reg(16), -2i32 as u32); // a6
+    assert_eq!(cpu.read_reg(17), 0xc0a00000); // a7 -5.0
+
+    // NaN-boxingされていない値は正規化されたNaNとして扱う
+    assert_eq!(cpu.read_reg(18), 0x7fc00000); // s2
+    assert_eq!(cpu.read_reg(19), FLAG_NX); // s3
+    assert_eq!(cpu.read_reg(20), 3 << 5 | FLAG_NX); // s4
+
+    // frmが予約された値の場合は不正な命令になる
+    assert_eq!(cpu.pc(), MEMORY_BASE + 0x100);
+    assert_eq!(cpu.read_csr(MCAUSE).unwrap(), 2);
+    assert_eq!(cpu.read_csr(MTVAL).unwrap(), 0x00007253);
+}
+
+#[test]
+fn test_fs_off() {
+    let program = [
+        0x0000f053, // fadd.s ft0, ft1, ft0
+    ];
+
+    let simulator = run(load(&program), 10);
+    let cpu = simulator.cpu();
+
+    assert_eq!(cpu.pc(), MEMORY_BASE + 0x100);
+    assert_eq!(cpu.read_csr(MCAUSE).unwrap(), 2);
+    assert_eq!(cpu.read_csr(MEPC).unwrap(), MEMORY_BASE + 0xc);
+    assert_eq!(cpu.read_csr(MTVAL).unwrap(), 0x0000f053);
+    assert!(cpu.read_csr(FFLAGS).is_err());
+}
@@ -0,0 +1,110 @@
+use tiny_rv32ima_sim::simulator::{MEMORY_BASE, Simulator, TestConfig, TestResult};
+
+fn to_bytes(program: &[u32]) -> Vec<u8> {
+    program.iter().flat_map(|i| i.to_le_bytes()).collect()
+}
+
+#[test]
+fn test_self_modifying_code() {
+    // patchを書き換えてから再度呼び出す
+    let program = [
+        0x00000297u32, // auipc t0, 0
+        0x0282a303,    // lw t1, 40(t0)
+        0x018000ef,    // jal patch
+        0x00050413,    // mv s0, a0
+        0x0262a023,    // sw t1, 32(t0)
+        0x0000100f,    // fence.i
+        0x008000ef,    // jal patch
+        0x0000006f,    // j .
+        0x00100513,    // patch: li a0, 1
+        0x00008067,    // ret
+        0x00700513,    // li a0, 7
+    ];
+
+    let mut simulator = Simulator::new().setup_headless();
+    simulator
+        .load_flat(&to_bytes(&program), MEMORY_BASE)
+        .unwrap();
+
+    let mut simulator = simulator.set_entry_point(MEMORY_BASE);
+
+    let config = TestConfig {
+        max_steps: 100,
+        ..Default::default()
+    };
+
+    assert_eq!(simulator.run_test(&config), TestResult::Timeout);
+    assert_eq!(simulator.cpu().pc(), MEMORY_BASE + 0x1c);
+    assert_eq!(simulator.cpu().read_reg(8), 1); // s0
+    assert_eq!(simulator.cpu().read_reg(10), 7); // a0
+}
+
+#[test]
+fn test_host_write_invalidates_cache() {
+    let program = [
+        0x00128293u32, // addi t0, t0, 1
+        0xffdff06f,    // j -4
+    ];
+
+    let mut simulator = Simulator::new().setup_headless();
+    simulator
+        .load_flat(&to_bytes(&program), MEMORY_BASE)
+        .unwrap();
+
+    let mut simulator = simulator.set_entry_point(MEMORY_BASE);
+
+    for _ in 0..10 {
+        simulator.step();
+    }
+
+    assert_eq!(simulator.cpu().read_reg(5), 5);
+
+    // デバッガやローダーからの書き込みも反映される
+    simulator
+        .load_flat(&to_bytes(&[0x00228293]), MEMORY_BASE) // addi t0, t0, 2
+        .unwrap();
+
+    for _ in 0..10 {
+        simulator.step();
+    }
+
+    assert_eq!(simulator.cpu().read_reg(5), 15);
+}
+
+#[test]
+fn test_fetch_past_end_of_memory() {
+    // RAMの末尾に32bit命令の前半しかない場合はホストではなくゲストのアクセスフォルトになる
+    let program = [
+        0x800002b7u32, // lui t0, 0x80000
+        0x10028293,    // addi t0, t0, 0x100
+        0x30529073,    // csrw mtvec, t0
+        0x80001337,    // lui t1, 0x80001
+        0x00030067,    // jr t1
+    ];
+
+    let mut simulator = Simulator::new().set_memory_size(0x1003).setup_headless();
+    simulator
+        .load_flat(&to_bytes(&program), MEMORY_BASE)
+        .unwrap();
+    simulator
+        .load_flat(&to_bytes(&[0x0000006f]), MEMORY_BASE + 0x100) // j .
+        .unwrap();
+    simulator
+        .load_flat(&[0x13, 0x00, 0x00], MEMORY_BASE + 0x1000) // addiの前半
+        .unwrap();
+
+    let mut simulator = simulator.set_entry_point(MEMORY_BASE);
+
+    let config = TestConfig {
+        max_steps: 20,
+        ..Default::default()
+    };
+
+    assert_eq!(simulator.run_test(&config), TestResult::Timeout);
+    assert_eq!(simulator.cpu().pc(), MEMORY_BASE + 0x100);
+    assert_eq!(simulator.cpu().read_csr(0x342).unwrap(), 1); // Instruction access fault
+    assert_eq!(
+        simulator.cpu().read_csr(0x343).unwrap(),
+        MEMORY_BASE + 0x1002
+    ); // mtval
+}
@@ -0,0 +1,233 @@
+use crate::common::{JUMP_SELF, load_program, load_words, run};
+use tiny_rv32ima_sim::simulator::{HeadlessLoaded, MEMORY_BASE, Simulator};
+
+mod common;
+
+const MSTATUS: u32 = 0x300;
+const MCAUSE: u32 = 0x342;
+const MEPC: u32 = 0x341;
+
+const INTERRUPT: u32 = 1 << 31;
+
+const STATUS_MIE: u32 = 1 << 3;
+const STATUS_MPIE: u32 = 1 << 7;
+const STATUS_MPP: u32 = 0x3 << 11;
+
+// 0x80000100とベクタモードのMTIの0x8000011cで止まる
+fn load(program: &[u32]) -> Simulator<HeadlessLoaded> {
+    // PMPを設定せずにS/Uモードで実行する
+    let mut simulator = Simulator::new().set_pmp_permissive(true).setup_headless();
+    load_words(&mut simulator, &[JUMP_SELF], MEMORY_BASE + 0x11c);
+
+    load_program(simulator, program)
+}
+
+#[test]
+fn test_machine_timer_interrupt_vectored() {
+    // mtimecmpの初期値は0なのでMTIPは立っている
+    let program = [
+        0x800002b7, // lui t0, 0x80000
+        0x10128293, // addi t0, t0, 0x101
+        0x30529073, // csrw mtvec, t0
+        0x08000293, // li t0, 0x80
+        0x3042a073, // csrs mie, t0
+        0x30046073, // csrsi mstatus, 8
+        0x0000006f, // j .
+    ];
+
+    let simulator = run(load(&program), 20);
+    let cpu = simulator.cpu();
+
+    assert_eq!(cpu.pc(), MEMORY_BASE + 0x11c);
+    assert_eq!(cpu.read_csr(MCAUSE).unwrap(), INTERRUPT | 7);
+    assert_eq!(cpu.read_csr(MEPC).unwrap(), MEMORY_BASE + 0x18);
+
+    let mstatus = cpu.read_csr(MSTATUS).unwrap();
+    assert_eq!(mstatus & (STATUS_MIE | STATUS_MPIE), STATUS_MPIE);
+}
+
+#[test]
+fn test_interrupt_priority() {
+    // MSI, MTI, SSIが同時に起こっている場合はMSIが優先される
+    let program = [
+        0x800002b7, // lui t0, 0x80000
+        0x10028293, // addi t0, t0, 0x100
+        0x30529073, // csrw mtvec, t0
+        0x02000337, // lui t1, 0x2000
+        0x00100393, // li t2, 1
+        0x00732023, // sw t2, 0(t1)
+        0x34416073, // csrsi mip, 2
+        0x08a00293, // li t0, 0x8a
+        0x3042a073, // csrs mie, t0
+        0x30046073, // csrsi mstatus, 8
+        0x0000006f, // j .
+    ];
+
+    let simulator = run(load(&program), 20);
+    let cpu = simulator.cpu();
+
+    assert_eq!(cpu.pc(), MEMORY_BASE + 0x100);
+    assert_eq!(cpu.read_csr(MCAUSE).unwrap(), INTERRUPT | 3);
+}
+
+#[test]
+fn test_machine_interrupt_from_supervisor() {
+    // Mモードの割り込みは委譲できず、Sモードからはmstatus.MIE=0でも起こる
+    // Sモードに委譲したSSIはmstatus.SIE=0なので起こらない
+    let program = [
+        0x800002b7, // lui t0, 0x80000
+        0x10028293, // addi t0, t0, 0x100
+        0x30529073, // csrw mtvec, t0
+        0xfff00293, // li t0, -1
+        0x30329073, // csrw mideleg, t0
+        0x30302473, // csrr s0, mideleg
+        0x34416073, // csrsi mip, 2
+        0x08200293, // li t0, 0x82
+        0x3042a073, // csrs mie, t0
+        0x000012b7, // lui t0, 0x1
+        0x80028293, // addi t0, t0, -0x800
+        0x3002a073, // csrs mstatus, t0
+        0x00000297, // auipc t0, 0
+        0x01028293, // addi t0, t0, 16
+        0x34129073, // csrw mepc, t0
+        0x30200073, // mret
+        0x0000006f, // j .
+    ];
+
+    let simulator = run(load(&program), 30);
+    let cpu = simulator.cpu();
+
+    assert_eq!(cpu.read_reg(8), 0x222); // s0 SSIP, STIP, SEIPのみ
+    assert_eq!(cpu.pc(), MEMORY_BASE + 0x100);
+    assert_eq!(cpu.read_csr(MCAUSE).unwrap(), INTERRUPT | 7);
+    assert_eq!(cpu.read_csr(MEPC).unwrap(), MEMORY_BASE + 0x40);
+
+    let mstatus = cpu.read_csr(MSTATUS).unwrap();
+    assert_eq!(mstatus & STATUS_MPP, 1 << 11); // Supervisor
+}
+
+#[test]
+fn test_wfi_fast_forward() {
+    // mstatus.MIE=0でもmieで有効な割り込みが起これば起きる
+    // 待っている間はmtimecmpまで時間を進めるので少ないステップ数で終わる
+    let program = [
+        0x800002b7, // lui t0, 0x80000
+        0x10028293, // addi t0, t0, 0x100
+        0x30529073, // csrw mtvec, t0
+        0x02004337, // lui t1, 0x2004
+        0x00032223, // sw zero, 4(t1)
+        0x000f43b7, // lui t2, 0xf4
+        0x24038393, // addi t2, t2, 0x240
+        0x00732023, // sw t2, 0(t1)
+        0x08000293, // li t0, 0x80
+        0x3042a073, // csrs mie, t0
+        0x10500073, // wfi
+        0xc0102573, // csrr a0, time
+        0x0000006f, // j .
+    ];
+
+    let simulator = run(load(&program), 200);
+    let cpu = simulator.cpu();
+
+    assert_eq!(cpu.pc(), MEMORY_BASE + 0x30);
+    assert!(cpu.read_reg(10) >= 1_000_000); // a0
+}
+
+#[test]
+fn test_wfi_tw() {
+    // mstatus.TW=1の場合はSモードでのWFIは不正な命令になる
+    let program = [
+        0x800002b7, // lui t0, 0x80000
+        0x10028293, // addi t0, t0, 0x100
+        0x30529073, // csrw mtvec, t0
+        0x002002b7, // lui t0, 0x200
+        0x3002a073, // csrs mstatus, t0
+        0x000012b7, // lui t0, 0x1
+        0x80028293, // addi t0, t0, -0x800
+        0x3002a073, // csrs mstatus, t0
+        0x00000297, // auipc t0, 0
+        0x01028293, // addi t0, t0, 16
+        0x34129073, // csrw mepc, t0
+        0x30200073, // mret
+        0x10500073, // wfi
+        0x0000006f, // j .
+    ];
+
+    let simulator = run(load(&program), 30);
+    let cpu = simulator.cpu();
+
+    assert_eq!(cpu.pc(), MEMORY_BASE + 0x100);
+    assert_eq!(cpu.read_csr(MCAUSE).unwrap(), 2);
+    assert_eq!(cpu.read_csr(MEPC).unwrap(), MEMORY_BASE + 0x30);
+}
+
+#[test]
+fn test_stimecmp_without_stce() {
+    // menvcfg.STCE=0の場合はMモードがSTIPを書き込み、Sモードからstimecmpにはアクセスできない
+    let program = [
+        0x800002b7, // lui t0, 0x80000
+        0x10028293, // addi t0, t0, 0x100
+        0x30529073, // csrw mtvec, t0
+        0x30616073, // csrsi mcounteren, 2
+        0x02000293, // li t0, 0x20
+        0x3442a073, // csrs mip, t0
+        0x34402473, // csrr s0, mip
+        0x3442b073, // csrc mip, t0
+        0x000012b7, // lui t0, 0x1
+        0x80028293, // addi t0, t0, -0x800
+        0x3002a073, // csrs mstatus, t0
+        0x00000297, // auipc t0, 0
+        0x01028293, // addi t0, t0, 16
+        0x34129073, // csrw mepc, t0
+        0x30200073, // mret
+        0x14d02573, // csrr a0, stimecmp
+        0x0000006f, // j .
+    ];
+
+    let simulator = run(load(&program), 30);
+    let cpu = simulator.cpu();
+
+    assert_eq!(cpu.read_reg(8) & 0x20, 0x20); // s0 STIP
+    assert_eq!(cpu.pc(), MEMORY_BASE + 0x100);
+    assert_eq!(cpu.read_csr(MCAUSE).unwrap(), 2);
+    assert_eq!(cpu.read_csr(MEPC).unwrap(), MEMORY_BASE + 0x3c);
+}
+
+#[test]
+fn test_stimecmp_with_stce() {
+    // menvcfg.STCE=1の場合はSTIPへの書き込みは無視され、stimecmpでSTIPが決まる
+    let program = [
+        0x800002b7, // lui t0, 0x80000
+        0x10028293, // addi t0, t0, 0x100
+        0x30529073, // csrw mtvec, t0
+        0x31a2a073, // csrs menvcfgh, t0
+        0x30616073, // csrsi mcounteren, 2
+        0xfff00313, // li t1, -1
+        0x14d31073, // csrw stimecmp, t1
+        0x02000293, // li t0, 0x20
+        0x3442a073, // csrs mip, t0
+        0x34402473, // csrr s0, mip
+        0x3042a073, // csrs mie, t0
+        0x000012b7, // lui t0, 0x1
+        0x80028293, // addi t0, t0, -0x800
+        0x3002a073, // csrs mstatus, t0
+        0x00000297, // auipc t0, 0
+        0x01028293, // addi t0, t0, 16
+        0x34129073, // csrw mepc, t0
+        0x30200073, // mret
+        0xc0102573, // csrr a0, time
+        0x06450513, // addi a0, a0, 100
+        0x14d51073, // csrw stimecmp, a0
+        0x144024f3, // csrr s1, sip
+        0x0000006f, // j .
+    ];
+
+    let simulator = run(load(&program), 300);
+    let cpu = simulator.cpu();
+
+    assert_eq!(cpu.read_reg(8) & 0x20, 0); // s0
+    assert_eq!(cpu.read_reg(9) & 0x20, 0); // s1
+    assert_eq!(cpu.pc(), MEMORY_BASE + 0x100);
+    assert_eq!(cpu.read_csr(MCAUSE).unwrap(), INTERRUPT | 5);
+    assert_eq!(cpu.read_csr(MEPC).unwrap(), MEMORY_BASE + 0x58);
+}
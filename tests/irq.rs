@@ -0,0 +1,153 @@
+use crate::common::{load_program, run};
+use tiny_rv32ima_sim::simulator::{AddDeviceError, FdtConfig, MEMORY_BASE, Simulator};
+
+mod common;
+
+const UART_BASE: u32 = 0x10000000;
+
+fn contains(blob: &[u8], needle: &[u8]) -> bool {
+    blob.windows(needle.len()).any(|w| w == needle)
+}
+
+#[test]
+fn test_irq_allocation() {
+    // 指定した番号はそのまま使い、指定しない場合は空いている最小の番号を割り当てる
+    let mut simulator = Simulator::new().setup_headless();
+
+    assert_eq!(simulator.add_uart(UART_BASE, Some(0xa)), Ok(0xa));
+    assert_eq!(simulator.add_uart(UART_BASE + 0x100, None), Ok(1));
+    assert_eq!(simulator.add_uart(UART_BASE + 0x200, Some(2)), Ok(2));
+    assert_eq!(simulator.add_uart(UART_BASE + 0x300, None), Ok(3));
+
+    let blob = simulator.generate_fdt(&FdtConfig::default());
+
+    for name in [
+        &b"serial@10000000\0"[..],
+        b"serial@10000100\0",
+        b"serial@10000200\0",
+        b"serial@10000300\0",
+    ] {
+        assert!(contains(&blob, name));
+    }
+}
+
+#[test]
+fn test_irq_invalid() {
+    // 範囲外の番号と使われている番号は接続せずにエラーにする
+    let mut simulator = Simulator::new().setup_headless();
+
+    assert_eq!(simulator.add_uart(UART_BASE, Some(1)), Ok(1));
+    assert_eq!(
+        simulator.add_uart(UART_BASE + 0x100, Some(1)),
+        Err(AddDeviceError::IrqInUse(1))
+    );
+    assert_eq!(
+        simulator.add_uart(UART_BASE + 0x100, Some(0)),
+        Err(AddDeviceError::InvalidIrq(0))
+    );
+    assert_eq!(
+        simulator.add_uart(UART_BASE + 0x100, Some(1024)),
+        Err(AddDeviceError::InvalidIrq(1024))
+    );
+
+    // エラーになった番号とアドレスはまだ使える
+    assert_eq!(simulator.add_uart(UART_BASE + 0x100, None), Ok(2));
+}
+
+#[test]
+fn test_irq_exhausted() {
+    let mut simulator = Simulator::new().setup_headless();
+
+    let mut count = 0;
+    let result = loop {
+        match simulator.add_uart(UART_BASE + count * 0x100, None) {
+            Ok(irq) => assert_eq!(irq, count + 1),
+            Err(e) => break e,
+        }
+
+        count += 1;
+    };
+
+    assert_eq!(result, AddDeviceError::NoIrqLeft);
+    assert_eq!(count, 1023);
+}
+
+#[test]
+fn test_device_range() {
+    let mut simulator = Simulator::new().setup_headless();
+
+    simulator.add_uart(UART_BASE, None).unwrap();
+
+    // 接続済みのデバイスと重なる
+    assert_eq!(
+        simulator.add_uart(UART_BASE + 0x80, None),
+        Err(AddDeviceError::Overlap {
+            start: UART_BASE + 0x80,
+            end: UART_BASE + 0x180
+        })
+    );
+
+    // RAMやCLINT、PLICと重なる
+    for base in [MEMORY_BASE, 0x2000000, 0xc000000] {
+        assert_eq!(
+            simulator.add_uart(base, None),
+            Err(AddDeviceError::Overlap {
+                start: base,
+                end: base + 0x100
+            })
+        );
+    }
+
+    // アドレス空間の末尾を超える
+    assert_eq!(
+        simulator.add_uart(0xffffff80, None),
+        Err(AddDeviceError::InvalidRange {
+            base: 0xffffff80,
+            size: 0x100
+        })
+    );
+
+    // 範囲の終わりもu32で表せる必要がある
+    assert_eq!(
+        simulator.add_uart(0xffffff00, None),
+        Err(AddDeviceError::InvalidRange {
+            base: 0xffffff00,
+            size: 0x100
+        })
+    );
+    assert_eq!(simulator.add_uart(0xfffffe00, None), Ok(2));
+}
+
+#[test]
+fn test_irq_routing() {
+    // 2つ目のUARTの割り込みは割り当てた番号でclaimされ、そのUARTに受け付けられたことが伝わる
+    let program: [u32; 14] = [
+        0x0c0002b7, // lui t0, 0xc000
+        0x00100313, // li t1, 1
+        0x0062a223, // sw t1, 4(t0)
+        0x0c0023b7, // lui t2, 0xc002
+        0x00200313, // li t1, 2
+        0x0063a023, // sw t1, 0(t2)
+        0x10000e37, // lui t3, 0x10000
+        0x106e00a3, // sb t1, 0x101(t3)
+        0x0c200eb7, // lui t4, 0xc200
+        0x004ea503, // lw a0, 4(t4)
+        0x102e4583, // lbu a1, 0x102(t3)
+        0x00aea223, // sw a0, 4(t4)
+        0x004ea603, // lw a2, 4(t4)
+        0x0000006f, // j .
+    ];
+
+    let mut simulator = Simulator::new().setup_headless();
+
+    simulator.add_uart(UART_BASE, Some(0xa)).unwrap();
+    simulator.add_uart(UART_BASE + 0x100, None).unwrap();
+
+    let simulator = run(load_program(simulator, &program), 30);
+    let cpu = simulator.cpu();
+
+    assert_eq!(cpu.pc(), MEMORY_BASE + 0x34);
+    assert_eq!(cpu.read_reg(10), 1); // a0
+    assert_eq!(cpu.read_reg(11), 0x2); // a1 IIRのTHRE
+    assert_eq!(cpu.read_reg(12), 0); // a2 IIRを読んだので割り込みは下がっている
+}
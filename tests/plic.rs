@@ -0,0 +1,232 @@
+use crate::common::{load_program, run};
+use tiny_rv32ima_sim::simulator::{HeadlessLoaded, MEMORY_BASE, Simulator};
+
+mod common;
+
+const MCAUSE: u32 = 0x342;
+const MEPC: u32 = 0x341;
+
+const INTERRUPT: u32 = 1 << 31;
+
+const IP_MEIP: u32 = 1 << 11;
+
+// トラップした場合は0x80000100で止まる
+fn load(program: &[u32]) -> Simulator<HeadlessLoaded> {
+    load_program(Simulator::new().setup_headless(), program)
+}
+
+#[test]
+fn test_plic_registers() {
+    // 優先度と閾値は3bitで、割り込み源0の優先度と有効ビットは0固定
+    let program = [
+        0x0c0002b7, // lui t0, 0xc000
+        0x00900313, // li t1, 9
+        0x0062a223, // sw t1, 4(t0)
+        0x0042a503, // lw a0, 4(t0)
+        0x0062a023, // sw t1, 0(t0)
+        0x0002a583, // lw a1, 0(t0)
+        0x0c0023b7, // lui t2, 0xc002
+        0xfff00313, // li t1, -1
+        0x0063a023, // sw t1, 0(t2)
+        0x0003a603, // lw a2, 0(t2)
+        0x0663ae23, // sw t1, 124(t2)
+        0x07c3a683, // lw a3, 124(t2)
+        0x0c200e37, // lui t3, 0xc200
+        0x00f00313, // li t1, 15
+        0x006e2023, // sw t1, 0(t3)
+        0x000e2703, // lw a4, 0(t3)
+        0x004e2783, // lw a5, 4(t3)
+        0x0000006f, // j .
+    ];
+
+    let simulator = run(load(&program), 30);
+    let cpu = simulator.cpu();
+
+    assert_eq!(cpu.pc(), MEMORY_BASE + 0x44);
+    assert_eq!(cpu.read_reg(10), 1); // a0
+    assert_eq!(cpu.read_reg(11), 0); // a1
+    assert_eq!(cpu.read_reg(12), 0xfffffffe); // a2
+    assert_eq!(cpu.read_reg(13), 0xffffffff); // a3
+    assert_eq!(cpu.read_reg(14), 7); // a4
+    assert_eq!(cpu.read_reg(15), 0); // a5 保留中の割り込みがないのでclaimは0
+}
+
+#[test]
+fn test_plic_access_fault() {
+    // 存在しないコンテキストと4バイト以外のアクセスはアクセスフォルトになる
+    let prologue = [
+        0x800002b7, // lui t0, 0x80000
+        0x10028293, // addi t0, t0, 0x100
+        0x30529073, // csrw mtvec, t0
+    ];
+
+    let undefined = [
+        0x0c202337, // lui t1, 0xc202
+        0x00032503, // lw a0, 0(t1)
+    ];
+
+    let program: Vec<u32> = prologue.iter().chain(&undefined).copied().collect();
+    let simulator = run(load(&program), 20);
+    let cpu = simulator.cpu();
+
+    assert_eq!(cpu.pc(), MEMORY_BASE + 0x100);
+    assert_eq!(cpu.read_csr(MCAUSE).unwrap(), 5); // Load access fault
+    assert_eq!(cpu.read_csr(MEPC).unwrap(), MEMORY_BASE + 0x10);
+
+    let byte_store = [
+        0x0c000337, // lui t1, 0xc000
+        0x00730023, // sb t2, 0(t1)
+    ];
+
+    let program: Vec<u32> = prologue.iter().chain(&byte_store).copied().collect();
+    let simulator = run(load(&program), 20);
+    let cpu = simulator.cpu();
+
+    assert_eq!(cpu.pc(), MEMORY_BASE + 0x100);
+    assert_eq!(cpu.read_csr(MCAUSE).unwrap(), 7); // Store/AMO access fault
+}
+
+#[test]
+fn test_plic_claim_priority() {
+    // 複数の割り込みが保留されている場合は優先度の高い順、同じ優先度では番号の小さい順にclaimされる
+    // 有効になっていない割り込み源40はclaimされない
+    let program = [
+        0x0c0002b7, // lui t0, 0xc000
+        0x00100313, // li t1, 1
+        0x0062a623, // sw t1, 12(t0)
+        0x00200313, // li t1, 2
+        0x0062aa23, // sw t1, 20(t0)
+        0x0062ae23, // sw t1, 28(t0)
+        0x00300313, // li t1, 3
+        0x0a62a023, // sw t1, 160(t0)
+        0x0c0023b7, // lui t2, 0xc002
+        0x0a800313, // li t1, 0xa8
+        0x0063a023, // sw t1, 0(t2)
+        0x0c001eb7, // lui t4, 0xc001
+        0x000ea803, // lw a6, 0(t4)
+        0x0c200e37, // lui t3, 0xc200
+        0x004e2503, // lw a0, 4(t3)
+        0x004e2583, // lw a1, 4(t3)
+        0x004e2603, // lw a2, 4(t3)
+        0x004e2683, // lw a3, 4(t3)
+        0x00ae2223, // sw a0, 4(t3)
+        0x00be2223, // sw a1, 4(t3)
+        0x00ce2223, // sw a2, 4(t3)
+        0x004ea703, // lw a4, 4(t4)
+        0x000ea783, // lw a5, 0(t4)
+        0x0000006f, // j .
+    ];
+
+    let mut simulator = load(&program);
+
+    for irq in [3, 5, 7, 40] {
+        simulator.trigger_irq(irq);
+    }
+
+    let simulator = run(simulator, 40);
+    let cpu = simulator.cpu();
+
+    assert_eq!(cpu.pc(), MEMORY_BASE + 0x5c);
+    assert_eq!(cpu.read_reg(16), 0xa8); // a6
+    assert_eq!(cpu.read_reg(10), 5); // a0
+    assert_eq!(cpu.read_reg(11), 7); // a1
+    assert_eq!(cpu.read_reg(12), 3); // a2
+    assert_eq!(cpu.read_reg(13), 0); // a3
+    assert_eq!(cpu.read_reg(14), 1 << (40 - 32)); // a4
+    assert_eq!(cpu.read_reg(15), 0); // a5
+}
+
+#[test]
+fn test_plic_threshold() {
+    // 優先度が閾値より高い場合のみmip.MEIPが立ち、外部割り込みが起こる
+    let program = [
+        0x800002b7, // lui t0, 0x80000
+        0x10028293, // addi t0, t0, 0x100
+        0x30529073, // csrw mtvec, t0
+        0x0c000337, // lui t1, 0xc000
+        0x00100393, // li t2, 1
+        0x00732423, // sw t2, 8(t1)
+        0x0c200e37, // lui t3, 0xc200
+        0x007e2023, // sw t2, 0(t3)
+        0x0c002eb7, // lui t4, 0xc002
+        0x00400f13, // li t5, 4
+        0x01eea023, // sw t5, 0(t4)
+        0x34402573, // csrr a0, mip
+        0x000e2023, // sw zero, 0(t3)
+        0x344025f3, // csrr a1, mip
+        0x00001f37, // lui t5, 0x1
+        0x800f0f13, // addi t5, t5, -2048
+        0x304f2073, // csrs mie, t5
+        0x30046073, // csrsi mstatus, 8
+        0x0000006f, // j .
+    ];
+
+    let mut simulator = load(&program);
+    simulator.set_irq_level(2, true);
+
+    let simulator = run(simulator, 30);
+    let cpu = simulator.cpu();
+
+    assert_eq!(cpu.read_reg(10) & IP_MEIP, 0); // a0
+    assert_eq!(cpu.read_reg(11) & IP_MEIP, IP_MEIP); // a1
+
+    assert_eq!(cpu.pc(), MEMORY_BASE + 0x100);
+    assert_eq!(cpu.read_csr(MCAUSE).unwrap(), INTERRUPT | 11);
+    assert_eq!(cpu.read_csr(MEPC).unwrap(), MEMORY_BASE + 0x48);
+}
+
+#[test]
+fn test_plic_gateway() {
+    // claimからcompleteまでの間は同じ割り込み源の割り込みは保留されない
+    // complete後、レベルトリガ(4)は信号が立っていれば、エッジトリガ(6)はclaim中に信号が来ていれば再び保留される
+    let program = [
+        0x0c0002b7, // lui t0, 0xc000
+        0x00100313, // li t1, 1
+        0x0062a823, // sw t1, 16(t0)
+        0x0062ac23, // sw t1, 24(t0)
+        0x0c0023b7, // lui t2, 0xc002
+        0x05000313, // li t1, 0x50
+        0x0063a023, // sw t1, 0(t2)
+        0x0c200e37, // lui t3, 0xc200
+        0x004e2503, // lw a0, 4(t3)
+        0x004e2583, // lw a1, 4(t3)
+        0x004e2603, // lw a2, 4(t3)
+        0x00ae2223, // sw a0, 4(t3)
+        0x00be2223, // sw a1, 4(t3)
+        0x004e2683, // lw a3, 4(t3)
+        0x004e2703, // lw a4, 4(t3)
+        0x00de2223, // sw a3, 4(t3)
+        0x00ee2223, // sw a4, 4(t3)
+        0x004e2783, // lw a5, 4(t3)
+        0x0000006f, // j .
+    ];
+
+    let mut simulator = load(&program);
+    simulator.set_irq_level(4, true);
+    simulator.trigger_irq(6);
+
+    // 2つともclaimした後にエッジトリガの信号を入れる
+    for _ in 0..10 {
+        simulator.step();
+    }
+
+    simulator.trigger_irq(6);
+
+    // 再びclaimした後にレベルトリガの信号を下げる
+    for _ in 0..5 {
+        simulator.step();
+    }
+
+    simulator.set_irq_level(4, false);
+
+    let simulator = run(simulator, 10);
+    let cpu = simulator.cpu();
+
+    assert_eq!(cpu.pc(), MEMORY_BASE + 0x48);
+    assert_eq!(cpu.read_reg(10), 4); // a0
+    assert_eq!(cpu.read_reg(11), 6); // a1
+    assert_eq!(cpu.read_reg(12), 0); // a2
+    assert_eq!(cpu.read_reg(13), 4); // a3
+    assert_eq!(cpu.read_reg(14), 6); // a4
+    assert_eq!(cpu.read_reg(15), 0); // a5
+}
@@ -0,0 +1,210 @@
+use crate::common::{load_program, load_words, run};
+use tiny_rv32ima_sim::simulator::{HeadlessLoaded, MEMORY_BASE, Simulator};
+
+mod common;
+
+const MCAUSE: u32 = 0x342;
+const MEPC: u32 = 0x341;
+const MTVAL: u32 = 0x343;
+
+// 0x80000000-0x80001000をTORでRWX、0x80002000からの4KiBをNAPOTで読み込みのみにして
+// Sモードに移る
+const SETUP: [u32; 21] = [
+    0x800002b7, // lui t0, 0x80000
+    0x10028293, // addi t0, t0, 0x100
+    0x30529073, // csrw mtvec, t0
+    0x200002b7, // lui t0, 0x20000
+    0x40028293, // addi t0, t0, 0x400
+    0x3b029073, // csrw pmpaddr0, t0
+    0x200012b7, // lui t0, 0x20001
+    0x9ff28293, // addi t0, t0, -0x601
+    0x3b129073, // csrw pmpaddr1, t0
+    0x000022b7, // lui t0, 0x2
+    0x90f28293, // addi t0, t0, -0x6f1
+    0x3a029073, // csrw pmpcfg0, t0
+    0x3a002473, // csrr s0, pmpcfg0
+    0x80002537, // lui a0, 0x80002
+    0x000012b7, // lui t0, 0x1
+    0x80028293, // addi t0, t0, -0x800
+    0x3002a073, // csrs mstatus, t0
+    0x00000297, // auipc t0, 0
+    0x01028293, // addi t0, t0, 16
+    0x34129073, // csrw mepc, t0
+    0x30200073, // mret
+];
+
+// トラップした場合は0x80000100で止まる
+fn load(program: &[u32]) -> Simulator<HeadlessLoaded> {
+    let mut simulator = Simulator::new().setup_headless();
+    load_words(&mut simulator, &[0x12345678], MEMORY_BASE + 0x2000);
+
+    load_program(simulator, program)
+}
+
+#[test]
+fn test_pmp_store() {
+    // 読み込みのみの領域には書き込めない
+    let program = [
+        &SETUP[..],
+        &[
+            0x00052583, // lw a1, 0(a0)
+            0x00b52023, // sw a1, 0(a0)
+            0x0000006f, // j .
+        ],
+    ]
+    .concat();
+
+    let simulator = run(load(&program), 40);
+    let cpu = simulator.cpu();
+
+    assert_eq!(cpu.read_reg(8), 0x190f); // s0
+    assert_eq!(cpu.read_reg(11), 0x12345678); // a1
+    assert_eq!(cpu.pc(), MEMORY_BASE + 0x100);
+    assert_eq!(cpu.read_csr(MCAUSE).unwrap(), 7); // Store access fault
+    assert_eq!(cpu.read_csr(MEPC).unwrap(), MEMORY_BASE + 0x58);
+    assert_eq!(cpu.read_csr(MTVAL).unwrap(), MEMORY_BASE + 0x2000);
+}
+
+#[test]
+fn test_pmp_no_match() {
+    // 有効なエントリがある場合、どのエントリにも一致しないSモードのアクセスは失敗する
+    let program = [
+        &SETUP[..],
+        &[
+            0x80003337, // lui t1, 0x80003
+            0x00030067, // jr t1
+        ],
+    ]
+    .concat();
+
+    let simulator = run(load(&program), 40);
+    let cpu = simulator.cpu();
+
+    assert_eq!(cpu.pc(), MEMORY_BASE + 0x100);
+    assert_eq!(cpu.read_csr(MCAUSE).unwrap(), 1); // Instruction access fault
+    assert_eq!(cpu.read_csr(MEPC).unwrap(), MEMORY_BASE + 0x3000);
+    assert_eq!(cpu.read_csr(MTVAL).unwrap(), MEMORY_BASE + 0x3000);
+}
+
+#[test]
+fn test_pmp_lock() {
+    // ロックされたエントリは書き換えられず、Mモードにも適用される
+    let program = [
+        0x800002b7, // lui t0, 0x80000
+        0x10028293, // addi t0, t0, 0x100
+        0x30529073, // csrw mtvec, t0
+        0x200012b7, // lui t0, 0x20001
+        0x9ff28293, // addi t0, t0, -0x601
+        0x3b029073, // csrw pmpaddr0, t0
+        0x09900293, // li t0, 0x99
+        0x3a029073, // csrw pmpcfg0, t0
+        0x3b001073, // csrw pmpaddr0, zero
+        0x3b002473, // csrr s0, pmpaddr0
+        0x3a001073, // csrw pmpcfg0, zero
+        0x3a0024f3, // csrr s1, pmpcfg0
+        0x80002537, // lui a0, 0x80002
+        0x00052583, // lw a1, 0(a0)
+        0x00b52023, // sw a1, 0(a0)
+        0x0000006f, // j .
+    ];
+
+    let simulator = run(load(&program), 30);
+    let cpu = simulator.cpu();
+
+    assert_eq!(cpu.read_reg(8), 0x200009ff); // s0
+    assert_eq!(cpu.read_reg(9), 0x99); // s1
+    assert_eq!(cpu.read_reg(11), 0x12345678); // a1
+    assert_eq!(cpu.pc(), MEMORY_BASE + 0x100);
+    assert_eq!(cpu.read_csr(MCAUSE).unwrap(), 7); // Store access fault
+    assert_eq!(cpu.read_csr(MEPC).unwrap(), MEMORY_BASE + 0x38);
+}
+
+// PMPを設定せずにSモードに移り、0x80002000から読み込む
+const ALL_OFF: [u32; 13] = [
+    0x800002b7, // lui t0, 0x80000
+    0x10028293, // addi t0, t0, 0x100
+    0x30529073, // csrw mtvec, t0
+    0x80002537, // lui a0, 0x80002
+    0x000012b7, // lui t0, 0x1
+    0x80028293, // addi t0, t0, -0x800
+    0x3002a073, // csrs mstatus, t0
+    0x00000297, // auipc t0, 0
+    0x01028293, // addi t0, t0, 16
+    0x34129073, // csrw mepc, t0
+    0x30200073, // mret
+    0x00052583, // lw a1, 0(a0)
+    0x0000006f, // j .
+];
+
+#[test]
+fn test_pmp_all_off() {
+    // 有効なエントリが一つもない場合も、Sモードのアクセスは失敗する
+    let simulator = run(load(&ALL_OFF), 20);
+    let cpu = simulator.cpu();
+
+    assert_eq!(cpu.pc(), MEMORY_BASE + 0x100);
+    assert_eq!(cpu.read_csr(MCAUSE).unwrap(), 1); // Instruction access fault
+    assert_eq!(cpu.read_csr(MEPC).unwrap(), MEMORY_BASE + 0x2c);
+    assert_eq!(cpu.read_csr(MTVAL).unwrap(), MEMORY_BASE + 0x2c);
+}
+
+#[test]
+fn test_pmp_all_off_permissive() {
+    // set_pmp_permissiveの場合は、有効なエントリが一つもなければSモードでもアクセスできる
+    let mut simulator = Simulator::new()
+        .set_pmp_permissive(true)
+        .set_hart_count(2)
+        .setup_headless();
+    load_words(&mut simulator, &[0x12345678], MEMORY_BASE + 0x2000);
+
+    let simulator = run(load_program(simulator, &ALL_OFF), 20);
+    let cpu = simulator.cpu();
+
+    assert_eq!(cpu.read_reg(11), 0x12345678); // a1
+    assert_eq!(cpu.pc(), MEMORY_BASE + 0x30);
+}
+
+#[test]
+fn test_pmp_cached_fetch() {
+    // 0x80000000-0x80000804だけを実行可能にし、境界をまたぐ命令をMモードで実行してキャッシュさせてから
+    // Sモードで実行する
+    let program = [
+        0x800002b7, // lui t0, 0x80000
+        0x10028293, // addi t0, t0, 0x100
+        0x30529073, // csrw mtvec, t0
+        0x00000593, // li a1, 0
+        0x200002b7, // lui t0, 0x20000
+        0x20128293, // addi t0, t0, 0x201
+        0x3b029073, // csrw pmpaddr0, t0
+        0x00f00293, // li t0, 0xf
+        0x3a029073, // csrw pmpcfg0, t0
+        0x800014b7, // lui s1, 0x80001
+        0x80248493, // addi s1, s1, -0x7fe
+        0x000480e7, // jalr s1
+        0x000012b7, // lui t0, 0x1
+        0x80028293, // addi t0, t0, -0x800
+        0x3002a073, // csrs mstatus, t0
+        0x34149073, // csrw mepc, s1
+        0x30200073, // mret
+    ];
+
+    let mut simulator = Simulator::new().setup_headless();
+    load_words(
+        &mut simulator,
+        &[
+            0x00158593, // addi a1, a1, 1
+            0x00008067, // ret
+        ],
+        MEMORY_BASE + 0x802,
+    );
+
+    let simulator = run(load_program(simulator, &program), 40);
+    let cpu = simulator.cpu();
+
+    // キャッシュされていても後半のPMPを確認する
+    assert_eq!(cpu.read_reg(11), 1); // a1
+    assert_eq!(cpu.pc(), MEMORY_BASE + 0x100);
+    assert_eq!(cpu.read_csr(MCAUSE).unwrap(), 1); // Instruction access fault
+    assert_eq!(cpu.read_csr(MEPC).unwrap(), MEMORY_BASE + 0x802);
+    assert_eq!(cpu.read_csr(MTVAL).unwrap(), MEMORY_BASE + 0x804);
+}
@@ -1,15 +1,14 @@
-use tiny_rv32ima_sim::cpu::Cpu;
-
-use crate::common::{TEST_ELVES_DIR, run_elf_test};
+use crate::common::{TEST_ELVES_DIR, require_dir, run_elf_test};
 
 mod common;
 
 #[test]
-fn test_ua_flats() {
-    let mut cpu = Cpu::new();
-
+#[ignore = "requires riscv-tests ELFs (scripts/riscv-tests.sh)"]
+fn test_mi_flats() {
     let rv32mi_p_dir = format!("{}/{}", TEST_ELVES_DIR, "rv32mi");
 
+    require_dir(&rv32mi_p_dir);
+
     let required_tests = [
         "rv32mi-p-csr",
         "rv32mi-p-illegal",
@@ -28,11 +27,7 @@ fn test_ua_flats() {
 
     for test in required_tests {
         println!("TRY: {}", test);
-        run_elf_test(
-            &mut cpu,
-            format!("{}/{}", rv32mi_p_dir, test),
-            0x80000000 | 0x1000,
-        );
+        run_elf_test(format!("{}/{}", rv32mi_p_dir, test), 0x80000000 | 0x1000);
         println!("PASS: {}", test);
     }
 }
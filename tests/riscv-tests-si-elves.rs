@@ -1,15 +1,14 @@
-use tiny_rv32ima_sim::cpu::Cpu;
-
-use crate::common::{TEST_ELVES_DIR, run_elf_test, run_test};
+use crate::common::{TEST_ELVES_DIR, require_dir, run_elf_test};
 
 mod common;
 
 #[test]
+#[ignore = "requires riscv-tests ELFs (scripts/riscv-tests.sh)"]
 fn test_si_elves() {
-    let mut cpu = Cpu::new();
-
     let rv32si_p_dir = format!("{}/{}", TEST_ELVES_DIR, "rv32si-p");
 
+    require_dir(&rv32si_p_dir);
+
     let required_tests = [
         "rv32si-p-csr",
         "rv32si-p-dirty",
@@ -20,7 +19,7 @@ fn test_si_elves() {
 
     for test in required_tests {
         println!("TRY: {}", test);
-        run_elf_test(&mut cpu, format!("{}/{}", rv32si_p_dir, test), 0x80001000);
+        run_elf_test(format!("{}/{}", rv32si_p_dir, test), 0x80001000);
         println!("PASS: {}", test);
     }
 }
@@ -0,0 +1,10 @@
+use crate::common::{TEST_ELVES_DIR, run_elf_tests};
+
+mod common;
+
+#[test]
+#[ignore = "requires riscv-tests ELFs (scripts/riscv-tests.sh)"]
+fn test_uf_flats() {
+    let rv32uf_dir = format!("{}/{}", TEST_ELVES_DIR, "rv32uf");
+    run_elf_tests(rv32uf_dir, 0x80000000 | 0x1000, vec![]);
+}
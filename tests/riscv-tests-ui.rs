@@ -1,16 +1,12 @@
-use tiny_rv32ima_sim::cpu::Cpu;
-
 use crate::common::{RiscvTest, TEST_ELVES_DIR, run_elf_tests};
 
 mod common;
 
 #[test]
+#[ignore = "requires riscv-tests ELFs (scripts/riscv-tests.sh)"]
 fn test_ui_elves() {
-    let mut cpu = Cpu::new();
-
     let rv32ui_dir = format!("{}/{}", TEST_ELVES_DIR, "rv32ui");
     run_elf_tests(
-        &mut cpu,
         rv32ui_dir,
         0x80000000 | 0x1000,
         vec![RiscvTest {
@@ -0,0 +1,62 @@
+use tiny_rv32ima_sim::simulator::{MEMORY_BASE, Simulator, TestConfig, TestResult};
+
+const TOHOST: u32 = 0x80001000;
+
+// gpにcodeを設定してtohostに書き込み、その場でループする
+fn tohost_program(code: u32) -> Vec<u8> {
+    let program = [
+        (code << 20) | 0x00000193, // addi gp, zero, code
+        0x800012b7,                // lui t0, 0x80001
+        0x0032a023,                // sw gp, 0(t0)
+        0x0000006f,                // j .
+    ];
+
+    program.iter().flat_map(|i| i.to_le_bytes()).collect()
+}
+
+fn run(program: &[u8], config: &TestConfig) -> TestResult {
+    let mut simulator = Simulator::new().setup_headless();
+
+    simulator.load_flat(program, MEMORY_BASE).unwrap();
+
+    simulator.set_entry_point(MEMORY_BASE).run_test(config)
+}
+
+#[test]
+fn test_run_until_tohost() {
+    let config = TestConfig {
+        tohost: Some(TOHOST),
+        ..Default::default()
+    };
+
+    assert_eq!(run(&tohost_program(1), &config), TestResult::Pass);
+    assert_eq!(
+        run(&tohost_program((3 << 1) | 1), &config),
+        TestResult::Fail(3)
+    );
+}
+
+#[test]
+fn test_run_until_exit_address() {
+    let config = TestConfig {
+        exit_address: Some(MEMORY_BASE + 12),
+        ..Default::default()
+    };
+
+    assert_eq!(run(&tohost_program(1), &config), TestResult::Pass);
+    assert_eq!(
+        run(&tohost_program((5 << 1) | 1), &config),
+        TestResult::Fail(5)
+    );
+}
+
+#[test]
+fn test_run_timeout() {
+    let config = TestConfig {
+        tohost: Some(TOHOST + 4),
+        max_steps: 100,
+        ..Default::default()
+    };
+
+    assert_eq!(run(&tohost_program(1), &config), TestResult::Timeout);
+}
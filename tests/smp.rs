@@ -0,0 +1,153 @@
+use crate::common::{load_program, run};
+use tiny_rv32ima_sim::simulator::{FdtConfig, HeadlessLoaded, MEMORY_BASE, Simulator};
+
+mod common;
+
+const MCAUSE: u32 = 0x342;
+
+const INTERRUPT: u32 = 1 << 31;
+
+// 全てのhartがMEMORY_BASEから実行を始め、トラップした場合は0x80000100で止まる
+fn load(hart_count: usize, program: &[u32]) -> Simulator<HeadlessLoaded> {
+    load_program(
+        Simulator::new().set_hart_count(hart_count).setup_headless(),
+        program,
+    )
+}
+
+#[test]
+fn test_smp_startup() {
+    // 全てのhartが同じエントリポイントから実行し、a0にmhartid、a1にdtbのアドレスが入る
+    let program = [
+        0xf1402473, // csrr s0, mhartid
+        0x0000006f, // j .
+    ];
+
+    let mut simulator = load(4, &program);
+    simulator.set_dtb_address(0x80100000);
+
+    let simulator = run(simulator, 10);
+
+    assert_eq!(simulator.harts().len(), 4);
+
+    for (hartid, cpu) in simulator.harts().iter().enumerate() {
+        assert_eq!(cpu.pc(), MEMORY_BASE + 0x4);
+        assert_eq!(cpu.read_reg(8), hartid as u32); // s0
+        assert_eq!(cpu.read_reg(10), hartid as u32); // a0
+        assert_eq!(cpu.read_reg(11), 0x80100000); // a1
+    }
+}
+
+#[test]
+fn test_smp_ipi() {
+    // hart 0がCLINTのhart 1のmsipに書き込むと、WFIで待っているhart 1にMSIが起こる
+    let program = [
+        0xf14022f3, // csrr t0, mhartid
+        0x00029a63, // bnez t0, secondary
+        0x02000337, // lui t1, 0x2000
+        0x00100393, // li t2, 1
+        0x00732223, // sw t2, 4(t1)
+        0x0000006f, // j .
+        // secondary:
+        0x800002b7, // lui t0, 0x80000
+        0x10028293, // addi t0, t0, 0x100
+        0x30529073, // csrw mtvec, t0
+        0x00800293, // li t0, 8
+        0x3042a073, // csrs mie, t0
+        0x30046073, // csrsi mstatus, 8
+        0x10500073, // wfi
+        0x0000006f, // j .
+    ];
+
+    let simulator = run(load(2, &program), 50);
+    let harts = simulator.harts();
+
+    assert_eq!(harts[0].pc(), MEMORY_BASE + 0x14);
+    assert_eq!(harts[0].read_csr(MCAUSE).unwrap(), 0);
+
+    assert_eq!(harts[1].pc(), MEMORY_BASE + 0x100);
+    assert_eq!(harts[1].read_csr(MCAUSE).unwrap(), INTERRUPT | 3);
+}
+
+// hart 1のLR.WとSC.Wの間にhart 0が0x80001000+offsetに書き込む
+fn lr_sc_program(offset: u32) -> [u32; 12] {
+    [
+        0xf14022f3,                 // csrr t0, mhartid
+        0x80001637,                 // lui a2, 0x80001
+        0x00029a63,                 // bnez t0, secondary
+        0x00000013,                 // nop
+        0x00000013,                 // nop
+        0x00562023 | (offset << 7), // sw t0, offset(a2)
+        0x0000006f,                 // j .
+        // secondary:
+        0x100626af, // lr.w a3, (a2)
+        0x00000013, // nop
+        0x00000013, // nop
+        0x1856272f, // sc.w a4, t0, (a2)
+        0x0000006f, // j .
+    ]
+}
+
+#[test]
+fn test_smp_lr_sc() {
+    // 他のhartが予約したアドレスに書き込むとSC.Wは失敗する
+    let simulator = run(load(2, &lr_sc_program(0)), 20);
+    let cpu = &simulator.harts()[1];
+
+    assert_eq!(cpu.pc(), MEMORY_BASE + 0x2c);
+    assert_eq!(cpu.read_reg(14), 1); // a4
+
+    // 別のアドレスへの書き込みでは予約は取り消されない
+    let simulator = run(load(2, &lr_sc_program(4)), 20);
+    let cpu = &simulator.harts()[1];
+
+    assert_eq!(cpu.pc(), MEMORY_BASE + 0x2c);
+    assert_eq!(cpu.read_reg(14), 0); // a4
+}
+
+#[test]
+fn test_smp_lr_sc_counter() {
+    // 2つのhartがLR.W/SC.Wで50回ずつカウンタを増やし、amoaddで終了を知らせる
+    // hart 0は両方の終了を待ってからカウンタをa0に読み込む
+    let program = [
+        0x80001637, // lui a2, 0x80001
+        0x03200493, // li s1, 50
+        // loop:
+        0x1006232f, // lr.w t1, (a2)
+        0x00130313, // addi t1, t1, 1
+        0x186623af, // sc.w t2, t1, (a2)
+        0xfe039ae3, // bnez t2, loop
+        0xfff48493, // addi s1, s1, -1
+        0xfe0496e3, // bnez s1, loop
+        0x00460693, // addi a3, a2, 4
+        0x00100e13, // li t3, 1
+        0x01c6a02f, // amoadd.w zero, t3, (a3)
+        0xf14022f3, // csrr t0, mhartid
+        0x00029a63, // bnez t0, end
+        // wait:
+        0x00462e83, // lw t4, 4(a2)
+        0x00200f13, // li t5, 2
+        0xffee9ce3, // bne t4, t5, wait
+        0x00062503, // lw a0, 0(a2)
+        // end:
+        0x0000006f, // j .
+    ];
+
+    let simulator = run(load(2, &program), 2000);
+    let cpu = simulator.cpu();
+
+    assert_eq!(cpu.pc(), MEMORY_BASE + 0x44);
+    assert_eq!(cpu.read_reg(10), 100); // a0
+}
+
+#[test]
+fn test_smp_fdt() {
+    let simulator = Simulator::new().set_hart_count(2);
+    let blob = simulator.generate_fdt(&FdtConfig::default());
+
+    let contains = |needle: &[u8]| blob.windows(needle.len()).any(|w| w == needle);
+
+    assert!(contains(b"cpu@0\0"));
+    assert!(contains(b"cpu@1\0"));
+    assert!(!contains(b"cpu@2\0"));
+}
@@ -0,0 +1,105 @@
+use tiny_rv32ima_sim::simulator::{HeadlessLoaded, MEMORY_BASE, Simulator, SnapshotError};
+
+// 0x80001000に書き込みながらt0を増やし続ける
+fn counter_program() -> Vec<u8> {
+    let program = [
+        0x80001337u32, // lui t1, 0x80001
+        0x00128293,    // addi t0, t0, 1
+        0x00532023,    // sw t0, 0(t1)
+        0xff9ff06f,    // j -8
+    ];
+
+    program.iter().flat_map(|i| i.to_le_bytes()).collect()
+}
+
+fn headless(memory_size: usize) -> Simulator<HeadlessLoaded> {
+    let mut simulator = Simulator::new()
+        .set_memory_size(memory_size)
+        .setup_headless();
+
+    simulator
+        .load_flat(&counter_program(), MEMORY_BASE)
+        .unwrap();
+
+    simulator.set_entry_point(MEMORY_BASE)
+}
+
+fn step(simulator: &mut Simulator<HeadlessLoaded>, n: usize) {
+    for _ in 0..n {
+        simulator.step();
+    }
+}
+
+#[test]
+fn test_snapshot_round_trip() {
+    let mut original = headless(0x100000);
+    step(&mut original, 1001);
+
+    let snapshot = original.save_snapshot();
+
+    // 別のSimulatorに復元しても同じ状態から続けられる
+    let mut restored = headless(0x100000);
+    restored.restore_snapshot(&snapshot).unwrap();
+
+    assert_eq!(restored.save_snapshot(), snapshot);
+    assert_eq!(restored.cpu().pc(), original.cpu().pc());
+
+    step(&mut original, 500);
+    step(&mut restored, 500);
+
+    assert_eq!(restored.save_snapshot(), original.save_snapshot());
+    assert_eq!(restored.cpu().read_reg(5), 500);
+}
+
+#[test]
+fn test_restore_invalid_snapshot() {
+    let mut simulator = headless(0x100000);
+    let snapshot = simulator.save_snapshot();
+
+    assert_eq!(
+        simulator.restore_snapshot(b"not a snapshot"),
+        Err(SnapshotError::InvalidMagic)
+    );
+
+    assert_eq!(
+        simulator.restore_snapshot(&snapshot[..snapshot.len() - 1]),
+        Err(SnapshotError::UnexpectedEof)
+    );
+
+    let mut other = headless(0x200000);
+
+    assert_eq!(
+        other.restore_snapshot(&snapshot),
+        Err(SnapshotError::MemorySizeMismatch {
+            expected: 0x100000,
+            actual: 0x200000
+        })
+    );
+}
+
+#[test]
+fn test_restore_truncated_snapshot_keeps_state() {
+    let mut original = headless(0x100000);
+    step(&mut original, 1001);
+
+    let snapshot = original.save_snapshot();
+
+    let mut simulator = headless(0x100000);
+    step(&mut simulator, 301);
+
+    let before = simulator.save_snapshot();
+    let counter = simulator.cpu().read_reg(5); // t0
+
+    // メモリの途中やデバイスの途中で切れていても、復元する前の状態のまま残る
+    for len in [snapshot.len() / 2, snapshot.len() - 1] {
+        assert_eq!(
+            simulator.restore_snapshot(&snapshot[..len]),
+            Err(SnapshotError::UnexpectedEof)
+        );
+        assert_eq!(simulator.save_snapshot(), before);
+    }
+
+    step(&mut simulator, 300);
+
+    assert_eq!(simulator.cpu().read_reg(5), counter + 100);
+}
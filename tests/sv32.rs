@@ -0,0 +1,154 @@
+use crate::common::{JUMP_SELF, load_program, load_words, run};
+use tiny_rv32ima_sim::simulator::{HeadlessLoaded, MEMORY_BASE, Simulator};
+
+mod common;
+
+const MCAUSE: u32 = 0x342;
+const MTVAL: u32 = 0x343;
+
+// 0x80004000を指すX|Aのリーフ
+const EXEC_ONLY_PTE: u32 = 0x20001049;
+// 0x80004000を指すU|R|W|A|Dのリーフ
+const USER_PTE: u32 = 0x200010d7;
+
+// mstatusにMXRとSUMを設定する命令
+const SET_MXR: [u32; 2] = [
+    0x000802b7, // lui t0, 0x80
+    0x3002a073, // csrs mstatus, t0
+];
+const SET_SUM: [u32; 2] = [
+    0x000402b7, // lui t0, 0x40
+    0x3002a073, // csrs mstatus, t0
+];
+
+// ASID=1でページングを有効にし、setupを実行してからSモードでprogramを実行する
+// 0x40005000は2段目のページテーブルのleaf_pteで、0x40400000はメガページでマップする
+// トラップした場合は0x80000100で止まる
+fn load(setup: &[u32], leaf_pte: u32, program: &[u32]) -> Simulator<HeadlessLoaded> {
+    let prologue = [
+        0x800002b7u32, // lui t0, 0x80000
+        0x10028293,    // addi t0, t0, 0x100
+        0x30529073,    // csrw mtvec, t0
+        0x804802b7,    // lui t0, 0x80480
+        0x00228293,    // addi t0, t0, 2
+        0x18029073,    // csrw satp, t0
+        0x18002473,    // csrr s0, satp
+        0x000012b7,    // lui t0, 0x1
+        0x80028293,    // addi t0, t0, -0x800
+        0x3002a073,    // csrs mstatus, t0
+    ];
+
+    let enter_supervisor = [
+        0x00000297u32, // auipc t0, 0
+        0x01028293,    // addi t0, t0, 16
+        0x34129073,    // csrw mepc, t0
+        0x30200073,    // mret
+    ];
+
+    let program: Vec<u32> = prologue
+        .iter()
+        .chain(setup)
+        .chain(&enter_supervisor)
+        .chain(program)
+        .chain(&[JUMP_SELF])
+        .copied()
+        .collect();
+
+    // PMPを設定せずにS/Uモードで実行する
+    let mut simulator = Simulator::new().set_pmp_permissive(true).setup_headless();
+
+    // 0x80002000に1段目、0x80003000に2段目のページテーブルを置く
+    // 0x80000000はV|R|W|X|A|Dのメガページでそのままマップする
+    let words = [
+        (0x2800, 0x200000cfu32),
+        (0x2400, 0x20000c01),
+        (0x2404, 0x201000c7), // 0x80400000を指すV|R|W|A|Dのメガページ
+        (0x3014, leaf_pte),
+        (0x4000, 0x11111111),
+        (0x400010, 0x22222222),
+        (0x401010, 0x33333333),
+        (0x800010, 0x44444444),
+    ];
+
+    for (offset, value) in words {
+        load_words(&mut simulator, &[value], MEMORY_BASE + offset);
+    }
+
+    load_program(simulator, &program)
+}
+
+const LOAD_0X40005000: [u32; 2] = [
+    0x40005537, // lui a0, 0x40005
+    0x00052583, // lw a1, 0(a0)
+];
+
+#[test]
+fn test_mxr() {
+    // 実行のみ可能なページは読み込めない
+    let simulator = run(load(&[], EXEC_ONLY_PTE, &LOAD_0X40005000), 30);
+    let cpu = simulator.cpu();
+
+    assert_eq!(cpu.pc(), MEMORY_BASE + 0x100);
+    assert_eq!(cpu.read_csr(MCAUSE).unwrap(), 13); // Load page fault
+    assert_eq!(cpu.read_csr(MTVAL).unwrap(), 0x40005000);
+
+    // mstatus.MXR=1の場合は読み込める
+    let simulator = run(load(&SET_MXR, EXEC_ONLY_PTE, &LOAD_0X40005000), 30);
+    let cpu = simulator.cpu();
+
+    assert_ne!(cpu.pc(), MEMORY_BASE + 0x100);
+    assert_eq!(cpu.read_reg(11), 0x11111111); // a1
+}
+
+#[test]
+fn test_sum() {
+    // SモードからU=1のページは読み込めない
+    let simulator = run(load(&[], USER_PTE, &LOAD_0X40005000), 30);
+    let cpu = simulator.cpu();
+
+    assert_eq!(cpu.pc(), MEMORY_BASE + 0x100);
+    assert_eq!(cpu.read_csr(MCAUSE).unwrap(), 13); // Load page fault
+
+    // mstatus.SUM=1の場合は読み込める
+    let simulator = run(load(&SET_SUM, USER_PTE, &LOAD_0X40005000), 30);
+    let cpu = simulator.cpu();
+
+    assert_ne!(cpu.pc(), MEMORY_BASE + 0x100);
+    assert_eq!(cpu.read_reg(11), 0x11111111); // a1
+}
+
+#[test]
+fn test_superpage_and_sfence_vma() {
+    let program = [
+        0x40400537, // lui a0, 0x40400
+        0x01052583, // lw a1, 0x10(a0)
+        0x404016b7, // lui a3, 0x40401
+        0x0106a603, // lw a2, 0x10(a3)
+        // メガページのPTEを0x80800000を指すように書き換える
+        0x80002337, // lui t1, 0x80002
+        0x202003b7, // lui t2, 0x20200
+        0x0c738393, // addi t2, t2, 0xc7
+        0x40732223, // sw t2, 0x404(t1)
+        0x01052683, // lw a3, 0x10(a0)
+        0x00200e13, // li t3, 2
+        0x13c50073, // sfence.vma a0, t3
+        0x01052703, // lw a4, 0x10(a0)
+        0x12050073, // sfence.vma a0, zero
+        0x01052783, // lw a5, 0x10(a0)
+    ];
+
+    let simulator = run(load(&[], 0, &program), 50);
+    let cpu = simulator.cpu();
+
+    assert_ne!(cpu.pc(), MEMORY_BASE + 0x100);
+    assert_eq!(cpu.read_reg(8) >> 22, 0x201); // s0 MODE=Sv32, ASID=1
+    assert_eq!(cpu.read_reg(11), 0x22222222); // a1
+    assert_eq!(cpu.read_reg(12), 0x33333333); // a2
+
+    // sfence.vmaを実行するまでは古い変換結果が使われる
+    assert_eq!(cpu.read_reg(13), 0x22222222); // a3
+
+    // 別のASIDを指定した場合は消えない
+    assert_eq!(cpu.read_reg(14), 0x22222222); // a4
+    assert_eq!(cpu.read_reg(15), 0x44444444); // a5
+}
@@ -0,0 +1,94 @@
+use crate::common::{load_program, load_words, run};
+use tiny_rv32ima_sim::simulator::{HeadlessLoaded, MEMORY_BASE, Simulator};
+
+mod common;
+
+const MCAUSE: u32 = 0x342;
+const MTVAL: u32 = 0x343;
+
+const PTE_A: u32 = 1 << 6;
+const PTE_D: u32 = 1 << 7;
+
+// 0x80004000を指すV|R|Wのリーフ
+const LEAF_PTE: u32 = 0x20001007;
+
+// 0x40005000に読み込みと書き込みをSモードで行う
+// TLBでコードのページと衝突しないように0x40005000を使う
+// トラップした場合は0x80000100で止まる
+fn load(adue: bool, leaf_pte: u32) -> Simulator<HeadlessLoaded> {
+    let program = [
+        0x800002b7u32, // lui t0, 0x80000
+        0x10028293,    // addi t0, t0, 0x100
+        0x30529073,    // csrw mtvec, t0
+        0x800802b7,    // lui t0, 0x80080
+        0x00228293,    // addi t0, t0, 2
+        0x18029073,    // csrw satp, t0
+        0x200002b7,    // lui t0, 0x20000
+        if adue {
+            0x31a29073 // csrw menvcfgh, t0
+        } else {
+            0x00000013 // nop
+        },
+        0x30a01073, // csrw menvcfg, zero
+        0x31a02473, // csrr s0, menvcfgh
+        0x000012b7, // lui t0, 0x1
+        0x80028293, // addi t0, t0, -0x800
+        0x3002a073, // csrs mstatus, t0
+        0x00000297, // auipc t0, 0
+        0x01028293, // addi t0, t0, 16
+        0x34129073, // csrw mepc, t0
+        0x30200073, // mret
+        0x40005537, // lui a0, 0x40005
+        0x00052583, // lw a1, 0(a0)
+        0x00b52223, // sw a1, 4(a0)
+        0x80003337, // lui t1, 0x80003
+        0x01432603, // lw a2, 0x14(t1)
+        0x0000006f, // j .
+    ];
+
+    // PMPを設定せずにS/Uモードで実行する
+    let mut simulator = Simulator::new().set_pmp_permissive(true).setup_headless();
+
+    // 0x80002000に1段目、0x80003000に2段目のページテーブルを置く
+    // 0x80000000はV|R|W|X|A|Dのメガページでそのままマップする
+    load_words(&mut simulator, &[0x200000cf], MEMORY_BASE + 0x2800);
+    load_words(&mut simulator, &[0x20000c01], MEMORY_BASE + 0x2400);
+    load_words(&mut simulator, &[leaf_pte], MEMORY_BASE + 0x3014);
+    load_words(&mut simulator, &[0x12345678], MEMORY_BASE + 0x4000);
+
+    load_program(simulator, &program)
+}
+
+#[test]
+fn test_adue_enabled() {
+    let simulator = run(load(true, LEAF_PTE), 40);
+    let cpu = simulator.cpu();
+
+    assert_eq!(cpu.pc(), MEMORY_BASE + 0x58);
+    assert_eq!(cpu.read_reg(8), 1 << 29); // s0 menvcfgへの書き込みでADUEが消えない
+    assert_eq!(cpu.read_reg(11), 0x12345678); // a1
+
+    // 読み込みでTLBに載った後の書き込みでもDビットが立つ
+    assert_eq!(cpu.read_reg(12), LEAF_PTE | PTE_A | PTE_D); // a2
+}
+
+#[test]
+fn test_adue_disabled() {
+    // Aビットが立っていない場合は読み込みでページフォルトになる
+    let simulator = run(load(false, LEAF_PTE), 40);
+    let cpu = simulator.cpu();
+
+    assert_eq!(cpu.pc(), MEMORY_BASE + 0x100);
+    assert_eq!(cpu.read_reg(8), 0); // s0
+    assert_eq!(cpu.read_csr(MCAUSE).unwrap(), 13); // Load page fault
+    assert_eq!(cpu.read_csr(MTVAL).unwrap(), 0x40005000);
+
+    // Dビットが立っていない場合は読み込みでTLBに載っていても書き込みでページフォルトになる
+    let simulator = run(load(false, LEAF_PTE | PTE_A), 40);
+    let cpu = simulator.cpu();
+
+    assert_eq!(cpu.pc(), MEMORY_BASE + 0x100);
+    assert_eq!(cpu.read_reg(11), 0x12345678); // a1
+    assert_eq!(cpu.read_csr(MCAUSE).unwrap(), 15); // Store/AMO page fault
+    assert_eq!(cpu.read_csr(MTVAL).unwrap(), 0x40005004);
+}
@@ -0,0 +1,87 @@
+use std::{thread, time::Duration, time::Instant};
+
+use crate::common::{JUMP_SELF, load_program, run};
+use tiny_rv32ima_sim::simulator::{HeadlessLoaded, Simulator, TimerMode};
+
+mod common;
+
+const TIME: u32 = 0xc01;
+const TIMEH: u32 = 0xc81;
+const INSTRET: u32 = 0xc02;
+
+// timebase-frequencyは10MHz
+const TICKS_PER_MS: u64 = 10_000;
+
+// ホストの時計を読む間隔より多く実行する
+const STEPS: u64 = 0x200;
+
+fn load(program: &[u32]) -> Simulator<HeadlessLoaded> {
+    load_program(
+        Simulator::new()
+            .set_timer_mode(TimerMode::WallClock)
+            .setup_headless(),
+        program,
+    )
+}
+
+fn time(simulator: &Simulator<HeadlessLoaded>) -> u64 {
+    let cpu = simulator.cpu();
+
+    (cpu.read_csr(TIMEH).unwrap() as u64) << 32 | cpu.read_csr(TIME).unwrap() as u64
+}
+
+#[test]
+fn test_wall_clock_time() {
+    // timeは実行した命令数ではなくホストで経過した時間になる
+    let start = Instant::now();
+
+    let simulator = run(load(&[JUMP_SELF]), STEPS);
+    let before = time(&simulator);
+
+    thread::sleep(Duration::from_millis(50));
+
+    let simulator = run(simulator, STEPS);
+    let after = time(&simulator);
+    let elapsed = start.elapsed().as_millis() as u64 * TICKS_PER_MS;
+
+    assert_eq!(simulator.cpu().read_csr(INSTRET).unwrap() as u64, STEPS * 2);
+    assert!(after - before >= 50 * TICKS_PER_MS);
+    assert!(after <= elapsed + TICKS_PER_MS);
+}
+
+#[test]
+fn test_wall_clock_never_goes_back() {
+    // mtimeを0x1_00000000に書き換える
+    let program = [
+        0x0200c337, // lui t1, 0x200c
+        0xfe032c23, // sw zero, -8(t1)
+        0x00100393, // li t2, 1
+        0xfe732e23, // sw t2, -4(t1)
+        0x0000006f, // j .
+    ];
+
+    // 書き込んだ値から実時間で進む
+    let simulator = run(load(&program), STEPS);
+    let written = time(&simulator);
+
+    assert!(written >= 1 << 32);
+
+    thread::sleep(Duration::from_millis(10));
+
+    let simulator = run(simulator, STEPS);
+
+    assert!(time(&simulator) >= written + 10 * TICKS_PER_MS);
+
+    // 実時間が始まったばかりのSimulatorに復元しても、止まらずに復元したtimeから進む
+    let snapshot = simulator.save_snapshot();
+    let restored_time = time(&simulator);
+
+    let mut restored = load(&program);
+    restored.restore_snapshot(&snapshot).unwrap();
+
+    thread::sleep(Duration::from_millis(10));
+
+    let restored = run(restored, STEPS);
+
+    assert!(time(&restored) >= restored_time + 10 * TICKS_PER_MS);
+}
@@ -0,0 +1,108 @@
+use std::{cell::RefCell, io::Write, rc::Rc};
+
+use tiny_rv32ima_sim::{
+    Priv,
+    simulator::{MEMORY_BASE, Simulator, TestConfig, TraceConfig, TraceFormat, Tracer},
+};
+
+// テストから書き込まれた内容を読むためのWrite
+#[derive(Clone, Default)]
+struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+// ストアとロードを行ってからecallする
+const PROGRAM: [u32; 5] = [
+    0x800012b7, // lui t0, 0x80001
+    0x02a00313, // li t1, 42
+    0x0062a223, // sw t1, 4(t0)
+    0x0042a383, // lw t2, 4(t0)
+    0x00000073, // ecall
+];
+
+fn trace(program: &[u32], config: TraceConfig) -> String {
+    let buffer = SharedBuffer::default();
+
+    let mut simulator = Simulator::new().setup_headless();
+    let program: Vec<u8> = program.iter().flat_map(|i| i.to_le_bytes()).collect();
+
+    simulator.load_flat(&program, MEMORY_BASE).unwrap();
+    simulator.set_tracer(Some(Tracer::new(Box::new(buffer.clone()), config)));
+
+    let config = TestConfig {
+        max_steps: 5,
+        ..Default::default()
+    };
+
+    simulator.set_entry_point(MEMORY_BASE).run_test(&config);
+
+    String::from_utf8(buffer.0.take()).unwrap()
+}
+
+#[test]
+fn test_trace_commit_log() {
+    let log = trace(&PROGRAM, TraceConfig::default());
+
+    assert_eq!(
+        log.lines().collect::<Vec<_>>(),
+        [
+            "core   0: 3 0x80000000 (0x800012b7) x5  0x80001000",
+            "core   0: 3 0x80000004 (0x02a00313) x6  0x0000002a",
+            "core   0: 3 0x80000008 (0x0062a223) mem 0x80001004 0x0000002a",
+            "core   0: 3 0x8000000c (0x0042a383) x7  0x0000002a mem 0x80001004",
+            "core   0: exception trap_machine_ecall, epc 0x80000010",
+        ]
+    );
+}
+
+#[test]
+fn test_trace_filter() {
+    let config = TraceConfig {
+        format: TraceFormat::Full,
+        range: Some(MEMORY_BASE + 4..MEMORY_BASE + 8),
+        ..Default::default()
+    };
+
+    assert_eq!(
+        trace(&PROGRAM, config).lines().collect::<Vec<_>>(),
+        [
+            "core   0: 0x80000004 (0x02a00313) li      t1, 42",
+            "core   0: 3 0x80000004 (0x02a00313) x6  0x0000002a",
+        ]
+    );
+
+    let config = TraceConfig {
+        privs: vec![Priv::Supervisor, Priv::User],
+        ..Default::default()
+    };
+
+    assert_eq!(trace(&PROGRAM, config), "");
+}
+
+#[test]
+fn test_trace_fld() {
+    // fldは2回に分けて読み込むが、記録するのは命令のアドレスだけ
+    let program = [
+        0x800012b7, // lui t0, 0x80001
+        0x00002e37, // lui t3, 0x2
+        0x300e2073, // csrs mstatus, t3
+        0x0002b087, // fld f1, 0(t0)
+        0x00000073, // ecall
+    ];
+
+    let log = trace(&program, TraceConfig::default());
+
+    assert_eq!(
+        log.lines().nth(3),
+        Some("core   0: 3 0x8000000c (0x0002b087) f1  0x0000000000000000 mem 0x80001000")
+    );
+}